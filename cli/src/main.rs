@@ -1,42 +1,29 @@
 use std::path;
-use std::path::PathBuf;
 
 use anyhow::anyhow;
 use clap::Parser;
-use lazy_static::lazy_static;
-use log::{debug, info, warn};
-use tap::Tap;
-use tempfile::TempPath;
-use tokio::sync::mpsc::{Receiver, Sender};
-
-use processing::processing::{ProcessContextBuilder, processor, ProcessOutput, ProcessType};
-use services::{ArchiveBuilder, log_err};
-
-lazy_static! {
-    static ref RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .expect("Failed to create tokio runtime");
-}
+use log::warn;
 
-/// Global asynchronous runtime.
-///
-pub fn runtime() -> &'static tokio::runtime::Runtime {
-    &RUNTIME
-}
+use processing::{ArchiveRoot, EntryNaming, process_with_options, ProcessOptionsBuilder};
+use processing::processing::{LineEnding, MetadataFormat, ProcessType};
+use services::{check_dependencies, download, Download, HttpClientConfig, is_url, log_level};
 
-/// The number of threads to use for handling outputs.
+/// The file to process, either on disk or at an `http` or `https` URL.
 ///
-const OUTPUT_HANDLING_THREADS: usize = 1000;
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Input {
+    Path(path::PathBuf),
+    Url(String),
+}
 
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(
         short = 'i',
         long,
-        value_parser = parse_input_file
+        value_parser = parse_input
     )]
-    input: path::PathBuf,
+    input: Input,
 
     #[arg(
         short = 'o',
@@ -45,7 +32,7 @@ struct Args {
     output: path::PathBuf,
 
     #[arg(short = 'm', long)]
-    mimetype: String,
+    mimetype: Option<String>,
 
     #[arg(
         short = 't',
@@ -57,9 +44,91 @@ struct Args {
 
     #[arg(short = 'a', long)]
     all: bool,
+
+    #[arg(long)]
+    trust_content: bool,
+
+    #[arg(long)]
+    max_input_bytes: Option<u64>,
+
+    #[arg(long)]
+    max_output_bytes: Option<u64>,
+
+    #[arg(long)]
+    max_total_outputs: Option<usize>,
+
+    #[arg(
+        long,
+        num_args = 1..,
+        value_delimiter = ' ',
+    )]
+    filter: Option<Vec<String>>,
+
+    #[arg(long, requires = "filter")]
+    keep_filtered: bool,
+
+    #[arg(long)]
+    keep_junk_files: bool,
+
+    #[arg(long)]
+    raw_mbox_messages: bool,
+
+    #[arg(long)]
+    compress_text: bool,
+
+    #[arg(long)]
+    line_ending: Option<LineEnding>,
+
+    #[arg(long)]
+    detect_language: bool,
+
+    #[arg(
+        long,
+        num_args = 1..,
+        value_delimiter = ' ',
+    )]
+    headers: Option<Vec<String>>,
+
+    #[arg(
+        long,
+        num_args = 1..,
+        value_delimiter = ' ',
+    )]
+    suppress_headers: Vec<String>,
+
+    #[arg(long)]
+    append_pdf_attachments: bool,
+
+    #[arg(long, default_value = "checksum")]
+    naming: EntryNaming,
+
+    #[arg(long)]
+    flatten: bool,
+
+    #[arg(long, default_value = "json")]
+    metadata_format: MetadataFormat,
+
+    #[arg(long)]
+    include_original: bool,
+
+    #[arg(long, conflicts_with = "root_from_input")]
+    root_label: Option<String>,
+
+    #[arg(long)]
+    root_from_input: bool,
+
+    #[arg(short = 'q', long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    #[arg(short = 'v', long, action = clap::ArgAction::Count)]
+    verbose: u8,
 }
 
-fn parse_input_file(path_str: &str) -> Result<path::PathBuf, String> {
+fn parse_input(path_str: &str) -> Result<Input, String> {
+    if is_url(path_str) {
+        return Ok(Input::Url(path_str.to_string()));
+    }
+
     let path = path::PathBuf::from(path_str.to_string());
     if !path.exists() {
         return Err(format!("Path {} not found", path_str))
@@ -67,158 +136,117 @@ fn parse_input_file(path_str: &str) -> Result<path::PathBuf, String> {
     if !path.is_file() {
         return Err(format!("Path {} is not a file", path_str))
     }
-    Ok(path)
+    Ok(Input::Path(path))
+}
+
+/// Resolves the path and MIME type of the file to process, downloading it first if it's a URL.
+///
+/// The MIME type of a downloaded file defaults to the `Content-Type` of the response. The download is returned along
+/// with the path, as its file is deleted once it's dropped.
+///
+async fn resolve_input(
+    input: Input,
+    mimetype: Option<String>,
+) -> anyhow::Result<(path::PathBuf, String, Option<Download>)> {
+    match input {
+        Input::Path(path) => {
+            let mimetype = mimetype.ok_or(anyhow!("--mimetype is required to process a file on disk"))?;
+            Ok((path, mimetype, None))
+        },
+        Input::Url(url) => {
+            let download = download(&url, &HttpClientConfig::default()).await?;
+            let mimetype = mimetype.or(download.mimetype.clone())
+                .ok_or(anyhow!("--mimetype is required, as {} has no Content-Type", url))?;
+            Ok((download.path.to_path_buf(), mimetype, Some(download)))
+        },
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    simple_logger::init_with_level(log::Level::Info)?;
-
     let args = Args::parse();
+    simple_logger::init_with_level(log_level(args.quiet, args.verbose))?;
+    for missing in check_dependencies() {
+        warn!("Files that need it will fail to be processed: {}", missing);
+    }
+
     let types = if args.all {
         ProcessType::all().to_vec()
     } else {
         args.types
     };
 
-    process(args.input, args.output, args.mimetype, types, true).await?;
+    let archive_root = match (args.root_label, args.root_from_input) {
+        (Some(label), _) => Some(ArchiveRoot::Label(label)),
+        (None, true) => Some(ArchiveRoot::InputStem),
+        (None, false) => None,
+    };
 
-    Ok(())
-}
+    let (input_path, mimetype, _download) = resolve_input(args.input, args.mimetype).await?;
+    let options = ProcessOptionsBuilder::new(mimetype)
+        .types(types)
+        .max_input_bytes(args.max_input_bytes)
+        .max_output_bytes(args.max_output_bytes)
+        .max_total_outputs(args.max_total_outputs)
+        .mimetype_allowlist(args.filter)
+        .keep_filtered(args.keep_filtered)
+        .keep_junk_files(args.keep_junk_files)
+        .raw_mbox_messages(args.raw_mbox_messages)
+        .compress_text(args.compress_text)
+        .line_ending(args.line_ending)
+        .detect_language(args.detect_language)
+        .trust_content(args.trust_content)
+        .message_headers(args.headers)
+        .suppressed_headers(args.suppress_headers)
+        .append_pdf_attachments(args.append_pdf_attachments)
+        .entry_naming(args.naming)
+        .flatten(args.flatten)
+        .metadata_format(args.metadata_format)
+        .include_original(args.include_original)
+        .archive_root(archive_root)
+        .build();
+
+    let mut archive = process_with_options(input_path, options).await?;
+    let mut output = std::fs::File::create(args.output)?;
+    std::io::copy(&mut archive, &mut output)?;
 
-/// Process a stream of bytes.
-///
-/// This function processes a stream of bytes, and returns an archive file
-/// containing the metadata.json of the processing operation.
-///
-/// # Arguments
-///
-/// * `stream` - The stream of bytes to process.
-/// * `mimetype` - The MIME type the stream of bytes represents.
-/// * `process_recursively` - Whether to process embedded files recursively.
-///
-/// # Returns
-///
-/// * `Ok(File)` - If the stream of bytes was processed successfully, where `File` is the file of the created archive
-///     containing the metadata.json files of the processing operation.
-/// * `Err(_)` - If there was an error processing the stream of bytes.
-///
-pub async fn process(
-    input_path: PathBuf,
-    output_path: PathBuf,
-    mimetype: String,
-    types: Vec<ProcessType>,
-    recurse: bool,
-) -> anyhow::Result<()> {
-    info!("Processing file with MIME type {}", &mimetype);
-
-    let (output_sink, outputs) = tokio::sync::mpsc::channel(100);
-    let (archive_entry_sink, archive_entries) = tokio::sync::mpsc::channel(100);
-
-    let ctx = ProcessContextBuilder::new(
-        mimetype,
-        types,
-        output_sink,
-    ).build();
-
-    let processing = tokio::spawn(processor().process(ctx, input_path));
-    let output_handling = tokio::spawn(handle_outputs(
-        outputs,
-        archive_entry_sink,
-        recurse,
-    ));
-    let archive = tokio::spawn(build_archive(archive_entries, output_path));
-
-    processing.await?.map_err(|err| anyhow!(format!("{}", err)))?;
-    output_handling.await?;
-    info!("Finished processing file");
-
-    archive.await??;
     Ok(())
 }
 
-/// Handle the outputs of the processing operation asynchronously.
-///
-/// Each metadata.json received is submitted to a thread pool to be handled on a separate thread. This allows us to
-/// continuing receiving processing outputs without blocking.
-///
-/// Archive entries created from each metadata.json is sent to the archive entry sink.
-///
-async fn handle_outputs(
-    mut outputs: Receiver<anyhow::Result<ProcessOutput>>,
-    archive_entry_sink: Sender<(TempPath, PathBuf)>,
-    recurse: bool,
-) {
-    let worker_pool = threadpool::ThreadPool::new(OUTPUT_HANDLING_THREADS);
-
-    while let Some(output) = outputs.recv().await {
-        if let Ok(output) = output.tap(log_err!("Error processing")) {
-            let archive_entry_sink = archive_entry_sink.clone();
-            worker_pool.execute(move || runtime().block_on(
-                handle_process_output(output, archive_entry_sink, recurse)
-            ));
-        }
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+
+    use processing::process_with_summary;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_process_url() -> anyhow::Result<()> {
+        let content = std::fs::read("../resources/rfc822/attachments.eml")?;
+        let server = MockServer::start_async().await;
+        server.mock_async(|when, then| {
+            when.method(GET).path("/attachments.eml");
+            then.status(200).header("Content-Type", "message/rfc822").body(content);
+        }).await;
+
+        let input = parse_input(&server.url("/attachments.eml")).map_err(|err| anyhow!(err))?;
+        let (input_path, mimetype, download) = resolve_input(input, None).await?;
+
+        assert_eq!(mimetype, "message/rfc822");
+        assert!(download.is_some());
+        let options = ProcessOptionsBuilder::new(mimetype)
+            .types(vec![ProcessType::Attachments])
+            .build();
+        let summary = process_with_summary(input_path, options).await?;
+        assert_eq!(summary.embedded_count, 2);
+        Ok(())
     }
 
-    worker_pool.join();
-}
-
-/// Regardless of if the metadata.json is normal or an embedded file, both will be used to create an archive entry and no additional
-/// processing will occur.
-///
-async fn handle_process_output(
-    output: ProcessOutput,
-    archive_entry_sink: Sender<(TempPath, PathBuf)>,
-    recurse: bool
-) {
-    let archive_entry: anyhow::Result<(TempPath, PathBuf)> = match output {
-        ProcessOutput::Processed(state, data) => {
-            let archive_path = build_archive_path(state.id_chain, data.name).await;
-            Ok((data.path, archive_path))
-        },
-
-        ProcessOutput::Embedded(state, data, output_sink) => {
-            let mut id_chain = state.id_chain;
-            id_chain.push(data.checksum);
-
-            if recurse {
-                let ctx = ProcessContextBuilder::new(data.mimetype, data.types, output_sink.clone())
-                    .id_chain(id_chain.clone())
-                    .build();
-                if let Err(e) = processor().process(ctx, data.path.to_path_buf()).await {
-                    warn!("Error processing: {:?}", e);
-                };
-            }
-
-            let archive_path = build_archive_path(id_chain, data.name).await;
-            Ok((data.path, archive_path))
-        }
-    };
-
-    match archive_entry {
-        Ok(archive_entry) => archive_entry_sink.send(archive_entry).await.unwrap(),
-        Err(e) => warn!("Error processing: {:?}", e),
-    }
-}
-
-/// Future for building the archive by reading from received `entries`.
-///
-async fn build_archive(mut entries: Receiver<(TempPath, PathBuf)>, output_path: PathBuf) -> anyhow::Result<()> {
-    let file = std::fs::File::create(output_path)?;
-    let mut archive_builder = ArchiveBuilder::new(file)?;
-    while let Some((path, zip_path)) = entries.recv().await {
-        debug!("Adding archive entry {:?}", zip_path);
-        archive_builder.push(path, zip_path)?;
-    }
-    let _ = archive_builder.build()?;
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_resolve_input_path_requires_mimetype() {
+        let input = parse_input("../resources/rfc822/attachments.eml").unwrap();
 
-async fn build_archive_path(id_chain: impl AsRef<[String]>, name: impl AsRef<str>) -> PathBuf {
-    let mut path = PathBuf::new();
-    for id in id_chain.as_ref() {
-        path.push(id);
+        assert!(resolve_input(input, None).await.is_err());
     }
-    path.push(name.as_ref());
-    path
 }
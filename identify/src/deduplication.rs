@@ -1,11 +1,79 @@
 use std::io::Cursor;
-use std::ops::{Deref, DerefMut};
 use std::path::Path;
+use std::str::FromStr;
 
 use bytesize::MB;
-use mail_parser::MessageParser;
+use lazy_static::lazy_static;
+use mail_parser::{Message, MessageParser};
 use tokio::io::{AsyncRead, AsyncReadExt};
 
+use services::config;
+
+/// The size of the chunks files are read in to calculate their checksums, unless `CHECKSUM_CHUNK_SIZE` is set.
+///
+pub const DEFAULT_CHECKSUM_CHUNK_SIZE: usize = MB as usize;
+
+/// The size of the blocks MD5 checksums are calculated over.
+///
+/// The last block is hashed in full too, holding whatever bytes of the block before it weren't overwritten, or zeros if
+/// there's none. This keeps checksums the same as they've always been, whatever the chunk size.
+///
+const MD5_BLOCK_SIZE: usize = MB as usize;
+
+lazy_static! {
+    static ref CHECKSUM_CHUNK_SIZE: usize = config().get("CHECKSUM_CHUNK_SIZE")
+        .and_then(|size| size.parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(DEFAULT_CHECKSUM_CHUNK_SIZE);
+}
+
+/// How a dedupe checksum is calculated from a file.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DedupeStrategy {
+    /// The MD5 of the file's content.
+    ///
+    Content,
+
+    /// The MD5 of the message's `Message-ID`, or of its identifying headers and body if it has none.
+    ///
+    Message,
+}
+
+impl DedupeStrategy {
+    /// Returns the strategy used for files of the MIME type, unless it's overridden.
+    ///
+    /// Messages are identified by their `Message-ID`, and everything else by its content.
+    ///
+    pub fn for_mimetype(mimetype: &str) -> Self {
+        match mimetype {
+            "message/rfc822" => Self::Message,
+            _ => Self::Content,
+        }
+    }
+}
+
+impl FromStr for DedupeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "content" => Ok(Self::Content),
+            "message" => Ok(Self::Message),
+            _ => Err(format!("Unknown dedupe strategy: {}", s)),
+        }
+    }
+}
+
+/// Returns the size in bytes of the chunks files are read in to calculate their checksums.
+///
+/// It's `CHECKSUM_CHUNK_SIZE` if it's set to a positive number, or 1 MB otherwise. Chunks are read into the 1 MB block
+/// being hashed, so larger chunks are read a block at a time. Checksums are the same whatever the chunk size.
+///
+pub fn checksum_chunk_size() -> usize {
+    *CHECKSUM_CHUNK_SIZE
+}
+
 /// Calculates a checksum that represents a unique identification of a file.
 ///
 /// This checksum can be used to identify duplicate files.
@@ -20,9 +88,22 @@ use tokio::io::{AsyncRead, AsyncReadExt};
 /// The checksum as a string.
 ///
 pub async fn dedupe_checksum_from_path(path: impl AsRef<Path>, mimetype: impl AsRef<str>) -> anyhow::Result<String> {
-    let checksum = match mimetype.as_ref() {
-        "message/rfc822" => dedupe_message_from_path(path).await,
-        _ => dedupe_md5_from_path(path).await,
+    dedupe_checksum_from_path_with_chunk_size(path, mimetype, checksum_chunk_size()).await
+}
+
+/// Calculates a checksum that represents a unique identification of a file, reading it in chunks of `chunk_size`
+/// bytes.
+///
+/// See [`dedupe_checksum_from_path`]; the checksum is the same whatever the chunk size.
+///
+pub async fn dedupe_checksum_from_path_with_chunk_size(
+    path: impl AsRef<Path>,
+    mimetype: impl AsRef<str>,
+    chunk_size: usize,
+) -> anyhow::Result<String> {
+    let checksum = match DedupeStrategy::for_mimetype(mimetype.as_ref()) {
+        DedupeStrategy::Message => dedupe_message_from_path(path).await,
+        DedupeStrategy::Content => dedupe_md5_from_path(path, chunk_size).await,
     }?;
     Ok(checksum)
 }
@@ -41,29 +122,110 @@ pub async fn dedupe_checksum_from_path(path: impl AsRef<Path>, mimetype: impl As
 /// The checksum as a string.
 ///
 pub async fn dedupe_checksum(content: &mut (impl AsyncRead + Unpin), mimetype: impl AsRef<str>) -> anyhow::Result<String> {
-    let checksum = match mimetype.as_ref() {
-        "message/rfc822" => dedupe_message(content).await,
-        _ => dedupe_md5(content).await,
+    dedupe_checksum_with_strategy(content, DedupeStrategy::for_mimetype(mimetype.as_ref())).await
+}
+
+/// Calculates a checksum that represents a unique identification of a file, using the given strategy rather than the
+/// one chosen for its MIME type.
+///
+/// See [`dedupe_checksum`].
+///
+pub async fn dedupe_checksum_with_strategy(
+    content: &mut (impl AsyncRead + Unpin),
+    strategy: DedupeStrategy,
+) -> anyhow::Result<String> {
+    let checksum = match strategy {
+        DedupeStrategy::Message => dedupe_message(content).await,
+        DedupeStrategy::Content => dedupe_md5(content, checksum_chunk_size()).await,
     }?;
     Ok(checksum)
 }
 
 /// Calculates an MD5 checksum from the contents of a file.
 ///
-async fn dedupe_md5_from_path(path: impl AsRef<Path>) -> anyhow::Result<String> {
+async fn dedupe_md5_from_path(path: impl AsRef<Path>, chunk_size: usize) -> anyhow::Result<String> {
     let mut content = tokio::fs::File::open(path).await?;
-    dedupe_md5(&mut content).await
+    dedupe_md5(&mut content, chunk_size).await
 }
 
-/// Calculates an MD5 checksum from the provided reader.
+/// Calculates an MD5 checksum from the provided reader, reading it in chunks of at most `chunk_size` bytes.
 ///
-async fn dedupe_md5(content: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<String> {
-    let mut ctx = md5::Context::new();
-    let mut buf = Box::new([0; MB as usize]);
-    while content.read(buf.deref_mut()).await? > 0 {
-        ctx.consume(buf.deref());
+/// Chunks are read straight into the block being hashed, so they're also at most a block, and only a block is held in
+/// memory.
+///
+async fn dedupe_md5(content: &mut (impl AsyncRead + Unpin), chunk_size: usize) -> anyhow::Result<String> {
+    let mut hasher = BlockMd5::new();
+    loop {
+        let len = content.read(hasher.unfilled(chunk_size.max(1))).await?;
+        if len == 0 {
+            break;
+        }
+        hasher.fill(len);
+    }
+    Ok(hasher.compute())
+}
+
+/// An MD5 hasher consuming its input in blocks of 1 MB, computing the checksums of files deduplicated by their
+/// content.
+///
+/// The last block is hashed in full, like the ones before it; see [`dedupe_checksum`].
+///
+pub struct BlockMd5 {
+    ctx: md5::Context,
+    block: Vec<u8>,
+    len: usize,
+}
+
+impl BlockMd5 {
+    /// Creates a hasher that has consumed nothing yet.
+    ///
+    pub fn new() -> Self {
+        Self { ctx: md5::Context::new(), block: vec![0; MD5_BLOCK_SIZE], len: 0 }
+    }
+
+    /// Consumes the bytes, following the ones consumed so far.
+    ///
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let unfilled = self.unfilled(bytes.len());
+            let len = unfilled.len();
+            unfilled.copy_from_slice(&bytes[..len]);
+            self.fill(len);
+            bytes = &bytes[len..];
+        }
+    }
+
+    /// Returns the next at most `max_len` bytes of the block to fill.
+    ///
+    fn unfilled(&mut self, max_len: usize) -> &mut [u8] {
+        let end = MD5_BLOCK_SIZE.min(self.len + max_len);
+        &mut self.block[self.len..end]
+    }
+
+    /// Marks `len` more bytes of the block as filled, hashing the block once it's full.
+    ///
+    fn fill(&mut self, len: usize) {
+        self.len += len;
+        if self.len == MD5_BLOCK_SIZE {
+            self.ctx.consume(&self.block);
+            self.len = 0;
+        }
+    }
+
+    /// Returns the hex checksum of the bytes consumed.
+    ///
+    pub fn compute(mut self) -> String {
+        if self.len > 0 {
+            self.ctx.consume(&self.block);
+        }
+        format!("{:x}", self.ctx.compute())
+    }
+}
+
+impl Default for BlockMd5 {
+    fn default() -> Self {
+        Self::new()
     }
-    Ok(format!("{:x}", ctx.compute()))
 }
 
 /// Calculates an RFC822-based checksum from the contents of a file.
@@ -75,28 +237,54 @@ async fn dedupe_message_from_path(path: impl AsRef<Path>) -> anyhow::Result<Stri
 
 /// Calculates an RFC822-based checksum from the provided reader.
 ///
+/// The checksum is the MD5 of the message's `Message-ID`. Messages without one fall back to the MD5 of their
+/// normalized `From`, `Date`, and `Subject` headers plus their body (see [`normalized_message`]), so the same message
+/// gets the same checksum regardless of its transport headers, while distinct messages don't collapse to one.
+/// Content that can't be parsed as a message falls back to the MD5 of the content itself.
+///
 async fn dedupe_message(content: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<String> {
     let mut buf = vec![];
     content.read_to_end(&mut buf).await?;
 
     let message = MessageParser::default().parse(&buf);
-    let raw_id = message
-        .as_ref()
-        .and_then(|msg| msg.message_id())
-        .map(|id| id.as_bytes().to_vec());
+    let identity = match &message {
+        Some(message) => match message.message_id() {
+            Some(id) => id.as_bytes().to_vec(),
+            None => normalized_message(message, &buf),
+        },
+        None => buf.clone(),
+    };
+
+    dedupe_md5(&mut Cursor::new(identity), checksum_chunk_size()).await
+}
+
+/// Builds the identifying content of a message without a `Message-ID`.
+///
+/// The `From` header is lowercased and the `From` and `Subject` headers have their whitespace collapsed, the `Date` is
+/// formatted as in RFC 3339, and the body has its line endings normalized to `\n` and trailing whitespace trimmed.
+///
+fn normalized_message(message: &Message, raw: &[u8]) -> Vec<u8> {
+    let collapse = |value: &str| value.split_whitespace().collect::<Vec<_>>().join(" ");
+    let from = message.header_raw("From").map(|from| collapse(from).to_lowercase()).unwrap_or_default();
+    let date = message.date().map(|date| date.to_rfc3339()).unwrap_or_default();
+    let subject = message.subject().map(collapse).unwrap_or_default();
 
-    let mut content = raw_id
-        .map(|raw_id| Box::new(Cursor::new(raw_id)))
-        .unwrap_or(Box::new(Cursor::new(buf)));
+    let root = message.root_part();
+    let body = raw.get(root.offset_body..root.offset_end).unwrap_or_default();
+    let body = String::from_utf8_lossy(body).replace("\r\n", "\n");
 
-    dedupe_md5(&mut content).await
+    format!("From: {}\nDate: {}\nSubject: {}\n\n{}", from, date, subject, body.trim_end()).into_bytes()
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use std::io::{Cursor, Write};
+
+    use tempfile::NamedTempFile;
 
-    use crate::deduplication::dedupe_checksum;
+    use crate::deduplication::{dedupe_checksum, dedupe_checksum_from_path_with_chunk_size, dedupe_checksum_with_strategy};
+
+    use super::*;
 
     #[tokio::test]
     async fn test_dedupe_checksum_message_no_data() {
@@ -129,6 +317,44 @@ Phillip";
         assert_eq!(checksum, "48746efe196a27e395f613b9c0773b8b");
     }
 
+    /// A message without a `Message-ID`, received through the given host.
+    ///
+    fn message_without_id(received_from: &str, subject: &str) -> Vec<u8> {
+        format!("\
+Received: from {} by mx.example.com; Wed, 21 Feb 2001 07:58:05 -0800
+Date: Wed, 21 Feb 2001 07:58:00 -0800 (PST)
+From: phillip.allen@enron.com
+To: cbpres@austin.rr.com
+Subject: {}
+Content-Type: text/plain; charset=us-ascii
+
+Tomorrow is fine.  Talk to you then.
+", received_from, subject).into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_checksum_message_without_id() {
+        let mut first = Cursor::new(message_without_id("relay.example.com", "Re: Weekly Status Meeting"));
+        let mut second = Cursor::new(message_without_id("relay.example.com", "Re: Monthly Status Meeting"));
+
+        let first = dedupe_checksum(&mut first, "message/rfc822").await.unwrap();
+        let second = dedupe_checksum(&mut second, "message/rfc822").await.unwrap();
+
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_checksum_message_without_id_identical() {
+        // The same message, delivered through different relays
+        let mut first = Cursor::new(message_without_id("relay.example.com", "Re: Weekly Status Meeting"));
+        let mut second = Cursor::new(message_without_id("backup.example.com", "Re:  Weekly Status Meeting"));
+
+        let first = dedupe_checksum(&mut first, "message/rfc822").await.unwrap();
+        let second = dedupe_checksum(&mut second, "message/rfc822").await.unwrap();
+
+        assert_eq!(first, second);
+    }
+
     #[tokio::test]
     async fn test_dedupe_checksum_md5_no_data() {
         let mut content = Cursor::new(b"".to_vec());
@@ -138,6 +364,20 @@ Phillip";
         assert_eq!(checksum, "d41d8cd98f00b204e9800998ecf8427e");
     }
 
+    #[tokio::test]
+    async fn test_dedupe_checksum_with_strategy() -> anyhow::Result<()> {
+        let message = b"Message-ID: <hello@example.com>\r\nSubject: Hello\r\n\r\nHello, world!";
+
+        let by_content = dedupe_checksum_with_strategy(&mut Cursor::new(message), DedupeStrategy::Content).await?;
+        let by_message = dedupe_checksum_with_strategy(&mut Cursor::new(message), DedupeStrategy::Message).await?;
+
+        assert_eq!(by_content, dedupe_checksum(&mut Cursor::new(message), "application/octet-stream").await?);
+        assert_eq!(by_message, dedupe_checksum(&mut Cursor::new(message), "message/rfc822").await?);
+        assert_ne!(by_content, by_message);
+        assert_eq!("content".parse(), Ok(DedupeStrategy::Content));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_dedupe_checksum_md5() {
         let mut content = Cursor::new(b"Hello, world!".to_vec());
@@ -146,4 +386,27 @@ Phillip";
 
         assert_eq!(checksum, "bccf69bd7101c797b298c8b5329b965f");
     }
+
+    #[tokio::test]
+    async fn test_dedupe_checksum_chunk_size() -> anyhow::Result<()> {
+        let content: Vec<u8> = (0..2_500_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut file = NamedTempFile::new()?;
+        file.write_all(&content)?;
+        file.flush()?;
+
+        // As the checksum was calculated before the chunk size was configurable
+        let mut expected = md5::Context::new();
+        let mut block = vec![0; MB as usize];
+        for chunk in content.chunks(MB as usize) {
+            block[..chunk.len()].copy_from_slice(chunk);
+            expected.consume(&block);
+        }
+        let expected = format!("{:x}", expected.compute());
+
+        for chunk_size in [4 * 1024, 1_500_000] {
+            let checksum = dedupe_checksum_from_path_with_chunk_size(file.path(), "application/octet-stream", chunk_size).await?;
+            assert_eq!(checksum, expected);
+        }
+        Ok(())
+    }
 }
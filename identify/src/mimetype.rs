@@ -1,9 +1,86 @@
 use std::path::Path;
 
+use async_trait::async_trait;
 use file_format::FileFormat;
-use log::info;
+use lazy_static::lazy_static;
+use log::{info, warn};
 
-use services::{tika, xdg_mime};
+use services::{config, tika, xdg_mime};
+
+lazy_static! {
+    static ref MIME_SNIFFER: Box<dyn MimeSniffer> = configured_mime_sniffer();
+}
+
+/// Returns the MIME sniffer selected by `MIME_SNIFFER`, which is either `xdg-mime` (the default) or `infer`.
+///
+/// `xdg-mime` requires the shared MIME info database to be installed, while `infer` is built in but recognizes
+/// fewer types.
+///
+pub fn mime_sniffer() -> &'static dyn MimeSniffer {
+    MIME_SNIFFER.as_ref()
+}
+
+fn configured_mime_sniffer() -> Box<dyn MimeSniffer> {
+    match config().get_or("MIME_SNIFFER", "xdg-mime").as_str() {
+        "infer" => Box::new(InferMimeSniffer),
+        "xdg-mime" => Box::new(XdgMimeSniffer),
+        other => {
+            warn!("Unknown MIME sniffer '{}', using 'xdg-mime'", other);
+            Box::new(XdgMimeSniffer)
+        },
+    }
+}
+
+/// Sniffs the MIME type of a file from its contents.
+///
+#[async_trait]
+pub trait MimeSniffer: Send + Sync {
+    /// Sniffs the MIME type of the file at `path`.
+    ///
+    /// Returns [`None`] if the MIME type can't be identified any more specifically than a generic type, like
+    /// `application/octet-stream`.
+    ///
+    async fn sniff(&self, path: &Path) -> anyhow::Result<Option<String>>;
+
+    /// Returns the name of the sniffer.
+    ///
+    fn name(&self) -> &'static str;
+}
+
+/// Sniffs MIME types using the `xdg-mime` command.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct XdgMimeSniffer;
+
+#[async_trait]
+impl MimeSniffer for XdgMimeSniffer {
+    async fn sniff(&self, path: &Path) -> anyhow::Result<Option<String>> {
+        let mimetype = xdg_mime().query_filetype(path).await?;
+        Ok((mimetype != "application/octet-stream" && mimetype != "text/plain").then_some(mimetype))
+    }
+
+    fn name(&self) -> &'static str {
+        "xdg-mime"
+    }
+}
+
+/// Sniffs MIME types from the magic bytes at the start of files using the `infer` crate.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InferMimeSniffer;
+
+#[async_trait]
+impl MimeSniffer for InferMimeSniffer {
+    async fn sniff(&self, path: &Path) -> anyhow::Result<Option<String>> {
+        let path = path.to_path_buf();
+        let kind = tokio::task::spawn_blocking(move || infer::get_from_path(path)).await??;
+        Ok(kind.map(|kind| kind.mime_type().to_string()))
+    }
+
+    fn name(&self) -> &'static str {
+        "infer"
+    }
+}
 
 /// Identifies the mimetype of a file.
 ///
@@ -16,8 +93,9 @@ use services::{tika, xdg_mime};
 /// The mimetype of the file.
 ///
 pub async fn identify_mimetype(path: impl AsRef<Path>) -> anyhow::Result<Option<String>> {
-    if let Some(mimetype) = identify_using_xdg_mime(&path).await? {
-        info!("Identified mimetype as '{}' using 'xdg-mime'", mimetype);
+    let sniffer = mime_sniffer();
+    if let Some(mimetype) = sniffer.sniff(path.as_ref()).await? {
+        info!("Identified mimetype as '{}' using '{}'", mimetype, sniffer.name());
         return Ok(Some(mimetype));
     }
 
@@ -34,13 +112,25 @@ pub async fn identify_mimetype(path: impl AsRef<Path>) -> anyhow::Result<Option<
     Ok(None)
 }
 
-async fn identify_using_xdg_mime(path: impl AsRef<Path>) -> anyhow::Result<Option<String>> {
-    let mimetype = xdg_mime().query_filetype(path).await?;
-    Ok((mimetype != "application/octet-stream" && mimetype != "text/plain").then_some(mimetype))
+/// Sniffs the MIME type of a file from its magic bytes, returning it only if it confidently contradicts the declared
+/// MIME type.
+///
+/// Binary magic bytes only contradict declared types that either have magic bytes of their own, or are text, which
+/// has none. A zip declared as a format built on zip that isn't recognized, for instance, isn't contradicted.
+///
+pub async fn sniff_contradicting_mimetype(path: impl AsRef<Path>, declared: &str) -> anyhow::Result<Option<String>> {
+    let sniffed = InferMimeSniffer.sniff(path.as_ref()).await?;
+    Ok(sniffed.filter(|sniffed| contradicts(declared, sniffed)))
+}
+
+fn contradicts(declared: &str, sniffed: &str) -> bool {
+    sniffed != declared
+        && !sniffed.starts_with("text/")
+        && (declared.starts_with("text/") || infer::is_mime_supported(declared))
 }
 
 async fn identify_using_tika(path: impl AsRef<Path>) -> anyhow::Result<Option<String>> {
-    let mimetype = tika().detect(path).await?;
+    let mimetype = tika()?.detect(path).await?;
     Ok((mimetype != "application/octet-stream").then_some(mimetype))
 }
 
@@ -63,4 +153,36 @@ mod tests {
         assert_eq!(mimetype.unwrap(), "application/mbox");
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_xdg_mime_sniffer() -> anyhow::Result<()> {
+        let sniffer = XdgMimeSniffer;
+
+        assert_eq!(sniffer.sniff(Path::new("../resources/pdf/zugferd-invoice.pdf")).await?.as_deref(), Some("application/pdf"));
+        assert_eq!(sniffer.sniff(Path::new("../resources/text/english.txt")).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_infer_sniffer() -> anyhow::Result<()> {
+        let sniffer = InferMimeSniffer;
+
+        assert_eq!(sniffer.sniff(Path::new("../resources/pdf/zugferd-invoice.pdf")).await?.as_deref(), Some("application/pdf"));
+        assert_eq!(sniffer.sniff(Path::new("../resources/jpg/PA280041.JPG")).await?.as_deref(), Some("image/jpeg"));
+        assert_eq!(sniffer.sniff(Path::new("../resources/zip/testzip.zip")).await?.as_deref(), Some("application/zip"));
+        assert_eq!(sniffer.sniff(Path::new("../resources/text/english.txt")).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sniff_contradicting_mimetype() -> anyhow::Result<()> {
+        let zip = "../resources/zip/testzip.zip";
+
+        assert_eq!(sniff_contradicting_mimetype(zip, "text/plain").await?.as_deref(), Some("application/zip"));
+        assert_eq!(sniff_contradicting_mimetype(zip, "image/jpeg").await?.as_deref(), Some("application/zip"));
+        assert_eq!(sniff_contradicting_mimetype(zip, "application/zip").await?, None);
+        assert_eq!(sniff_contradicting_mimetype(zip, "application/vnd.example+zip").await?, None);
+        assert_eq!(sniff_contradicting_mimetype("../resources/text/english.txt", "image/jpeg").await?, None);
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -1,15 +1,16 @@
-use std::io::{Cursor, Write};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
 use log::{info, warn};
-use mail_parser::mailbox::mbox::{Message, MessageIterator};
+use mail_parser::mailbox::mbox::MessageIterator;
+use mail_parser::MessageParser;
 use serde::{Deserialize, Serialize};
 use tempfile::{NamedTempFile, TempPath};
 
-use identify::deduplication::dedupe_checksum;
-
+use crate::EmbeddedInfo;
 use crate::processing::{Process, ProcessContext, ProcessOutput};
 
 /// MboxProcessor is responsible for processing mbox files.
@@ -17,22 +18,32 @@ use crate::processing::{Process, ProcessContext, ProcessOutput};
 /// Internally it uses the `mail_parser` crate to parse the mbox file.
 /// The processor only writes out embedded messages and doesn't produce any processed metadata.json.
 ///
+/// The mbox is read twice: first to group the messages into threads, then to write out each message along with the
+/// ID of its thread.
+///
 #[derive(Debug, Default, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 pub struct MboxEmbeddedProcessor;
 
 impl MboxEmbeddedProcessor {
     /// Writes a message to the metadata.json directory.
     ///
-    async fn process_message(&self, ctx: &ProcessContext, message: Message) -> anyhow::Result<ProcessOutput> {
+    /// The message is only held in memory until it's written, and the checksum is calculated from the written file.
+    ///
+    async fn process_message(
+        &self,
+        ctx: &ProcessContext,
+        contents: Vec<u8>,
+        thread_id: Option<String>,
+    ) -> anyhow::Result<ProcessOutput> {
         let mut file = NamedTempFile::new()?;
-        let contents = message.unwrap_contents();
         file.write_all(&contents)?;
+        file.flush()?;
 
         let mimetype = "message/rfc822";
-        let ctx = ctx.new_clone(mimetype.to_string());
+        let mut ctx = ctx.new_clone(mimetype.to_string());
+        ctx.thread_id = thread_id;
 
-        let mut contents = Cursor::new(contents);
-        let checksum = dedupe_checksum(&mut contents, &mimetype).await?;
+        let checksum = ctx.checksum_from_path(file.path(), mimetype).await?;
 
         Ok(ProcessOutput::embedded(
             &ctx,
@@ -53,19 +64,16 @@ impl Process for MboxEmbeddedProcessor {
         _: TempPath,
         _: &str,
     ) -> anyhow::Result<()> {
-        info!("Reading mbox into iterator");
-        let file = std::fs::File::open(input_path)?;
-        let reader = std::io::BufReader::new(file);
-        let message_iter = MessageIterator::new(reader);
+        info!("Grouping messages into threads");
+        let mut headers = vec![];
+        for contents in messages(&ctx, input_path)? {
+            headers.push(ThreadHeaders::parse(&contents?));
+        }
+        let thread_ids = thread_ids(&headers);
 
         info!("Processing embedded messages");
-        for message_res in message_iter {
-            let message = message_res.map_err(|err| {
-                let msg = format!("failed to parse message from mbox: {:?}", err);
-                warn!("{}", msg);
-                anyhow!(msg)
-            })?;
-            let result = self.process_message(&ctx, message).await;
+        for (contents, thread_id) in messages(&ctx, input_path)?.zip(thread_ids) {
+            let result = self.process_message(&ctx, contents?, thread_id).await;
             ctx.add_output(result).await?;
         }
         Ok(())
@@ -74,10 +82,228 @@ impl Process for MboxEmbeddedProcessor {
     fn name(&self) -> &'static str {
         "Mbox Embedded"
     }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "application/octet-stream"
+    }
+}
+
+/// Iterates over the contents of the messages of the mbox at `input_path`.
+///
+/// See `ProcessContext.raw_mbox_messages` for how the contents are read.
+///
+fn messages(
+    ctx: &ProcessContext,
+    input_path: &Path,
+) -> anyhow::Result<Box<dyn Iterator<Item = anyhow::Result<Vec<u8>>> + Send>> {
+    let file = std::fs::File::open(input_path)?;
+    let reader = std::io::BufReader::new(file);
+
+    if ctx.raw_mbox_messages {
+        return Ok(Box::new(RawMessageIterator::new(reader).map(|contents| contents.map_err(anyhow::Error::from))));
+    }
+    Ok(Box::new(MessageIterator::new(reader).map(|message_res| {
+        let message = message_res.map_err(|err| {
+            let msg = format!("failed to parse message from mbox: {:?}", err);
+            warn!("{}", msg);
+            anyhow!(msg)
+        })?;
+        Ok(message.unwrap_contents())
+    })))
+}
+
+/// Scans an mbox for the byte offsets of its messages, which each start at their `From ` line.
+///
+/// The offsets can be used to split the mbox into ranges of messages; see [`crate::process_mbox_range`].
+///
+pub fn mbox_message_offsets(path: impl AsRef<Path>) -> anyhow::Result<Vec<u64>> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    Ok(scan_message_offsets(&mut reader, 0, None)?)
+}
+
+/// Lists the messages of the mbox at the path, from the byte offsets of their `From ` lines.
+///
+/// The size of each message is the number of bytes it takes up in the mbox, including its `From ` line.
+///
+pub(crate) fn list_messages(path: &Path) -> anyhow::Result<Vec<EmbeddedInfo>> {
+    let len = std::fs::metadata(path)?.len();
+    let offsets = mbox_message_offsets(path)?;
+    let ends = offsets.iter().skip(1).copied().chain([len]);
+
+    Ok(offsets.iter().zip(ends)
+        .map(|(start, end)| EmbeddedInfo {
+            name: "mbox-message.eml".to_string(),
+            size: end - start,
+            mimetype: Some("message/rfc822".to_string()),
+        })
+        .collect())
+}
+
+/// Finds the bytes of the messages of an mbox starting within the byte range from `start` up to `end`.
+///
+/// Returns the range of bytes from the `From ` line of the first of those messages up to the `From ` line of the next
+/// message after them, or the end of the mbox. Returns [`None`] if no message starts within the range.
+///
+pub(crate) fn mbox_range_bytes(path: impl AsRef<Path>, start: u64, end: u64) -> anyhow::Result<Option<(u64, u64)>> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = start.min(len);
+
+    // Scanning starts at the beginning of the first line at or after `start`
+    file.seek(SeekFrom::Start(start.saturating_sub(1)))?;
+    let mut reader = BufReader::new(file);
+    let mut position = start;
+    if start > 0 {
+        let mut partial_line = vec![];
+        position = start - 1 + reader.read_until(b'\n', &mut partial_line)? as u64;
+    }
+
+    let offsets = scan_message_offsets(&mut reader, position, Some(end))?;
+    let Some(range_start) = offsets.first().copied().filter(|offset| *offset < end) else {
+        return Ok(None);
+    };
+    let range_end = offsets.last().copied().filter(|offset| *offset >= end).unwrap_or(len);
+    Ok(Some((range_start, range_end)))
+}
+
+/// Scans the lines of an mbox for the byte offsets of `From ` lines, stopping after the first at or after `until`.
+///
+/// The reader must be positioned at the beginning of a line, at the byte offset `position`.
+///
+fn scan_message_offsets(reader: &mut impl BufRead, mut position: u64, until: Option<u64>) -> std::io::Result<Vec<u64>> {
+    let mut offsets = vec![];
+    let mut line = vec![];
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(offsets);
+        }
+        if line.starts_with(b"From ") {
+            offsets.push(position);
+            if until.is_some_and(|until| position >= until) {
+                return Ok(offsets);
+            }
+        }
+        position += read as u64;
+    }
+}
+
+/// Copies the bytes of the mbox from `start` up to `end` into `writer`.
+///
+pub(crate) fn copy_mbox_bytes(path: impl AsRef<Path>, start: u64, end: u64, writer: &mut impl Write) -> anyhow::Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    std::io::copy(&mut file.take(end - start), writer)?;
+    Ok(())
+}
+
+/// The headers of a message used to place it in a thread.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct ThreadHeaders {
+    /// The `Message-ID` of the message, if any.
+    ///
+    message_id: Option<String>,
+
+    /// The ID of the oldest message the message replies to, if any.
+    ///
+    /// This is the first of the `References`, which lists the thread from its root, falling back to the `In-Reply-To`.
+    ///
+    parent_id: Option<String>,
+}
+
+impl ThreadHeaders {
+    fn parse(contents: &[u8]) -> Self {
+        let Some(message) = MessageParser::new().parse_headers(contents) else {
+            return Self::default();
+        };
+
+        let first_id = |ids: Option<Vec<&str>>| ids.and_then(|ids| ids.first().map(|id| id.to_string()));
+        Self {
+            message_id: message.message_id().map(str::to_string),
+            parent_id: first_id(message.references().as_text_list())
+                .or_else(|| first_id(message.in_reply_to().as_text_list())),
+        }
+    }
+}
+
+/// Returns the thread ID of each message, in the same order as the messages.
+///
+/// The thread ID is the ID of the root message of the thread, found by following the replied-to messages until
+/// reaching a message that doesn't reply to anything, or that isn't in the mbox. Messages without any IDs aren't
+/// part of a thread.
+///
+fn thread_ids(headers: &[ThreadHeaders]) -> Vec<Option<String>> {
+    let parents: HashMap<&str, &str> = headers.iter()
+        .filter_map(|headers| Some((headers.message_id.as_deref()?, headers.parent_id.as_deref()?)))
+        .collect();
+
+    headers.iter()
+        .map(|headers| {
+            let mut path = vec![headers.parent_id.as_deref().or(headers.message_id.as_deref())?];
+            while let Some(parent) = path.last().and_then(|id| parents.get(id)) {
+                // Replies forming a cycle have no root, so the smallest ID of the cycle is used for all of them
+                if let Some(cycle_start) = path.iter().position(|id| id == parent) {
+                    return path[cycle_start..].iter().min().map(|id| id.to_string());
+                }
+                path.push(parent);
+            }
+            path.last().map(|id| id.to_string())
+        })
+        .collect()
+}
+
+/// Iterator over the messages of an mbox, yielding the exact bytes of each message as they appear in the mailbox.
+///
+/// Messages are separated by lines starting with `From `, which aren't part of the message. Unlike
+/// `mail_parser`'s iterator, quoted `>From ` lines are left as they are.
+///
+struct RawMessageIterator<R: BufRead> {
+    reader: R,
+    in_message: bool,
+}
+
+impl<R: BufRead> RawMessageIterator<R> {
+    fn new(reader: R) -> Self {
+        Self { reader, in_message: false }
+    }
+}
+
+impl<R: BufRead> Iterator for RawMessageIterator<R> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut message = if self.in_message { Some(vec![]) } else { None };
+        let mut line = vec![];
+
+        loop {
+            line.clear();
+            match self.reader.read_until(b'\n', &mut line) {
+                Ok(0) => {
+                    self.in_message = false;
+                    return message.map(Ok);
+                },
+                Ok(_) => {},
+                Err(err) => return Some(Err(err)),
+            }
+
+            if line.starts_with(b"From ") {
+                self.in_message = true;
+                if message.is_some() {
+                    return message.map(Ok);
+                }
+                message = Some(vec![]);
+            } else if let Some(message) = &mut message {
+                message.extend_from_slice(&line);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::io::Cursor;
     use std::path;
 
     use tokio::sync::mpsc::Receiver;
@@ -158,5 +384,86 @@ mod tests {
         assert_eq!(output_count, 344);
         Ok(())
     }
-}
 
+    #[tokio::test]
+    async fn test_process_raw_messages() -> anyhow::Result<()> {
+        let first = b"Subject: First\r\n\r\n>From the start, keep this quoted.\r\n\r\n".as_slice();
+        let second = b"Subject: Second\n\nNo trailing newline".as_slice();
+        let mbox = [
+            b"From alice@example.com Tue Oct  3 10:00:00 2023\r\n".as_slice(),
+            first,
+            b"From bob@example.com Wed Oct  4 10:00:00 2023\n",
+            second,
+        ].concat();
+        let mut file = NamedTempFile::new()?;
+        file.write_all(&mbox)?;
+        let path = file.path().to_path_buf();
+
+        let (output_sink, mut output_rx) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/mbox", vec![], output_sink)
+            .raw_mbox_messages(true)
+            .build();
+        let proc_fut = tokio::spawn(async move {
+            MboxEmbeddedProcessor.process(ctx, &path, temp_path()?, "checksum").await
+        });
+
+        let mut contents = vec![];
+        while let Some(output) = output_rx.recv().await {
+            match output? {
+                ProcessOutput::Processed(_, _) => panic!("Expected embedded metadata.json"),
+                ProcessOutput::Embedded(_, data, _) => contents.push(std::fs::read(&data.path)?),
+            }
+        }
+        proc_fut.await??;
+
+        assert_eq!(contents, vec![first.to_vec(), second.to_vec()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_threads() -> anyhow::Result<()> {
+        let path = path::PathBuf::from("../resources/mbox/reply-chain.mbox");
+        let (proc_fut, mut output_rx) = process(path)?;
+
+        let mut thread_ids = vec![];
+        while let Some(output) = output_rx.recv().await {
+            match output? {
+                ProcessOutput::Processed(_, _) => panic!("Expected embedded metadata.json"),
+                ProcessOutput::Embedded(_, _, ctx) => thread_ids.push(ctx.thread_id),
+            }
+        }
+        proc_fut.await??;
+
+        let root = Some("root@example.com".to_string());
+        assert_eq!(thread_ids, vec![root.clone(), root.clone(), Some("unrelated@example.com".to_string()), root]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_thread_ids_follow_parents() {
+        let headers = |message_id: Option<&str>, parent_id: Option<&str>| ThreadHeaders {
+            message_id: message_id.map(str::to_string),
+            parent_id: parent_id.map(str::to_string),
+        };
+        let thread_ids = thread_ids(&[
+            headers(Some("c"), Some("b")),
+            headers(Some("b"), Some("a")),
+            headers(Some("d"), Some("missing")),
+            headers(None, None),
+            headers(Some("x"), Some("y")),
+            headers(Some("y"), Some("x")),
+        ]);
+
+        let ids: Vec<Option<&str>> = thread_ids.iter().map(Option::as_deref).collect();
+        assert_eq!(ids, vec![Some("a"), Some("a"), Some("missing"), None, Some("x"), Some("x")]);
+    }
+
+    #[test]
+    fn test_raw_message_iterator_skips_preamble() -> anyhow::Result<()> {
+        let mbox = b"preamble\nFrom a@example.com Tue Oct  3 10:00:00 2023\nSubject: A\n";
+        let messages = RawMessageIterator::new(Cursor::new(mbox)).collect::<std::io::Result<Vec<_>>>()?;
+
+        assert_eq!(messages, vec![b"Subject: A\n".to_vec()]);
+        Ok(())
+    }
+}
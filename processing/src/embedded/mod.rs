@@ -1,7 +1,11 @@
 mod mbox;
+mod pdf;
 mod rfc822;
+mod tnef;
 mod zip;
 
 pub use mbox::*;
+pub use pdf::*;
 pub use rfc822::*;
+pub use tnef::*;
 pub use zip::*;
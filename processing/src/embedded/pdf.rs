@@ -0,0 +1,285 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::{info, warn};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+use tempfile::{NamedTempFile, TempPath};
+
+use identify::mimetype::identify_mimetype;
+use services::pdf_password;
+
+use crate::processing::{Process, ProcessContext, ProcessOutput};
+
+/// The maximum depth of the `EmbeddedFiles` name tree walked, guarding against crafted PDFs nesting it endlessly.
+///
+const MAX_NAME_TREE_DEPTH: usize = 32;
+
+/// An attachment read from a PDF's embedded files.
+///
+struct PdfAttachment {
+    name: String,
+    path: TempPath,
+    mimetype: Option<String>,
+}
+
+/// PdfEmbeddedProcessor is responsible for extracting files embedded in PDFs.
+///
+/// Internally it uses the `lopdf` crate to walk the `EmbeddedFiles` name tree of the document catalog,
+/// emitting each file specification's embedded file stream.
+///
+#[derive(Debug, Default, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+pub struct PdfEmbeddedProcessor;
+
+#[async_trait]
+impl Process for PdfEmbeddedProcessor {
+    async fn process(
+        &self,
+        ctx: ProcessContext,
+        input_path: &Path,
+        _: TempPath,
+        _: &str,
+    ) -> anyhow::Result<()> {
+        info!("Reading PDF embedded files");
+        let mut document = Document::load(input_path)?;
+        crate::pdf::encryption::decrypt(&mut document, pdf_password().as_deref())?;
+        let attachments = read_attachments(&document)?;
+
+        for PdfAttachment { name, path, mimetype } in attachments {
+            info!("Discovered embedded file {}", name);
+            let mimetype = match mimetype {
+                Some(mimetype) => mimetype,
+                None => identify_mimetype(&path).await?.unwrap_or("embedded/octet-stream".to_string()),
+            };
+            let checksum = ctx.checksum_from_path(&path, &mimetype).await?;
+
+            let output = ProcessOutput::embedded(&ctx, name, path, mimetype, checksum);
+            ctx.add_output(Ok(output)).await?;
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "PDF Embedded"
+    }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "application/octet-stream"
+    }
+}
+
+/// Reads all attachments found in the `EmbeddedFiles` name tree of the document.
+///
+fn read_attachments(document: &Document) -> anyhow::Result<Vec<PdfAttachment>> {
+    let mut attachments = vec![];
+
+    let names = match document.catalog()?.get(b"Names") {
+        Ok(names) => document.dereference(names)?.1.as_dict()?,
+        Err(_) => return Ok(attachments),
+    };
+    if let Ok(embedded_files) = names.get(b"EmbeddedFiles") {
+        let (root_id, root) = document.dereference(embedded_files)?;
+        let mut visited = HashSet::from_iter(root_id);
+        collect_name_tree(document, root.as_dict()?, 0, &mut visited, &mut attachments)?;
+    }
+
+    Ok(attachments)
+}
+
+/// Recursively walks a name tree node, reading the file specification of each leaf.
+///
+/// Nodes already visited are skipped and nodes nested deeper than [`MAX_NAME_TREE_DEPTH`] aren't walked, so a name
+/// tree referencing itself can't recurse endlessly.
+///
+fn collect_name_tree(
+    document: &Document,
+    node: &Dictionary,
+    depth: usize,
+    visited: &mut HashSet<ObjectId>,
+    attachments: &mut Vec<PdfAttachment>,
+) -> anyhow::Result<()> {
+    if let Ok(kids) = node.get(b"Kids") {
+        for kid in document.dereference(kids)?.1.as_array()? {
+            let (kid_id, kid) = document.dereference(kid)?;
+            if kid_id.is_some_and(|kid_id| !visited.insert(kid_id)) {
+                warn!("Skipping name tree node {:?} visited before", kid_id);
+                continue;
+            }
+            if depth + 1 > MAX_NAME_TREE_DEPTH {
+                warn!("Skipping name tree nodes nested deeper than {}", MAX_NAME_TREE_DEPTH);
+                break;
+            }
+            collect_name_tree(document, kid.as_dict()?, depth + 1, visited, attachments)?;
+        }
+    }
+
+    if let Ok(names) = node.get(b"Names") {
+        // Pairs of [key, file specification]
+        for pair in document.dereference(names)?.1.as_array()?.chunks(2) {
+            if let [key, file_spec] = pair {
+                match read_attachment(document, key, file_spec) {
+                    Ok(attachment) => attachments.push(attachment),
+                    Err(err) => warn!("Failed to read embedded file: {}", err),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the embedded file stream referenced by a file specification into a temporary file.
+///
+fn read_attachment(document: &Document, key: &Object, file_spec: &Object) -> anyhow::Result<PdfAttachment> {
+    let file_spec = document.dereference(file_spec)?.1.as_dict()?;
+    let name = [b"UF".as_slice(), b"F"].iter()
+        .find_map(|k| file_spec.get(k).ok())
+        .or(Some(key))
+        .and_then(|name| document.dereference(name).ok())
+        .and_then(|(_, name)| name.as_str().ok())
+        .map(decode_text_string)
+        .ok_or(anyhow!("failed to get name for embedded file"))?;
+
+    let embedded_file = file_spec.get(b"EF")
+        .and_then(|ef| document.dereference(ef))?.1
+        .as_dict()?;
+    let stream = [b"F".as_slice(), b"UF"].iter()
+        .find_map(|k| embedded_file.get(k).ok())
+        .ok_or(anyhow!("embedded file '{}' has no file stream", name))
+        .and_then(|stream| Ok(document.dereference(stream)?.1.as_stream()?))?;
+
+    let mimetype = stream.dict.get(b"Subtype")
+        .and_then(|subtype| subtype.as_name_str())
+        .ok()
+        .map(|subtype| subtype.to_string());
+    // Streams without filters are stored as they are, while those failing to decode are skipped rather than
+    // passed on still encoded
+    let content = if stream.dict.has(b"Filter") {
+        stream.decompressed_content()
+            .map_err(|err| anyhow!("failed to decode embedded file '{}': {}", name, err))?
+    } else {
+        stream.content.clone()
+    };
+
+    let mut file = NamedTempFile::new()?;
+    file.write_all(&content)?;
+
+    Ok(PdfAttachment { name, path: file.into_temp_path(), mimetype })
+}
+
+/// Decodes a PDF text string, which is either UTF-16BE with a byte order mark or PDFDocEncoding.
+///
+fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes {
+        [0xFE, 0xFF, rest @ ..] => {
+            let utf16: Vec<u16> = rest.chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&utf16)
+        },
+        _ => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    use lopdf::{dictionary, Stream};
+    use test_utils::temp_path;
+
+    use crate::processing::ProcessContextBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_process() -> anyhow::Result<()> {
+        let (output_sink, mut output_rx) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/pdf", vec![], output_sink).build();
+        let path = path::PathBuf::from("../resources/pdf/zugferd-invoice.pdf");
+
+        let proc_fut = tokio::spawn(async move {
+            PdfEmbeddedProcessor.process(ctx, &path, temp_path()?, "checksum").await
+        });
+
+        let mut outputs = vec![];
+        while let Some(output) = output_rx.recv().await {
+            match output? {
+                ProcessOutput::Processed(_, _) => panic!("Expected embedded output"),
+                ProcessOutput::Embedded(_, data, _) => outputs.push(data),
+            }
+        }
+        proc_fut.await??;
+
+        assert_eq!(outputs.len(), 1);
+        let data = &outputs[0];
+        assert_eq!(data.name, "factur-x.xml");
+        assert_eq!(data.mimetype, "text/xml");
+
+        let xml = std::fs::read_to_string(&data.path)?;
+        assert!(xml.starts_with("<?xml"));
+        assert!(xml.contains("<rsm:CrossIndustryInvoice"));
+        assert!(xml.contains("INV-0001"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_attachments_name_tree_cycle() -> anyhow::Result<()> {
+        let mut document = Document::with_version("1.7");
+        let node_id = document.new_object_id();
+        document.objects.insert(node_id, Object::Dictionary(dictionary! {
+            "Kids" => vec![Object::Reference(node_id)],
+        }));
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Names" => dictionary! { "EmbeddedFiles" => node_id },
+        });
+        document.trailer.set("Root", catalog_id);
+
+        assert!(read_attachments(&document)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_attachments_skips_undecodable() -> anyhow::Result<()> {
+        let mut document = Document::with_version("1.7");
+        let plain_id = document.add_object(Stream::new(dictionary! {}, b"plain notes".to_vec()));
+        let encoded_id = document.add_object(Stream::new(
+            dictionary! { "Filter" => "ASCIIHexDecode" },
+            b"6E6F746573>".to_vec(),
+        ));
+        let names = vec![
+            Object::string_literal("notes.txt"),
+            Object::Dictionary(dictionary! {
+                "F" => Object::string_literal("notes.txt"),
+                "EF" => dictionary! { "F" => plain_id },
+            }),
+            Object::string_literal("encoded.txt"),
+            Object::Dictionary(dictionary! {
+                "F" => Object::string_literal("encoded.txt"),
+                "EF" => dictionary! { "F" => encoded_id },
+            }),
+        ];
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Names" => dictionary! { "EmbeddedFiles" => dictionary! { "Names" => names } },
+        });
+        document.trailer.set("Root", catalog_id);
+
+        let attachments = read_attachments(&document)?;
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].name, "notes.txt");
+        assert_eq!(std::fs::read(&attachments[0].path)?, b"plain notes");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_text_string() {
+        assert_eq!(decode_text_string(b"invoice.xml"), "invoice.xml");
+        assert_eq!(decode_text_string(&[0xFE, 0xFF, 0x00, 0x78, 0x00, 0x6D, 0x00, 0x6C]), "xml");
+    }
+}
@@ -4,12 +4,10 @@ use std::path::Path;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
-use mail_parser::{MessageParser, MimeHeaders};
+use mail_parser::{MessageParser, MessagePart, MimeHeaders, PartType};
 use tempfile::{NamedTempFile, TempPath};
 
-use identify::deduplication::dedupe_checksum;
-
-use crate::mimetype;
+use crate::{EmbeddedInfo, mimetype};
 use crate::processing::{Process, ProcessContext, ProcessOutput};
 
 #[derive(Debug, Default)]
@@ -34,14 +32,11 @@ impl Process for Rfc822EmbeddedProcessor {
             let part = message
                 .part(*part_id)
                 .ok_or(anyhow!("failed to get attachment part"))?;
-            let content_type = part
-                .content_type()
-                .ok_or(anyhow!("failed to get attachment content type"))?;
-            let mimetype = mimetype(content_type);
+            let (mimetype, default_name) = attachment_type(part)?;
 
             let mut reader = Cursor::new(part.contents());
-            let checksum = dedupe_checksum(&mut reader, &mimetype).await?;
-            let name = part.attachment_name().unwrap_or("message-attachment.dat");
+            let checksum = ctx.checksum(&mut reader, &mimetype).await?;
+            let name = part.attachment_name().unwrap_or(default_name);
 
             let mut file = NamedTempFile::new()?;
             std::io::copy(&mut part.contents(), &mut file)?;
@@ -56,4 +51,43 @@ impl Process for Rfc822EmbeddedProcessor {
     fn name(&self) -> &'static str {
         "RFC 822 Embedded"
     }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "application/octet-stream"
+    }
+}
+
+/// Lists the attachments of the message at the path, as they're named when extracted.
+///
+pub(crate) fn list_attachments(path: &Path) -> anyhow::Result<Vec<EmbeddedInfo>> {
+    let content = std::fs::read(path)?;
+    let message = MessageParser::default().parse(&content)
+        .ok_or(anyhow!("Failed to parse message"))?;
+
+    message.attachments()
+        .map(|part| {
+            let (mimetype, default_name) = attachment_type(part)?;
+            Ok(EmbeddedInfo {
+                name: part.attachment_name().unwrap_or(default_name).to_string(),
+                size: part.contents().len() as u64,
+                mimetype: Some(mimetype),
+            })
+        })
+        .collect()
+}
+
+/// Returns the MIME type of an attachment, and the name to give it if it doesn't have one.
+///
+/// Attached messages, including internationalized `message/global` ones, are recursed into as messages.
+///
+fn attachment_type(part: &MessagePart) -> anyhow::Result<(String, &'static str)> {
+    match &part.body {
+        PartType::Message(_) => Ok(("message/rfc822".to_string(), "message-attachment.eml")),
+        _ => {
+            let content_type = part
+                .content_type()
+                .ok_or(anyhow!("failed to get attachment content type"))?;
+            Ok((mimetype(content_type), "message-attachment.dat"))
+        },
+    }
 }
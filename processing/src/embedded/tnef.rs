@@ -0,0 +1,330 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tempfile::{NamedTempFile, TempPath};
+
+use identify::mimetype::identify_mimetype;
+
+use crate::extension_to_mimetype;
+use crate::processing::{Process, ProcessContext, ProcessOutput};
+
+/// The signature every TNEF stream starts with.
+///
+const TNEF_SIGNATURE: u32 = 0x223e9f78;
+
+/// The level of attributes of an attachment, rather than of the message.
+///
+const LVL_ATTACHMENT: u8 = 0x02;
+
+/// The attribute starting each attachment, holding how it's rendered.
+///
+const ATT_ATTACH_REND_DATA: u32 = 0x00069002;
+
+/// The attribute holding the short name of an attachment.
+///
+const ATT_ATTACH_TITLE: u32 = 0x00018010;
+
+/// The attribute holding the contents of an attachment.
+///
+const ATT_ATTACH_DATA: u32 = 0x0006800f;
+
+/// The attribute holding the MAPI properties of an attachment.
+///
+const ATT_ATTACHMENT: u32 = 0x00069005;
+
+/// The MAPI property of the long name of an attachment.
+///
+const PR_ATTACH_LONG_FILENAME: u16 = 0x3707;
+
+/// The MAPI property types with variable sized values, and the flag of multi-valued types.
+///
+const PT_STRING8: u16 = 0x001e;
+const PT_UNICODE: u16 = 0x001f;
+const PT_OBJECT: u16 = 0x000d;
+const PT_BINARY: u16 = 0x0102;
+const PT_MV_FLAG: u16 = 0x1000;
+
+/// An attachment read from a TNEF stream.
+///
+#[derive(Debug, Default, PartialEq)]
+struct TnefAttachment {
+    name: Option<String>,
+    data: Vec<u8>,
+}
+
+/// TnefEmbeddedProcessor is responsible for extracting the attachments of TNEF streams, the `winmail.dat` files
+/// Outlook attaches to messages in place of their actual attachments.
+///
+/// Attachments are typed by the extensions of their names, or by their contents if the extension isn't known.
+///
+#[derive(Debug, Default, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+pub struct TnefEmbeddedProcessor;
+
+#[async_trait]
+impl Process for TnefEmbeddedProcessor {
+    async fn process(
+        &self,
+        ctx: ProcessContext,
+        input_path: &Path,
+        _: TempPath,
+        _: &str,
+    ) -> anyhow::Result<()> {
+        info!("Reading TNEF attachments");
+        let attachments = read_attachments(&std::fs::read(input_path)?)?;
+
+        for (index, TnefAttachment { name, data }) in attachments.into_iter().enumerate() {
+            let name = name.unwrap_or_else(|| format!("tnef-attachment-{}.dat", index + 1));
+            info!("Discovered attachment {}", name);
+
+            let mut file = NamedTempFile::new()?;
+            file.write_all(&data)?;
+            let path = file.into_temp_path();
+            let mimetype = match Path::new(&name).extension().and_then(|extension| extension_to_mimetype(&extension.to_string_lossy())) {
+                Some(mimetype) => mimetype.to_string(),
+                None => identify_mimetype(&path).await?.unwrap_or("embedded/octet-stream".to_string()),
+            };
+            let checksum = ctx.checksum_from_path(&path, &mimetype).await?;
+
+            let output = ProcessOutput::embedded(&ctx, name, path, mimetype, checksum);
+            ctx.add_output(Ok(output)).await?;
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "TNEF Embedded"
+    }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "application/octet-stream"
+    }
+}
+
+/// Reads the attachments of a TNEF stream, in the order they're stored in.
+///
+/// Attachments are named by their long names from their MAPI properties if they have one, or by their short names
+/// otherwise. Attachments without contents, like attached messages, are left out.
+///
+fn read_attachments(tnef: &[u8]) -> anyhow::Result<Vec<TnefAttachment>> {
+    let mut reader = TnefReader::new(tnef);
+    if reader.u32()? != TNEF_SIGNATURE {
+        return Err(anyhow!("Not a TNEF stream"));
+    }
+    // The legacy key, which is of no use to decode the stream
+    reader.u16()?;
+
+    let mut attachments: Vec<TnefAttachment> = vec![];
+    while !reader.is_empty() {
+        let level = reader.u8()?;
+        let id = reader.u32()?;
+        let length = reader.u32()? as usize;
+        let data = reader.bytes(length)?;
+        // The checksum of the data, which isn't verified
+        reader.u16()?;
+
+        if level != LVL_ATTACHMENT {
+            continue;
+        }
+        if id == ATT_ATTACH_REND_DATA {
+            attachments.push(TnefAttachment::default());
+            continue;
+        }
+        let Some(attachment) = attachments.last_mut() else {
+            warn!("Skipping TNEF attachment attribute {:#010x} before any attachment", id);
+            continue;
+        };
+        match id {
+            ATT_ATTACH_TITLE => {
+                attachment.name.get_or_insert_with(|| c_string(data));
+            },
+            ATT_ATTACH_DATA => attachment.data = data.to_vec(),
+            ATT_ATTACHMENT => match long_filename(data) {
+                Ok(Some(name)) => attachment.name = Some(name),
+                Ok(None) => {},
+                Err(err) => warn!("Failed to read MAPI properties of TNEF attachment: {}", err),
+            },
+            _ => {},
+        }
+    }
+
+    attachments.retain(|attachment| !attachment.data.is_empty());
+    Ok(attachments)
+}
+
+/// Reads the long name of an attachment from its MAPI properties, if it has one.
+///
+fn long_filename(properties: &[u8]) -> anyhow::Result<Option<String>> {
+    let mut reader = TnefReader::new(properties);
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let kind = reader.u16()?;
+        let id = reader.u16()?;
+        if id >= 0x8000 {
+            // Named properties are followed by their GUID, and either a numeric ID or a name
+            reader.bytes(16)?;
+            if reader.u32()? == 0 {
+                reader.u32()?;
+            } else {
+                let length = reader.u32()? as usize;
+                reader.padded_bytes(length)?;
+            }
+        }
+
+        let values = read_property_values(&mut reader, kind)?;
+        if id == PR_ATTACH_LONG_FILENAME {
+            let name = match kind {
+                PT_UNICODE => values.first().map(|value| utf16_string(value)),
+                _ => values.first().map(|value| c_string(value)),
+            };
+            return Ok(name.filter(|name| !name.is_empty()));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the values of a MAPI property of the type, skipping past them.
+///
+fn read_property_values<'a>(reader: &mut TnefReader<'a>, kind: u16) -> anyhow::Result<Vec<&'a [u8]>> {
+    let multi_valued = kind & PT_MV_FLAG != 0;
+    let size = match kind & !PT_MV_FLAG {
+        PT_STRING8 | PT_UNICODE | PT_OBJECT | PT_BINARY => None,
+        0x0001 | 0x0002 | 0x0003 | 0x0004 | 0x000a | 0x000b => Some(4),
+        0x0005 | 0x0006 | 0x0007 | 0x0014 | 0x0040 => Some(8),
+        0x0048 => Some(16),
+        other => return Err(anyhow!("Unsupported MAPI property type {:#06x}", other)),
+    };
+
+    let count = match (multi_valued, size) {
+        (false, Some(_)) => 1,
+        _ => reader.u32()?,
+    };
+    (0..count)
+        .map(|_| match size {
+            Some(size) => reader.bytes(size),
+            None => {
+                let length = reader.u32()? as usize;
+                reader.padded_bytes(length)
+            },
+        })
+        .collect()
+}
+
+/// Decodes a null-terminated string of single byte characters as Latin-1.
+///
+fn c_string(data: &[u8]) -> String {
+    let end = data.iter().position(|byte| *byte == 0).unwrap_or(data.len());
+    data[..end].iter().map(|byte| *byte as char).collect()
+}
+
+/// Decodes a null-terminated UTF-16LE string.
+///
+fn utf16_string(data: &[u8]) -> String {
+    let units: Vec<u16> = data.chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Reads the little-endian values of a TNEF stream.
+///
+struct TnefReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TnefReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        if length > self.data.len() {
+            return Err(anyhow!("TNEF stream ended unexpectedly"));
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Reads the bytes, and the padding after them up to a multiple of 4 bytes.
+    ///
+    fn padded_bytes(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self.bytes(length)?;
+        self.bytes((4 - length % 4) % 4)?;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    use test_utils::temp_path;
+
+    use crate::processing::ProcessContextBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_read_attachments() -> anyhow::Result<()> {
+        let attachments = read_attachments(&std::fs::read("../resources/tnef/winmail.dat")?)?;
+
+        assert_eq!(attachments, vec![
+            TnefAttachment { name: Some("Quarterly report notes.txt".to_string()), data: b"Revenue is up.\n".to_vec() },
+            TnefAttachment { name: Some("HELLO.TXT".to_string()), data: b"Hello from Outlook!\n".to_vec() },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_attachments_not_tnef() {
+        assert!(read_attachments(b"Not a TNEF stream").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_process() -> anyhow::Result<()> {
+        let (output_sink, mut output_rx) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/vnd.ms-tnef", vec![], output_sink).build();
+        let path = path::PathBuf::from("../resources/tnef/winmail.dat");
+
+        let proc_fut = tokio::spawn(async move {
+            TnefEmbeddedProcessor.process(ctx, &path, temp_path()?, "checksum").await
+        });
+
+        let mut outputs = vec![];
+        while let Some(output) = output_rx.recv().await {
+            match output? {
+                ProcessOutput::Processed(_, _) => panic!("Expected embedded output"),
+                ProcessOutput::Embedded(_, data, _) => outputs.push(data),
+            }
+        }
+        proc_fut.await??;
+
+        let names: Vec<&str> = outputs.iter().map(|data| data.name.as_str()).collect();
+        assert_eq!(names, vec!["Quarterly report notes.txt", "HELLO.TXT"]);
+        assert!(outputs.iter().all(|data| data.mimetype == "text/plain"));
+        assert_eq!(std::fs::read(&outputs[1].path)?, b"Hello from Outlook!\n");
+        Ok(())
+    }
+}
@@ -1,30 +1,33 @@
-use std::io::{Read, Seek};
-use std::path::Path;
+use std::future::Future;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
 
 use anyhow::anyhow;
-use async_stream::stream;
 use async_trait::async_trait;
-use futures::{pin_mut, StreamExt};
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use tempfile::{NamedTempFile, TempPath};
+use tokio::task::JoinSet;
 use zip::ZipArchive;
 
-use identify::deduplication::dedupe_checksum_from_path;
 use identify::mimetype::identify_mimetype;
 
+use crate::{EmbeddedInfo, extension_to_mimetype, GENERIC_MIMETYPES};
 use crate::processing::{Process, ProcessContext, ProcessOutput};
 
+/// The maximum number of entries of a zip file extracted concurrently.
+///
+const MAX_CONCURRENT_ENTRIES: usize = 8;
+
+/// A zip file opened by a worker, shared with the blocking threads its entries are read on.
+///
+type SharedArchive = Arc<Mutex<ZipArchive<BufReader<std::fs::File>>>>;
+
 enum NextArchiveEntry {
     Dir(String),
-    File(ArchiveEntry),
-}
-
-struct ArchiveEntry {
-    name: String,
-    path: TempPath,
-    checksum: String,
-    mimetype: String,
+    Junk(String),
+    File(String, TempPath),
 }
 
 #[derive(Debug, Default, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
@@ -40,63 +43,184 @@ impl Process for ZipEmbeddedProcessor {
         _: &str,
     ) -> anyhow::Result<()> {
         info!("Opening zip file");
-        let file = std::fs::File::open(path)?;
-        let reader = std::io::BufReader::new(file);
-        let mut archive = ZipArchive::new(reader)?;
-
-        info!("Streaming zip file entries");
-        let output_stream = stream! {
-            for i in 0..archive.len() {
-                yield next_archive_entry(&mut archive, i).await;
-            }
-        };
+        let path = path.to_path_buf();
+        let entry_count = open_archive_blocking(path.clone()).await?
+            .lock().unwrap_or_else(PoisonError::into_inner)
+            .len();
 
-        pin_mut!(output_stream);
-        while let Some(result) = output_stream.next().await {
-            match result {
-                Ok(NextArchiveEntry::File(entry)) => {
-                    info!("Discovered entry {}", entry.name);
-                    let ArchiveEntry { name, path, checksum: dedupe_checksum, mimetype } = entry;
-                    let output = ProcessOutput::embedded(&ctx, name, path, mimetype, dedupe_checksum);
-                    ctx.add_output(Ok(output)).await?;
-                },
-                Ok(NextArchiveEntry::Dir(name)) => debug!("Discovered directory {}", name),
-                Err(e) => warn!("Failed to read entry: {}", e),
+        info!("Extracting {} zip file entries", entry_count);
+        extract_in_workers(entry_count, MAX_CONCURRENT_ENTRIES, |indices| {
+            let ctx = ctx.clone();
+            let path = path.clone();
+            async move {
+                // Each worker reads the zip file with its own handle, so entries are decompressed in parallel
+                let archive = open_archive_blocking(path).await?;
+                for index in indices {
+                    match next_archive_entry(&archive, index, ctx.keep_junk_files).await {
+                        Ok(NextArchiveEntry::File(name, path)) => {
+                            info!("Discovered entry {}", name);
+                            match embedded_output(&ctx, name, path).await {
+                                Ok(output) => ctx.add_output(Ok(output)).await?,
+                                Err(e) => warn!("Failed to read entry: {}", e),
+                            }
+                        },
+                        Ok(NextArchiveEntry::Dir(name)) => debug!("Discovered directory {}", name),
+                        Ok(NextArchiveEntry::Junk(name)) => debug!("Skipped junk entry {}", name),
+                        Err(e) => warn!("Failed to read entry: {}", e),
+                    }
+                }
+                Ok(())
             }
-        }
-
-        Ok(())
+        }).await
     }
 
     fn name(&self) -> &'static str {
         "zip"
     }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "application/octet-stream"
+    }
+}
+
+/// Lists the file entries of the zip file at the path, with their MIME types guessed from their extensions.
+///
+/// Only the central directory of the zip file is read, so no entry is decompressed.
+///
+pub(crate) fn list_entries(path: &Path) -> anyhow::Result<Vec<EmbeddedInfo>> {
+    let mut archive = open_archive(path)?;
+    let mut entries = vec![];
+    for index in 0..archive.len() {
+        let zipfile = archive.by_index_raw(index)?;
+        let Some(name) = zipfile.enclosed_name().and_then(|name| name.file_name()) else {
+            continue;
+        };
+        if zipfile.is_dir() {
+            continue;
+        }
+
+        let name = name.to_string_lossy().to_string();
+        let mimetype = Path::new(&name).extension()
+            .and_then(|extension| extension_to_mimetype(&extension.to_string_lossy()))
+            .map(str::to_string);
+        entries.push(EmbeddedInfo { name, size: zipfile.size(), mimetype });
+    }
+    Ok(entries)
+}
+
+fn open_archive(path: &Path) -> anyhow::Result<ZipArchive<BufReader<std::fs::File>>> {
+    Ok(ZipArchive::new(BufReader::new(std::fs::File::open(path)?))?)
 }
 
-async fn next_archive_entry<R>(archive: &mut ZipArchive<R>, index: usize) -> anyhow::Result<NextArchiveEntry>
-    where R: Read + Seek
+/// Opens the zip file on a blocking thread, as its central directory is read.
+///
+/// The archive is shared with the blocking threads its entries are read on; see [`next_archive_entry`].
+///
+async fn open_archive_blocking(path: PathBuf) -> anyhow::Result<SharedArchive> {
+    let archive = tokio::task::spawn_blocking(move || open_archive(&path)).await??;
+    Ok(Arc::new(Mutex::new(archive)))
+}
+
+/// Splits the entries `0..entry_count` of an archive between at most `max_workers` tasks, running concurrently.
+///
+/// Each worker is given the indices of its share of the entries, in order, and sends its outputs as they're
+/// extracted, so outputs of different workers are interleaved in no particular order.
+///
+async fn extract_in_workers<F, Fut>(entry_count: usize, max_workers: usize, extract: F) -> anyhow::Result<()>
+    where
+        F: Fn(Vec<usize>) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
 {
-    // Create an inner scope because `ZipFile` is not `Send` and must be dropped before `await`ing
-    let (name, path) = {
+    let workers = max_workers.clamp(1, entry_count.max(1));
+    let mut extracting = JoinSet::new();
+    for worker in 0..workers {
+        extracting.spawn(extract((worker..entry_count).step_by(workers).collect()));
+    }
+
+    while let Some(result) = extracting.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+/// Reads the entry at the index of the archive, spooling it to a temporary file if it's a file.
+///
+/// Entries are decompressed as they're read, so they're read on a blocking thread.
+///
+async fn next_archive_entry(
+    archive: &SharedArchive,
+    index: usize,
+    keep_junk_files: bool,
+) -> anyhow::Result<NextArchiveEntry> {
+    let archive = archive.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut archive = archive.lock().unwrap_or_else(PoisonError::into_inner);
         let mut zipfile = archive.by_index(index)?;
 
-        let name = zipfile.enclosed_name()
-            .and_then(|name| name.file_name())
+        let enclosed_name = zipfile.enclosed_name()
+            .ok_or(anyhow!("failed to get name for zip entry"))?
+            .to_path_buf();
+        let name = enclosed_name.file_name()
             .map(|name| name.to_string_lossy().to_string())
             .ok_or(anyhow!("failed to get name for zip entry"))?;
 
+        if !keep_junk_files && is_junk_entry(&enclosed_name) {
+            return Ok(NextArchiveEntry::Junk(enclosed_name.to_string_lossy().to_string()));
+        }
         if zipfile.is_dir() {
             return Ok(NextArchiveEntry::Dir(name));
         }
+        Ok(NextArchiveEntry::File(name, spool_read(&mut zipfile)?))
+    }).await?
+}
 
-        let emb_path = spool_read(&mut zipfile)?;
-        (name, emb_path)
-    };
+/// Creates the output of an entry spooled to the path, determining its MIME type and checksum.
+///
+async fn embedded_output(ctx: &ProcessContext, name: String, path: TempPath) -> anyhow::Result<ProcessOutput> {
+    let mimetype = entry_mimetype(&name, &path).await;
+    let checksum = ctx.checksum_from_path(&path, &mimetype).await?;
+    Ok(ProcessOutput::embedded(ctx, name, path, mimetype, checksum))
+}
 
-    let mimetype = identify_mimetype(&path).await?.unwrap_or("embedded/octet-stream".to_string());
-    let checksum = dedupe_checksum_from_path(&path, &mimetype).await?;
+/// Whether the entry at the path within an archive is a system or junk file, rather than content of the archive.
+///
+/// These are the resource forks macOS stores under `__MACOSX/` (and as `._` files), the `.DS_Store` and `Thumbs.db`
+/// files of file browsers, and the `~$` lock files of open Office documents.
+///
+pub(crate) fn is_junk_entry(path: &Path) -> bool {
+    let in_macosx = path.components().any(|component| component.as_os_str() == "__MACOSX");
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    in_macosx
+        || name == ".DS_Store"
+        || name == "Thumbs.db"
+        || name.starts_with("._")
+        || name.starts_with("~$")
+}
 
-    Ok(NextArchiveEntry::File(ArchiveEntry { name, path, checksum, mimetype }))
+/// Determines the MIME type of an extracted entry.
+///
+/// Entries are identified from their contents, so ones without a meaningful name, or with a misleading one, are still
+/// recursed into as their actual type. The extension is only used if the contents are identified as no more than a
+/// generic type, or fail to be identified at all.
+///
+async fn entry_mimetype(name: &str, path: &Path) -> String {
+    let identified = match identify_mimetype(path).await {
+        Ok(mimetype) => mimetype.filter(|mimetype| !is_generic_mimetype(mimetype)),
+        Err(err) => {
+            warn!("Failed to identify MIME type of zip entry {}, falling back to its extension: {}", name, err);
+            None
+        },
+    };
+    let by_extension = || Path::new(name).extension()
+        .and_then(|extension| extension_to_mimetype(&extension.to_string_lossy()))
+        .map(str::to_string);
+    identified.or_else(by_extension).unwrap_or("embedded/octet-stream".to_string())
+}
+
+/// Whether the MIME type says no more about a file than that it's binary data or text.
+///
+fn is_generic_mimetype(mimetype: &str) -> bool {
+    GENERIC_MIMETYPES.contains(&mimetype) || mimetype == "text/plain"
 }
 
 /// Write contents to a temporary file and return the temporary path.
@@ -105,4 +229,120 @@ fn spool_read(mut reader: impl Read) -> anyhow::Result<TempPath> {
     let mut file = NamedTempFile::new()?;
     std::io::copy(&mut reader, &mut file)?;
     Ok(file.into_temp_path())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use test_utils::temp_path;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use crate::processing::ProcessContextBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_extract_in_workers() -> anyhow::Result<()> {
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let extracted = Arc::new(std::sync::Mutex::new(vec![]));
+
+        extract_in_workers(20, 4, |indices| {
+            let (active, max_active, extracted) = (active.clone(), max_active.clone(), extracted.clone());
+            async move {
+                for index in indices {
+                    let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now_active, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    extracted.lock().unwrap().push(index);
+                    active.fetch_sub(1, Ordering::SeqCst);
+                }
+                Ok(())
+            }
+        }).await?;
+
+        let mut extracted = extracted.lock().unwrap().clone();
+        extracted.sort();
+        assert_eq!(extracted, (0..20).collect::<Vec<_>>());
+        assert_eq!(max_active.load(Ordering::SeqCst), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_many_entries() -> anyhow::Result<()> {
+        let zip_path = temp_path()?;
+        let mut writer = ZipWriter::new(std::fs::File::create(&zip_path)?);
+        writer.add_directory("notes/", FileOptions::default())?;
+        for i in 0..20 {
+            writer.start_file(format!("notes/note-{}.txt", i), FileOptions::default())?;
+            writer.write_all(format!("This is note number {}.", i).as_bytes())?;
+        }
+        writer.finish()?;
+
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(100);
+        let ctx = ProcessContextBuilder::new("application/zip", vec![], output_sink).build();
+        ZipEmbeddedProcessor.process(ctx, &zip_path, temp_path()?, "checksum").await?;
+
+        let mut names = vec![];
+        while let Ok(output) = outputs.try_recv() {
+            match output? {
+                ProcessOutput::Embedded(_, data, _) => names.push(data.name),
+                ProcessOutput::Processed(_, _) => panic!("Expected embedded output"),
+            }
+        }
+        names.sort();
+
+        let mut expected = (0..20).map(|i| format!("note-{}.txt", i)).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(names, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_junk_entry() {
+        assert!(is_junk_entry(Path::new("__MACOSX/reports/._q3.pdf")));
+        assert!(is_junk_entry(Path::new("reports/.DS_Store")));
+        assert!(is_junk_entry(Path::new("reports/~$q3.docx")));
+        assert!(!is_junk_entry(Path::new("reports/q3.docx")));
+    }
+
+    fn process_zip_with_junk(keep_junk_files: bool) -> anyhow::Result<Vec<String>> {
+        let zip_path = temp_path()?;
+        let mut writer = ZipWriter::new(std::fs::File::create(&zip_path)?);
+        for name in ["notes.txt", ".DS_Store", "__MACOSX/._notes.txt", "~$notes.txt"] {
+            writer.start_file(name, FileOptions::default())?;
+            writer.write_all(b"Meeting notes")?;
+        }
+        writer.finish()?;
+
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/zip", vec![], output_sink)
+            .keep_junk_files(keep_junk_files)
+            .build();
+        tokio::runtime::Runtime::new()?.block_on(ZipEmbeddedProcessor.process(ctx, &zip_path, temp_path()?, "checksum"))?;
+
+        let mut names = vec![];
+        while let Ok(output) = outputs.try_recv() {
+            match output? {
+                ProcessOutput::Embedded(_, data, _) => names.push(data.name),
+                ProcessOutput::Processed(_, _) => panic!("Expected embedded output"),
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn test_process_skips_junk_entries() -> anyhow::Result<()> {
+        assert_eq!(process_zip_with_junk(false)?, vec!["notes.txt"]);
+        let kept = process_zip_with_junk(true)?;
+        assert!(kept.contains(&"._notes.txt".to_string()));
+        assert!(kept.contains(&"~$notes.txt".to_string()));
+        Ok(())
+    }
+}
@@ -15,10 +15,27 @@ use mail_parser::ContentType;
 ///
 pub mod processing;
 
+/// Helpers for streams of bytes.
+///
+pub mod streaming;
+
+mod list;
+mod naming;
+mod options;
+mod process;
+pub use list::*;
+pub use naming::*;
+pub use options::*;
+pub use process::*;
+pub use processing::{process_outputs, StreamedOutput};
+
 pub(crate) mod text;
 pub(crate) mod metadata;
 pub(crate) mod pdf;
 pub(crate) mod embedded;
+pub(crate) mod ocr;
+#[cfg(feature = "transcription")]
+pub(crate) mod transcription;
 
 /// Get the MIME type from a `mail_parser::ContentType`.
 ///
@@ -37,6 +54,86 @@ pub fn mimetype(content_type: &ContentType) -> String {
     }
 }
 
+/// Get the file extension conventionally used for a MIME type.
+///
+/// # Arguments
+///
+/// * `mimetype` - The MIME type to get the extension for.
+///
+/// # Returns
+///
+/// The extension without a leading dot, or [`None`] if the MIME type isn't known.
+///
+pub fn mimetype_to_extension(mimetype: &str) -> Option<&'static str> {
+    let extension = match mimetype {
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/gzip" => "gz",
+        "application/mbox" => "mbox",
+        "application/json" => "json",
+        "application/yaml" => "yaml",
+        "application/toml" => "toml",
+        "application/xml" | "text/xml" => "xml",
+        "application/msword" => "doc",
+        "application/vnd.ms-excel" => "xls",
+        "application/vnd.ms-powerpoint" => "ppt",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => "docx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => "xlsx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation" => "pptx",
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/tiff" => "tiff",
+        "message/rfc822" => "eml",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "text/csv" => "csv",
+        "text/calendar" => "ics",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// Get the MIME type conventionally meant by a file extension.
+///
+/// # Arguments
+///
+/// * `extension` - The extension without a leading dot, compared case-insensitively.
+///
+/// # Returns
+///
+/// The MIME type, or [`None`] if the extension isn't known.
+///
+pub fn extension_to_mimetype(extension: &str) -> Option<&'static str> {
+    let mimetype = match extension.to_ascii_lowercase().as_str() {
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "mbox" => "application/mbox",
+        "json" => "application/json",
+        "yaml" | "yml" => "application/yaml",
+        "toml" => "application/toml",
+        "xml" => "application/xml",
+        "doc" => "application/msword",
+        "xls" => "application/vnd.ms-excel",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "tif" | "tiff" => "image/tiff",
+        "eml" => "message/rfc822",
+        "txt" => "text/plain",
+        "htm" | "html" => "text/html",
+        "csv" => "text/csv",
+        "ics" => "text/calendar",
+        _ => return None,
+    };
+    Some(mimetype)
+}
+
 #[cfg(test)]
 mod tests {
     use std::borrow::Cow;
@@ -66,4 +163,34 @@ mod tests {
 
         assert_eq!(mimetype(&content_type), "text");
     }
+
+    #[test]
+    fn test_mimetype_to_extension() {
+        assert_eq!(mimetype_to_extension("application/pdf"), Some("pdf"));
+        assert_eq!(mimetype_to_extension("image/jpeg"), Some("jpg"));
+        assert_eq!(mimetype_to_extension("image/png"), Some("png"));
+        assert_eq!(mimetype_to_extension("message/rfc822"), Some("eml"));
+        assert_eq!(mimetype_to_extension("application/yaml"), Some("yaml"));
+        assert_eq!(mimetype_to_extension("application/toml"), Some("toml"));
+        assert_eq!(
+            mimetype_to_extension("application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+            Some("docx"),
+        );
+    }
+
+    #[test]
+    fn test_extension_to_mimetype() {
+        assert_eq!(extension_to_mimetype("JPG"), Some("image/jpeg"));
+        assert_eq!(extension_to_mimetype("eml"), Some("message/rfc822"));
+        assert_eq!(extension_to_mimetype("yml"), Some("application/yaml"));
+        assert_eq!(extension_to_mimetype("toml"), Some("application/toml"));
+        assert_eq!(extension_to_mimetype("unknown"), None);
+    }
+
+    #[test]
+    fn test_mimetype_to_extension_unknown() {
+        assert_eq!(mimetype_to_extension("application/x-unknown"), None);
+        assert_eq!(mimetype_to_extension("embedded/octet-stream"), None);
+        assert_eq!(mimetype_to_extension(""), None);
+    }
 }
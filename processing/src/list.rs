@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::embedded;
+
+/// Information about a file embedded in another, listed without extracting it.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EmbeddedInfo {
+    /// The name of the embedded file, as it's named when extracted.
+    ///
+    pub name: String,
+
+    /// The size of the embedded file in bytes.
+    ///
+    pub size: u64,
+
+    /// The MIME type of the embedded file, if it's known without reading the file.
+    ///
+    /// Entries of zip files are typed by their extensions, so it's [`None`] for unknown extensions.
+    ///
+    pub mimetype: Option<String>,
+}
+
+/// Lists the files embedded in a file, without extracting or processing them.
+///
+/// Only as much of the file is read as is needed to find its embedded files, like the central directory of a zip
+/// file. Embedded files are listed in the order they're stored in, and aren't listed recursively.
+///
+/// # Arguments
+///
+/// * `path` - The path to the file to list the embedded files of.
+/// * `mimetype` - The MIME type of the file, one of `application/zip`, `application/mbox`, or `message/rfc822`.
+///
+/// # Returns
+///
+/// * `Ok(Vec<EmbeddedInfo>)` - The embedded files of the file.
+/// * `Err(_)` - If the file couldn't be read, or listing files of its MIME type isn't supported.
+///
+pub fn list_embedded(path: impl AsRef<Path>, mimetype: &str) -> anyhow::Result<Vec<EmbeddedInfo>> {
+    let path = path.as_ref();
+    match mimetype {
+        "application/zip" => embedded::list_entries(path),
+        "application/mbox" => embedded::list_messages(path),
+        "message/rfc822" => embedded::list_attachments(path),
+        _ => Err(anyhow!("listing the embedded files of {} files isn't supported", mimetype)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_embedded_zip() -> anyhow::Result<()> {
+        let entries = list_embedded("../resources/zip/testzip.zip", "application/zip")?;
+
+        assert_eq!(entries, vec![
+            EmbeddedInfo { name: "PA280041.JPG".to_string(), size: 362958, mimetype: Some("image/jpeg".to_string()) },
+            EmbeddedInfo { name: "PA280040.JPG".to_string(), size: 358265, mimetype: Some("image/jpeg".to_string()) },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_embedded_mbox() -> anyhow::Result<()> {
+        let path = "../resources/mbox/ubuntu-no-small.mbox";
+        let messages = list_embedded(path, "application/mbox")?;
+
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|message| message.mimetype.as_deref() == Some("message/rfc822")));
+        assert_eq!(messages.iter().map(|message| message.size).sum::<u64>(), std::fs::metadata(path)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_embedded_unsupported() {
+        assert!(list_embedded("../resources/text/crlf.txt", "text/plain").is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use exif::{Exif, In, Reader, Tag, Value};
+use json::JsonValue;
+
+/// MIME types of the images whose EXIF data is read natively.
+///
+const EXIF_MIMETYPES: [&str; 4] = ["image/jpeg", "image/tiff", "image/heic", "image/heif"];
+
+/// Whether the MIME type is an image whose EXIF data is read natively, i.e. a jpeg, tiff, or heic file.
+///
+pub fn is_exif_image(mimetype: &str) -> bool {
+    EXIF_MIMETYPES.contains(&mimetype)
+}
+
+/// Reads the camera, the time the picture was taken, and the GPS coordinates from the EXIF data of an image.
+///
+/// Properties are keyed like the metadata keys used by tika: `tiff:Make`, `tiff:Model`, `exif:DateTimeOriginal` as an
+/// ISO 8601 date and time, and `geo:lat` and `geo:long` in decimal degrees, negative to the south and west. Images
+/// without EXIF data have no properties.
+///
+pub fn read_properties(path: &Path) -> anyhow::Result<JsonValue> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let exif = match Reader::new().read_from_container(&mut reader) {
+        Ok(exif) => exif,
+        Err(exif::Error::NotFound(_)) => return Ok(JsonValue::new_object()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut properties = JsonValue::new_object();
+
+    for (tag, key) in [(Tag::Make, "tiff:Make"), (Tag::Model, "tiff:Model")] {
+        if let Some(text) = ascii(&exif, tag) {
+            properties[key] = text.into();
+        }
+    }
+    if let Some(date_time) = ascii(&exif, Tag::DateTimeOriginal).and_then(|date_time| iso_date_time(&date_time)) {
+        properties["exif:DateTimeOriginal"] = date_time.into();
+    }
+    if let Some(latitude) = coordinate(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S") {
+        properties["geo:lat"] = latitude.into();
+    }
+    if let Some(longitude) = coordinate(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W") {
+        properties["geo:long"] = longitude.into();
+    }
+
+    Ok(properties)
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_string())
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
+
+/// Converts an EXIF date and time, like `2023:10:01 09:30:00`, to ISO 8601, like `2023-10-01T09:30:00`.
+///
+fn iso_date_time(date_time: &str) -> Option<String> {
+    let (date, time) = date_time.split_once(' ')?;
+    Some(format!("{}T{}", date.replace(':', "-"), time))
+}
+
+/// Converts a GPS coordinate from degrees, minutes, and seconds to decimal degrees, negated if its reference is
+/// `negative_ref`.
+///
+fn coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: &str) -> Option<f64> {
+    let Value::Rational(parts) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let degrees: f64 = parts.iter().zip([1.0, 60.0, 3600.0]).map(|(part, unit)| part.to_f64() / unit).sum();
+    let negative = ascii(exif, ref_tag).is_some_and(|reference| reference.eq_ignore_ascii_case(negative_ref));
+    degrees.is_finite().then_some(if negative { -degrees } else { degrees })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_properties() -> anyhow::Result<()> {
+        let properties = read_properties(Path::new("../resources/jpg/geotagged.jpg"))?;
+
+        assert_eq!(properties["tiff:Make"], "Rusty Optics");
+        assert_eq!(properties["tiff:Model"], "RP-100");
+        assert_eq!(properties["exif:DateTimeOriginal"], "2023-10-01T09:30:00");
+        assert!((properties["geo:lat"].as_f64().unwrap() - 37.8199).abs() < 1e-6);
+        assert!((properties["geo:long"].as_f64().unwrap() + 122.478633).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_properties_without_exif() -> anyhow::Result<()> {
+        let properties = read_properties(Path::new("../resources/jpg/jQuery-text.jpg"))?;
+
+        assert!(properties.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_properties_truncated_exif() {
+        assert!(read_properties(Path::new("../resources/jpg/truncated-exif.jpg")).is_err());
+    }
+
+    #[test]
+    fn test_is_exif_image() {
+        assert!(is_exif_image("image/jpeg"));
+        assert!(is_exif_image("image/heic"));
+        assert!(!is_exif_image("image/png"));
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::anyhow;
+use json::JsonValue;
+
+use crate::processing::MetadataFormat;
+
+/// Serializes the metadata to the format.
+///
+/// TOML has no null, so null values are left out of TOML output.
+///
+pub(crate) fn serialize(metadata: &JsonValue, format: MetadataFormat) -> anyhow::Result<String> {
+    match format {
+        MetadataFormat::Json => Ok(metadata.dump()),
+        MetadataFormat::Yaml => Ok(serde_yaml::to_string(&to_yaml(metadata))?),
+        MetadataFormat::Toml => match to_toml(metadata) {
+            Some(toml::Value::Table(table)) => Ok(toml::to_string(&table)?),
+            _ => Err(anyhow!("metadata must be an object to be serialized as TOML")),
+        },
+    }
+}
+
+fn to_yaml(value: &JsonValue) -> serde_yaml::Value {
+    match value {
+        JsonValue::Null => serde_yaml::Value::Null,
+        JsonValue::Boolean(b) => serde_yaml::Value::Bool(*b),
+        JsonValue::Number(_) => match value.as_i64() {
+            Some(n) => serde_yaml::Value::Number(n.into()),
+            None => serde_yaml::Value::Number(value.as_f64().unwrap_or(f64::NAN).into()),
+        },
+        JsonValue::Short(_) | JsonValue::String(_) => serde_yaml::Value::String(value.to_string()),
+        JsonValue::Array(values) => serde_yaml::Value::Sequence(values.iter().map(to_yaml).collect()),
+        JsonValue::Object(object) => serde_yaml::Value::Mapping(
+            object.iter()
+                .map(|(key, value)| (serde_yaml::Value::String(key.to_string()), to_yaml(value)))
+                .collect()
+        ),
+    }
+}
+
+fn to_toml(value: &JsonValue) -> Option<toml::Value> {
+    match value {
+        JsonValue::Null => None,
+        JsonValue::Boolean(b) => Some(toml::Value::Boolean(*b)),
+        JsonValue::Number(_) => match value.as_i64() {
+            Some(n) => Some(toml::Value::Integer(n)),
+            None => value.as_f64().map(toml::Value::Float),
+        },
+        JsonValue::Short(_) | JsonValue::String(_) => Some(toml::Value::String(value.to_string())),
+        JsonValue::Array(values) => Some(toml::Value::Array(values.iter().filter_map(to_toml).collect())),
+        JsonValue::Object(object) => Some(toml::Value::Table(
+            object.iter()
+                .filter_map(|(key, value)| to_toml(value).map(|value| (key.to_string(), value)))
+                .collect()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> JsonValue {
+        json::object! {
+            "content_type": "text/calendar",
+            "rusty.original_size": 1120,
+            "latitude": 37.8199,
+            "encrypted": false,
+            "title": null,
+            "keywords": ["quarterly", "review"],
+            "rusty.events": [{ "summary": "Quarterly review", "location": null }],
+        }
+    }
+
+    fn round_trip(format: MetadataFormat) -> anyhow::Result<serde_json::Value> {
+        let serialized = serialize(&metadata(), format)?;
+        Ok(match format {
+            MetadataFormat::Json => serde_json::from_str(&serialized)?,
+            MetadataFormat::Yaml => serde_yaml::from_str(&serialized)?,
+            MetadataFormat::Toml => toml::from_str(&serialized)?,
+        })
+    }
+
+    #[test]
+    fn test_serialize_json() -> anyhow::Result<()> {
+        let expected: serde_json::Value = serde_json::from_str(&metadata().dump())?;
+        assert_eq!(round_trip(MetadataFormat::Json)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_yaml() -> anyhow::Result<()> {
+        let expected: serde_json::Value = serde_json::from_str(&metadata().dump())?;
+        assert_eq!(round_trip(MetadataFormat::Yaml)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_toml() -> anyhow::Result<()> {
+        let expected = serde_json::json!({
+            "content_type": "text/calendar",
+            "rusty.original_size": 1120,
+            "latitude": 37.8199,
+            "encrypted": false,
+            "keywords": ["quarterly", "review"],
+            "rusty.events": [{ "summary": "Quarterly review" }],
+        });
+        assert_eq!(round_trip(MetadataFormat::Toml)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_toml_not_object() {
+        assert!(serialize(&json::array!["quarterly"], MetadataFormat::Toml).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use json::JsonValue;
+
+/// A property of an iCalendar component, like `DTSTART;TZID=America/Chicago:20231011T093000`.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads the events (`VEVENT` components) of an iCalendar file.
+///
+/// Each event is an object with its `uid`, `summary`, `description`, `location`, `start`, `end`, `organizer`, and
+/// `attendees`, where present. Dates and times are formatted as in RFC 3339, and the time zone of local times is kept
+/// in `start_tzid` and `end_tzid`. Components nested in events, like alarms, are ignored.
+///
+pub fn read_events(content: &str) -> JsonValue {
+    let mut events = JsonValue::new_array();
+    let mut components: Vec<String> = vec![];
+    let mut event = JsonValue::new_object();
+
+    for line in unfold(content) {
+        let Some(property) = parse_property(&line) else {
+            continue;
+        };
+
+        match property.name.as_str() {
+            "BEGIN" => {
+                if property.value.eq_ignore_ascii_case("VEVENT") {
+                    event = JsonValue::new_object();
+                }
+                components.push(property.value.to_uppercase());
+            },
+            "END" => {
+                let ended = components.pop();
+                if ended.is_some_and(|component| component == "VEVENT") {
+                    let _ = events.push(event.take());
+                }
+            },
+            _ if components.last().is_some_and(|component| component == "VEVENT") => add_property(&mut event, property),
+            _ => {},
+        }
+    }
+
+    events
+}
+
+fn add_property(event: &mut JsonValue, property: Property) {
+    match property.name.as_str() {
+        "UID" | "SUMMARY" | "DESCRIPTION" | "LOCATION" => {
+            event[property.name.to_lowercase()] = unescape(&property.value).into();
+        },
+        "DTSTART" | "DTEND" => {
+            let key = if property.name == "DTSTART" { "start" } else { "end" };
+            event[key] = format_date_time(&property.value).into();
+            if let Some(tzid) = property.param("TZID") {
+                event[format!("{}_tzid", key)] = tzid.into();
+            }
+        },
+        "ORGANIZER" => event["organizer"] = person(&property),
+        "ATTENDEE" => {
+            if !event.has_key("attendees") {
+                event["attendees"] = JsonValue::new_array();
+            }
+            let _ = event["attendees"].push(person(&property));
+        },
+        _ => {},
+    }
+}
+
+/// Returns the name and email of an organizer or attendee.
+///
+fn person(property: &Property) -> JsonValue {
+    let address = property.value.strip_prefix("mailto:")
+        .or_else(|| property.value.strip_prefix("MAILTO:"))
+        .unwrap_or(&property.value);
+
+    let mut person = json::object! { "email": address };
+    if let Some(name) = property.param("CN") {
+        person["name"] = name.into();
+    }
+    person
+}
+
+/// Joins folded lines, which continue on the next line after a line break followed by a space or tab.
+///
+fn unfold(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in content.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(continuation) if !lines.is_empty() => lines.last_mut().unwrap().push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Parses a content line into its name, parameters, and value.
+///
+/// Parameter values may be quoted to contain `;`, `:`, and `,`.
+///
+fn parse_property(line: &str) -> Option<Property> {
+    let mut in_quotes = false;
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut value_start = None;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                segments.push(&line[start..i]);
+                start = i + 1;
+            },
+            ':' if !in_quotes => {
+                segments.push(&line[start..i]);
+                value_start = Some(i + 1);
+                break;
+            },
+            _ => {},
+        }
+    }
+
+    let value = line[value_start?..].to_string();
+    let (name, params) = segments.split_first()?;
+    let params = params.iter()
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| (name.to_uppercase(), value.trim_matches('"').to_string()))
+        .collect();
+
+    Some(Property { name: name.to_uppercase(), params, value })
+}
+
+/// Unescapes a text value, where `\n`, `\,`, `\;`, and `\\` are escaped.
+///
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Formats a date (`20231010`) or date-time (`20231010T150000Z`) value as in RFC 3339.
+///
+/// Values in any other form are returned as they are.
+///
+fn format_date_time(value: &str) -> String {
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+    let (time, utc) = time.strip_suffix('Z').map_or((time, ""), |time| (time, "Z"));
+
+    match (date.len(), time.len()) {
+        (8, 0) if is_digits(date) => format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]),
+        (8, 6) if is_digits(date) && is_digits(time) => format!(
+            "{}-{}-{}T{}:{}:{}{}",
+            &date[..4], &date[4..6], &date[6..], &time[..2], &time[2..4], &time[4..], utc,
+        ),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_events() -> anyhow::Result<()> {
+        let content = std::fs::read_to_string("../resources/ics/meeting.ics")?;
+
+        let events = read_events(&content);
+
+        assert_eq!(events.len(), 3);
+        let review = &events[0];
+        assert_eq!(review["summary"], "Quarterly review");
+        assert_eq!(review["start"], "2023-10-10T15:00:00Z");
+        assert_eq!(review["end"], "2023-10-10T16:00:00Z");
+        assert_eq!(review["location"], "Conference Room 2, Building A");
+        assert_eq!(review["description"], "Walk through the quarterly report.\nBring your numbers; questions welcome.");
+        assert_eq!(review["organizer"], json::object! { "email": "alice@example.com", "name": "Example, Alice" });
+        assert_eq!(review["attendees"], json::array! [
+            { "email": "bob@example.com", "name": "Bob Example" },
+            { "email": "carol@example.com" },
+        ]);
+
+        assert_eq!(events[1]["summary"], "Team offsite");
+        assert_eq!(events[1]["start"], "2023-10-20");
+        assert!(events[1]["attendees"].is_null());
+
+        assert_eq!(events[2]["start"], "2023-10-11T09:30:00");
+        assert_eq!(events[2]["start_tzid"], "America/Chicago");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_property_quoted_params() {
+        let property = parse_property(r#"ORGANIZER;CN="Doe: Jane; PhD":mailto:jane@example.com"#).unwrap();
+
+        assert_eq!(property.name, "ORGANIZER");
+        assert_eq!(property.param("cn"), Some("Doe: Jane; PhD"));
+        assert_eq!(property.value, "mailto:jane@example.com");
+    }
+}
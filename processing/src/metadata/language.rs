@@ -0,0 +1,53 @@
+/// The language detected from a file's text.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedLanguage {
+    /// The ISO 639-1 code of the language.
+    ///
+    pub code: &'static str,
+
+    /// The confidence of the detection, between 0 and 1.
+    ///
+    pub confidence: f64,
+}
+
+/// Detects the language of the text.
+///
+/// Returns `None` if the language couldn't be detected or has no ISO 639-1 code.
+///
+pub fn detect_language(text: &str) -> Option<DetectedLanguage> {
+    let info = whatlang::detect(text)?;
+    let code = isolang::Language::from_639_3(info.lang().code())?.to_639_1()?;
+
+    Some(DetectedLanguage {
+        code,
+        confidence: info.confidence(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        let cases = vec![
+            ("../resources/text/english.txt", "en"),
+            ("../resources/text/french.txt", "fr"),
+        ];
+
+        for (path, expected_code) in cases {
+            let text = std::fs::read_to_string(path).unwrap();
+
+            let language = detect_language(&text).expect("failed to detect language");
+
+            assert_eq!(language.code, expected_code, "wrong language for {}", path);
+            assert!(language.confidence > 0.5, "low confidence for {}", path);
+        }
+    }
+
+    #[test]
+    fn test_detect_language_no_text() {
+        assert_eq!(detect_language(""), None);
+    }
+}
@@ -1,12 +1,153 @@
 use std::path::Path;
 use async_trait::async_trait;
+use sha2::{Digest, Sha256};
 use tempfile::TempPath;
-use services::tika;
-use crate::processing::{Process, ProcessContext, ProcessOutput};
+use json::JsonValue;
+use log::warn;
+use tokio::io::AsyncReadExt;
+use services::{external_extractors, tika};
+use crate::processing::{Process, ProcessContext, ProcessOutput, ProcessType};
+
+use self::normalize::normalize;
+
+mod exif_data;
+mod format;
+mod ical;
+mod language;
+mod normalize;
+mod ooxml;
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DefaultMetadataProcessor;
 
+impl DefaultMetadataProcessor {
+    /// Extracts the text of the input file, the same way the text processor does.
+    ///
+    async fn text(&self, ctx: &ProcessContext, input_path: &Path) -> anyhow::Result<String> {
+        match external_extractors().get(&ctx.mimetype) {
+            Some(extractor) => {
+                let text = extractor.text_into_writer(input_path, vec![]).await?;
+                Ok(String::from_utf8_lossy(&text).to_string())
+            },
+            None => tika()?.text(input_path).await,
+        }
+    }
+
+    /// Adds the language detected from the text of the input file to the metadata.
+    ///
+    fn add_language(&self, text: &str, metadata: String) -> anyhow::Result<String> {
+        let mut metadata = json::parse(&metadata)?;
+
+        if let Some(language) = language::detect_language(text) {
+            metadata["rusty.language"] = language.code.into();
+            metadata["rusty.language_confidence"] = language.confidence.into();
+        }
+        Ok(metadata.dump())
+    }
+
+    /// Adds the first `preview_chars` characters of the text of the input file to the metadata, as `rusty.preview`.
+    ///
+    /// Leading whitespace is skipped, as it makes for a poor preview.
+    ///
+    fn add_preview(&self, text: &str, preview_chars: usize, metadata: String) -> anyhow::Result<String> {
+        let mut metadata = json::parse(&metadata)?;
+        metadata["rusty.preview"] = text.trim_start().chars().take(preview_chars).collect::<String>().into();
+        Ok(metadata.dump())
+    }
+
+    /// Adds the natively read Office Open XML document properties to the metadata, replacing tika's values.
+    ///
+    /// The metadata is left as is if the properties fail to be read, like for corrupt or encrypted documents.
+    ///
+    fn add_ooxml_properties(&self, input_path: &Path, metadata: String) -> anyhow::Result<String> {
+        let properties = match ooxml::read_properties(input_path) {
+            Ok(properties) => properties,
+            Err(err) => {
+                warn!("Failed to read Office Open XML properties, keeping tika's values: {}", err);
+                return Ok(metadata);
+            },
+        };
+        let mut metadata = json::parse(&metadata)?;
+
+        for (key, value) in properties.entries() {
+            metadata[key] = value.clone();
+        }
+        Ok(metadata.dump())
+    }
+
+    /// Adds the camera, time taken, and GPS coordinates read from the EXIF data of the input image to the metadata,
+    /// replacing tika's values.
+    ///
+    /// The metadata is left as is if the EXIF data fails to be read, like when it's malformed or truncated.
+    ///
+    fn add_exif_properties(&self, input_path: &Path, metadata: String) -> anyhow::Result<String> {
+        let properties = match exif_data::read_properties(input_path) {
+            Ok(properties) => properties,
+            Err(err) => {
+                warn!("Failed to read EXIF data, keeping tika's values: {}", err);
+                return Ok(metadata);
+            },
+        };
+        let mut metadata = json::parse(&metadata)?;
+
+        for (key, value) in properties.entries() {
+            metadata[key] = value.clone();
+        }
+        Ok(metadata.dump())
+    }
+
+    /// Adds the size and SHA-256 hash of the input file to the metadata, as `rusty.original_size` and `rusty.sha256`.
+    ///
+    async fn add_size_and_sha256(&self, input_path: &Path, metadata: String) -> anyhow::Result<String> {
+        let mut file = tokio::fs::File::open(input_path).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let bytes_read = file.read(&mut buf).await?;
+            if bytes_read == 0 {
+                break;
+            }
+            hasher.update(&buf[..bytes_read]);
+            size += bytes_read as u64;
+        }
+
+        let mut metadata = json::parse(&metadata)?;
+        metadata["rusty.original_size"] = size.into();
+        metadata["rusty.sha256"] = format!("{:x}", hasher.finalize()).into();
+        Ok(metadata.dump())
+    }
+
+    /// Adds the ID of the conversation thread of the file to the metadata.
+    ///
+    fn add_thread_id(&self, thread_id: &str, metadata: String) -> anyhow::Result<String> {
+        let mut metadata = json::parse(&metadata)?;
+        metadata["rusty.thread_id"] = thread_id.into();
+        Ok(metadata.dump())
+    }
+}
+
+/// Normalizes the metadata and writes it to the output file, in the format of `ctx.metadata_format`.
+///
+/// Whether the text of the file was truncated is recorded as `rusty.truncated`, if it was.
+///
+async fn write_metadata(
+    ctx: &ProcessContext,
+    metadata: &JsonValue,
+    output_path: TempPath,
+    checksum: &str,
+) -> anyhow::Result<ProcessOutput> {
+    let format = ctx.metadata_format;
+    let mut metadata = normalize(metadata);
+    if ctx.text_truncated {
+        metadata["rusty.truncated"] = true.into();
+    }
+    tokio::fs::write(&output_path, format::serialize(&metadata, format)?).await?;
+
+    let name = ctx.output_name(ProcessType::Metadata, &format!("metadata.{}", format.extension()));
+    Ok(ProcessOutput::processed(ctx, name, output_path, format.mimetype(), checksum))
+}
+
 #[async_trait]
 impl Process for DefaultMetadataProcessor {
     async fn process(
@@ -17,11 +158,28 @@ impl Process for DefaultMetadataProcessor {
         checksum: &str,
     ) -> anyhow::Result<()> {
         let result = async {
-            let mut metadata = tika().metadata(input_path).await?;
-            tokio::fs::write(&output_path, &mut metadata).await?;
-
-            let output = ProcessOutput::processed(&ctx, "metadata.json", output_path, "application/json", checksum);
-            anyhow::Ok(output)
+            let mut metadata = tika()?.metadata(input_path).await?;
+            let text = match ctx.detect_language || ctx.preview_chars.is_some() {
+                true => Some(self.text(&ctx, input_path).await?),
+                false => None,
+            };
+            if let (true, Some(text)) = (ctx.detect_language, &text) {
+                metadata = self.add_language(text, metadata)?;
+            }
+            if let (Some(preview_chars), Some(text)) = (ctx.preview_chars, &text) {
+                metadata = self.add_preview(text, preview_chars, metadata)?;
+            }
+            if ooxml::is_ooxml(&ctx.mimetype) {
+                metadata = self.add_ooxml_properties(input_path, metadata)?;
+            }
+            if exif_data::is_exif_image(&ctx.mimetype) {
+                metadata = self.add_exif_properties(input_path, metadata)?;
+            }
+            if let Some(thread_id) = &ctx.thread_id {
+                metadata = self.add_thread_id(thread_id, metadata)?;
+            }
+            metadata = self.add_size_and_sha256(input_path, metadata).await?;
+            write_metadata(&ctx, &json::parse(&metadata)?, output_path, checksum).await
         }.await;
 
         ctx.add_output(result).await
@@ -30,4 +188,190 @@ impl Process for DefaultMetadataProcessor {
     fn name(&self) -> &'static str {
         "Default Metadata"
     }
-}
\ No newline at end of file
+
+    fn output_mimetype(&self, ctx: &ProcessContext) -> &'static str {
+        ctx.metadata_format.mimetype()
+    }
+}
+
+/// Metadata processor for empty files, producing the metadata without running any external tools.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EmptyMetadataProcessor;
+
+#[async_trait]
+impl Process for EmptyMetadataProcessor {
+    async fn process(
+        &self,
+        ctx: ProcessContext,
+        _: &Path,
+        output_path: TempPath,
+        checksum: &str,
+    ) -> anyhow::Result<()> {
+        let result = async {
+            let metadata = json::object! {
+                "Content-Type": ctx.mimetype.as_str(),
+                "Content-Length": "0",
+            };
+            write_metadata(&ctx, &metadata, output_path, checksum).await
+        }.await;
+
+        ctx.add_output(result).await
+    }
+
+    fn name(&self) -> &'static str {
+        "Empty Metadata"
+    }
+
+    fn output_mimetype(&self, ctx: &ProcessContext) -> &'static str {
+        ctx.metadata_format.mimetype()
+    }
+}
+
+/// Metadata processor for iCalendar files, producing the details of their events without running any external tools.
+///
+/// The events are listed under `rusty.events`; see [`ical::read_events`] for their structure.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IcalMetadataProcessor;
+
+#[async_trait]
+impl Process for IcalMetadataProcessor {
+    async fn process(
+        &self,
+        ctx: ProcessContext,
+        input_path: &Path,
+        output_path: TempPath,
+        checksum: &str,
+    ) -> anyhow::Result<()> {
+        let result = async {
+            let content = tokio::fs::read(input_path).await?;
+            let metadata = json::object! {
+                "Content-Type": ctx.mimetype.as_str(),
+                "rusty.events": ical::read_events(&String::from_utf8_lossy(&content)),
+            };
+            write_metadata(&ctx, &metadata, output_path, checksum).await
+        }.await;
+
+        ctx.add_output(result).await
+    }
+
+    fn name(&self) -> &'static str {
+        "iCalendar Metadata"
+    }
+
+    fn output_mimetype(&self, ctx: &ProcessContext) -> &'static str {
+        ctx.metadata_format.mimetype()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use test_utils::temp_path;
+
+    use crate::processing::{MetadataFormat, ProcessContextBuilder};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_add_size_and_sha256() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/ics/meeting.ics");
+        let metadata = json::object! { "Content-Type": "text/calendar" }.dump();
+
+        let metadata = DefaultMetadataProcessor.add_size_and_sha256(&path, metadata).await?;
+
+        let metadata = json::parse(&metadata)?;
+        assert_eq!(metadata["Content-Type"], "text/calendar");
+        assert_eq!(metadata["rusty.original_size"], 1120);
+        assert_eq!(metadata["rusty.sha256"], "aa9b8355ae4dc54ce6e76a8f88ad542107b5e4c4c49d1d24ede6eddc97a9311f");
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_ooxml_properties_corrupt() -> anyhow::Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(b"not a zip file")?;
+        let metadata = json::object! { "Content-Type": "application/vnd.openxmlformats-officedocument.wordprocessingml.document" }.dump();
+
+        assert_eq!(DefaultMetadataProcessor.add_ooxml_properties(file.path(), metadata.clone())?, metadata);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_exif_properties() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/jpg/geotagged.jpg");
+        let metadata = json::object! { "Content-Type": "image/jpeg", "tiff:Model": "Unknown" }.dump();
+
+        let metadata = json::parse(&DefaultMetadataProcessor.add_exif_properties(&path, metadata)?)?;
+        let metadata = normalize(&metadata);
+
+        assert_eq!(metadata["camera_model"], "RP-100");
+        assert_eq!(metadata["created"], "2023-10-01T09:30:00");
+        assert!((metadata["latitude"].as_f64().unwrap() - 37.8199).abs() < 1e-6);
+        assert!((metadata["longitude"].as_f64().unwrap() + 122.478633).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_exif_properties_truncated() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/jpg/truncated-exif.jpg");
+        let metadata = json::object! { "Content-Type": "image/jpeg", "tiff:Model": "Unknown" }.dump();
+
+        assert_eq!(DefaultMetadataProcessor.add_exif_properties(&path, metadata.clone())?, metadata);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_preview() -> anyhow::Result<()> {
+        let text = format!("\n\n{}", std::fs::read_to_string("../resources/text/french.txt")?);
+        let metadata = json::object! { "Content-Type": "text/plain" }.dump();
+
+        let metadata = json::parse(&DefaultMetadataProcessor.add_preview(&text, 33, metadata)?)?;
+
+        let preview = metadata["rusty.preview"].as_str().unwrap();
+        assert_eq!(preview.chars().count(), 33);
+        assert_eq!(preview, "Le rapport trimestriel résume les");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ical_metadata_processor() -> anyhow::Result<()> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("text/calendar", vec![], output_sink).build();
+        let path = PathBuf::from("../resources/ics/meeting.ics");
+
+        IcalMetadataProcessor.process(ctx, &path, temp_path()?, "checksum").await?;
+
+        let Some(Ok(ProcessOutput::Processed(_, data))) = outputs.recv().await else {
+            panic!("expected processed output");
+        };
+        let metadata = json::parse(&std::fs::read_to_string(&data.path)?)?;
+        assert_eq!(metadata["content_type"], "text/calendar");
+        assert_eq!(metadata["rusty.events"].len(), 3);
+        assert_eq!(metadata["rusty.events"][0]["summary"], "Quarterly review");
+        assert_eq!(metadata["rusty.events"][0]["start"], "2023-10-10T15:00:00Z");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metadata_format() -> anyhow::Result<()> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("text/plain", vec![], output_sink)
+            .metadata_format(MetadataFormat::Yaml)
+            .build();
+
+        EmptyMetadataProcessor.process(ctx, &PathBuf::from("empty.txt"), temp_path()?, "checksum").await?;
+
+        let Some(Ok(ProcessOutput::Processed(_, data))) = outputs.recv().await else {
+            panic!("expected processed output");
+        };
+        assert_eq!(data.name, "metadata.yaml");
+        assert_eq!(data.mimetype, "application/yaml");
+        let metadata: serde_yaml::Value = serde_yaml::from_str(&std::fs::read_to_string(&data.path)?)?;
+        assert_eq!(metadata["content_type"], "text/plain");
+        Ok(())
+    }
+}
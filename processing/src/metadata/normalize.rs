@@ -0,0 +1,100 @@
+use json::JsonValue;
+
+/// The key the original metadata is kept under once it's normalized.
+///
+pub const RAW_KEY: &str = "raw";
+
+/// Prefix of the keys added by the processors themselves, which are already stable.
+///
+const RUSTY_PREFIX: &str = "rusty.";
+
+/// The stable keys of the normalized metadata, each with the variants of the key used by tika in order of preference.
+///
+/// Variants are matched regardless of their casing.
+///
+const NORMALIZED_KEYS: [(&str, &[&str]); 18] = [
+    ("content_type", &["Content-Type"]),
+    ("content_length", &["Content-Length"]),
+    ("content_encoding", &["Content-Encoding"]),
+    ("language", &["dc:language", "language", "Content-Language"]),
+    ("title", &["dc:title", "title"]),
+    ("subject", &["dc:subject", "subject"]),
+    ("author", &["dc:creator", "meta:author", "Author", "creator"]),
+    ("created", &["dcterms:created", "meta:creation-date", "Creation-Date", "created", "exif:DateTimeOriginal"]),
+    ("modified", &["dcterms:modified", "meta:save-date", "Last-Modified", "Last-Save-Date", "modified"]),
+    ("creator_tool", &["xmp:CreatorTool", "extended-properties:Application", "Application-Name"]),
+    ("producer", &["pdf:producer", "producer"]),
+    ("page_count", &["xmpTPg:NPages", "meta:page-count", "Page-Count"]),
+    ("word_count", &["meta:word-count", "Word-Count"]),
+    ("character_count", &["meta:character-count", "Character Count"]),
+    ("camera_make", &["tiff:Make"]),
+    ("camera_model", &["tiff:Model"]),
+    ("latitude", &["geo:lat"]),
+    ("longitude", &["geo:long"]),
+];
+
+/// Normalizes the metadata to a stable set of snake_case keys, like `content_type` and `created`, keeping the original
+/// metadata under `raw`.
+///
+/// The keys added by the processors, prefixed with `rusty.`, are kept as they are.
+///
+pub fn normalize(metadata: &JsonValue) -> JsonValue {
+    let mut normalized = JsonValue::new_object();
+    let mut raw = JsonValue::new_object();
+    for (key, value) in metadata.entries() {
+        match key.starts_with(RUSTY_PREFIX) {
+            true => normalized[key] = value.clone(),
+            false => raw[key] = value.clone(),
+        }
+    }
+
+    for (normalized_key, variants) in NORMALIZED_KEYS {
+        let value = variants.iter().find_map(|variant|
+            raw.entries().find(|(key, _)| key.eq_ignore_ascii_case(variant)).map(|(_, value)| value)
+        );
+        if let Some(value) = value {
+            normalized[normalized_key] = value.clone();
+        }
+    }
+
+    normalized[RAW_KEY] = raw;
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let variants = [
+            json::object! { "Content-Type": "application/pdf", "dcterms:created": "2023-10-01T09:00:00Z" },
+            json::object! { "content-type": "application/pdf", "Creation-Date": "2023-10-01T09:00:00Z" },
+            json::object! { "CONTENT-TYPE": "application/pdf", "meta:creation-date": "2023-10-01T09:00:00Z" },
+        ];
+
+        for metadata in variants {
+            let normalized = normalize(&metadata);
+
+            assert_eq!(normalized["content_type"], "application/pdf");
+            assert_eq!(normalized["created"], "2023-10-01T09:00:00Z");
+            assert_eq!(normalized[RAW_KEY], metadata);
+        }
+    }
+
+    #[test]
+    fn test_normalize_preference_and_rusty_keys() {
+        let metadata = json::object! {
+            "Last-Modified": "2023-10-02T09:00:00Z",
+            "dcterms:modified": "2023-10-03T09:00:00Z",
+            "rusty.sha256": "abc",
+        };
+
+        let normalized = normalize(&metadata);
+
+        assert_eq!(normalized["modified"], "2023-10-03T09:00:00Z");
+        assert_eq!(normalized["rusty.sha256"], "abc");
+        assert!(!normalized[RAW_KEY].has_key("rusty.sha256"));
+        assert!(!normalized.has_key("created"));
+    }
+}
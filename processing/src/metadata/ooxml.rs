@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use json::JsonValue;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Prefixes of the namespaces used by the core properties, matching the metadata keys used by tika.
+///
+const CORE_NAMESPACES: [(&str, &str); 3] = [
+    ("http://purl.org/dc/elements/1.1/", "dc"),
+    ("http://schemas.openxmlformats.org/package/2006/metadata/core-properties", "cp"),
+    ("http://purl.org/dc/terms/", "dcterms"),
+];
+
+/// Prefix of the extended properties, matching the metadata keys used by tika.
+///
+const EXTENDED_PREFIX: &str = "extended-properties";
+
+/// Whether the MIME type is an Office Open XML document, i.e. a docx, xlsx, or pptx file.
+///
+pub fn is_ooxml(mimetype: &str) -> bool {
+    mimetype.starts_with("application/vnd.openxmlformats-")
+}
+
+/// Reads the core properties (`docProps/core.xml`) and extended properties (`docProps/app.xml`) of an
+/// Office Open XML document.
+///
+/// Properties are keyed like `dc:creator`, `cp:revision`, and `extended-properties:Company`. Missing property
+/// parts are ignored.
+///
+pub fn read_properties(path: &Path) -> anyhow::Result<JsonValue> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut properties = JsonValue::new_object();
+
+    if let Some(core) = read_entry(&mut archive, "docProps/core.xml")? {
+        for (namespace, name, value) in leaf_elements(&core)? {
+            let prefix = CORE_NAMESPACES.iter()
+                .find(|(uri, _)| Some(*uri) == namespace.as_deref())
+                .map(|(_, prefix)| *prefix);
+            if let Some(prefix) = prefix {
+                properties[format!("{}:{}", prefix, name)] = value.into();
+            }
+        }
+    }
+    if let Some(app) = read_entry(&mut archive, "docProps/app.xml")? {
+        for (_, name, value) in leaf_elements(&app)? {
+            properties[format!("{}:{}", EXTENDED_PREFIX, name)] = value.into();
+        }
+    }
+
+    Ok(properties)
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> anyhow::Result<Option<String>> {
+    let mut entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut content = String::new();
+    entry.read_to_string(&mut content)?;
+    Ok(Some(content))
+}
+
+/// Returns the namespace, name, and text of the root's child elements that only contain text.
+///
+fn leaf_elements(xml: &str) -> anyhow::Result<Vec<(Option<String>, String, String)>> {
+    let document = roxmltree::Document::parse(xml)?;
+    let leaves = document.root_element().children()
+        .filter(|node| node.is_element() && !node.children().any(|child| child.is_element()))
+        .filter_map(|node| {
+            let text = node.text()?.trim();
+            let name = node.tag_name();
+            (!text.is_empty()).then(|| (name.namespace().map(str::to_string), name.name().to_string(), text.to_string()))
+        })
+        .collect();
+    Ok(leaves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_properties() -> anyhow::Result<()> {
+        let properties = read_properties(Path::new("../resources/docx/report.docx"))?;
+
+        assert_eq!(properties["dc:creator"], "Alice Example");
+        assert_eq!(properties["cp:lastModifiedBy"], "Bob Example");
+        assert_eq!(properties["cp:revision"], "7");
+        assert_eq!(properties["dcterms:created"], "2023-10-01T09:00:00Z");
+        assert_eq!(properties["extended-properties:Company"], "Example Corp");
+        assert!(properties["extended-properties:HeadingPairs"].is_null());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_ooxml() {
+        assert!(is_ooxml("application/vnd.openxmlformats-officedocument.wordprocessingml.document"));
+        assert!(is_ooxml("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"));
+        assert!(!is_ooxml("application/msword"));
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::processing::ProcessType;
+
+/// Strategy for naming the entries of the archive built from processing outputs.
+///
+/// Each embedded file gets a directory containing the file itself and everything produced from it.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+pub enum EntryNaming {
+    /// Directories are named by the checksums of embedded files, i.e. `88dde30c.../mbox-message.eml`.
+    ///
+    #[default]
+    Checksum,
+
+    /// Directories are named by the original names of embedded files without their extension, i.e. `invoice/invoice.pdf`.
+    ///
+    /// When different files would share a directory, all but the first (ordered by name and checksum) get a counter
+    /// appended, i.e. `invoice (1)/invoice.pdf`.
+    ///
+    OriginalName,
+
+    /// Directories are named by the original names of embedded files followed by their checksums,
+    /// i.e. `invoice-88dde30c.../invoice.pdf`.
+    ///
+    OriginalNameWithChecksumSuffix,
+}
+
+impl FromStr for EntryNaming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "checksum" => Ok(EntryNaming::Checksum),
+            "original-name" => Ok(EntryNaming::OriginalName),
+            "original-name-with-checksum-suffix" => Ok(EntryNaming::OriginalNameWithChecksumSuffix),
+            _ => Err(format!("Can not convert {} to EntryNaming", s)),
+        }
+    }
+}
+
+/// The directory all entries of the archive are nested under, so archives combined downstream don't collide.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ArchiveRoot {
+    /// The directory is named after the input file without its extension, i.e. `invoice/` for `invoice.pdf`.
+    ///
+    InputStem,
+
+    /// The directory is named by the given label.
+    ///
+    Label(String),
+}
+
+impl ArchiveRoot {
+    /// Builds the name of the directory for the input file at the path.
+    ///
+    /// The name is sanitized with [`sanitize_path_component`], so it's always a single directory.
+    ///
+    pub(crate) fn directory(&self, input_path: &Path) -> String {
+        let name = match self {
+            ArchiveRoot::InputStem => input_path.file_name()
+                .map(|name| stem(&name.to_string_lossy()))
+                .unwrap_or_default(),
+            ArchiveRoot::Label(label) => label.clone(),
+        };
+        sanitize_path_component(&name)
+    }
+}
+
+/// Templates for the names of the files produced from a file, like `{stem}.txt` for its extracted text.
+///
+/// `{stem}` is replaced by the original name of the file without its extension, and `{name}` by its full original
+/// name. Outputs without a template, or produced from a file without an original name like the root file, keep their
+/// default names, like `extracted.txt`.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OutputNameTemplates {
+    /// The template for the name of the extracted text, if any.
+    ///
+    /// A `.gz` extension is appended to the name when the text is compressed.
+    ///
+    pub text: Option<String>,
+
+    /// The template for the name of the metadata, if any.
+    ///
+    pub metadata: Option<String>,
+
+    /// The template for the name of the rendered PDF, if any.
+    ///
+    pub pdf: Option<String>,
+
+    /// The template for the name of the text recognized by OCR, if any.
+    ///
+    pub ocr: Option<String>,
+}
+
+impl OutputNameTemplates {
+    /// Resolves the name of an output of the given type against the original name of the file it's produced from.
+    ///
+    /// The `default_name` is returned if there's no template for the type, or no original name.
+    ///
+    pub fn resolve(&self, process_type: &ProcessType, original_name: Option<&str>, default_name: &str) -> String {
+        let template = match process_type {
+            ProcessType::Text => self.text.as_ref(),
+            ProcessType::Metadata => self.metadata.as_ref(),
+            ProcessType::Pdf => self.pdf.as_ref(),
+            ProcessType::Ocr => self.ocr.as_ref(),
+            ProcessType::Embedded | ProcessType::Attachments => None,
+        };
+
+        match (template, original_name) {
+            (Some(template), Some(original_name)) => template
+                .replace("{stem}", &stem(original_name))
+                .replace("{name}", original_name),
+            _ => default_name.to_string(),
+        }
+    }
+}
+
+/// An embedded file in the chain of files leading to an archive entry, as its name and ID.
+///
+pub(crate) type ChainLink = (String, String);
+
+/// The chain of embedded files leading to an archive entry.
+///
+/// The names and IDs of the files become components of the paths of archive entries, so they're sanitized with
+/// [`sanitize_path_component`] when the chain is created, and can't make a path escape the root of the archive.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct IdChain(Vec<ChainLink>);
+
+impl IdChain {
+    /// Creates a new IdChain from the names and IDs of the embedded files, from the outermost one.
+    ///
+    pub(crate) fn new(links: impl IntoIterator<Item = ChainLink>) -> Self {
+        let links = links.into_iter()
+            .map(|(name, id)| (sanitize_path_component(&name), sanitize_path_component(&id)))
+            .collect();
+        IdChain(links)
+    }
+
+    /// The names and IDs of the embedded files, from the outermost one.
+    ///
+    pub(crate) fn links(&self) -> &[ChainLink] {
+        &self.0
+    }
+
+    /// The IDs of the embedded files, from the outermost one.
+    ///
+    pub(crate) fn ids(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(_, id)| id.as_str())
+    }
+
+    /// A short hash of the IDs of the embedded files, as 16 hex characters.
+    ///
+    /// 64 bits keeps collisions between the chains of an archive unlikely even with millions of entries, where 32 bits
+    /// would collide within tens of thousands.
+    ///
+    pub(crate) fn short_hash(&self) -> String {
+        let ids = self.ids().collect::<Vec<_>>().join("/");
+        format!("{:x}", md5::compute(ids))[..16].to_string()
+    }
+}
+
+/// Makes a name or ID safe to use as a single component of a path.
+///
+/// Path separators and control characters are replaced by `_`, and so are names made of nothing but dots, like
+/// `..`, so a component can never refer to a parent directory.
+///
+pub(crate) fn sanitize_path_component(component: &str) -> String {
+    let sanitized: String = component.chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+
+    match sanitized.chars().all(|c| c == '.') {
+        true => "_".repeat(sanitized.len().max(1)),
+        false => sanitized,
+    }
+}
+
+impl EntryNaming {
+    /// Builds the path of an archive entry from the names and checksums of the embedded files leading to it.
+    ///
+    /// Returns [`None`] if the path depends on other entries and needs to be resolved with [`EntryNaming::resolve_paths`].
+    ///
+    pub(crate) fn entry_path(&self, chain: &IdChain, name: &str) -> Option<PathBuf> {
+        let mut path = PathBuf::new();
+        for (link_name, checksum) in chain.links() {
+            match self {
+                EntryNaming::Checksum => path.push(checksum),
+                EntryNaming::OriginalNameWithChecksumSuffix => path.push(format!("{}-{}", stem(link_name), checksum)),
+                EntryNaming::OriginalName => return None,
+            }
+        }
+        path.push(sanitize_path_component(name));
+        Some(path)
+    }
+
+    /// Builds the path of an archive entry at the root of the archive, for a flat layout.
+    ///
+    /// Entries of embedded files are prefixed with a short hash of the IDs of the files leading to them, like
+    /// `3f2a9c1e8b7d4a60-extracted.txt`, so entries with the same name produced from different files don't collide.
+    /// Entries of the original file keep their names.
+    ///
+    pub(crate) fn flat_entry_path(chain: &IdChain, name: &str) -> PathBuf {
+        let name = sanitize_path_component(name);
+        match chain.links().is_empty() {
+            true => PathBuf::from(name),
+            false => PathBuf::from(format!("{}-{}", chain.short_hash(), name)),
+        }
+    }
+
+    /// Builds the paths of archive entries named by [`EntryNaming::OriginalName`], given as pairs of their chain and name.
+    ///
+    /// Collisions are resolved using the full set of entries, so the paths don't depend on the order of the entries.
+    ///
+    pub(crate) fn resolve_paths(entries: &[(IdChain, String)]) -> Vec<PathBuf> {
+        // Parents sort before their children, so they're always resolved first
+        let links: BTreeSet<&[ChainLink]> = entries.iter()
+            .map(|(chain, _)| chain.links())
+            .flat_map(|links| (1..=links.len()).map(move |len| &links[..len]))
+            .collect();
+
+        let mut directories: BTreeMap<&[ChainLink], PathBuf> = BTreeMap::new();
+        let mut taken: HashMap<(PathBuf, String), usize> = HashMap::new();
+        for link in links {
+            let (link_name, _) = &link[link.len() - 1];
+            let parent = directories.get(&link[..link.len() - 1]).cloned().unwrap_or_default();
+
+            let stem = stem(link_name);
+            let count = taken.entry((parent.clone(), stem.clone())).or_insert(0);
+            let directory = match *count {
+                0 => stem,
+                n => format!("{} ({})", stem, n),
+            };
+            *count += 1;
+
+            directories.insert(link, parent.join(directory));
+        }
+
+        entries.iter()
+            .map(|(chain, name)| {
+                let directory = directories.get(chain.links()).cloned().unwrap_or_default();
+                directory.join(sanitize_path_component(name))
+            })
+            .collect()
+    }
+}
+
+/// The name without its extension, sanitized again, since stripping the extension of a name like `...txt` leaves `..`.
+///
+#[inline]
+fn stem(name: &str) -> String {
+    let stem = Path::new(name).file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or(name.to_string());
+    sanitize_path_component(&stem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(name: &str, checksum: &str) -> ChainLink {
+        (name.to_string(), checksum.to_string())
+    }
+
+    #[test]
+    fn test_output_name_templates() {
+        let templates = OutputNameTemplates {
+            text: Some("{stem}.txt".to_string()),
+            metadata: Some("{name}.metadata.json".to_string()),
+            pdf: None,
+            ocr: None,
+        };
+
+        assert_eq!(templates.resolve(&ProcessType::Text, Some("invoice.pdf"), "extracted.txt"), "invoice.txt");
+        assert_eq!(
+            templates.resolve(&ProcessType::Metadata, Some("invoice.pdf"), "metadata.json"),
+            "invoice.pdf.metadata.json",
+        );
+        assert_eq!(templates.resolve(&ProcessType::Pdf, Some("invoice.pdf"), "rendered.pdf"), "rendered.pdf");
+        assert_eq!(templates.resolve(&ProcessType::Text, None, "extracted.txt"), "extracted.txt");
+    }
+
+    /// Two different embedded files with the same name, each with an extracted text file.
+    ///
+    fn colliding_entries() -> Vec<(IdChain, String)> {
+        let first = link("invoice.pdf", "bbb");
+        let second = link("invoice.pdf", "aaa");
+        vec![
+            (IdChain::new([first.clone()]), "invoice.pdf".to_string()),
+            (IdChain::new([first]), "extracted.txt".to_string()),
+            (IdChain::new([second.clone()]), "invoice.pdf".to_string()),
+            (IdChain::new([second]), "extracted.txt".to_string()),
+        ]
+    }
+
+    fn paths(naming: EntryNaming) -> Vec<PathBuf> {
+        colliding_entries().iter()
+            .map(|(chain, name)| naming.entry_path(chain, name).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_archive_root_directory() {
+        let path = Path::new("/tmp/inbox/invoice.pdf");
+
+        assert_eq!(ArchiveRoot::InputStem.directory(path), "invoice");
+        assert_eq!(ArchiveRoot::Label("batch-7".to_string()).directory(path), "batch-7");
+        assert_eq!(ArchiveRoot::Label("../etc".to_string()).directory(path), ".._etc");
+    }
+
+    #[test]
+    fn test_flat_entry_path() {
+        let paths: Vec<PathBuf> = colliding_entries().iter()
+            .map(|(chain, name)| EntryNaming::flat_entry_path(chain, name))
+            .collect();
+
+        let bbb = IdChain::new([link("invoice.pdf", "bbb")]).short_hash();
+        let aaa = IdChain::new([link("invoice.pdf", "aaa")]).short_hash();
+        assert_ne!(aaa, bbb);
+        assert_eq!(aaa.len(), 16);
+        assert_eq!(paths, vec![
+            PathBuf::from(format!("{}-invoice.pdf", bbb)),
+            PathBuf::from(format!("{}-extracted.txt", bbb)),
+            PathBuf::from(format!("{}-invoice.pdf", aaa)),
+            PathBuf::from(format!("{}-extracted.txt", aaa)),
+        ]);
+        assert_eq!(EntryNaming::flat_entry_path(&IdChain::default(), "metadata.json"), PathBuf::from("metadata.json"));
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(paths(EntryNaming::Checksum), vec![
+            PathBuf::from("bbb/invoice.pdf"),
+            PathBuf::from("bbb/extracted.txt"),
+            PathBuf::from("aaa/invoice.pdf"),
+            PathBuf::from("aaa/extracted.txt"),
+        ]);
+    }
+
+    #[test]
+    fn test_original_name_with_checksum_suffix() {
+        assert_eq!(paths(EntryNaming::OriginalNameWithChecksumSuffix), vec![
+            PathBuf::from("invoice-bbb/invoice.pdf"),
+            PathBuf::from("invoice-bbb/extracted.txt"),
+            PathBuf::from("invoice-aaa/invoice.pdf"),
+            PathBuf::from("invoice-aaa/extracted.txt"),
+        ]);
+    }
+
+    #[test]
+    fn test_original_name() {
+        let entries = colliding_entries();
+        assert_eq!(EntryNaming::OriginalName.entry_path(&entries[0].0, &entries[0].1), None);
+
+        assert_eq!(EntryNaming::resolve_paths(&entries), vec![
+            PathBuf::from("invoice (1)/invoice.pdf"),
+            PathBuf::from("invoice (1)/extracted.txt"),
+            PathBuf::from("invoice/invoice.pdf"),
+            PathBuf::from("invoice/extracted.txt"),
+        ]);
+    }
+
+    #[test]
+    fn test_original_name_is_deterministic() {
+        let mut entries = colliding_entries();
+        let expected = EntryNaming::resolve_paths(&entries);
+
+        entries.reverse();
+        let mut paths = EntryNaming::resolve_paths(&entries);
+        paths.reverse();
+
+        assert_eq!(paths, expected);
+    }
+
+    #[test]
+    fn test_original_name_nested() {
+        let message = link("message.eml", "aaa");
+        let entries = vec![
+            (IdChain::new([message.clone()]), "message.eml".to_string()),
+            (IdChain::new([message.clone(), link("invoice.pdf", "bbb")]), "invoice.pdf".to_string()),
+            (IdChain::new([message, link("invoice.pdf", "ccc")]), "invoice.pdf".to_string()),
+        ];
+
+        assert_eq!(EntryNaming::resolve_paths(&entries), vec![
+            PathBuf::from("message/message.eml"),
+            PathBuf::from("message/invoice/invoice.pdf"),
+            PathBuf::from("message/invoice (1)/invoice.pdf"),
+        ]);
+    }
+
+    #[test]
+    fn test_traversal_is_neutralized() {
+        let chain = IdChain::new([link("../../etc/passwd.eml", ".."), link("..", "bbb")]);
+
+        for naming in [EntryNaming::Checksum, EntryNaming::OriginalNameWithChecksumSuffix] {
+            let path = naming.entry_path(&chain, "../escape.txt").unwrap();
+            assert!(path.components().all(|component| matches!(component, std::path::Component::Normal(_))));
+        }
+        assert_eq!(
+            EntryNaming::Checksum.entry_path(&chain, "../escape.txt").unwrap(),
+            PathBuf::from("__/bbb/.._escape.txt"),
+        );
+        assert_eq!(
+            EntryNaming::resolve_paths(&[(chain, "..".to_string())]),
+            vec![PathBuf::from(".._.._etc_passwd/__/__")],
+        );
+    }
+
+    #[test]
+    fn test_dotted_stems_stay_under_root() {
+        for name in ["...txt", "..pdf", "..."] {
+            let chain = IdChain::new([link("message.eml", "aaa"), link(name, "bbb")]);
+            let entries = vec![(chain.clone(), "extracted.txt".to_string())];
+
+            let mut paths = EntryNaming::resolve_paths(&entries);
+            paths.push(EntryNaming::OriginalNameWithChecksumSuffix.entry_path(&chain, "extracted.txt").unwrap());
+            for path in paths {
+                assert!(
+                    path.components().all(|component| matches!(component, std::path::Component::Normal(_))),
+                    "{:?} escapes the root for {:?}", path, name,
+                );
+                assert_eq!(path.components().count(), 3, "{:?} isn't nested under its parent for {:?}", path, name);
+            }
+        }
+    }
+}
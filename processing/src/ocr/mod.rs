@@ -0,0 +1,152 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use tempfile::TempPath;
+
+use services::tesseract;
+
+use crate::processing::{Process, ProcessContext, ProcessOutput, ProcessType};
+
+/// Recognizes the text of a single image with tesseract.
+///
+async fn recognize_image(image: impl tokio::io::AsyncRead + Unpin) -> anyhow::Result<Vec<u8>> {
+    let mut text = vec![];
+    let output = tesseract().run(image, &mut text).await
+        .context("tesseract failed to recognize the text")?;
+
+    if !output.exit_status.success() {
+        Err(anyhow!("tesseract exited with status {}: {}", output.exit_status, output.error))?;
+    }
+    Ok(text)
+}
+
+/// Writes the recognized text as the OCR output of the file.
+///
+async fn add_ocr_output(ctx: &ProcessContext, text: Vec<u8>, output_path: TempPath, checksum: &str) -> anyhow::Result<()> {
+    tokio::fs::write(&output_path, text).await?;
+
+    let name = ctx.output_name(ProcessType::Ocr, "ocr.txt");
+    let output = ProcessOutput::processed(ctx, name, output_path, "text/plain", checksum);
+    ctx.add_output(Ok(output)).await
+}
+
+/// OCR processor for images, recognizing their text with tesseract.
+///
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ImageOcrProcessor;
+
+#[async_trait]
+impl Process for ImageOcrProcessor {
+    async fn process(
+        &self,
+        ctx: ProcessContext,
+        input_path: &Path,
+        output_path: TempPath,
+        checksum: &str,
+    ) -> anyhow::Result<()> {
+        let text = recognize_image(tokio::fs::File::open(input_path).await?).await?;
+        add_ocr_output(&ctx, text, output_path, checksum).await
+    }
+
+    fn name(&self) -> &'static str {
+        "Image OCR"
+    }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "text/plain"
+    }
+}
+
+/// OCR processor for PDFs, rendering each page to an image with ghostscript and recognizing its text with tesseract.
+///
+/// The text of the pages is concatenated in order, each page ending with a form feed, as output by tesseract.
+///
+#[cfg(feature = "pdf")]
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PdfOcrProcessor;
+
+#[cfg(feature = "pdf")]
+impl PdfOcrProcessor {
+    async fn render_page(&self, input_path: &Path, page: u32) -> anyhow::Result<Vec<u8>> {
+        let mut image = vec![];
+        let input = tokio::fs::File::open(input_path).await?;
+        let output = services::pdf_to_image().run_page(input, &mut image, page, services::DEFAULT_DPI).await
+            .with_context(|| format!("ghostscript failed to render page {}", page))?;
+
+        if !output.exit_status.success() {
+            Err(anyhow!("ghostscript exited with status {}: {}", output.exit_status, output.error))?;
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(feature = "pdf")]
+#[async_trait]
+impl Process for PdfOcrProcessor {
+    async fn process(
+        &self,
+        ctx: ProcessContext,
+        input_path: &Path,
+        output_path: TempPath,
+        checksum: &str,
+    ) -> anyhow::Result<()> {
+        // Loading the document parses all of it, so it's done on a blocking thread
+        let path = input_path.to_path_buf();
+        let page_count = tokio::task::spawn_blocking(move || lopdf::Document::load(path)).await??
+            .get_pages().len() as u32;
+        log::info!("Recognizing the text of {} PDF pages", page_count);
+
+        let mut text = vec![];
+        for page in 1..=page_count {
+            let image = self.render_page(input_path, page).await?;
+            text.extend(recognize_image(image.as_slice()).await?);
+        }
+        add_ocr_output(&ctx, text, output_path, checksum).await
+    }
+
+    fn name(&self) -> &'static str {
+        "PDF OCR"
+    }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "text/plain"
+    }
+}
+
+#[cfg(all(test, feature = "pdf"))]
+mod tests {
+    use std::path::PathBuf;
+
+    use test_utils::temp_path;
+
+    use crate::processing::ProcessContextBuilder;
+    use crate::text::DefaultTextProcessor;
+
+    use super::*;
+
+    async fn processed_text(processor: impl Process, path: &Path) -> anyhow::Result<(String, String)> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/pdf", vec![], output_sink).build();
+
+        processor.process(ctx, path, temp_path()?, "checksum").await?;
+
+        let Some(Ok(ProcessOutput::Processed(_, data))) = outputs.recv().await else {
+            panic!("expected processed output");
+        };
+        Ok((data.name, std::fs::read_to_string(&data.path)?))
+    }
+
+    #[tokio::test]
+    async fn test_process_scanned_pdf() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/pdf/scanned.pdf");
+
+        let (name, ocr_text) = processed_text(PdfOcrProcessor, &path).await?;
+        let (_, native_text) = processed_text(DefaultTextProcessor, &path).await?;
+
+        assert_eq!(name, "ocr.txt");
+        assert!(ocr_text.to_uppercase().contains("HELLO WORLD"), "unexpected OCR text: {}", ocr_text);
+        assert_eq!(native_text.trim(), "");
+        Ok(())
+    }
+}
@@ -0,0 +1,736 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use identify::deduplication::DedupeStrategy;
+use services::{config, CompressionPolicy};
+
+use crate::naming::{ArchiveRoot, EntryNaming, OutputNameTemplates};
+use crate::processing::{LineEnding, MetadataFormat, ProcessOutput, ProcessType};
+
+/// The maximum number of embedded files processed concurrently when recursing, unless configured otherwise.
+///
+const DEFAULT_MAX_CONCURRENT_RECURSIONS: usize = 64;
+
+/// The capacity of each queue between the stages of processing, unless configured otherwise.
+///
+const DEFAULT_CHANNEL_CAPACITY: usize = 100;
+
+/// A function run on an output of the processing pipeline.
+///
+pub type PostProcessFn = dyn Fn(&mut ProcessOutput) -> anyhow::Result<()> + Send + Sync;
+
+/// A hook run on each output of the processing pipeline before it's turned into an archive entry.
+///
+/// The hook may modify the output, such as renaming it. If it returns an error, the output is left out of the archive
+/// and counted as skipped.
+///
+/// Two hooks are equal only if they're the same function.
+///
+#[derive(Clone)]
+pub struct PostProcessHook(Arc<PostProcessFn>);
+
+impl PostProcessHook {
+    /// Creates a new PostProcessHook running the given function.
+    ///
+    pub fn new(hook: impl Fn(&mut ProcessOutput) -> anyhow::Result<()> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    /// Runs the hook on the output.
+    ///
+    pub fn run(&self, output: &mut ProcessOutput) -> anyhow::Result<()> {
+        (self.0)(output)
+    }
+}
+
+impl Debug for PostProcessHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PostProcessHook")
+    }
+}
+
+impl PartialEq for PostProcessHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Options configuring the whole processing pipeline.
+///
+/// Built with [`ProcessOptionsBuilder`].
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessOptions {
+    /// The MIME type of the file to process.
+    ///
+    pub mimetype: String,
+
+    /// The types of output to generate.
+    ///
+    pub types: Vec<ProcessType>,
+
+    /// Whether to process embedded files recursively.
+    ///
+    pub recurse: bool,
+
+    /// The maximum depth of embedded files to process when recursing, if any.
+    ///
+    /// Files directly embedded in the original file are at depth 1. Embedded files deeper than this are still
+    /// added to the archive, but not processed any further.
+    ///
+    pub max_depth: Option<usize>,
+
+    /// The maximum number of embedded files processed concurrently when recursing.
+    ///
+    /// Beyond it, an embedded file is processed by the consumer of the outputs of the file it's embedded in, which
+    /// takes no further outputs until it's done. Defaults to `MAX_CONCURRENT_RECURSIONS` if it's set to a positive
+    /// number, or 64 otherwise.
+    ///
+    pub max_concurrent_recursions: usize,
+
+    /// The capacity of each queue between the stages of processing: from the processors of each file, including
+    /// each embedded file processed recursively, to the output handling, and from it to the archive.
+    ///
+    /// Once a queue is full, the stage sending to it waits for the next stage to catch up, so a smaller capacity
+    /// holds fewer outputs in flight when the archive is consumed slowly. Embedded files waiting to be processed
+    /// recursively are held back the same way, so at most one queue's worth of outputs is in flight for each file
+    /// being processed. Defaults to `CHANNEL_CAPACITY` if it's set to a positive number, or 100 otherwise.
+    ///
+    pub channel_capacity: usize,
+
+    /// The maximum number of outputs of processing, counting both processed and embedded files at any depth, if any.
+    ///
+    /// Once reached, further outputs are left out of the archive and embedded files aren't processed any further,
+    /// guarding against inputs fanning out into an unbounded number of files. See `ProcessSummary.outputs_truncated`.
+    ///
+    pub max_total_outputs: Option<usize>,
+
+    /// The maximum size of the input in bytes, if any; larger inputs are rejected.
+    ///
+    pub max_input_bytes: Option<u64>,
+
+    /// The maximum size in bytes of each file produced by a processor, if any; larger text is truncated, and other
+    /// larger files are failed.
+    ///
+    pub max_output_bytes: Option<u64>,
+
+    /// Whether to detect the language of each file's text and include it in its metadata.
+    ///
+    pub detect_language: bool,
+
+    /// The MIME types of embedded files to keep when recursing, if any.
+    ///
+    pub mimetype_allowlist: Option<Vec<String>>,
+
+    /// Whether embedded files not in the `mimetype_allowlist` are kept as unprocessed outputs or dropped.
+    ///
+    pub keep_filtered: bool,
+
+    /// Whether system and junk files in archives, like `__MACOSX/` entries, `.DS_Store` and Office lock files, are
+    /// extracted rather than skipped.
+    ///
+    pub keep_junk_files: bool,
+
+    /// Whether messages in an mbox are written out with their exact original bytes, rather than as parsed, where
+    /// quoted `>From ` lines are unquoted.
+    ///
+    pub raw_mbox_messages: bool,
+
+    /// Whether the extracted text is gzip compressed, as `extracted.txt.gz`.
+    ///
+    pub compress_text: bool,
+
+    /// The line ending to normalize the extracted text to, if any; by default, the text keeps the line endings it
+    /// was extracted with.
+    ///
+    pub line_ending: Option<LineEnding>,
+
+    /// Whether embedded files are added to the archive, or only the outputs produced from them when recursing.
+    ///
+    pub keep_embedded: bool,
+
+    /// Whether the original file, and the original of each embedded file when recursing, is added to the archive
+    /// alongside its outputs, as `original.<ext>`.
+    ///
+    pub include_original: bool,
+
+    /// The number of characters of the extracted text of each file to include in its metadata as `rusty.preview`, if
+    /// any.
+    ///
+    pub preview_chars: Option<usize>,
+
+    /// The names of the headers to include in rendered messages, if any; by default, the `Date`, `Subject`, and
+    /// address headers are included.
+    ///
+    pub message_headers: Option<Vec<String>>,
+
+    /// The names of the headers never to include in rendered messages, like `BCC`; a name ending with `*` suppresses
+    /// every header starting with it.
+    ///
+    pub suppressed_headers: Vec<String>,
+
+    /// Whether PDF and image attachments of rendered messages are appended to the rendered PDF as additional pages.
+    ///
+    pub append_pdf_attachments: bool,
+
+    /// How to name the entries of the archive.
+    ///
+    pub entry_naming: EntryNaming,
+
+    /// Whether all entries are put at the root of the archive, rather than nested under directories of the embedded
+    /// files leading to them.
+    ///
+    /// Entries of embedded files are then prefixed with a short hash of the IDs of the files leading to them, so their
+    /// names stay unique; `entry_naming` and `id_chain` don't affect their paths.
+    ///
+    pub flatten: bool,
+
+    /// The directory all entries of the archive are nested under, named after the input file or by a label, if any.
+    ///
+    /// It's the outermost directory of the archive, above the directories named by `id_chain`; see [`ArchiveRoot`].
+    ///
+    pub archive_root: Option<ArchiveRoot>,
+
+    /// The templates for the names of the files produced from each file, like `{stem}.txt`.
+    ///
+    /// See [`OutputNameTemplates`] for more information.
+    ///
+    pub output_names: OutputNameTemplates,
+
+    /// The format metadata is written in, JSON by default.
+    ///
+    /// The metadata files are named with the extension of the format, like `metadata.yaml`.
+    ///
+    pub metadata_format: MetadataFormat,
+
+    /// The IDs of the parents of the file in an external system, if the file is logically nested under them.
+    ///
+    /// This seeds `ProcessState.id_chain` of all outputs, and the archive entries are nested under directories
+    /// named by these IDs.
+    ///
+    pub id_chain: Vec<String>,
+
+    /// Whether the MIME types of embedded files declared with a generic type, like `application/octet-stream`, are
+    /// detected from their contents before they're processed any further.
+    ///
+    pub redetect_generic_mimetypes: bool,
+
+    /// Whether the MIME type sniffed from the file's contents is trusted over the declared one, when they confidently
+    /// disagree.
+    ///
+    /// A file declared as `text/plain` that's actually a zip is then processed as a zip, with the discrepancy logged.
+    ///
+    pub trust_content: bool,
+
+    /// Whether archive entries are staged in a directory concurrently as they're received, and only zipped once
+    /// processing finishes, rather than zipped one at a time as they're received.
+    ///
+    pub stage_archive_entries: bool,
+
+    /// Whether computing the deduplication checksums of files is skipped, for when deduplication isn't needed.
+    ///
+    /// Random placeholder IDs are used in place of the checksums, so archive entries named by checksums get
+    /// different paths each time the file is processed.
+    ///
+    pub skip_checksum: bool,
+
+    /// The dedupe strategies to use for files of the MIME types, in place of the ones chosen for them by default.
+    ///
+    /// For instance, messages can be deduplicated by their content rather than by their `Message-ID`.
+    ///
+    pub dedupe_strategies: HashMap<String, DedupeStrategy>,
+
+    /// How the entries of the archive are compressed.
+    ///
+    /// By default, entries are compressed according to their MIME type; see [`CompressionPolicy::ByMimetype`].
+    ///
+    pub compression: CompressionPolicy,
+
+    /// Whether the archive is built deterministically, so the same input always produces an identical archive byte
+    /// for byte.
+    ///
+    /// Entries are then written once processing finishes, sorted by the IDs of the files leading to them and their
+    /// names, and with a fixed modification time. Entries aren't staged in this mode.
+    ///
+    pub deterministic_archive: bool,
+
+    /// A hook to run on each output before it's turned into an archive entry, if any.
+    ///
+    /// Outputs are passed to the hook one at a time, in the order they're received from processing, after the MIME
+    /// types of embedded files are redetected and files filtered out by the `mimetype_allowlist` are dropped. An
+    /// embedded file is passed to the hook before it's processed recursively, so the outputs of its own processing
+    /// are always passed to the hook after it. No ordering is guaranteed between outputs of separate files.
+    ///
+    pub post_process: Option<PostProcessHook>,
+
+    /// Whether the output files are kept in a debug directory after processing, rather than deleted once they've been
+    /// added to the archive.
+    ///
+    /// The directory is reported in `ProcessSummary.kept_temp_dir`, and each kept file is logged. Defaults to whether
+    /// `RUSTY_KEEP_TEMP` is set to `1` or `true`.
+    ///
+    pub keep_temp: bool,
+}
+
+impl ProcessOptions {
+    /// The maximum depth of embedded files to process, taking `recurse` into account.
+    ///
+    /// Returns [`None`] if there's no limit.
+    ///
+    pub(crate) fn recursion_depth(&self) -> Option<usize> {
+        match self.recurse {
+            true => self.max_depth,
+            false => Some(0),
+        }
+    }
+}
+
+/// Builder for ProcessOptions.
+///
+#[derive(Debug, Clone)]
+pub struct ProcessOptionsBuilder {
+    mimetype: String,
+    types: Vec<ProcessType>,
+    recurse: bool,
+    max_depth: Option<usize>,
+    max_concurrent_recursions: usize,
+    channel_capacity: usize,
+    max_total_outputs: Option<usize>,
+    max_input_bytes: Option<u64>,
+    max_output_bytes: Option<u64>,
+    detect_language: bool,
+    mimetype_allowlist: Option<Vec<String>>,
+    keep_filtered: bool,
+    keep_junk_files: bool,
+    raw_mbox_messages: bool,
+    compress_text: bool,
+    line_ending: Option<LineEnding>,
+    keep_embedded: bool,
+    include_original: bool,
+    preview_chars: Option<usize>,
+    message_headers: Option<Vec<String>>,
+    suppressed_headers: Vec<String>,
+    append_pdf_attachments: bool,
+    entry_naming: EntryNaming,
+    flatten: bool,
+    archive_root: Option<ArchiveRoot>,
+    output_names: OutputNameTemplates,
+    metadata_format: MetadataFormat,
+    id_chain: Vec<String>,
+    redetect_generic_mimetypes: bool,
+    trust_content: bool,
+    stage_archive_entries: bool,
+    skip_checksum: bool,
+    dedupe_strategies: HashMap<String, DedupeStrategy>,
+    compression: CompressionPolicy,
+    deterministic_archive: bool,
+    post_process: Option<PostProcessHook>,
+    keep_temp: bool,
+}
+
+impl ProcessOptionsBuilder {
+    /// Creates a new ProcessOptionsBuilder for a file with the given MIME type.
+    ///
+    /// By default, all types of output are generated and embedded files are processed recursively without limits.
+    ///
+    pub fn new(mimetype: impl Into<String>) -> Self {
+        ProcessOptionsBuilder {
+            mimetype: mimetype.into(),
+            types: ProcessType::all().to_vec(),
+            recurse: true,
+            max_depth: None,
+            max_concurrent_recursions: config().get("MAX_CONCURRENT_RECURSIONS")
+                .and_then(|max| max.parse().ok())
+                .filter(|max| *max > 0)
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_RECURSIONS),
+            channel_capacity: config().get("CHANNEL_CAPACITY")
+                .and_then(|capacity| capacity.parse().ok())
+                .filter(|capacity| *capacity > 0)
+                .unwrap_or(DEFAULT_CHANNEL_CAPACITY),
+            max_total_outputs: None,
+            max_input_bytes: None,
+            max_output_bytes: None,
+            detect_language: false,
+            mimetype_allowlist: None,
+            keep_filtered: false,
+            keep_junk_files: false,
+            raw_mbox_messages: false,
+            compress_text: false,
+            line_ending: None,
+            keep_embedded: true,
+            include_original: false,
+            preview_chars: None,
+            message_headers: None,
+            suppressed_headers: Vec::new(),
+            append_pdf_attachments: false,
+            entry_naming: EntryNaming::default(),
+            flatten: false,
+            archive_root: None,
+            output_names: OutputNameTemplates::default(),
+            metadata_format: MetadataFormat::default(),
+            id_chain: Vec::new(),
+            redetect_generic_mimetypes: false,
+            trust_content: false,
+            stage_archive_entries: false,
+            skip_checksum: false,
+            dedupe_strategies: HashMap::new(),
+            compression: CompressionPolicy::default(),
+            deterministic_archive: false,
+            post_process: None,
+            keep_temp: config().get("RUSTY_KEEP_TEMP").is_some_and(|value| value == "1" || value == "true"),
+        }
+    }
+
+    /// Sets the types of output to generate.
+    ///
+    pub fn types(mut self, types: Vec<ProcessType>) -> Self {
+        self.types = types;
+        self
+    }
+
+    /// Sets whether to process embedded files recursively.
+    ///
+    pub fn recurse(mut self, recurse: bool) -> Self {
+        self.recurse = recurse;
+        self
+    }
+
+    /// Sets the maximum depth of embedded files to process.
+    ///
+    /// See `ProcessOptions.max_depth` for more information.
+    ///
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the maximum number of embedded files processed concurrently when recursing.
+    ///
+    /// See `ProcessOptions.max_concurrent_recursions` for more information.
+    ///
+    pub fn max_concurrent_recursions(mut self, max_concurrent_recursions: usize) -> Self {
+        self.max_concurrent_recursions = max_concurrent_recursions.max(1);
+        self
+    }
+
+    /// Sets the capacity of each queue between the stages of processing.
+    ///
+    /// See `ProcessOptions.channel_capacity` for more information.
+    ///
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity.max(1);
+        self
+    }
+
+    /// Sets the maximum number of outputs of processing.
+    ///
+    /// See `ProcessOptions.max_total_outputs` for more information.
+    ///
+    pub fn max_total_outputs(mut self, max_total_outputs: Option<usize>) -> Self {
+        self.max_total_outputs = max_total_outputs;
+        self
+    }
+
+    /// Sets the maximum size of the input in bytes.
+    ///
+    pub fn max_input_bytes(mut self, max_input_bytes: Option<u64>) -> Self {
+        self.max_input_bytes = max_input_bytes;
+        self
+    }
+
+    /// Sets the maximum size in bytes of each file produced by a processor.
+    ///
+    /// See `ProcessOptions.max_output_bytes` for more information.
+    ///
+    pub fn max_output_bytes(mut self, max_output_bytes: Option<u64>) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
+    }
+
+    /// Sets whether to detect the language of each file's text.
+    ///
+    /// See `ProcessOptions.detect_language` for more information.
+    ///
+    pub fn detect_language(mut self, detect_language: bool) -> Self {
+        self.detect_language = detect_language;
+        self
+    }
+
+    /// Sets the MIME types of embedded files to keep when recursing.
+    ///
+    pub fn mimetype_allowlist(mut self, mimetype_allowlist: Option<Vec<String>>) -> Self {
+        self.mimetype_allowlist = mimetype_allowlist;
+        self
+    }
+
+    /// Sets whether embedded files filtered out by the MIME type allowlist are kept.
+    ///
+    pub fn keep_filtered(mut self, keep_filtered: bool) -> Self {
+        self.keep_filtered = keep_filtered;
+        self
+    }
+
+    /// Sets whether system and junk files in archives are extracted.
+    ///
+    /// See `ProcessOptions.keep_junk_files` for more information.
+    ///
+    pub fn keep_junk_files(mut self, keep_junk_files: bool) -> Self {
+        self.keep_junk_files = keep_junk_files;
+        self
+    }
+
+    /// Sets whether messages in an mbox are written out with their exact original bytes.
+    ///
+    /// See `ProcessOptions.raw_mbox_messages` for more information.
+    ///
+    pub fn raw_mbox_messages(mut self, raw_mbox_messages: bool) -> Self {
+        self.raw_mbox_messages = raw_mbox_messages;
+        self
+    }
+
+    /// Sets whether the extracted text is gzip compressed.
+    ///
+    /// See `ProcessOptions.compress_text` for more information.
+    ///
+    pub fn compress_text(mut self, compress_text: bool) -> Self {
+        self.compress_text = compress_text;
+        self
+    }
+
+    /// Sets the line ending to normalize the extracted text to.
+    ///
+    /// See `ProcessOptions.line_ending` for more information.
+    ///
+    pub fn line_ending(mut self, line_ending: Option<LineEnding>) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Sets whether embedded files are added to the archive.
+    ///
+    /// See `ProcessOptions.keep_embedded` for more information.
+    ///
+    pub fn keep_embedded(mut self, keep_embedded: bool) -> Self {
+        self.keep_embedded = keep_embedded;
+        self
+    }
+
+    /// Sets whether the original files are added to the archive.
+    ///
+    /// See `ProcessOptions.include_original` for more information.
+    ///
+    pub fn include_original(mut self, include_original: bool) -> Self {
+        self.include_original = include_original;
+        self
+    }
+
+    /// Sets the number of characters of the extracted text to include in the metadata as a preview.
+    ///
+    pub fn preview_chars(mut self, preview_chars: Option<usize>) -> Self {
+        self.preview_chars = preview_chars;
+        self
+    }
+
+    /// Sets the names of the headers to include in rendered messages.
+    ///
+    /// See `ProcessOptions.message_headers` for more information.
+    ///
+    pub fn message_headers(mut self, message_headers: Option<Vec<String>>) -> Self {
+        self.message_headers = message_headers;
+        self
+    }
+
+    /// Sets the names of the headers never to include in rendered messages.
+    ///
+    /// See `ProcessOptions.suppressed_headers` for more information.
+    ///
+    pub fn suppressed_headers(mut self, suppressed_headers: Vec<String>) -> Self {
+        self.suppressed_headers = suppressed_headers;
+        self
+    }
+
+    /// Sets whether attachments of rendered messages are appended to the rendered PDF.
+    ///
+    /// See `ProcessOptions.append_pdf_attachments` for more information.
+    ///
+    pub fn append_pdf_attachments(mut self, append_pdf_attachments: bool) -> Self {
+        self.append_pdf_attachments = append_pdf_attachments;
+        self
+    }
+
+    /// Sets how to name the entries of the archive.
+    ///
+    pub fn entry_naming(mut self, entry_naming: EntryNaming) -> Self {
+        self.entry_naming = entry_naming;
+        self
+    }
+
+    /// Sets whether all entries are put at the root of the archive.
+    ///
+    /// See `ProcessOptions.flatten` for more information.
+    ///
+    pub fn flatten(mut self, flatten: bool) -> Self {
+        self.flatten = flatten;
+        self
+    }
+
+    /// Sets the directory all entries of the archive are nested under.
+    ///
+    /// See `ProcessOptions.archive_root` for more information.
+    ///
+    pub fn archive_root(mut self, archive_root: Option<ArchiveRoot>) -> Self {
+        self.archive_root = archive_root;
+        self
+    }
+
+    /// Sets the templates for the names of the files produced from each file.
+    ///
+    /// See `ProcessOptions.output_names` for more information.
+    ///
+    pub fn output_names(mut self, output_names: OutputNameTemplates) -> Self {
+        self.output_names = output_names;
+        self
+    }
+
+    /// Sets the format metadata is written in.
+    ///
+    /// See `ProcessOptions.metadata_format` for more information.
+    ///
+    pub fn metadata_format(mut self, metadata_format: MetadataFormat) -> Self {
+        self.metadata_format = metadata_format;
+        self
+    }
+
+    /// Sets the IDs of the parents of the file in an external system.
+    ///
+    /// See `ProcessOptions.id_chain` for more information.
+    ///
+    pub fn id_chain(mut self, id_chain: Vec<String>) -> Self {
+        self.id_chain = id_chain;
+        self
+    }
+
+    /// Sets whether the MIME types of embedded files declared with a generic type are detected from their contents.
+    ///
+    /// See `ProcessOptions.redetect_generic_mimetypes` for more information.
+    ///
+    pub fn redetect_generic_mimetypes(mut self, redetect_generic_mimetypes: bool) -> Self {
+        self.redetect_generic_mimetypes = redetect_generic_mimetypes;
+        self
+    }
+
+    /// Sets whether the MIME type sniffed from the file's contents is trusted over the declared one.
+    ///
+    /// See `ProcessOptions.trust_content` for more information.
+    ///
+    pub fn trust_content(mut self, trust_content: bool) -> Self {
+        self.trust_content = trust_content;
+        self
+    }
+
+    /// Sets whether archive entries are staged in a directory before being zipped.
+    ///
+    /// See `ProcessOptions.stage_archive_entries` for more information.
+    ///
+    pub fn stage_archive_entries(mut self, stage_archive_entries: bool) -> Self {
+        self.stage_archive_entries = stage_archive_entries;
+        self
+    }
+
+    /// Sets whether computing the deduplication checksums of files is skipped.
+    ///
+    /// See `ProcessOptions.skip_checksum` for more information.
+    ///
+    pub fn skip_checksum(mut self, skip_checksum: bool) -> Self {
+        self.skip_checksum = skip_checksum;
+        self
+    }
+
+    /// Sets the dedupe strategies to use for files of the MIME types.
+    ///
+    /// See `ProcessOptions.dedupe_strategies` for more information.
+    ///
+    pub fn dedupe_strategies(mut self, dedupe_strategies: HashMap<String, DedupeStrategy>) -> Self {
+        self.dedupe_strategies = dedupe_strategies;
+        self
+    }
+
+    /// Sets how the entries of the archive are compressed.
+    ///
+    pub fn compression(mut self, compression: CompressionPolicy) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets whether the archive is built deterministically.
+    ///
+    /// See `ProcessOptions.deterministic_archive` for more information.
+    ///
+    pub fn deterministic_archive(mut self, deterministic_archive: bool) -> Self {
+        self.deterministic_archive = deterministic_archive;
+        self
+    }
+
+    /// Sets the hook to run on each output before it's turned into an archive entry.
+    ///
+    /// See `ProcessOptions.post_process` for more information.
+    ///
+    pub fn post_process(mut self, post_process: Option<PostProcessHook>) -> Self {
+        self.post_process = post_process;
+        self
+    }
+
+    /// Sets whether the output files are kept in a debug directory after processing.
+    ///
+    /// See `ProcessOptions.keep_temp` for more information.
+    ///
+    pub fn keep_temp(mut self, keep_temp: bool) -> Self {
+        self.keep_temp = keep_temp;
+        self
+    }
+
+    /// Build the ProcessOptions.
+    ///
+    pub fn build(self) -> ProcessOptions {
+        ProcessOptions {
+            mimetype: self.mimetype,
+            types: self.types,
+            recurse: self.recurse,
+            max_depth: self.max_depth,
+            max_concurrent_recursions: self.max_concurrent_recursions,
+            channel_capacity: self.channel_capacity,
+            max_total_outputs: self.max_total_outputs,
+            max_input_bytes: self.max_input_bytes,
+            max_output_bytes: self.max_output_bytes,
+            detect_language: self.detect_language,
+            mimetype_allowlist: self.mimetype_allowlist,
+            keep_filtered: self.keep_filtered,
+            keep_junk_files: self.keep_junk_files,
+            raw_mbox_messages: self.raw_mbox_messages,
+            compress_text: self.compress_text,
+            line_ending: self.line_ending,
+            keep_embedded: self.keep_embedded,
+            include_original: self.include_original,
+            preview_chars: self.preview_chars,
+            message_headers: self.message_headers,
+            suppressed_headers: self.suppressed_headers,
+            append_pdf_attachments: self.append_pdf_attachments,
+            entry_naming: self.entry_naming,
+            flatten: self.flatten,
+            archive_root: self.archive_root,
+            output_names: self.output_names,
+            metadata_format: self.metadata_format,
+            id_chain: self.id_chain,
+            redetect_generic_mimetypes: self.redetect_generic_mimetypes,
+            trust_content: self.trust_content,
+            stage_archive_entries: self.stage_archive_entries,
+            skip_checksum: self.skip_checksum,
+            dedupe_strategies: self.dedupe_strategies,
+            compression: self.compression,
+            deterministic_archive: self.deterministic_archive,
+            post_process: self.post_process,
+            keep_temp: self.keep_temp,
+        }
+    }
+}
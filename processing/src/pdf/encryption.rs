@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use log::warn;
+use lopdf::encryption::DecryptionError;
+use lopdf::Document;
+
+/// Checks that the PDF at `path` can be opened, either because it isn't encrypted or it's encrypted with an empty
+/// password or the given `password`.
+///
+/// PDFs that can't be read, or are encrypted with a scheme that can't be checked, are assumed to be openable.
+///
+/// The document is loaded on a blocking thread, as parsing a large PDF would otherwise stall the runtime.
+///
+pub(crate) async fn check_encryption(path: &Path, password: Option<String>) -> anyhow::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || match Document::load(path) {
+        Ok(mut document) => decrypt(&mut document, password.as_deref()),
+        Err(_) => Ok(()),
+    }).await?
+}
+
+/// Decrypts the document if it's encrypted, first with an empty password and then with the given `password`.
+///
+/// Fails with a descriptive error if the document is encrypted and neither password opens it.
+///
+pub(crate) fn decrypt(document: &mut Document, password: Option<&str>) -> anyhow::Result<()> {
+    if !document.is_encrypted() {
+        return Ok(());
+    }
+
+    // Encrypted PDFs that only restrict permissions can be opened with an empty password
+    for candidate in [Some(""), password].into_iter().flatten() {
+        match document.decrypt(candidate) {
+            Ok(()) => return Ok(()),
+            Err(lopdf::Error::Decryption(DecryptionError::IncorrectPassword)) => {},
+            Err(err) => {
+                warn!("Unable to check the encryption of the PDF: {}", err);
+                return Ok(());
+            },
+        }
+    }
+
+    match password {
+        Some(_) => Err(anyhow!("PDF is encrypted and the configured password is incorrect")),
+        None => Err(anyhow!("PDF is encrypted and no password is configured")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCRYPTED_PDF: &str = "../resources/pdf/encrypted.pdf";
+
+    #[tokio::test]
+    async fn test_check_encryption_without_password() {
+        let result = check_encryption(Path::new(ENCRYPTED_PDF), None).await;
+
+        assert_eq!(result.unwrap_err().to_string(), "PDF is encrypted and no password is configured");
+    }
+
+    #[tokio::test]
+    async fn test_check_encryption_incorrect_password() {
+        let result = check_encryption(Path::new(ENCRYPTED_PDF), Some("guess".to_string())).await;
+
+        assert_eq!(result.unwrap_err().to_string(), "PDF is encrypted and the configured password is incorrect");
+    }
+
+    #[test]
+    fn test_decrypt() -> anyhow::Result<()> {
+        let mut document = Document::load(ENCRYPTED_PDF)?;
+        decrypt(&mut document, Some("user-secret"))?;
+
+        let content = document.get_page_content(document.page_iter().next().unwrap())?;
+        assert!(String::from_utf8_lossy(&content).contains("This document is encrypted."));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_encryption_unencrypted() {
+        assert!(check_encryption(Path::new("../resources/pdf/zugferd-invoice.pdf"), None).await.is_ok());
+    }
+}
@@ -1,3 +1,6 @@
+pub(crate) mod encryption;
+#[cfg(feature = "pdf")]
 mod rfc822;
 
-pub use rfc822::*;
\ No newline at end of file
+#[cfg(feature = "pdf")]
+pub use rfc822::*;
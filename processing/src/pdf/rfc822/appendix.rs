@@ -0,0 +1,194 @@
+use anyhow::anyhow;
+use image::GenericImageView;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+/// Page attributes that a page inherits from its ancestors in the page tree if it doesn't define them itself.
+///
+const INHERITABLE_ATTRIBUTES: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Appends the pages of the appendix to the end of the document.
+///
+/// The appendix's objects are renumbered to follow the document's objects, and its pages are moved
+/// under the document's root page tree node. Outlines and other catalog entries of the appendix are dropped.
+///
+pub(crate) fn append_document(document: &mut Document, mut appendix: Document) -> anyhow::Result<()> {
+    let pages_id = document.catalog()?.get(b"Pages")?.as_reference()?;
+
+    appendix.renumber_objects_with(document.max_id + 1);
+    let page_ids: Vec<ObjectId> = appendix.get_pages().into_values().collect();
+    for page_id in &page_ids {
+        let mut page = inherited_page(&appendix, *page_id)?;
+        page.set("Parent", pages_id);
+        appendix.objects.insert(*page_id, Object::Dictionary(page));
+    }
+
+    for (id, object) in appendix.objects {
+        match object.type_name().unwrap_or("") {
+            "Catalog" | "Pages" | "Outlines" | "Outline" => {},
+            _ => { document.objects.insert(id, object); },
+        }
+    }
+    document.max_id = document.max_id.max(appendix.max_id);
+
+    let pages = document.get_object_mut(pages_id)?.as_dict_mut()?;
+    let count = pages.get(b"Count")?.as_i64()?;
+    let mut kids = pages.get(b"Kids")?.as_array()?.clone();
+    kids.extend(page_ids.iter().map(|id| Object::Reference(*id)));
+    pages.set("Kids", kids);
+    pages.set("Count", count + page_ids.len() as i64);
+
+    Ok(())
+}
+
+/// Returns the dictionary of the page with the attributes it inherits from its ancestors set on it directly.
+///
+fn inherited_page(document: &Document, page_id: ObjectId) -> anyhow::Result<Dictionary> {
+    let mut page = document.get_dictionary(page_id)?.clone();
+
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+    while let Some(parent_id) = parent {
+        let node = document.get_dictionary(parent_id)?;
+        for attribute in INHERITABLE_ATTRIBUTES {
+            if !page.has(attribute) {
+                if let Ok(value) = node.get(attribute) {
+                    page.set(attribute, value.clone());
+                }
+            }
+        }
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+
+    Ok(page)
+}
+
+/// Creates a single-page document showing the image, with the page sized to the image.
+///
+pub(crate) fn image_document(content: &[u8]) -> anyhow::Result<Document> {
+    let image = image::load_from_memory(content)?;
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err(anyhow!("image has no pixels"));
+    }
+
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+
+    let mut image_stream = Stream::new(dictionary! {
+        "Type" => "XObject",
+        "Subtype" => "Image",
+        "Width" => width,
+        "Height" => height,
+        "ColorSpace" => "DeviceRGB",
+        "BitsPerComponent" => 8,
+    }, image.to_rgb8().into_raw());
+    image_stream.compress()?;
+    let image_id = document.add_object(image_stream);
+
+    let content = Content {
+        operations: vec![
+            Operation::new("q", vec![]),
+            Operation::new("cm", vec![width.into(), 0.into(), 0.into(), height.into(), 0.into(), 0.into()]),
+            Operation::new("Do", vec!["Im0".into()]),
+            Operation::new("Q", vec![]),
+        ],
+    };
+    let content_id = document.add_object(Stream::new(dictionary! {}, content.encode()?));
+
+    let page_id = document.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "Contents" => content_id,
+        "Resources" => dictionary! {
+            "XObject" => dictionary! {
+                "Im0" => image_id,
+            },
+        },
+        "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+    });
+    document.objects.insert(pages_id, Object::Dictionary(dictionary! {
+        "Type" => "Pages",
+        "Kids" => vec![page_id.into()],
+        "Count" => 1,
+    }));
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, RgbaImage};
+
+    use super::*;
+
+    /// Creates a document with the given number of empty pages, with the media box inherited from the page tree.
+    ///
+    fn document(page_count: usize) -> Document {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let kids: Vec<Object> = (0..page_count)
+            .map(|_| document.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id }).into())
+            .collect();
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => page_count as i64,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        }));
+        let catalog_id = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog_id);
+        document
+    }
+
+    /// Saves and reloads the document, to make sure the result is a valid PDF.
+    ///
+    fn reload(mut document: Document) -> anyhow::Result<Document> {
+        let mut bytes = vec![];
+        document.save_to(&mut bytes)?;
+        Ok(Document::load_mem(&bytes)?)
+    }
+
+    #[test]
+    fn test_append_document() -> anyhow::Result<()> {
+        let mut body = document(2);
+        append_document(&mut body, document(3))?;
+
+        let merged = reload(body)?;
+        let pages = merged.get_pages();
+        assert_eq!(pages.len(), 5);
+        for page_id in pages.values() {
+            assert!(inherited_page(&merged, *page_id)?.has(b"MediaBox"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_image_document() -> anyhow::Result<()> {
+        let mut png = vec![];
+        RgbaImage::new(4, 3).write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+
+        let mut body = document(1);
+        append_document(&mut body, image_document(&png)?)?;
+
+        let merged = reload(body)?;
+        let pages = merged.get_pages();
+        assert_eq!(pages.len(), 2);
+        let media_box: Vec<i64> = merged.get_dictionary(pages[&2])?.get(b"MediaBox")?.as_array()?.iter()
+            .map(Object::as_i64)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(media_box, vec![0, 0, 4, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_image_document_invalid() {
+        assert!(image_document(b"not an image").is_err());
+    }
+}
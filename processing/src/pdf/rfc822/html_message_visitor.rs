@@ -1,15 +1,56 @@
 use std::borrow::Cow;
 
 use html_escape::encode_text;
-use mail_parser::{Addr, ContentType, DateTime, Group};
+use mail_parser::{Addr, ContentType, DateTime, Group, Received};
 use crate::pdf::rfc822::message_formatter::MessageFormatter;
 use crate::pdf::rfc822::message_visitor::MessageVisitor;
+use crate::pdf::rfc822::rtf::rtf_to_text;
 
 const HEADERS: [&str; 6] = ["Date", "From", "To", "CC", "BCC", "Subject"];
 
 #[derive(Default)]
 pub struct HtmlMessageVisitor {
     formatter: MessageFormatter,
+    headers: Option<Vec<String>>,
+    suppressed_headers: Vec<String>,
+}
+
+impl HtmlMessageVisitor {
+    /// Creates a new visitor rendering only the headers with the given names, compared case-insensitively.
+    ///
+    /// By default, the `Date`, `Subject`, and address headers are rendered. Names of headers the message doesn't
+    /// have are ignored.
+    ///
+    pub fn with_headers(headers: Option<Vec<String>>) -> Self {
+        Self { headers, ..Self::default() }
+    }
+
+    /// Suppresses the headers with the given names, compared case-insensitively, even if they'd otherwise be rendered.
+    ///
+    /// A name ending with `*` suppresses every header starting with it, like `X-*` for all the `X-` headers.
+    ///
+    pub fn suppressing(self, suppressed_headers: Vec<String>) -> Self {
+        Self { suppressed_headers, ..self }
+    }
+
+    /// Whether the header with the given name is rendered, or `default` if no header names were given.
+    ///
+    fn includes(&self, name: &str, default: bool) -> bool {
+        if self.suppresses(name) {
+            return false;
+        }
+        match &self.headers {
+            Some(headers) => headers.iter().any(|header| header.eq_ignore_ascii_case(name)),
+            None => default,
+        }
+    }
+
+    fn suppresses(&self, name: &str) -> bool {
+        self.suppressed_headers.iter().any(|header| match header.strip_suffix('*') {
+            Some(prefix) => name.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix)),
+            None => header.eq_ignore_ascii_case(name),
+        })
+    }
 }
 
 impl MessageVisitor for HtmlMessageVisitor {
@@ -33,40 +74,51 @@ impl MessageVisitor for HtmlMessageVisitor {
         Some("</div>".to_string())
     }
 
-    // fn on_header_received<'a>(&self, name: &str, received: &Received<'a>) -> Option<String> {
-    //     received.
-    //         Some(format!(
-    //             "<b>{}</b>: {}",
-    //             name,
-    //             encode_text(received.to_string().as_str())
-    //         ))
-    // }
+    fn on_header_received(&self, name: &str, received: &Received) -> Option<String> {
+        if !self.includes(name, false) {
+            return None;
+        }
+        self.formatter
+            .format_received(received)
+            .map(|received| format!("<b>{}</b>: {}", name, encode_text(received.as_str())))
+    }
 
     fn on_header_addresses(&self, name: &str, address_list: &[Addr]) -> Option<String> {
+        if !self.includes(name, true) {
+            return None;
+        }
         self.formatter
             .format_addresses(address_list)
             .map(|addrs| format!("<b>{}</b>: {}", name, encode_text(addrs.as_str())))
     }
 
     fn on_header_groups(&self, name: &str, group_list: &[Group]) -> Option<String> {
+        if !self.includes(name, true) {
+            return None;
+        }
         self.formatter
             .format_groups(group_list)
             .map(|groups| format!("<b>{}</b>: {}", name, encode_text(groups.as_str())))
     }
 
     fn on_header_text(&self, name: &str, text: Cow<str>) -> Option<String> {
-        HEADERS
-            .contains(&name)
+        self.includes(name, HEADERS.contains(&name))
             .then_some(format!("<b>{}</b>: {}", name, encode_text(&text)))
     }
 
     fn on_header_text_list(&self, name: &str, text_list: &[Cow<str>]) -> Option<String> {
+        if !self.includes(name, true) {
+            return None;
+        }
         self.formatter
             .format_text_list(text_list)
             .map(|texts| format!("<b>{}</b>: {}", name, encode_text(&texts)))
     }
 
     fn on_header_date_time(&self, name: &str, date_time: &DateTime) -> Option<String> {
+        if !self.includes(name, true) {
+            return None;
+        }
         Some(format!(
             "<b>{}</b>: {}",
             name,
@@ -79,11 +131,12 @@ impl MessageVisitor for HtmlMessageVisitor {
     }
 
     fn on_part_text(&self, value: Cow<str>) -> String {
-        value
-            .split('\n')
-            .map(|line| format!("<p>{}</p>", encode_text(line)))
-            .collect::<Vec<String>>()
-            .join("\n")
+        // Preserves the line breaks and whitespace of the text, while still wrapping long lines
+        format!("<pre style=\"white-space: pre-wrap; font-family: inherit\">{}</pre>", encode_text(&value))
+    }
+
+    fn on_part_rtf(&self, value: Cow<str>) -> String {
+        self.on_part_text(Cow::from(rtf_to_text(&value)))
     }
 }
 
@@ -113,11 +166,78 @@ mod test {
 <div><b>To</b>: &lt;processing.rusty@emim.com&gt;</div>
 <div><b>Subject</b>: Now THATS A LOT OF RUST</div>
 <br>
-<div><p>This is a rusty email</p>
-<p></p>
-<p>;)</p>
-<p></p></div>";
+<div><pre style=\"white-space: pre-wrap; font-family: inherit\">This is a rusty email
+
+;)
+</pre></div>";
         assert_eq!(expected_content, String::from_utf8(content)?);
         Ok(())
     }
+
+    #[test]
+    fn test_html_message_visitor_custom_headers() -> anyhow::Result<()> {
+        let content = read_contents("../resources/rfc822/received-chain.eml").unwrap();
+        let message = MessageParser::default().parse(&content).ok_or(anyhow!("Failed to parse message"))?;
+        let headers = ["received", "Subject", "X-Unknown"].map(String::from).to_vec();
+        let transformer = MessageTransformer::new(Box::new(HtmlMessageVisitor::with_headers(Some(headers))));
+
+        let mut content = vec![];
+        transformer.transform(&message, &mut content)?;
+
+        let expected_headers = "\
+<div><b>Received</b>: from mx.example.org by inbox.example.org with ESMTP; 2021-02-21T07:58:02-08:00</div>
+<div><b>Received</b>: from mail.example.com by mx.example.org with ESMTPS; 2021-02-21T07:58:01-08:00</div>
+<div><b>Subject</b>: Delivered through two hops</div>
+<br>
+";
+        assert!(String::from_utf8(content)?.starts_with(expected_headers));
+        Ok(())
+    }
+
+    #[test]
+    fn test_html_message_visitor_suppressed_headers() -> anyhow::Result<()> {
+        let content = "\
+Date: Sun, 21 Feb 2021 07:58:00 -0800
+From: rusty.processing@mime.com
+To: processing.rusty@emim.com
+Bcc: secret@mime.com
+Subject: Suppressed
+X-Internal-Id: 12345
+X-Review: pending
+
+Body
+";
+        let message = MessageParser::default().parse(content).ok_or(anyhow!("Failed to parse message"))?;
+        let headers = ["Date", "From", "To", "BCC", "Subject", "X-Internal-Id", "X-Review"].map(String::from).to_vec();
+        let suppressed = ["bcc", "x-*"].map(String::from).to_vec();
+        let visitor = HtmlMessageVisitor::with_headers(Some(headers)).suppressing(suppressed);
+        let transformer = MessageTransformer::new(Box::new(visitor));
+
+        let mut content = vec![];
+        transformer.transform(&message, &mut content)?;
+
+        let content = String::from_utf8(content)?;
+        assert!(content.contains("<b>From</b>: &lt;rusty.processing@mime.com&gt;"));
+        assert!(content.contains("<b>Subject</b>: Suppressed"));
+        assert!(!content.contains("secret@mime.com"));
+        assert!(!content.contains("12345"));
+        assert!(!content.contains("pending"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_html_message_visitor_rtf_body() -> anyhow::Result<()> {
+        let content = read_contents("../resources/rfc822/rtf-body.eml").unwrap();
+        let message = MessageParser::default().parse(&content).ok_or(anyhow!("Failed to parse message"))?;
+        let transformer = MessageTransformer::new(Box::<HtmlMessageVisitor>::default());
+
+        let mut content = vec![];
+        transformer.transform(&message, &mut content)?;
+
+        let content = String::from_utf8(content)?;
+        assert!(content.ends_with(
+            "<div><pre style=\"white-space: pre-wrap; font-family: inherit\">Hi Bob,\n\nThe café report is attached — see the totals.\n</pre></div>"
+        ));
+        Ok(())
+    }
 }
@@ -1,6 +1,6 @@
 use std::borrow::Cow;
 
-use mail_parser::{Addr, Group};
+use mail_parser::{Addr, Group, Received};
 
 /// A formatter for RFC 822 messages.
 ///
@@ -91,6 +91,36 @@ impl MessageFormatter {
         (!list.is_empty()).then(|| list.join(", "))
     }
 
+    /// Formats a `Received` trace into an optional `String`.
+    ///
+    /// The hosts it was received from and by, and the protocol it was received with, are joined with their keywords,
+    /// followed by the date after a "; ". If none of them are known, `None` is returned.
+    ///
+    /// ### Example Output
+    ///
+    /// ```text
+    /// "from mail.example.com by mx.example.org with ESMTP; 2021-02-21T07:58:00-08:00"
+    /// ```
+    ///
+    pub fn format_received(&self, received: &Received) -> Option<String> {
+        let route = [
+            received.from.as_ref().map(|from| format!("from {}", from)),
+            received.by.as_ref().map(|by| format!("by {}", by)),
+            received.with.as_ref().map(|with| format!("with {}", with)),
+        ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        match (route.is_empty(), &received.date) {
+            (true, None) => None,
+            (true, Some(date)) => Some(date.to_string()),
+            (false, None) => Some(route),
+            (false, Some(date)) => Some(format!("{}; {}", route, date)),
+        }
+    }
+
     /// Formats a name and an address into an optional `String`.
     ///
     fn format_name_address(
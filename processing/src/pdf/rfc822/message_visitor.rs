@@ -2,6 +2,8 @@ use std::borrow::Cow;
 
 use mail_parser::{Addr, ContentType, DateTime, Group, Received};
 
+use crate::pdf::rfc822::rtf::rtf_to_text;
+
 pub trait MessageVisitor {
     fn on_header_prefix(&self) -> Option<String> {
         None
@@ -75,6 +77,10 @@ pub trait MessageVisitor {
         value.to_string()
     }
 
+    fn on_part_rtf(&self, value: Cow<str>) -> String {
+        rtf_to_text(&value)
+    }
+
     fn on_part_binary(&self, value: Cow<[u8]>) -> Vec<u8> {
         value.to_vec()
     }
@@ -4,14 +4,19 @@ use std::path::Path;
 use anyhow::anyhow;
 
 use async_trait::async_trait;
-use mail_parser::MessageParser;
+use log::warn;
+use lopdf::Document;
+use mail_parser::{Message, MessageParser, MimeHeaders};
 use tempfile::TempPath;
 
-use crate::processing::{Process, ProcessContext, ProcessOutput};
+use crate::mimetype;
+use crate::processing::{Process, ProcessContext, ProcessOutput, ProcessType};
 
+mod appendix;
 mod html_message_visitor;
 mod message_formatter;
 mod message_visitor;
+mod rtf;
 mod transformer;
 
 mod pdf;
@@ -34,15 +39,140 @@ impl Process for Rfc822PdfProcessor {
         let message = self.message_parser.parse(&content)
             .ok_or(anyhow!("Failed to parse message"))?;
 
+        let name = ctx.output_name(ProcessType::Pdf, "rendered.pdf");
         let mut writer = File::create(&output_path)?;
-        let result = self.render_pdf(&message, &mut writer).await.map(|_|
-            ProcessOutput::processed(&ctx, "rendered.pdf", output_path, "embedded/pdf", checksum)
-        );
-        ctx.add_output(result).await
-    }
+        match ctx.append_pdf_attachments {
+            true => self.render_pdf_with_attachments(&ctx, &message, &mut writer).await?,
+            false => self.render_pdf(&ctx, &message, &mut writer).await?,
+        }
 
+        let output = ProcessOutput::processed(&ctx, name, output_path, self.output_mimetype(&ctx), checksum);
+        ctx.add_output(Ok(output)).await
+    }
 
     fn name(&self) -> &'static str {
         "RFC 822 PDF"
     }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "application/pdf"
+    }
+}
+
+impl Rfc822PdfProcessor {
+    /// Renders the message to a PDF, followed by the pages of its PDF and image attachments.
+    ///
+    /// Attachments that can't be appended are skipped with a warning.
+    ///
+    async fn render_pdf_with_attachments<W>(
+        &self,
+        ctx: &ProcessContext,
+        message: &Message<'_>,
+        writer: &mut W,
+    ) -> anyhow::Result<()>
+        where W: std::io::Write,
+    {
+        let mut pdf = vec![];
+        self.render_pdf(ctx, message, &mut pdf).await?;
+        let mut document = Document::load_mem(&pdf)?;
+
+        for part in message.attachments() {
+            let name = part.attachment_name().unwrap_or("message-attachment.dat");
+            let mimetype = part.content_type().map(mimetype).unwrap_or_default();
+
+            let appendix = match mimetype.as_str() {
+                "application/pdf" => Document::load_mem(part.contents()).map_err(anyhow::Error::from),
+                _ if mimetype.starts_with("image/") => appendix::image_document(part.contents()),
+                _ => {
+                    warn!("Skipping attachment {} with MIME type {} that can't be appended to the PDF", name, mimetype);
+                    continue;
+                },
+            };
+            match appendix.and_then(|appendix| appendix::append_document(&mut document, appendix)) {
+                Ok(()) => {},
+                Err(err) => warn!("Skipping attachment {} that failed to be appended to the PDF: {}", name, err),
+            }
+        }
+
+        document.save_to(writer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use tempfile::NamedTempFile;
+    use test_utils::temp_path;
+
+    use crate::processing::ProcessContextBuilder;
+
+    use super::*;
+
+    async fn render(path: &str, append_pdf_attachments: bool) -> anyhow::Result<TempPath> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("message/rfc822", vec![], output_sink)
+            .append_pdf_attachments(append_pdf_attachments)
+            .build();
+
+        Rfc822PdfProcessor::default().process(ctx, &PathBuf::from(path), temp_path()?, "checksum").await?;
+
+        match outputs.recv().await {
+            Some(Ok(ProcessOutput::Processed(_, data))) => Ok(data.path),
+            Some(Err(err)) => Err(err),
+            _ => Err(anyhow!("expected rendered output")),
+        }
+    }
+
+    async fn rendered_page_count(append_pdf_attachments: bool) -> anyhow::Result<usize> {
+        let path = render("../resources/rfc822/pdf-attachment.eml", append_pdf_attachments).await?;
+        Ok(Document::load(&path)?.get_pages().len())
+    }
+
+    async fn assert_renders_pages(path: &str) -> anyhow::Result<()> {
+        let path = render(path, false).await?;
+
+        assert!(!Document::load(&path)?.get_pages().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_append_pdf_attachments() -> anyhow::Result<()> {
+        let body_pages = rendered_page_count(false).await?;
+        let attachment_pages = Document::load("../resources/pdf/zugferd-invoice.pdf")?.get_pages().len();
+
+        assert_eq!(rendered_page_count(true).await?, body_pages + attachment_pages);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_render_error() -> anyhow::Result<()> {
+        let mut message = NamedTempFile::new()?;
+        message.write_all(b"\
+From: Sender <sender@example.com>
+Subject: Local image
+Content-Type: text/html
+
+<html><body><img src=\"file:///nonexistent.png\"></body></html>
+")?;
+
+        let err = render(message.path().to_str().unwrap(), false).await.expect_err("expected the render to fail");
+
+        let err = format!("{:#}", err);
+        assert!(err.contains("wkhtmltopdf failed to render the message"), "unexpected error: {}", err);
+        assert!(err.contains("Blocked access to file"), "unexpected error: {}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_plain_text() -> anyhow::Result<()> {
+        assert_renders_pages("../resources/rfc822/plain-text.eml").await
+    }
+
+    #[tokio::test]
+    async fn test_process_rtf_body() -> anyhow::Result<()> {
+        assert_renders_pages("../resources/rfc822/rtf-body.eml").await
+    }
 }
@@ -1,18 +1,21 @@
 use std::io::Write;
 
-use anyhow::anyhow;
+use anyhow::Context;
 use mail_parser::Message;
 use services::html_to_pdf;
 
 use crate::pdf::rfc822::html_message_visitor::HtmlMessageVisitor;
 use crate::pdf::rfc822::transformer::MessageTransformer;
 use crate::pdf::Rfc822PdfProcessor;
+use crate::processing::ProcessContext;
 
 impl Rfc822PdfProcessor {
-    pub async fn render_pdf<W>(&self, message: &Message<'_>, writer: &mut W) -> anyhow::Result<()>
+    pub async fn render_pdf<W>(&self, ctx: &ProcessContext, message: &Message<'_>, writer: &mut W) -> anyhow::Result<()>
         where W: Write,
     {
-        let transformer = MessageTransformer::new(Box::<HtmlMessageVisitor>::default());
+        let visitor = HtmlMessageVisitor::with_headers(ctx.message_headers.clone())
+            .suppressing(ctx.suppressed_headers.clone());
+        let transformer = MessageTransformer::new(Box::new(visitor));
 
         let mut html = Vec::<u8>::new();
         let mut pdf: Vec<u8> = Vec::new();
@@ -23,13 +26,11 @@ impl Rfc822PdfProcessor {
         Ok(())
     }
 
+    /// Renders the HTML to a PDF with wkhtmltopdf, failing if it exits with a non-zero status.
+    ///
     async fn render_html_to_pdf(&self, html: Vec<u8>, output: &mut Vec<u8>) -> anyhow::Result<()> {
-        let output = html_to_pdf().run(html.as_ref(), output).await?;
-        let status = output.exit_status;
-
-        if !status.success() && status.code().is_some_and(|code| code != 1) {
-            Err(anyhow!("wkhtmltopdf exited with status {}", status))?;
-        }
+        html_to_pdf().run(html.as_ref(), output).await
+            .context("wkhtmltopdf failed to render the message")?;
         Ok(())
     }
 }
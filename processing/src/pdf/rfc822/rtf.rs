@@ -0,0 +1,156 @@
+/// Destinations whose contents aren't part of the document's text.
+///
+const IGNORED_DESTINATIONS: [&str; 14] = [
+    "fonttbl", "colortbl", "stylesheet", "info", "pict", "object", "header", "footer", "headerl", "headerr",
+    "footerl", "footerr", "themedata", "listtable",
+];
+
+/// The characters of bytes `0x80` to `0x9F` in the Windows-1252 code page, which RTF uses by default.
+///
+/// The other bytes are the same as their Unicode code points.
+///
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// The state of an RTF group, which is inherited by its nested groups.
+///
+#[derive(Debug, Clone, Copy)]
+struct Group {
+    /// Whether the text of the group is skipped.
+    ///
+    skip: bool,
+
+    /// The number of fallback characters following each `\u` control word.
+    ///
+    unicode_skip: usize,
+}
+
+/// Extracts the plain text of an RTF document.
+///
+/// Paragraphs and line breaks become newlines, and formatting, fonts, pictures, and other non-text destinations
+/// are dropped.
+///
+pub fn rtf_to_text(rtf: &str) -> String {
+    let mut text = String::new();
+    let mut groups = vec![];
+    let mut group = Group { skip: false, unicode_skip: 1 };
+    // The number of fallback characters of a `\u` control word that have yet to be skipped
+    let mut pending_skip = 0;
+    let mut chars = rtf.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                groups.push(group);
+                pending_skip = 0;
+            },
+            '}' => {
+                group = groups.pop().unwrap_or(group);
+                pending_skip = 0;
+            },
+            '\r' | '\n' => {},
+            '\\' => match chars.next() {
+                Some('\'') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    if pending_skip > 0 {
+                        pending_skip -= 1;
+                    } else if let (false, Ok(byte)) = (group.skip, u8::from_str_radix(&hex, 16)) {
+                        text.push(windows_1252_char(byte));
+                    }
+                },
+                Some('*') => group.skip = true,
+                Some(symbol @ ('\\' | '{' | '}')) => push_text(&mut text, group, &mut pending_skip, symbol),
+                Some('~') => push_text(&mut text, group, &mut pending_skip, '\u{A0}'),
+                Some('\r' | '\n') => push_text(&mut text, group, &mut pending_skip, '\n'),
+                Some(letter) if letter.is_ascii_alphabetic() => {
+                    let mut word = String::from(letter);
+                    while let Some(letter) = chars.next_if(char::is_ascii_alphabetic) {
+                        word.push(letter);
+                    }
+                    let mut param = String::new();
+                    if let Some(sign) = chars.next_if_eq(&'-') {
+                        param.push(sign);
+                    }
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        param.push(digit);
+                    }
+                    // A space delimits the control word and isn't part of the text
+                    chars.next_if_eq(&' ');
+
+                    let param = param.parse::<i32>().ok();
+                    match word.as_str() {
+                        "par" | "line" => push_text(&mut text, group, &mut pending_skip, '\n'),
+                        "tab" => push_text(&mut text, group, &mut pending_skip, '\t'),
+                        "emdash" => push_text(&mut text, group, &mut pending_skip, '—'),
+                        "endash" => push_text(&mut text, group, &mut pending_skip, '–'),
+                        "lquote" => push_text(&mut text, group, &mut pending_skip, '‘'),
+                        "rquote" => push_text(&mut text, group, &mut pending_skip, '’'),
+                        "ldblquote" => push_text(&mut text, group, &mut pending_skip, '“'),
+                        "rdblquote" => push_text(&mut text, group, &mut pending_skip, '”'),
+                        "bullet" => push_text(&mut text, group, &mut pending_skip, '•'),
+                        "uc" => group.unicode_skip = param.unwrap_or(1).max(0) as usize,
+                        "u" => {
+                            // Code points above 32767 are written as negative numbers
+                            let code = param.map(|code| if code < 0 { code + 0x10000 } else { code });
+                            if let Some(c) = code.and_then(|code| char::from_u32(code as u32)) {
+                                push_text(&mut text, group, &mut pending_skip, c);
+                            }
+                            pending_skip = group.unicode_skip;
+                        },
+                        word if IGNORED_DESTINATIONS.contains(&word) => group.skip = true,
+                        _ => {},
+                    }
+                },
+                _ => {},
+            },
+            c => push_text(&mut text, group, &mut pending_skip, c),
+        }
+    }
+
+    text
+}
+
+/// Pushes a character of text, unless the group is skipped or it's a fallback character of a `\u` control word.
+///
+fn push_text(text: &mut String, group: Group, pending_skip: &mut usize, c: char) {
+    if *pending_skip > 0 {
+        *pending_skip -= 1;
+    } else if !group.skip {
+        text.push(c);
+    }
+}
+
+fn windows_1252_char(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtf_to_text() {
+        let rtf = r"{\rtf1\ansi\ansicpg1252\deff0{\fonttbl{\f0\fswiss Arial;}}{\colortbl;\red0\green0\blue0;}
+{\*\generator Riched20;}\pard\f0\fs20 Hello,\par
+\par
+The caf\'e9 is open \emdash  bring \{braces\} and a \\backslash.\line Caf\u233?s too.\par
+}";
+
+        assert_eq!(
+            rtf_to_text(rtf),
+            "Hello,\n\nThe café is open — bring {braces} and a \\backslash.\nCafés too.\n",
+        );
+    }
+
+    #[test]
+    fn test_rtf_to_text_unicode_skip() {
+        let rtf = r"{\rtf1{\uc2\u8364\'80\'80 euro}\u-3913?}";
+
+        assert_eq!(rtf_to_text(rtf), "€ euro\u{F0B7}");
+    }
+}
@@ -1,9 +1,19 @@
 use std::borrow::Cow;
 use std::io::Write;
 
-use mail_parser::{Address, HeaderValue, Message, MessagePart, PartType};
+use encoding_rs::{Encoding, UTF_8};
+use mail_parser::{Address, HeaderValue, Message, MessagePart, MessagePartId, MimeHeaders, PartType};
+use mail_parser::decoders::base64::base64_decode;
+use mail_parser::decoders::charsets::map::charset_decoder;
+use mail_parser::decoders::quoted_printable::quoted_printable_decode;
+
+use crate::mimetype;
 use crate::pdf::rfc822::message_visitor::MessageVisitor;
 
+/// The MIME types of RTF bodies.
+///
+const RTF_MIMETYPES: [&str; 2] = ["text/rtf", "application/rtf"];
+
 /// Service to transform message content using a provided visitor implementation.
 ///
 pub struct MessageTransformer {
@@ -42,12 +52,20 @@ impl MessageTransformer {
 
         self.write_if_some(writer, self.visitor.on_head_body_separator())?;
 
-        let bodies = if message.html_body_count() > 0 {
-            message.html_bodies()
-        } else {
-            message.text_bodies()
-        };
-        for part in bodies {
+        if message.html_body_count() == 0 && message.text_body_count() == 0 {
+            // Messages with only an RTF body, as some mail clients send, have the body parsed as an attachment
+            for part in message.attachments().filter(|part| is_rtf_body(part)) {
+                self.write_if_some(writer, self.visitor.on_part_prefix())?;
+                let text = self.visitor.on_part_rtf(decode_text(message, part));
+                writer.write_all(text.as_bytes())?;
+                self.write_if_some(writer, self.visitor.on_part_suffix())?;
+            }
+            return Ok(());
+        }
+
+        let mut body_ids = vec![];
+        collect_body_ids(message, 0, &mut body_ids);
+        for part in body_ids.into_iter().filter_map(|part_id| message.part(part_id)) {
             self.write_if_some(writer, self.visitor.on_part_prefix())?;
             self.transform_part(message, writer, part)?;
             self.write_if_some(writer, self.visitor.on_part_suffix())?;
@@ -97,13 +115,13 @@ impl MessageTransformer {
         W: Write,
     {
         match &part.body {
-            PartType::Text(text) => {
-                let text = self.visitor.on_part_text(Cow::to_owned(text));
+            PartType::Text(_) => {
+                let text = self.visitor.on_part_text(decode_text(message, part));
                 writer.write_all(text.as_bytes())?;
             }
 
-            PartType::Html(html) => {
-                let html = self.visitor.on_part_html(Cow::to_owned(html));
+            PartType::Html(_) => {
+                let html = self.visitor.on_part_html(decode_text(message, part));
                 writer.write_all(html.as_bytes())?;
             }
 
@@ -144,6 +162,102 @@ impl MessageTransformer {
     }
 }
 
+/// Collects the IDs of the body parts to render under the part, in order.
+///
+/// Only the preferred alternative of each `multipart/alternative` is walked, the last one with an HTML body or else
+/// the last one with a text body, so the same content isn't rendered once per alternative. Every body of other
+/// multiparts is walked, and attachments are skipped.
+///
+fn collect_body_ids(message: &Message, part_id: MessagePartId, body_ids: &mut Vec<MessagePartId>) {
+    let Some(part) = message.part(part_id) else {
+        return;
+    };
+
+    match &part.body {
+        PartType::Multipart(part_ids) if is_alternative(part) => {
+            let preferred = part_ids.iter().rev().find(|id| contains_body(message, **id, true))
+                .or_else(|| part_ids.iter().rev().find(|id| contains_body(message, **id, false)));
+            if let Some(preferred) = preferred {
+                collect_body_ids(message, *preferred, body_ids);
+            }
+        },
+        PartType::Multipart(part_ids) => {
+            for part_id in part_ids {
+                collect_body_ids(message, *part_id, body_ids);
+            }
+        },
+        PartType::Text(_) | PartType::Html(_) if is_body(message, part_id) => body_ids.push(part_id),
+        _ => {},
+    }
+}
+
+/// Whether the part is, or contains, an HTML body if `html`, or a text body otherwise.
+///
+fn contains_body(message: &Message, part_id: MessagePartId, html: bool) -> bool {
+    match message.part(part_id).map(|part| &part.body) {
+        Some(PartType::Multipart(part_ids)) => part_ids.iter().any(|id| contains_body(message, *id, html)),
+        Some(PartType::Html(_)) => html && is_body(message, part_id),
+        Some(PartType::Text(_)) => !html && is_body(message, part_id),
+        _ => false,
+    }
+}
+
+/// Returns the text of a part, decoded with the charset declared in its `Content-Type`.
+///
+/// `mail-parser` decodes the charsets it supports while parsing, and falls back to lossy UTF-8 for the others, so
+/// parts with those charsets are decoded from their raw body with `encoding_rs` instead. The text is lossy UTF-8 if
+/// neither supports the charset.
+///
+fn decode_text<'a>(message: &'a Message, part: &'a MessagePart) -> Cow<'a, str> {
+    let parsed = match &part.body {
+        PartType::Text(text) | PartType::Html(text) => Cow::from(text.as_ref()),
+        _ => String::from_utf8_lossy(part.contents()),
+    };
+
+    let Some(charset) = part.content_type().and_then(|content_type| content_type.attribute("charset")) else {
+        return parsed;
+    };
+    let encoding = match Encoding::for_label(charset.as_bytes()) {
+        Some(encoding) if encoding != UTF_8 && charset_decoder(charset.as_bytes()).is_none() => encoding,
+        _ => return parsed,
+    };
+
+    let raw_body = message.raw_message.get(part.offset_body..part.offset_end).unwrap_or_default();
+    let body = match part.encoding {
+        mail_parser::Encoding::None => Some(raw_body.to_vec()),
+        mail_parser::Encoding::QuotedPrintable => quoted_printable_decode(raw_body),
+        mail_parser::Encoding::Base64 => base64_decode(raw_body),
+    };
+    match body {
+        Some(body) => Cow::from(encoding.decode_without_bom_handling(&body).0.into_owned()),
+        None => parsed,
+    }
+}
+
+/// Whether the part is one of the message's bodies, rather than an attachment.
+///
+fn is_body(message: &Message, part_id: MessagePartId) -> bool {
+    message.html_body.contains(&part_id) || message.text_body.contains(&part_id)
+}
+
+/// Whether the part is a `multipart/alternative`.
+///
+fn is_alternative(part: &MessagePart) -> bool {
+    part.content_type()
+        .and_then(|content_type| content_type.subtype())
+        .is_some_and(|subtype| subtype.eq_ignore_ascii_case("alternative"))
+}
+
+/// Whether the part is an RTF body, i.e. an RTF part that isn't explicitly an attachment.
+///
+fn is_rtf_body(part: &MessagePart) -> bool {
+    let is_rtf = part.content_type()
+        .is_some_and(|content_type| RTF_MIMETYPES.contains(&mimetype(content_type).as_str()));
+    let is_attachment = part.content_disposition()
+        .is_some_and(|disposition| disposition.is_attachment());
+    is_rtf && !is_attachment
+}
+
 #[cfg(test)]
 mod test {
     use std::borrow::Cow;
@@ -290,4 +404,56 @@ Text part";
         assert_eq!(expected_content, String::from_utf8(content)?);
         Ok(())
     }
+
+    struct BodyVisitor;
+
+    impl MessageVisitor for BodyVisitor {
+        fn on_part_text(&self, value: Cow<str>) -> String {
+            format!("[text]{}", value)
+        }
+
+        fn on_part_html(&self, value: Cow<str>) -> String {
+            format!("[html]{}", value)
+        }
+    }
+
+    fn transform_body(path: &str) -> anyhow::Result<String> {
+        let content = read_contents(path).unwrap();
+        let message = MessageParser::default().parse(&content).ok_or(anyhow!("Failed to parse message"))?;
+        let transformer = MessageTransformer::new(Box::new(BodyVisitor {}));
+
+        let mut content = vec![];
+        transformer.transform(&message, &mut content)?;
+        Ok(String::from_utf8(content)?)
+    }
+
+    #[test]
+    fn test_transform_charsets() -> anyhow::Result<()> {
+        for (path, expected) in [
+            ("../resources/rfc822/latin1-body.eml", "[text]Grüße aus Köln, à bientôt!"),
+            ("../resources/rfc822/shift-jis-body.eml", "[text]こんにちは、世界。"),
+            ("../resources/rfc822/mac-roman-body.eml", "[text]Café crème über Zürich"),
+        ] {
+            let content = transform_body(path)?;
+            assert!(content.contains(expected), "unexpected text of {}: {}", path, content);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_transform_alternative() -> anyhow::Result<()> {
+        let content = read_contents("../resources/rfc822/alternative.eml").unwrap();
+        let message = MessageParser::default().parse(&content).ok_or(anyhow!("Failed to parse message"))?;
+        let transformer = MessageTransformer::new(Box::new(BodyVisitor {}));
+
+        let mut content = vec![];
+        transformer.transform(&message, &mut content)?;
+
+        let content = String::from_utf8(content)?;
+        assert_eq!(content.matches("[html]").count(), 1);
+        assert!(content.contains("<p>The <b>HTML</b> variant.</p>"));
+        assert!(!content.contains("[text]"));
+        assert!(!content.contains("The plain text variant."));
+        Ok(())
+    }
 }
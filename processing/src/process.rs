@@ -0,0 +1,1990 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use anyhow::anyhow;
+use futures::future::{BoxFuture, join_all};
+use json::JsonValue;
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use tap::Tap;
+use tempfile::{NamedTempFile, TempPath};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Semaphore;
+use tokio::task::{JoinHandle, JoinSet};
+
+use identify::mimetype::identify_mimetype;
+use services::{ArchiveBuilder, CommittedBytes, CompressionPolicy, log_err, StagingArchiveBuilder, StreamingArchiveWriter};
+
+use crate::embedded::{copy_mbox_bytes, mbox_range_bytes};
+pub use crate::embedded::mbox_message_offsets;
+use crate::naming::{EntryNaming, IdChain, sanitize_path_component};
+use crate::options::{PostProcessHook, ProcessOptions, ProcessOptionsBuilder};
+use crate::processing::{FileError, ProcessContext, ProcessContextBuilder, processor, ProcessOutput, ProcessOutputData, ProcessState, ProcessType};
+
+lazy_static! {
+    static ref RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create tokio runtime");
+}
+
+/// Global asynchronous runtime.
+///
+pub fn runtime() -> &'static tokio::runtime::Runtime {
+    &RUNTIME
+}
+
+/// MIME types declared for files whose actual type is unknown.
+///
+pub(crate) const GENERIC_MIMETYPES: [&str; 4] = [
+    "application/octet-stream",
+    "binary/octet-stream",
+    "embedded/octet-stream",
+    "application/unknown",
+];
+
+/// The name of the archive entry recording the failures of processing, at the root of the archive.
+///
+pub const ERRORS_ENTRY_NAME: &str = "errors.json";
+
+/// The stem of the name of the archive entry holding an original file, at the root of its outputs.
+///
+pub const ORIGINAL_ENTRY_STEM: &str = "original";
+
+/// An output file to add to the archive, along with the chain of embedded files leading to it, its name, and its MIME
+/// type.
+///
+type ArchiveEntry = (TempPath, IdChain, String, String);
+
+/// Summary of a processing operation.
+///
+#[derive(Debug)]
+pub struct ProcessSummary {
+    /// The number of files created by processing, such as extracted text and metadata.
+    ///
+    pub output_count: usize,
+
+    /// The number of embedded files discovered and added to the archive.
+    ///
+    pub embedded_count: usize,
+
+    /// The number of outputs left out of the archive, either because processing or the post-processing hook failed,
+    /// because they were embedded files filtered out by the MIME type allowlist, or because they exceeded
+    /// `ProcessOptions.max_total_outputs`.
+    ///
+    pub skipped_count: usize,
+
+    /// The number of text outputs truncated for exceeding `ProcessOptions.max_output_bytes`.
+    ///
+    pub truncated_count: usize,
+
+    /// Whether outputs were left out of the archive for exceeding `ProcessOptions.max_total_outputs`.
+    ///
+    /// The archive then records it in its [`ERRORS_ENTRY_NAME`] entry too.
+    ///
+    pub outputs_truncated: bool,
+
+    /// The created archive containing the output files of the processing operation, positioned at its start.
+    ///
+    pub archive: File,
+
+    /// The directory the output files were kept in, if `ProcessOptions.keep_temp` was set.
+    ///
+    /// Files are kept at the same paths as their archive entries. The directory isn't removed once processing
+    /// finishes.
+    ///
+    pub kept_temp_dir: Option<PathBuf>,
+}
+
+/// Counts of the outputs handled during processing.
+///
+#[derive(Debug, Default)]
+struct OutputCounts {
+    output_count: usize,
+    embedded_count: usize,
+    skipped_count: usize,
+    truncated_count: usize,
+    outputs_truncated: bool,
+}
+
+/// How the outputs of processing are handled, from the options processing was started with; see [`handle_outputs`].
+///
+struct OutputSettings {
+    max_depth: Option<usize>,
+    max_concurrent_recursions: usize,
+    channel_capacity: usize,
+    max_total_outputs: Option<usize>,
+    keep_embedded: bool,
+    include_original: bool,
+    post_process: Option<PostProcessHook>,
+}
+
+impl OutputSettings {
+    fn new(options: &ProcessOptions) -> Self {
+        Self {
+            max_depth: options.recursion_depth(),
+            max_concurrent_recursions: options.max_concurrent_recursions,
+            channel_capacity: options.channel_capacity,
+            max_total_outputs: options.max_total_outputs,
+            keep_embedded: options.keep_embedded,
+            include_original: options.include_original,
+            post_process: options.post_process.clone(),
+        }
+    }
+}
+
+/// How the archive is built from the entries it receives; see [`build_archive`].
+///
+struct ArchiveSettings {
+    entry_naming: EntryNaming,
+    flatten: bool,
+    prefix: PathBuf,
+    deterministic: bool,
+    kept_temp_dir: Option<PathBuf>,
+}
+
+/// Process a file.
+///
+/// This function processes a file, and returns an archive file
+/// containing the outputs of the processing operation.
+///
+/// # Arguments
+///
+/// * `input_path` - The path to the file to process.
+/// * `mimetype` - The MIME type of the file.
+/// * `types` - The types of output to generate.
+/// * `recurse` - Whether to process embedded files recursively.
+///
+/// See [`process_with_options`] to configure processing any further.
+///
+/// # Returns
+///
+/// * `Ok(File)` - If the file was processed successfully, where `File` is the created archive
+///   containing the output files of the processing operation, positioned at its start.
+/// * `Err(_)` - If there was an error processing the file.
+///
+pub async fn process(
+    input_path: PathBuf,
+    mimetype: String,
+    types: Vec<ProcessType>,
+    recurse: bool,
+) -> anyhow::Result<File> {
+    let options = ProcessOptionsBuilder::new(mimetype)
+        .types(types)
+        .recurse(recurse)
+        .build();
+    process_with_options(input_path, options).await
+}
+
+/// Process a file configured by the given options.
+///
+/// # Returns
+///
+/// * `Ok(File)` - If the file was processed successfully, where `File` is the created archive
+///   containing the output files of the processing operation, positioned at its start.
+/// * `Err(_)` - If there was an error processing the file.
+///
+pub async fn process_with_options(input_path: PathBuf, options: ProcessOptions) -> anyhow::Result<File> {
+    process_with_summary(input_path, options).await.map(|summary| summary.archive)
+}
+
+/// Process a file configured by the given options, summarizing what the created archive contains.
+///
+pub async fn process_with_summary(input_path: PathBuf, options: ProcessOptions) -> anyhow::Result<ProcessSummary> {
+    let kept_temp_dir = match options.keep_temp {
+        true => Some(keep_temp_dir()?),
+        false => None,
+    };
+    let staged = options.stage_archive_entries && !options.deterministic_archive;
+    let archive_writer = ArchiveWriter::new(staged, options.compression, options.deterministic_archive)?;
+
+    let (counts, archive) = process_into_archive(input_path, options, archive_writer, kept_temp_dir.clone()).await?;
+
+    Ok(ProcessSummary {
+        output_count: counts.output_count,
+        embedded_count: counts.embedded_count,
+        skipped_count: counts.skipped_count,
+        truncated_count: counts.truncated_count,
+        outputs_truncated: counts.outputs_truncated,
+        archive: archive.ok_or(anyhow!("archive was streamed rather than written to a file"))?,
+        kept_temp_dir,
+    })
+}
+
+/// Process a file, writing the created archive into `writer` as it's built rather than into a file.
+///
+/// Each entry is written once it's been added to the archive, so only the entry being added is held in memory.
+///
+/// # Arguments
+///
+/// * `input_path` - The path to the file to process.
+/// * `mimetype` - The MIME type of the file.
+/// * `types` - The types of output to generate.
+/// * `recurse` - Whether to process embedded files recursively.
+/// * `writer` - The writer to write the archive into.
+///
+/// See [`process_into_with_options`] to configure processing any further.
+///
+pub async fn process_into<W>(
+    input_path: PathBuf,
+    mimetype: String,
+    types: Vec<ProcessType>,
+    recurse: bool,
+    writer: W,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    let options = ProcessOptionsBuilder::new(mimetype)
+        .types(types)
+        .recurse(recurse)
+        .build();
+    process_into_with_options(input_path, options, writer).await
+}
+
+/// Process a file configured by the given options, writing the created archive into `writer` as it's built.
+///
+/// See [`process_into`] for more information.
+///
+pub async fn process_into_with_options<W>(
+    input_path: PathBuf,
+    options: ProcessOptions,
+    mut writer: W,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    let kept_temp_dir = match options.keep_temp {
+        true => Some(keep_temp_dir()?),
+        false => None,
+    };
+    let archive_writer = ArchiveWriter::streaming(options.compression, &mut writer)?;
+
+    process_into_archive(input_path, options, archive_writer, kept_temp_dir).await?;
+    Ok(())
+}
+
+/// Processes a file, adding its outputs to the archive as they're received.
+///
+/// Returns the counts of the outputs, along with the archive file unless the archive was streamed into a writer.
+///
+async fn process_into_archive(
+    input_path: PathBuf,
+    options: ProcessOptions,
+    archive_writer: ArchiveWriter<'_>,
+    kept_temp_dir: Option<PathBuf>,
+) -> anyhow::Result<(OutputCounts, Option<File>)> {
+    info!("Processing file with MIME type {}", &options.mimetype);
+
+    let (output_sink, outputs) = tokio::sync::mpsc::channel(options.channel_capacity);
+    let (archive_entry_sink, archive_entries) = tokio::sync::mpsc::channel(options.channel_capacity);
+
+    let mut prefix = PathBuf::new();
+    if let Some(archive_root) = &options.archive_root {
+        prefix.push(archive_root.directory(&input_path));
+    }
+    if !options.flatten {
+        prefix.extend(options.id_chain.iter().map(|id| sanitize_path_component(id)));
+    }
+    let output_settings = OutputSettings::new(&options);
+    let archive_settings = ArchiveSettings {
+        entry_naming: options.entry_naming,
+        flatten: options.flatten,
+        prefix,
+        deterministic: options.deterministic_archive,
+        kept_temp_dir,
+    };
+    if options.include_original {
+        let name = input_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let entry = original_entry(&input_path, IdChain::default(), &name, &options.mimetype).await?;
+        archive_entry_sink.send(entry).await?;
+    }
+    let ctx = process_context(options, output_sink);
+
+    let processing = tokio::spawn(processor().process(ctx, input_path));
+    let output_handling = tokio::spawn(handle_outputs(outputs, archive_entry_sink, output_settings));
+    let building = build_archive(archive_entries, archive_settings, archive_writer);
+    let processed = async {
+        processing.await??;
+        let counts = output_handling.await?;
+        info!("Finished processing file");
+        anyhow::Ok(counts)
+    };
+
+    // The archive is built while processing, as the outputs are received
+    let (archive, counts) = tokio::join!(building, processed);
+    Ok((counts?, archive?))
+}
+
+/// Process only the metadata of a file, and of its embedded files when recursing, for a quick look at what it contains.
+///
+/// The created archive only contains the metadata.json files; embedded files are processed, but left out of it. See
+/// [`crate::processing::process_metadata_ndjson`] for the same metadata as newline-delimited JSON instead.
+///
+/// # Arguments
+///
+/// * `input_path` - The path to the file to process.
+/// * `mimetype` - The MIME type of the file.
+/// * `recurse` - Whether to process the metadata of embedded files recursively.
+///
+/// # Returns
+///
+/// * `Ok(File)` - If the file was processed successfully, where `File` is the created archive
+///   containing the metadata.json files, positioned at its start.
+/// * `Err(_)` - If there was an error processing the file.
+///
+pub async fn process_metadata_only(
+    input_path: PathBuf,
+    mimetype: impl Into<String>,
+    recurse: bool,
+) -> anyhow::Result<File> {
+    let types = match recurse {
+        true => vec![ProcessType::Metadata, ProcessType::Embedded],
+        false => vec![ProcessType::Metadata],
+    };
+    let options = ProcessOptionsBuilder::new(mimetype)
+        .types(types)
+        .recurse(recurse)
+        .keep_embedded(false)
+        .build();
+    process_with_options(input_path, options).await
+}
+
+/// Creates a directory to keep the output files in for debugging, which outlives processing.
+///
+fn keep_temp_dir() -> anyhow::Result<PathBuf> {
+    let dir = tempfile::Builder::new().prefix("rusty-processing-").tempdir()?.keep();
+    info!("Keeping output files in {:?}", dir);
+    Ok(dir)
+}
+
+/// Process a file, blocking the current thread until finished.
+///
+/// Uses the global [`runtime`] to drive [`process_with_options`], so it can be used by consumers that don't run
+/// inside a tokio runtime.
+///
+/// This function must not be called from within an asynchronous context, as blocking on the runtime
+/// from one of its own threads panics.
+///
+/// See [`process_with_options`] for more information on the returned archive.
+///
+pub fn process_blocking(input_path: PathBuf, options: ProcessOptions) -> anyhow::Result<File> {
+    runtime().block_on(process_with_options(input_path, options))
+}
+
+/// Process the contents of a file that's already in memory.
+///
+/// The contents are written to a temporary file, which is removed once processing finishes.
+///
+/// See [`process_with_options`] for more information on the returned archive.
+///
+pub async fn process_bytes(data: Vec<u8>, options: ProcessOptions) -> anyhow::Result<File> {
+    let mut input = NamedTempFile::new()?;
+    input.write_all(&data)?;
+    input.flush()?;
+    drop(data);
+
+    let input_path = input.into_temp_path();
+    process_with_options(input_path.to_path_buf(), options).await
+}
+
+/// Process only the messages of an mbox that start within the byte range from `start` up to `end`.
+///
+/// A message starts at its `From ` line. Splitting an mbox into adjacent ranges processes each message in exactly
+/// one of them, wherever the ranges are split, so the ranges can be processed in parallel, such as by separate
+/// workers. Splitting at offsets from [`mbox_message_offsets`] avoids scanning for the messages in each range.
+///
+/// The messages are only grouped into threads with the other messages of the same range. The MIME type of the
+/// options is ignored.
+///
+/// See [`process_with_options`] for more information on the returned archive.
+///
+pub async fn process_mbox_range(
+    input_path: PathBuf,
+    start: u64,
+    end: u64,
+    options: ProcessOptions,
+) -> anyhow::Result<File> {
+    let Some((range_start, range_end)) = mbox_range_bytes(&input_path, start, end)? else {
+        info!("No messages start within bytes {} to {} of the mbox", start, end);
+        let mut archive = ArchiveBuilder::new(tempfile::tempfile()?)?.build()?;
+        archive.seek(SeekFrom::Start(0))?;
+        return Ok(archive);
+    };
+
+    info!("Processing the messages within bytes {} to {} of the mbox", range_start, range_end);
+    let mut range = NamedTempFile::new()?;
+    copy_mbox_bytes(&input_path, range_start, range_end, &mut range)?;
+    range.flush()?;
+
+    let range_path = range.into_temp_path();
+    let options = ProcessOptions { mimetype: "application/mbox".to_string(), ..options };
+    process_with_options(range_path.to_path_buf(), options).await
+}
+
+/// Handle the outputs of the processing operation asynchronously.
+///
+/// Archive entries created from each output are sent to the archive entry sink. Embedded files filtered out by the
+/// MIME type allowlist are dropped here, unless they're to be kept.
+///
+/// Embedded files are processed recursively up to `max_depth`, or without limits if it's [`None`]; see
+/// [`OutputHandler::recurse`]. Embedded files themselves are only added to the archive if `keep_embedded` is set.
+///
+/// Once `max_total_outputs` outputs have been handled, any further outputs are dropped, so embedded files beyond the
+/// limit aren't processed recursively either.
+///
+/// The `post_process` hook, if any, is run on each output in the order they're received, before it's handled.
+///
+/// Failures of processing and of the `post_process` hook are recorded in an [`ERRORS_ENTRY_NAME`] archive entry, as
+/// a list of objects with the `stage` that failed, the `id_chain` and `name` of the file it failed on, and the error
+/// `message`. The root file has an empty `id_chain` and no `name`. It's only added if anything failed.
+///
+async fn handle_outputs(
+    outputs: Receiver<anyhow::Result<ProcessOutput>>,
+    archive_entry_sink: Sender<ArchiveEntry>,
+    settings: OutputSettings,
+) -> OutputCounts {
+    let handler = Arc::new(OutputHandler {
+        recursions: Arc::new(Semaphore::new(settings.max_concurrent_recursions)),
+        archive_entry_sink,
+        settings,
+        counts: Mutex::default(),
+        errors: Mutex::new(JsonValue::new_array()),
+    });
+    handler.clone().handle(outputs).await;
+
+    let errors = handler.errors.lock().unwrap_or_else(PoisonError::into_inner).take();
+    if !errors.is_empty() {
+        match errors_entry(&errors) {
+            Ok(errors_entry) => if let Err(err) = handler.archive_entry_sink.send(errors_entry).await {
+                warn!("Failed to add the processing errors to the archive: {}", err);
+            },
+            Err(err) => warn!("Failed to record processing errors: {}", err),
+        }
+    }
+    let counts = std::mem::take(&mut *handler.counts());
+    counts
+}
+
+/// Handles the outputs of processing a file and of its embedded files processed recursively; see [`handle_outputs`].
+///
+/// The counts and errors are shared by the outputs of all the files.
+///
+struct OutputHandler {
+    archive_entry_sink: Sender<ArchiveEntry>,
+    settings: OutputSettings,
+    recursions: Arc<Semaphore>,
+    counts: Mutex<OutputCounts>,
+    errors: Mutex<JsonValue>,
+}
+
+impl OutputHandler {
+    fn counts(&self) -> MutexGuard<'_, OutputCounts> {
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record_error(&self, stage: &str, id_chain: Vec<String>, name: Option<String>, message: String) {
+        let mut errors = self.errors.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = errors.push(error_record(stage, id_chain, name, message));
+    }
+
+    /// Handles the outputs of processing a file as they're received, until it and its embedded files are done.
+    ///
+    fn handle(self: Arc<Self>, mut outputs: Receiver<anyhow::Result<ProcessOutput>>) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            let mut recursing = JoinSet::new();
+
+            while let Some(output) = outputs.recv().await {
+                let Some(output) = self.accept(output).await else {
+                    continue;
+                };
+
+                match output {
+                    ProcessOutput::Embedded(state, data, ctx) if recurses(&state, &data, &ctx, self.settings.max_depth) => {
+                        match self.recursions.clone().try_acquire_owned() {
+                            Ok(permit) => {
+                                let handler = self.clone();
+                                recursing.spawn(async move {
+                                    handler.recurse(state, data, ctx).await;
+                                    drop(permit);
+                                });
+                            },
+                            Err(_) => self.clone().recurse(state, data, ctx).await,
+                        }
+                    },
+                    output => self.handle_process_output(output).await,
+                }
+                while recursing.try_join_next().is_some() {}
+            }
+
+            while recursing.join_next().await.is_some() {}
+        })
+    }
+
+    /// Returns the output if it's to be handled, after running the `post_process` hook on it, or [`None`] if it's
+    /// dropped.
+    ///
+    async fn accept(&self, output: anyhow::Result<ProcessOutput>) -> Option<ProcessOutput> {
+        let output = match output.tap(log_err!("Error processing")) {
+            Ok(output) => output,
+            Err(err) => {
+                let (id_chain, name) = match err.downcast_ref::<FileError>() {
+                    Some(file_error) => (file_error.id_chain.clone(), file_error.name.clone()),
+                    None => (vec![], None),
+                };
+                self.record_error("processing", id_chain, name, err.to_string());
+                self.counts().skipped_count += 1;
+                return None;
+            },
+        };
+
+        let output = match output {
+            ProcessOutput::Embedded(state, data, ctx) if ctx.redetect_generic_mimetypes => {
+                ProcessOutput::Embedded(state, redetect_mimetype(data).await, ctx)
+            },
+            output => output,
+        };
+
+        if let ProcessOutput::Embedded(_, data, ctx) = &output {
+            if !ctx.is_mimetype_allowed(&data.mimetype) && !ctx.keep_filtered {
+                debug!("Dropping embedded file {} with filtered MIME type {}", data.name, data.mimetype);
+                self.counts().skipped_count += 1;
+                return None;
+            }
+        }
+
+        // The output is counted before it's post-processed, so outputs handled concurrently can't exceed the limit
+        {
+            let mut counts = self.counts();
+            let max_total_outputs = self.settings.max_total_outputs;
+            if let Some(max) = max_total_outputs.filter(|max| counts.output_count + counts.embedded_count >= *max) {
+                if !counts.outputs_truncated {
+                    let message = format!("Stopped adding outputs at the limit of {}", max);
+                    warn!("{}", message);
+                    let (id_chain, name) = output_file(&output);
+                    self.record_error("limits", id_chain, name, message);
+                    counts.outputs_truncated = true;
+                }
+                counts.skipped_count += 1;
+                return None;
+            }
+            match &output {
+                ProcessOutput::Processed(_, _) => counts.output_count += 1,
+                ProcessOutput::Embedded(_, _, _) => counts.embedded_count += 1,
+            }
+        }
+
+        let output = match &self.settings.post_process {
+            Some(hook) => {
+                let (id_chain, name) = output_file(&output);
+                let is_processed = matches!(output, ProcessOutput::Processed(_, _));
+                match run_post_process(hook.clone(), output).await.tap(log_err!("Error post-processing")) {
+                    Ok(output) => output,
+                    Err(err) => {
+                        self.record_error("post-processing", id_chain, name, err.to_string());
+                        let mut counts = self.counts();
+                        match is_processed {
+                            true => counts.output_count -= 1,
+                            false => counts.embedded_count -= 1,
+                        }
+                        counts.skipped_count += 1;
+                        return None;
+                    },
+                }
+            },
+            None => output,
+        };
+
+        if let ProcessOutput::Processed(_, data) = &output {
+            self.counts().truncated_count += data.truncated as usize;
+        }
+        Some(output)
+    }
+
+    /// Processes an embedded file recursively, handling its outputs as they're received.
+    ///
+    /// The outputs of each embedded file are sent through a queue of their own, bounded by `channel_capacity`, and
+    /// received by a consumer dedicated to it. At most `max_concurrent_recursions` embedded files are processed on
+    /// tasks of their own at a time. While they're all taken, the consumer receiving an embedded file processes it
+    /// itself before receiving any more outputs, so the processing sending to it waits rather than outputs piling up,
+    /// without waiting on a recursion that may itself be waiting to send.
+    ///
+    /// See [`OutputHandler::add_embedded`] for how the embedded file itself is added to the archive.
+    ///
+    async fn recurse(self: Arc<Self>, state: ProcessState, data: ProcessOutputData, ctx: ProcessContext) {
+        let (output_sink, outputs) = tokio::sync::mpsc::channel(self.settings.channel_capacity);
+        let mut embedded_state = state.clone();
+        embedded_state.id_chain.push(data.checksum.clone());
+        embedded_state.name_chain.push(data.name.clone());
+        let ctx = ProcessContextBuilder::from(ctx)
+            .mimetype(data.mimetype.clone())
+            .types(data.types.clone())
+            .id_chain(embedded_state.id_chain.clone())
+            .name_chain(embedded_state.name_chain)
+            .output_sink(output_sink)
+            .build();
+
+        let processing = processor().process(ctx, data.path.to_path_buf());
+        let (processed, _) = tokio::join!(processing, self.clone().handle(outputs));
+        if let Err(e) = processed {
+            warn!("Error processing: {:?}", e);
+            self.record_error("processing", embedded_state.id_chain, Some(data.name.clone()), e.to_string());
+        }
+
+        self.add_embedded(state, data).await
+    }
+
+    /// Adds the archive entry of an output, or of an embedded file that isn't processed recursively.
+    ///
+    async fn handle_process_output(&self, output: ProcessOutput) {
+        match output {
+            ProcessOutput::Processed(state, data) => {
+                self.add_entry((data.path, chain_links(state), data.name, data.mimetype)).await
+            },
+            ProcessOutput::Embedded(state, data, _) => self.add_embedded(state, data).await,
+        }
+    }
+
+    /// Adds the archive entries of an embedded file, once any processing of it is done.
+    ///
+    /// Embedded files are left out of the archive unless `keep_embedded` is set, though they're still processed.
+    /// Embedded files extracted as [`ProcessType::Attachments`] are leaves instead: they're always added to the archive
+    /// as they are, and never processed.
+    ///
+    /// If `include_original` is set, each embedded file is added at the root of its outputs too, unless it's an
+    /// attachment; see [`original_entry`].
+    ///
+    async fn add_embedded(&self, mut state: ProcessState, data: ProcessOutputData) {
+        let is_attachment = data.types.contains(&ProcessType::Attachments);
+        state.id_chain.push(data.checksum);
+        state.name_chain.push(data.name.clone());
+
+        if self.settings.include_original && !is_attachment {
+            match original_entry(&data.path, chain_links(state.clone()), &data.name, &data.mimetype).await {
+                Ok(entry) => self.add_entry(entry).await,
+                Err(e) => warn!("Error copying original: {:?}", e),
+            }
+        }
+        if self.settings.keep_embedded || is_attachment {
+            self.add_entry((data.path, chain_links(state), data.name, data.mimetype)).await
+        }
+    }
+
+    async fn add_entry(&self, entry: ArchiveEntry) {
+        if let Err(e) = self.archive_entry_sink.send(entry).await {
+            warn!("Error adding archive entry: {:?}", e);
+        }
+    }
+}
+
+/// Creates a record of a failure for the errors entry.
+///
+fn error_record(stage: &str, id_chain: Vec<String>, name: Option<String>, message: String) -> JsonValue {
+    json::object! { "stage": stage, "id_chain": id_chain, "name": name, "message": message }
+}
+
+/// The ID chain and name of the file an output belongs to; for embedded files, the embedded file itself.
+///
+fn output_file(output: &ProcessOutput) -> (Vec<String>, Option<String>) {
+    match output {
+        ProcessOutput::Processed(state, _) => (state.id_chain.clone(), state.name_chain.last().cloned()),
+        ProcessOutput::Embedded(state, data, _) => {
+            let mut id_chain = state.id_chain.clone();
+            id_chain.push(data.checksum.clone());
+            (id_chain, Some(data.name.clone()))
+        },
+    }
+}
+
+/// Creates the archive entry recording the failures of processing.
+///
+fn errors_entry(errors: &JsonValue) -> anyhow::Result<ArchiveEntry> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(json::stringify_pretty(errors.clone(), 2).as_bytes())?;
+    file.flush()?;
+    Ok((file.into_temp_path(), IdChain::default(), ERRORS_ENTRY_NAME.to_string(), "application/json".to_string()))
+}
+
+/// Creates the archive entry holding an original file at the root of its outputs.
+///
+/// The entry's file is a hard link to the original, so the original isn't copied unless it's on another file system.
+/// The entry is named [`ORIGINAL_ENTRY_STEM`] with the extension of the file's name, or of its MIME type if the name
+/// has none.
+///
+async fn original_entry(path: &Path, chain: IdChain, name: &str, mimetype: &str) -> anyhow::Result<ArchiveEntry> {
+    let copy = NamedTempFile::new()?.into_temp_path();
+    tokio::fs::remove_file(&copy).await?;
+    if let Err(err) = tokio::fs::hard_link(path, &copy).await {
+        debug!("Copying original {:?} that failed to be linked: {}", path, err);
+        tokio::fs::copy(path, &copy).await?;
+    }
+
+    let extension = Path::new(name).extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .or_else(|| crate::mimetype_to_extension(mimetype).map(str::to_string));
+    let name = match extension {
+        Some(extension) => format!("{}.{}", ORIGINAL_ENTRY_STEM, extension),
+        None => ORIGINAL_ENTRY_STEM.to_string(),
+    };
+    Ok((copy, chain, name, mimetype.to_string()))
+}
+
+/// Detects the MIME type of an embedded file from its contents if its declared MIME type is generic.
+///
+/// The declared MIME type is kept if detection fails.
+///
+async fn redetect_mimetype(mut data: ProcessOutputData) -> ProcessOutputData {
+    if !GENERIC_MIMETYPES.contains(&data.mimetype.as_str()) {
+        return data;
+    }
+
+    match identify_mimetype(&data.path).await {
+        Ok(Some(mimetype)) => {
+            debug!("Detected MIME type {} of embedded file {} declared as {}", mimetype, data.name, data.mimetype);
+            data.mimetype = mimetype;
+        },
+        Ok(None) => {},
+        Err(err) => warn!("Failed to detect MIME type of embedded file {}: {}", data.name, err),
+    }
+    data
+}
+
+/// Runs the post-processing hook on an output on a blocking thread, as the hook may block.
+///
+async fn run_post_process(hook: PostProcessHook, mut output: ProcessOutput) -> anyhow::Result<ProcessOutput> {
+    tokio::task::spawn_blocking(move || {
+        hook.run(&mut output)?;
+        Ok(output)
+    }).await?
+}
+
+/// Whether an embedded file is processed recursively, rather than only added to the archive.
+///
+/// Embedded files are processed if their MIME type is allowed and they're no deeper than `max_depth`. Attachments are
+/// never processed.
+///
+fn recurses(state: &ProcessState, data: &ProcessOutputData, ctx: &ProcessContext, max_depth: Option<usize>) -> bool {
+    let depth = state.name_chain.len() + 1;
+    ctx.is_mimetype_allowed(&data.mimetype)
+        && !data.types.contains(&ProcessType::Attachments)
+        && max_depth.is_none_or(|max_depth| depth <= max_depth)
+}
+
+/// Future for building the archive by reading from received `entries`.
+///
+/// Entries are added as they're received, unless their path can only be determined once all entries have been received.
+/// All entries are nested under the `prefix` directory. If the archive is `flatten`ed, entries are put directly under
+/// it; see [`EntryNaming::flat_entry_path`].
+///
+/// If the archive is `deterministic`, all entries are added once they've been received, sorted by the IDs of the files
+/// leading to them and their names.
+///
+/// If a `kept_temp_dir` is given, the files of the entries are moved into it rather than deleted once added.
+///
+async fn build_archive(
+    mut entries: Receiver<ArchiveEntry>,
+    settings: ArchiveSettings,
+    mut archive_writer: ArchiveWriter<'_>,
+) -> anyhow::Result<Option<File>> {
+    let ArchiveSettings { entry_naming, flatten, prefix, deterministic, kept_temp_dir } = settings;
+    let mut pending = vec![];
+    while let Some((path, chain, name, mimetype)) = entries.recv().await {
+        match entry_path(&entry_naming, flatten, &chain, &name).filter(|_| !deterministic) {
+            Some(zip_path) => push_entry(&mut archive_writer, path, prefix.join(zip_path), mimetype, &kept_temp_dir).await?,
+            None => pending.push(((path, mimetype), (chain, name))),
+        }
+    }
+
+    if deterministic {
+        pending.sort_by(|(_, (chain, name)), (_, (other_chain, other_name))| {
+            chain.ids().cmp(other_chain.ids()).then_with(|| name.cmp(other_name))
+        });
+    }
+
+    let (files, entries): (Vec<_>, Vec<_>) = pending.into_iter().unzip();
+    for ((path, mimetype), zip_path) in files.into_iter().zip(entry_paths(&entry_naming, flatten, &entries)) {
+        push_entry(&mut archive_writer, path, prefix.join(zip_path), mimetype, &kept_temp_dir).await?;
+    }
+
+    let mut file = archive_writer.finish().await?;
+    if let Some(file) = &mut file {
+        file.seek(SeekFrom::Start(0))?;
+    }
+    Ok(file)
+}
+
+/// Adds an entry to the archive, first moving its file into the `kept_temp_dir` if there's one.
+///
+async fn push_entry(
+    archive_writer: &mut ArchiveWriter<'_>,
+    path: TempPath,
+    zip_path: PathBuf,
+    mimetype: String,
+    kept_temp_dir: &Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let Some(kept_temp_dir) = kept_temp_dir else {
+        return archive_writer.push(path, zip_path, mimetype).await;
+    };
+
+    let kept_path = kept_temp_dir.join(&zip_path);
+    if let Some(parent) = kept_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Renaming fails across file systems, so the file is copied instead
+    if let Err(err) = path.persist(&kept_path) {
+        std::fs::copy(&err.path, &kept_path)?;
+    }
+    info!("Kept output file {:?} at {:?}", zip_path, kept_path);
+    archive_writer.push(kept_path, zip_path, mimetype).await
+}
+
+/// Builds the path of an archive entry, or returns [`None`] if it depends on the other entries.
+///
+fn entry_path(entry_naming: &EntryNaming, flatten: bool, chain: &IdChain, name: &str) -> Option<PathBuf> {
+    match flatten {
+        true => Some(EntryNaming::flat_entry_path(chain, name)),
+        false => entry_naming.entry_path(chain, name),
+    }
+}
+
+/// Builds the paths of the archive entries, resolving them from all the entries if needed.
+///
+fn entry_paths(entry_naming: &EntryNaming, flatten: bool, entries: &[(IdChain, String)]) -> Vec<PathBuf> {
+    entries.iter()
+        .map(|(chain, name)| entry_path(entry_naming, flatten, chain, name))
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_else(|| EntryNaming::resolve_paths(entries))
+}
+
+/// Writes archive entries either directly to the archive, to a staging directory that's zipped once finished, or to
+/// an archive streamed into a writer as its entries are added.
+///
+enum ArchiveWriter<'a> {
+    Incremental(ArchiveBuilder),
+    Staged(Arc<StagingArchiveBuilder>, Vec<JoinHandle<anyhow::Result<()>>>),
+    Streaming(ArchiveBuilder<StreamingArchiveWriter>, CommittedBytes, &'a mut (dyn AsyncWrite + Send + Unpin)),
+}
+
+impl<'a> ArchiveWriter<'a> {
+    fn new(staged: bool, compression: CompressionPolicy, deterministic: bool) -> anyhow::Result<Self> {
+        let file = tempfile::tempfile()?;
+        Ok(match staged {
+            true => {
+                let builder = StagingArchiveBuilder::new(file)?.compression(compression);
+                ArchiveWriter::Staged(Arc::new(builder), vec![])
+            },
+            false => {
+                let builder = ArchiveBuilder::new(file)?.compression(compression).deterministic(deterministic);
+                ArchiveWriter::Incremental(builder)
+            },
+        })
+    }
+
+    fn streaming(compression: CompressionPolicy, writer: &'a mut (dyn AsyncWrite + Send + Unpin)) -> anyhow::Result<Self> {
+        let streaming_writer = StreamingArchiveWriter::new();
+        let committed = streaming_writer.committed_bytes();
+        let builder = ArchiveBuilder::new(streaming_writer)?.compression(compression);
+        Ok(ArchiveWriter::Streaming(builder, committed, writer))
+    }
+
+    /// Adds an entry to the archive, or starts staging it in the background.
+    ///
+    async fn push<P>(&mut self, path: P, zip_path: PathBuf, mimetype: String) -> anyhow::Result<()>
+    where
+        P: AsRef<Path> + Send + 'static,
+    {
+        debug!("Adding archive entry {:?}", zip_path);
+        match self {
+            ArchiveWriter::Incremental(builder) => builder.push_with_mimetype(path, zip_path, Some(&mimetype)),
+            ArchiveWriter::Staged(builder, staging) => {
+                let builder = builder.clone();
+                // A temporary file is removed once it's been staged
+                staging.push(tokio::task::spawn_blocking(move || {
+                    builder.push_with_mimetype(path, zip_path, Some(&mimetype))
+                }));
+                Ok(())
+            },
+            ArchiveWriter::Streaming(builder, committed, writer) => {
+                builder.push_with_mimetype(path, zip_path, Some(&mimetype))?;
+                write_committed(committed, writer).await
+            },
+        }
+    }
+
+    /// Finishes the archive, returning its file unless it was streamed into a writer.
+    ///
+    async fn finish(self) -> anyhow::Result<Option<File>> {
+        match self {
+            ArchiveWriter::Incremental(mut builder) => builder.build().map(Some),
+            ArchiveWriter::Staged(builder, staging) => {
+                for result in join_all(staging).await {
+                    result??;
+                }
+                Arc::into_inner(builder)
+                    .ok_or(anyhow!("archive entries are still being staged"))?
+                    .build()
+                    .map(Some)
+            },
+            ArchiveWriter::Streaming(mut builder, committed, writer) => {
+                builder.build()?.commit()?;
+                write_committed(&committed, writer).await?;
+                writer.flush().await?;
+                Ok(None)
+            },
+        }
+    }
+}
+
+/// Creates the context the file is processed with from the options.
+///
+fn process_context(options: ProcessOptions, output_sink: Sender<anyhow::Result<ProcessOutput>>) -> ProcessContext {
+    ProcessContextBuilder::new(
+        options.mimetype,
+        options.types,
+        output_sink,
+    )
+        .max_input_bytes(options.max_input_bytes)
+        .max_output_bytes(options.max_output_bytes)
+        .detect_language(options.detect_language)
+        .mimetype_allowlist(options.mimetype_allowlist)
+        .keep_filtered(options.keep_filtered)
+        .keep_junk_files(options.keep_junk_files)
+        .raw_mbox_messages(options.raw_mbox_messages)
+        .compress_text(options.compress_text)
+        .line_ending(options.line_ending)
+        .preview_chars(options.preview_chars)
+        .message_headers(options.message_headers)
+        .suppressed_headers(options.suppressed_headers)
+        .append_pdf_attachments(options.append_pdf_attachments)
+        .id_chain(options.id_chain)
+        .redetect_generic_mimetypes(options.redetect_generic_mimetypes)
+        .trust_content(options.trust_content)
+        .skip_checksum(options.skip_checksum)
+        .dedupe_strategies(options.dedupe_strategies)
+        .output_names(options.output_names)
+        .metadata_format(options.metadata_format)
+        .build()
+}
+
+/// Copies the bytes of the archive committed so far into the writer, in chunks, since a committed entry may be too
+/// large to be read into memory.
+///
+async fn write_committed(
+    committed: &CommittedBytes,
+    writer: &mut (dyn AsyncWrite + Send + Unpin),
+) -> anyhow::Result<()> {
+    let mut chunk = vec![0; 64 * 1024];
+    for mut bytes in committed.take() {
+        loop {
+            let len = bytes.read(&mut chunk)?;
+            if len == 0 {
+                break;
+            }
+            writer.write_all(&chunk[..len]).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Pairs the names and IDs of the embedded files leading to an output.
+///
+/// IDs supplied by the caller as a prefix of the ID chain have no names, and are left out.
+///
+#[inline]
+fn chain_links(state: ProcessState) -> IdChain {
+    let prefix_len = state.id_chain.len().saturating_sub(state.name_chain.len());
+    IdChain::new(state.name_chain.into_iter().zip(state.id_chain.into_iter().skip(prefix_len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Read, Seek};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::io::AsyncReadExt;
+
+    use zip::{CompressionMethod, ZipArchive, ZipWriter};
+    use zip::write::FileOptions;
+
+    use identify::deduplication::{dedupe_checksum, dedupe_checksum_with_strategy, DedupeStrategy};
+
+    use crate::naming::{ArchiveRoot, OutputNameTemplates};
+    use crate::processing::LineEnding;
+
+    use super::*;
+
+    #[test]
+    fn test_process_blocking() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .recurse(false)
+            .build();
+        let archive = process_blocking(PathBuf::from("../resources/mbox/ubuntu-no-small.mbox"), options)?;
+
+        let archive = ZipArchive::new(archive)?;
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+
+        assert_eq!(names, vec![
+            "88dde30cbe134ce0dd8aa0979546646a/mbox-message.eml",
+            "c694e99230b3cbf36d8aef4131596864/mbox-message.eml",
+        ]);
+        Ok(())
+    }
+
+    fn process_attachments_mbox(keep_filtered: bool) -> anyhow::Result<Vec<String>> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .mimetype_allowlist(Some(vec!["message/rfc822".to_string()]))
+            .keep_filtered(keep_filtered)
+            .build();
+        let archive = process_blocking(PathBuf::from("../resources/mbox/attachments.mbox"), options)?;
+
+        let archive = ZipArchive::new(archive)?;
+        let mut names: Vec<String> = archive.file_names()
+            .map(|name| name.rsplit('/').next().unwrap().to_string())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    #[tokio::test]
+    async fn test_process_archive_root() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/mbox/ubuntu-no-small.mbox");
+        let roots = [
+            (ArchiveRoot::Label("batch-7".to_string()), "batch-7/parent/"),
+            (ArchiveRoot::InputStem, "ubuntu-no-small/parent/"),
+        ];
+
+        for (archive_root, expected_prefix) in roots {
+            let options = ProcessOptionsBuilder::new("application/mbox")
+                .types(vec![ProcessType::Embedded])
+                .recurse(false)
+                .id_chain(vec!["parent".to_string()])
+                .archive_root(Some(archive_root))
+                .build();
+            let archive = process_with_options(path.clone(), options).await?;
+
+            let names: Vec<String> = archive_contents(archive)?.into_iter().map(|(name, _)| name).collect();
+            assert_eq!(names.len(), 2);
+            assert!(names.iter().all(|name| name.starts_with(expected_prefix)), "{:?}", names);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_include_original() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/rfc822/attachments.eml");
+        let options = ProcessOptionsBuilder::new("message/rfc822")
+            .types(vec![ProcessType::Metadata, ProcessType::Embedded])
+            .keep_embedded(false)
+            .include_original(true)
+            .build();
+        let archive = process_with_options(path.clone(), options).await?;
+
+        let contents = archive_contents(archive)?;
+        let original = contents.iter().find(|(name, _)| name == "original.eml").expect("expected the original file");
+        assert_eq!(original.1, std::fs::read(&path)?);
+        let embedded = contents.iter()
+            .find(|(name, _)| name.ends_with("/original.png"))
+            .expect("expected the original embedded file");
+        assert_eq!(embedded.1.len(), 70);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_channel_capacity() -> anyhow::Result<()> {
+        let mut mbox = NamedTempFile::new()?;
+        for i in 0..50 {
+            write!(mbox, "\
+From sender@example.com Mon Oct  2 09:00:00 2023
+From: Sender <sender@example.com>
+Subject: Message {i}
+Message-ID: <message-{i}@example.com>
+
+Message {i}
+
+")?;
+        }
+        mbox.flush()?;
+
+        let handled = Arc::new(AtomicUsize::new(0));
+        let counter = handled.clone();
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .recurse(false)
+            .channel_capacity(1)
+            .post_process(Some(PostProcessHook::new(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })))
+            .build();
+        let path = mbox.path().to_path_buf();
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        let processing = tokio::spawn(async move {
+            let archive_writer = ArchiveWriter::streaming(options.compression, &mut writer)?;
+            process_into_archive(path, options, archive_writer, None).await
+        });
+
+        // Nothing reads the archive yet, so processing waits once the queues are full
+        tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+        let stalled = handled.load(Ordering::SeqCst);
+        assert!(stalled < 10, "handled {} outputs while the archive wasn't read", stalled);
+
+        let mut streamed = vec![];
+        reader.read_to_end(&mut streamed).await?;
+        processing.await??;
+        assert_eq!(handled.load(Ordering::SeqCst), 50);
+        assert_eq!(archive_contents(std::io::Cursor::new(streamed))?.len(), 50);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_channel_capacity_recursing() -> anyhow::Result<()> {
+        let mut mbox = NamedTempFile::new()?;
+        for i in 0..50 {
+            write!(mbox, "\
+From sender@example.com Mon Oct  2 09:00:00 2023
+From: Sender <sender@example.com>
+Subject: Message {i}
+Message-ID: <message-{i}@example.com>
+
+Message {i}
+
+")?;
+        }
+        mbox.flush()?;
+
+        let handled = Arc::new(AtomicUsize::new(0));
+        let counter = handled.clone();
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .channel_capacity(1)
+            .max_concurrent_recursions(1)
+            .post_process(Some(PostProcessHook::new(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })))
+            .build();
+        let path = mbox.path().to_path_buf();
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        let processing = tokio::spawn(async move {
+            let archive_writer = ArchiveWriter::streaming(options.compression, &mut writer)?;
+            process_into_archive(path, options, archive_writer, None).await
+        });
+
+        // Embedded files processed recursively wait for the archive too, rather than piling up in front of it
+        tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+        let stalled = handled.load(Ordering::SeqCst);
+        assert!(stalled < 10, "handled {} outputs while the archive wasn't read", stalled);
+
+        let mut streamed = vec![];
+        reader.read_to_end(&mut streamed).await?;
+        processing.await??;
+        assert_eq!(handled.load(Ordering::SeqCst), 50);
+        assert_eq!(archive_contents(std::io::Cursor::new(streamed))?.len(), 50);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_flatten() -> anyhow::Result<()> {
+        // Two messages, each with an attachment of the same name
+        let mut mbox = NamedTempFile::new()?;
+        for i in 0..2 {
+            write!(mbox, "\
+From sender@example.com Mon Oct  2 09:00:00 2023
+From: Sender <sender@example.com>
+Subject: Message {i}
+Message-ID: <message-{i}@example.com>
+MIME-Version: 1.0
+Content-Type: multipart/mixed; boundary=\"BOUNDARY\"
+
+--BOUNDARY
+Content-Type: text/plain
+
+Message {i}
+--BOUNDARY
+Content-Type: text/plain
+Content-Disposition: attachment; filename=\"note.txt\"
+
+Note {i}
+--BOUNDARY--
+
+")?;
+        }
+        mbox.flush()?;
+
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .id_chain(vec!["parent".to_string()])
+            .flatten(true)
+            .build();
+        let archive = process_with_options(mbox.path().to_path_buf(), options).await?;
+
+        let names: Vec<String> = archive_contents(archive)?.into_iter().map(|(name, _)| name).collect();
+        let unique: std::collections::HashSet<&String> = names.iter().collect();
+        assert_eq!(names.len(), 4);
+        assert_eq!(unique.len(), names.len());
+        assert!(names.iter().all(|name| !name.contains('/')));
+        assert_eq!(names.iter().filter(|name| name.ends_with("-mbox-message.eml")).count(), 2);
+        assert_eq!(names.iter().filter(|name| name.ends_with("-note.txt")).count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_mimetype_allowlist() -> anyhow::Result<()> {
+        assert_eq!(process_attachments_mbox(false)?, vec!["forwarded.eml", "mbox-message.eml"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_mimetype_allowlist_keep_filtered() -> anyhow::Result<()> {
+        assert_eq!(process_attachments_mbox(true)?, vec!["forwarded.eml", "mbox-message.eml", "pixel.png"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_attachments() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("message/rfc822")
+            .types(vec![ProcessType::Attachments])
+            .keep_embedded(false)
+            .build();
+        let archive = process_with_options(PathBuf::from("../resources/rfc822/attachments.eml"), options).await?;
+
+        let mut contents: Vec<(String, Vec<u8>)> = archive_contents(archive)?.into_iter()
+            .map(|(name, content)| (name.rsplit('/').next().unwrap().to_string(), content))
+            .collect();
+        contents.sort();
+
+        let message = std::fs::read_to_string("../resources/rfc822/attachments.eml")?;
+        let forwarded = message.split_once("filename=\"forwarded.eml\"\r\n\r\n").unwrap().1
+            .split_once("\r\n--BOUNDARY--").unwrap().0;
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0].0, "forwarded.eml");
+        assert_eq!(contents[0].1, forwarded.as_bytes());
+        assert_eq!(contents[1].0, "pixel.png");
+        assert!(contents[1].1.starts_with(b"\x89PNG\r\n"));
+        assert_eq!(contents[1].1.len(), 70);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_max_concurrent_recursions() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .mimetype_allowlist(Some(vec!["message/rfc822".to_string()]))
+            .max_concurrent_recursions(1)
+            .build();
+        let summary = runtime().block_on(process_with_summary(
+            PathBuf::from("../resources/mbox/attachments.mbox"),
+            options,
+        ))?;
+
+        let archive = ZipArchive::new(summary.archive)?;
+        let mut names: Vec<&str> = archive.file_names()
+            .map(|name| name.rsplit('/').next().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["forwarded.eml", "mbox-message.eml"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_recursion_reuses_runtime() -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("caller-runtime")
+            .enable_all()
+            .build()?;
+        let threads = Arc::new(Mutex::new(vec![]));
+        let recorded = threads.clone();
+        let options = ProcessOptionsBuilder::new("application/zip")
+            .types(vec![ProcessType::Embedded])
+            .max_concurrent_recursions(1)
+            .post_process(Some(PostProcessHook::new(move |_| {
+                let name = std::thread::current().name().map(String::from);
+                recorded.lock().unwrap_or_else(PoisonError::into_inner).push(name);
+                Ok(())
+            })))
+            .build();
+        runtime.block_on(process_bytes(nested_zip()?, options))?;
+
+        // Both the embedded zip and the file embedded in it are handled by tasks of the caller's runtime
+        let threads = threads.lock().unwrap_or_else(PoisonError::into_inner);
+        assert_eq!(threads.len(), 2);
+        assert!(threads.iter().all(|name| name.as_deref() == Some("caller-runtime")), "{:?}", threads);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_metadata_only() -> anyhow::Result<()> {
+        let archive = process_metadata_only(
+            PathBuf::from("../resources/mbox/ubuntu-no-small.mbox"),
+            "application/mbox",
+            true,
+        ).await?;
+
+        let archive = ZipArchive::new(archive)?;
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, vec![
+            "88dde30cbe134ce0dd8aa0979546646a/metadata.json",
+            "c694e99230b3cbf36d8aef4131596864/metadata.json",
+            "metadata.json",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_summary() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .recurse(false)
+            .build();
+        let summary = runtime().block_on(process_with_summary(
+            PathBuf::from("../resources/mbox/ubuntu-no-small.mbox"),
+            options,
+        ))?;
+
+        assert_eq!(summary.embedded_count, 2);
+        assert_eq!(summary.output_count, 0);
+        assert_eq!(summary.skipped_count, 0);
+        assert_eq!(ZipArchive::new(summary.archive)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_with_options_id_chain() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .recurse(false)
+            .id_chain(vec!["case-1".to_string(), "custodian-7".to_string()])
+            .build();
+        let archive = runtime().block_on(process_with_options(
+            PathBuf::from("../resources/mbox/ubuntu-no-small.mbox"),
+            options,
+        ))?;
+
+        let archive = ZipArchive::new(archive)?;
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+
+        assert_eq!(names, vec![
+            "case-1/custodian-7/88dde30cbe134ce0dd8aa0979546646a/mbox-message.eml",
+            "case-1/custodian-7/c694e99230b3cbf36d8aef4131596864/mbox-message.eml",
+        ]);
+        Ok(())
+    }
+
+    fn process_attachments_mbox_to_depth(max_depth: usize) -> anyhow::Result<Vec<String>> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .max_depth(Some(max_depth))
+            .entry_naming(EntryNaming::OriginalName)
+            .build();
+        let archive = runtime().block_on(process_with_options(
+            PathBuf::from("../resources/mbox/attachments.mbox"),
+            options,
+        ))?;
+
+        let archive = ZipArchive::new(archive)?;
+        let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn test_process_with_options_max_depth() -> anyhow::Result<()> {
+        assert_eq!(process_attachments_mbox_to_depth(0)?, vec!["mbox-message/mbox-message.eml"]);
+        assert_eq!(process_attachments_mbox_to_depth(1)?, vec![
+            "mbox-message/forwarded/forwarded.eml",
+            "mbox-message/mbox-message.eml",
+            "mbox-message/pixel/pixel.png",
+        ]);
+        Ok(())
+    }
+
+    fn process_octet_stream_attachment(redetect_generic_mimetypes: bool) -> anyhow::Result<Vec<String>> {
+        let options = ProcessOptionsBuilder::new("message/rfc822")
+            .types(vec![ProcessType::Embedded])
+            .redetect_generic_mimetypes(redetect_generic_mimetypes)
+            .entry_naming(EntryNaming::OriginalName)
+            .build();
+        let archive = runtime().block_on(process_with_options(
+            PathBuf::from("../resources/rfc822/octet-stream-attachment.eml"),
+            options,
+        ))?;
+
+        let archive = ZipArchive::new(archive)?;
+        let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn test_process_generic_mimetype() -> anyhow::Result<()> {
+        assert_eq!(process_octet_stream_attachment(false)?, vec!["invoice/invoice.pdf"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_redetect_generic_mimetypes() -> anyhow::Result<()> {
+        // Only the PDF embedded processor extracts the embedded invoice XML
+        assert_eq!(process_octet_stream_attachment(true)?, vec![
+            "invoice/factur-x/factur-x.xml",
+            "invoice/invoice.pdf",
+        ]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_post_process() -> anyhow::Result<()> {
+        let rename = PostProcessHook::new(|output| {
+            let data = match output {
+                ProcessOutput::Processed(_, data) => data,
+                ProcessOutput::Embedded(_, data, _) => data,
+            };
+            data.name = format!("renamed-{}", data.name);
+            Ok(())
+        });
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .max_depth(Some(1))
+            .entry_naming(EntryNaming::OriginalName)
+            .post_process(Some(rename))
+            .build();
+        let summary = process_with_summary(PathBuf::from("../resources/mbox/attachments.mbox"), options).await?;
+
+        assert_eq!(summary.embedded_count, 3);
+        let names: Vec<String> = archive_contents(summary.archive)?.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec![
+            "renamed-mbox-message/renamed-forwarded/renamed-forwarded.eml",
+            "renamed-mbox-message/renamed-mbox-message.eml",
+            "renamed-mbox-message/renamed-pixel/renamed-pixel.png",
+        ]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_output_names() -> anyhow::Result<()> {
+        let output_names = OutputNameTemplates {
+            metadata: Some("{stem}.metadata.json".to_string()),
+            ..OutputNameTemplates::default()
+        };
+        let options = ProcessOptionsBuilder::new("message/rfc822")
+            .types(vec![ProcessType::Metadata, ProcessType::Embedded])
+            .entry_naming(EntryNaming::OriginalName)
+            .output_names(output_names)
+            .build();
+        let summary = process_with_summary(PathBuf::from("../resources/rfc822/calendar-invite.eml"), options).await?;
+
+        let names: Vec<String> = archive_contents(summary.archive)?.into_iter().map(|(name, _)| name).collect();
+        assert!(names.contains(&"invite/invite.ics".to_string()));
+        assert!(names.contains(&"invite/invite.metadata.json".to_string()));
+        assert!(!names.contains(&"invite/metadata.json".to_string()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_forwarded_message() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("message/rfc822")
+            .types(vec![ProcessType::Text, ProcessType::Embedded])
+            .build();
+        let summary = process_with_summary(PathBuf::from("../resources/rfc822/forwarded-global.eml"), options).await?;
+
+        let contents = archive_contents(summary.archive)?;
+        let (message_name, _) = contents.iter()
+            .find(|(name, _)| name.ends_with(".eml"))
+            .expect("expected the forwarded message");
+        let (id, _) = message_name.split_once('/').unwrap();
+        let text = contents.iter()
+            .find(|(name, _)| name == &format!("{}/extracted.txt", id))
+            .map(|(_, content)| String::from_utf8_lossy(content).to_string())
+            .expect("expected the text of the forwarded message");
+        assert!(text.contains("These are the original notes."));
+        assert!(!text.contains("Forwarding Bob's notes."));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_post_process_error() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .recurse(false)
+            .post_process(Some(PostProcessHook::new(|_| Err(anyhow!("rejected")))))
+            .build();
+        let summary = process_with_summary(PathBuf::from("../resources/mbox/ubuntu-no-small.mbox"), options).await?;
+
+        assert_eq!(summary.embedded_count, 0);
+        assert_eq!(summary.skipped_count, 2);
+        let contents = archive_contents(summary.archive)?;
+        assert_eq!(contents.len(), 1);
+        let errors = json::parse(std::str::from_utf8(&contents[0].1)?)?;
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["stage"], "post-processing");
+        assert_eq!(errors[0]["message"], "rejected");
+        assert_eq!(errors[0]["id_chain"].len(), 1);
+        assert!(errors[0]["name"].is_string());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_deterministic_archive() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/mbox/attachments.mbox");
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .entry_naming(EntryNaming::OriginalName)
+            .stage_archive_entries(true)
+            .deterministic_archive(true)
+            .build();
+
+        let mut first = vec![];
+        process_with_options(path.clone(), options.clone()).await?.read_to_end(&mut first)?;
+        let mut second = vec![];
+        process_with_options(path, options).await?.read_to_end(&mut second)?;
+
+        assert_eq!(ZipArchive::new(std::io::Cursor::new(&first))?.len(), 3);
+        assert_eq!(first, second);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_dedupe_strategies() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .recurse(false)
+            .dedupe_strategies(HashMap::from([("message/rfc822".to_string(), DedupeStrategy::Content)]))
+            .build();
+        let archive = process_with_options(PathBuf::from("../resources/mbox/ubuntu-no-small.mbox"), options).await?;
+
+        let contents = archive_contents(archive)?;
+        assert_eq!(contents.len(), 2);
+        for (name, content) in contents {
+            let by_content = dedupe_checksum_with_strategy(&mut content.as_slice(), DedupeStrategy::Content).await?;
+            let by_message = dedupe_checksum(&mut content.as_slice(), "message/rfc822").await?;
+            assert!(name.starts_with(&format!("{}/", by_content)), "{}", name);
+            assert_ne!(by_content, by_message);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_skip_checksum() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .max_depth(Some(1))
+            .skip_checksum(true)
+            .build();
+        let summary = process_with_summary(PathBuf::from("../resources/mbox/attachments.mbox"), options).await?;
+
+        // The embedded files are still processed recursively, nested under their placeholder IDs
+        let names: Vec<String> = archive_contents(summary.archive)?.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.iter().any(|name| name.ends_with("/pixel.png") && name.split('/').count() == 3));
+        assert!(names.iter().all(|name| !name.contains("88dde30cbe134ce0dd8aa0979546646a")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_wide_mbox() -> anyhow::Result<()> {
+        // Far more embedded files than fit in the output, recursion, and archive entry channels at once
+        let mut mbox = NamedTempFile::new()?;
+        for i in 0..500 {
+            write!(mbox, "\
+From sender@example.com Mon Oct  2 09:00:00 2023
+From: Sender <sender@example.com>
+Subject: Message {i}
+Message-ID: <message-{i}@example.com>
+MIME-Version: 1.0
+Content-Type: multipart/mixed; boundary=\"BOUNDARY\"
+
+--BOUNDARY
+Content-Type: text/plain
+
+Message {i}
+--BOUNDARY
+Content-Type: text/plain
+Content-Disposition: attachment; filename=\"note-{i}.txt\"
+
+Note {i}
+--BOUNDARY--
+
+")?;
+        }
+        mbox.flush()?;
+
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .build();
+        let processing = process_with_summary(mbox.path().to_path_buf(), options);
+        let summary = tokio::time::timeout(std::time::Duration::from_secs(120), processing).await??;
+
+        assert_eq!(summary.embedded_count, 1000);
+        assert_eq!(archive_contents(summary.archive)?.len(), 1000);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_max_total_outputs() -> anyhow::Result<()> {
+        let mut mbox = NamedTempFile::new()?;
+        for i in 0..50 {
+            write!(mbox, "\
+From sender@example.com Mon Oct  2 09:00:00 2023
+From: Sender <sender@example.com>
+Subject: Message {i}
+Message-ID: <message-{i}@example.com>
+
+Message {i}
+
+")?;
+        }
+        mbox.flush()?;
+
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .recurse(false)
+            .max_total_outputs(Some(10))
+            .build();
+        let summary = process_with_summary(mbox.path().to_path_buf(), options).await?;
+
+        assert!(summary.outputs_truncated);
+        assert_eq!(summary.embedded_count, 10);
+        assert_eq!(summary.skipped_count, 40);
+        let contents = archive_contents(summary.archive)?;
+        assert_eq!(contents.len(), 11);
+        let errors = contents.iter().find(|(name, _)| name == ERRORS_ENTRY_NAME).expect("expected an errors entry");
+        let errors = json::parse(std::str::from_utf8(&errors.1)?)?;
+        assert_eq!(errors[0]["stage"], "limits");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_outputs_records_errors() -> anyhow::Result<()> {
+        let (output_sink, outputs) = tokio::sync::mpsc::channel(10);
+        let (archive_entry_sink, archive_entries) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/pdf", vec![], output_sink)
+            .id_chain(vec!["a1b2c3".to_string()])
+            .name_chain(vec!["scan.pdf".to_string()])
+            .build();
+        let output_settings = OutputSettings::new(&ProcessOptionsBuilder::new("application/pdf").build());
+        let output_handling = tokio::spawn(handle_outputs(outputs, archive_entry_sink, output_settings));
+        let archive_writer = ArchiveWriter::new(false, CompressionPolicy::default(), false)?;
+        let archive_settings = ArchiveSettings {
+            entry_naming: EntryNaming::Checksum,
+            flatten: false,
+            prefix: PathBuf::new(),
+            deterministic: false,
+            kept_temp_dir: None,
+        };
+        let archive = tokio::spawn(build_archive(archive_entries, archive_settings, archive_writer));
+
+        // As sent by a failing processor
+        ctx.add_output(Err(anyhow!("Stub processor failed: boom"))).await?;
+        drop(ctx);
+
+        assert_eq!(output_handling.await?.skipped_count, 1);
+        let contents = archive_contents(archive.await??.expect("expected an archive file"))?;
+        assert_eq!(contents.len(), 1);
+        assert_eq!(contents[0].0, ERRORS_ENTRY_NAME);
+        let errors = json::parse(std::str::from_utf8(&contents[0].1)?)?;
+        assert_eq!(errors, json::array! [{
+            "stage": "processing",
+            "id_chain": ["a1b2c3"],
+            "name": "scan.pdf",
+            "message": "Stub processor failed: boom",
+        }]);
+        Ok(())
+    }
+
+    async fn process_mbox_range_names(path: &Path, start: u64, end: u64) -> anyhow::Result<Vec<String>> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .recurse(false)
+            .build();
+        let archive = process_mbox_range(path.to_path_buf(), start, end, options).await?;
+        Ok(archive_contents(archive)?.into_iter().map(|(name, _)| name).collect())
+    }
+
+    #[tokio::test]
+    async fn test_process_mbox_range() -> anyhow::Result<()> {
+        let path = Path::new("../resources/mbox/reply-chain.mbox");
+        let len = std::fs::metadata(path)?.len();
+        let offsets = mbox_message_offsets(path)?;
+        assert_eq!(offsets.len(), 4);
+        assert_eq!(offsets[0], 0);
+
+        let all = process_mbox_range_names(path, 0, len).await?;
+        assert_eq!(all.len(), 4);
+
+        // Split both at a message boundary and in the middle of a message
+        for split in [offsets[2], offsets[2] + 10] {
+            let mut names = process_mbox_range_names(path, 0, split).await?;
+            names.extend(process_mbox_range_names(path, split, len).await?);
+            names.sort();
+
+            assert_eq!(names, all);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_mbox_range_without_messages() -> anyhow::Result<()> {
+        let path = Path::new("../resources/mbox/reply-chain.mbox");
+        let offsets = mbox_message_offsets(path)?;
+
+        assert!(process_mbox_range_names(path, offsets[1] + 1, offsets[2]).await?.is_empty());
+        Ok(())
+    }
+
+    /// Reads the names and contents of all entries in the archive, sorted by name.
+    ///
+    fn archive_contents(archive: impl Read + Seek) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let mut archive = ZipArchive::new(archive)?;
+        let mut contents = vec![];
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let mut content = vec![];
+            entry.read_to_end(&mut content)?;
+            contents.push((entry.name().to_string(), content));
+        }
+        contents.sort();
+        Ok(contents)
+    }
+
+    #[tokio::test]
+    async fn test_process_stage_archive_entries() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/mbox/attachments.mbox");
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .entry_naming(EntryNaming::OriginalName);
+
+        let incremental = process_with_options(path.clone(), options.clone().build()).await?;
+        let staged = process_with_options(path, options.stage_archive_entries(true).build()).await?;
+
+        let contents = archive_contents(staged)?;
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents, archive_contents(incremental)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_keep_temp() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/mbox")
+            .types(vec![ProcessType::Embedded])
+            .keep_temp(true)
+            .build();
+
+        let summary = process_with_summary(PathBuf::from("../resources/mbox/attachments.mbox"), options).await?;
+
+        let kept_temp_dir = summary.kept_temp_dir.expect("expected a kept temp directory");
+        let contents = archive_contents(summary.archive)?;
+        assert_eq!(contents.len(), 3);
+        for (name, content) in contents {
+            assert_eq!(std::fs::read(kept_temp_dir.join(&name))?, content);
+        }
+        std::fs::remove_dir_all(kept_temp_dir)?;
+        Ok(())
+    }
+
+    /// Writes a zip holding a stored zip, which holds a large text file, so the inner zip is far larger than the
+    /// outer one.
+    ///
+    fn nested_zip() -> anyhow::Result<Vec<u8>> {
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut inner = ZipWriter::new(std::io::Cursor::new(vec![]));
+        inner.start_file("notes.txt", stored)?;
+        inner.write_all(&[b'a'; 50_000])?;
+        let inner = inner.finish()?.into_inner();
+
+        let mut outer = ZipWriter::new(std::io::Cursor::new(vec![]));
+        outer.start_file("inner.zip", FileOptions::default())?;
+        outer.write_all(&inner)?;
+        Ok(outer.finish()?.into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_process_embedded_inherits_limits() -> anyhow::Result<()> {
+        let zip = nested_zip()?;
+        assert!(zip.len() < 10_000);
+
+        for (max_input_bytes, expected) in [(None, vec!["inner.zip", "notes.txt"]), (Some(10_000), vec![ERRORS_ENTRY_NAME, "inner.zip"])] {
+            let options = ProcessOptionsBuilder::new("application/zip")
+                .types(vec![ProcessType::Embedded])
+                .max_input_bytes(max_input_bytes)
+                .build();
+            let contents = archive_contents(process_bytes(zip.clone(), options).await?)?;
+
+            let mut names: Vec<&str> = contents.iter().map(|(name, _)| name.rsplit('/').next().unwrap()).collect();
+            names.sort();
+            assert_eq!(names, expected);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_records_embedded_errors() -> anyhow::Result<()> {
+        let options = ProcessOptionsBuilder::new("application/zip")
+            .types(vec![ProcessType::Embedded])
+            .max_input_bytes(Some(10_000))
+            .build();
+        let contents = archive_contents(process_bytes(nested_zip()?, options).await?)?;
+
+        let (_, errors) = contents.iter().find(|(name, _)| name == ERRORS_ENTRY_NAME).expect("expected errors.json");
+        let errors = json::parse(std::str::from_utf8(errors)?)?;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["stage"], "processing");
+        assert_eq!(errors[0]["name"], "inner.zip");
+        assert_eq!(errors[0]["id_chain"].len(), 1);
+        assert!(errors[0]["message"].to_string().starts_with("Input too large"), "{}", errors[0]["message"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_embedded_output_carries_context() -> anyhow::Result<()> {
+        let mut zip = NamedTempFile::new()?;
+        zip.write_all(&nested_zip()?)?;
+        let allowlist = Some(vec!["application/zip".to_string()]);
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/zip", vec![ProcessType::Embedded], output_sink)
+            .max_input_bytes(Some(10_000))
+            .max_output_bytes(Some(1_000))
+            .mimetype_allowlist(allowlist.clone())
+            .build();
+
+        processor().process(ctx, zip.path().to_path_buf()).await?;
+
+        let Some(Ok(ProcessOutput::Embedded(_, data, ctx))) = outputs.recv().await else {
+            panic!("expected an embedded output");
+        };
+        assert_eq!(data.name, "inner.zip");
+        assert_eq!(ctx.max_input_bytes, Some(10_000));
+        assert_eq!(ctx.max_output_bytes, Some(1_000));
+        assert_eq!(ctx.mimetype_allowlist, allowlist);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_context_from_options() {
+        let options = ProcessOptionsBuilder::new("message/rfc822")
+            .detect_language(true)
+            .raw_mbox_messages(true)
+            .compress_text(true)
+            .line_ending(Some(LineEnding::Crlf))
+            .append_pdf_attachments(true)
+            .build();
+        let (output_sink, _) = tokio::sync::mpsc::channel(1);
+
+        let ctx = process_context(options, output_sink);
+
+        assert!(ctx.detect_language);
+        assert!(ctx.raw_mbox_messages);
+        assert!(ctx.compress_text);
+        assert_eq!(ctx.line_ending, Some(LineEnding::Crlf));
+        assert!(ctx.append_pdf_attachments);
+    }
+
+    #[tokio::test]
+    async fn test_process_raw_mbox_messages() -> anyhow::Result<()> {
+        let message = b"Subject: Quarterly\n\n>From the start, keep this quoted.\n".as_slice();
+        let mbox = [b"From alice@example.com Tue Oct  3 10:00:00 2023\n".as_slice(), message].concat();
+
+        for raw_mbox_messages in [false, true] {
+            let options = ProcessOptionsBuilder::new("application/mbox")
+                .types(vec![ProcessType::Embedded])
+                .recurse(false)
+                .raw_mbox_messages(raw_mbox_messages)
+                .build();
+            let contents = archive_contents(process_bytes(mbox.clone(), options).await?)?;
+
+            assert_eq!(contents.len(), 1);
+            assert_eq!(contents[0].1 == message, raw_mbox_messages);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_compress_text() -> anyhow::Result<()> {
+        services::external_extractors().register("application/x-rusty-options-test", "cat {input}")?;
+        let options = ProcessOptionsBuilder::new("application/x-rusty-options-test")
+            .types(vec![ProcessType::Text])
+            .compress_text(true)
+            .build();
+
+        let contents = archive_contents(process_bytes(b"Quarterly review".to_vec(), options).await?)?;
+
+        assert_eq!(contents.len(), 1);
+        assert_eq!(contents[0].0, "extracted.txt.gz");
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(contents[0].1.as_slice()).read_to_string(&mut text)?;
+        assert_eq!(text, "Quarterly review");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_line_ending() -> anyhow::Result<()> {
+        services::external_extractors().register("application/x-rusty-line-ending-test", "cat {input}")?;
+        let options = ProcessOptionsBuilder::new("application/x-rusty-line-ending-test")
+            .types(vec![ProcessType::Text])
+            .line_ending(Some(LineEnding::Crlf))
+            .build();
+
+        let contents = archive_contents(process_bytes(b"Quarterly\nreview\r".to_vec(), options).await?)?;
+
+        assert_eq!(contents, vec![("extracted.txt".to_string(), b"Quarterly\r\nreview\r\n".to_vec())]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_bytes() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/rfc822/attachments.eml");
+        let mimetype = "message/rfc822".to_string();
+        let types = vec![ProcessType::Embedded];
+
+        let options = ProcessOptionsBuilder::new(mimetype.clone()).types(types.clone()).build();
+        let from_bytes = process_bytes(std::fs::read(&path)?, options).await?;
+        let from_path = process(path, mimetype, types, true).await?;
+
+        let contents = archive_contents(from_bytes)?;
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents, archive_contents(from_path)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_into() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/rfc822/attachments.eml");
+        let mimetype = "message/rfc822".to_string();
+        let types = vec![ProcessType::Embedded];
+
+        let mut streamed = vec![];
+        process_into(path.clone(), mimetype.clone(), types.clone(), true, &mut streamed).await?;
+        let from_path = process(path, mimetype, types, true).await?;
+
+        let contents = archive_contents(std::io::Cursor::new(streamed))?;
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents, archive_contents(from_path)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_into_with_options() -> anyhow::Result<()> {
+        let path = PathBuf::from("../resources/rfc822/attachments.eml");
+        let options = ProcessOptionsBuilder::new("message/rfc822")
+            .types(vec![ProcessType::Embedded])
+            .entry_naming(EntryNaming::OriginalName)
+            .build();
+
+        let mut streamed = vec![];
+        process_into_with_options(path.clone(), options.clone(), &mut streamed).await?;
+        let from_path = process_with_options(path, options).await?;
+
+        let contents = archive_contents(std::io::Cursor::new(streamed))?;
+        let names: Vec<&str> = contents.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["forwarded/forwarded.eml", "pixel/pixel.png"]);
+        assert_eq!(contents, archive_contents(from_path)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use flate2::bufread::GzDecoder;
+use tempfile::{NamedTempFile, TempPath};
+
+/// The MIME type of gzip files.
+///
+pub(crate) const GZIP_MIMETYPE: &str = "application/gzip";
+
+/// Decompresses a gzip file wrapping a single file into a temporary file.
+///
+/// Returns [`None`] if the gzip file has more than one member, as it doesn't wrap a single file.
+///
+/// At most `max_bytes` + 1 bytes are decompressed if it's given, so files too large once decompressed can be rejected
+/// without decompressing them entirely.
+///
+pub(crate) fn decompress_single_member(path: &Path, max_bytes: Option<u64>) -> anyhow::Result<Option<TempPath>> {
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(path)?));
+    let mut output = NamedTempFile::new()?;
+
+    let limit = max_bytes.map_or(u64::MAX, |max_bytes| max_bytes.saturating_add(1));
+    let size = std::io::copy(&mut (&mut decoder).take(limit), &mut output)?;
+    if size < limit && !decoder.into_inner().fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(output.into_temp_path()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::*;
+
+    fn gzip(members: &[&[u8]]) -> anyhow::Result<NamedTempFile> {
+        let mut file = NamedTempFile::new()?;
+        for member in members {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(member)?;
+            file.write_all(&encoder.finish()?)?;
+        }
+        Ok(file)
+    }
+
+    #[test]
+    fn test_decompress_single_member() -> anyhow::Result<()> {
+        let file = gzip(&[b"hello world"])?;
+
+        let decompressed = decompress_single_member(file.path(), None)?.expect("expected a single member");
+
+        assert_eq!(std::fs::read(decompressed)?, b"hello world");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_multiple_members() -> anyhow::Result<()> {
+        let file = gzip(&[b"hello", b"world"])?;
+
+        assert!(decompress_single_member(file.path(), None)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_over_limit() -> anyhow::Result<()> {
+        let file = gzip(&[b"hello world"])?;
+
+        let decompressed = decompress_single_member(file.path(), Some(5))?.expect("expected a single member");
+
+        assert_eq!(std::fs::read(decompressed)?, b"hello ");
+        Ok(())
+    }
+}
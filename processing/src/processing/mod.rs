@@ -1,12 +1,26 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::anyhow;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::Sender;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
 
+use identify::deduplication::{dedupe_checksum, dedupe_checksum_with_strategy, DedupeStrategy};
+
+use crate::naming::OutputNameTemplates;
+
+pub use self::outputs::*;
 pub use self::processor::*;
+pub use self::sink::*;
 
+mod gzip;
+mod outputs;
 mod processor;
+mod sink;
 
 /// The type of metadata.json to produce from processing.
 ///
@@ -26,11 +40,25 @@ pub enum ProcessType {
 
     /// Files embedded in the original.
     ///
-    Embedded
+    Embedded,
+
+    /// Files embedded in the original, kept as they are without being processed any further.
+    ///
+    /// Unlike [`ProcessType::Embedded`], the embedded files are always added to the archive, and never recursed into,
+    /// even if both types are asked for.
+    ///
+    Attachments,
+
+    /// Text recognized in the rendered pages of a PDF, or in an image, separately from its extracted text.
+    ///
+    Ocr,
 }
 
 impl ProcessType {
-    /// Returns all possible ProcessTypes.
+    /// Returns all ProcessTypes generated by default.
+    ///
+    /// [`ProcessType::Ocr`] isn't included, as it's slow and overlaps with the extracted text, so it has to be asked
+    /// for explicitly. Neither is [`ProcessType::Attachments`], as it stops [`ProcessType::Embedded`] from recursing.
     ///
     pub fn all() -> &'static [ProcessType] {
         &[
@@ -51,11 +79,152 @@ impl FromStr for ProcessType {
             "metadata" => Ok(ProcessType::Metadata),
             "pdf" => Ok(ProcessType::Pdf),
             "embedded" => Ok(ProcessType::Embedded),
+            "attachments" => Ok(ProcessType::Attachments),
+            "ocr" => Ok(ProcessType::Ocr),
             _ => Err(format!("Can not convert {} to OutputType", s)),
         }
     }
 }
 
+/// The line ending extracted text is normalized to.
+///
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+pub enum LineEnding {
+    /// `\n`, as on Unix.
+    ///
+    Lf,
+
+    /// `\r\n`, as on Windows.
+    ///
+    Crlf,
+}
+
+impl LineEnding {
+    /// Replaces all line endings in the text, whether `\r\n`, `\n`, or a lone `\r`, with this line ending.
+    ///
+    pub fn normalize(&self, text: &[u8]) -> Vec<u8> {
+        let mut normalized = Vec::with_capacity(text.len());
+        self.normalize_into(text, &mut false, &mut normalized);
+        normalized
+    }
+
+    /// Normalizes a chunk of text onto the end of `normalized`.
+    ///
+    /// `after_cr` carries whether the previous chunk ended with `\r`, so a `\r\n` split across chunks is still
+    /// replaced by a single line ending.
+    ///
+    pub(crate) fn normalize_into(&self, text: &[u8], after_cr: &mut bool, normalized: &mut Vec<u8>) {
+        for &byte in text {
+            match byte {
+                b'\n' if *after_cr => {},
+                b'\r' | b'\n' => match self {
+                    LineEnding::Lf => normalized.push(b'\n'),
+                    LineEnding::Crlf => normalized.extend_from_slice(b"\r\n"),
+                },
+                byte => normalized.push(byte),
+            }
+            *after_cr = byte == b'\r';
+        }
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => Err(format!("Can not convert {} to LineEnding", s)),
+        }
+    }
+}
+
+/// The format metadata is written in.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
+pub enum MetadataFormat {
+    /// JSON, written to `metadata.json`.
+    ///
+    #[default]
+    Json,
+
+    /// YAML, written to `metadata.yaml`.
+    ///
+    Yaml,
+
+    /// TOML, written to `metadata.toml`. TOML has no null, so null values are left out.
+    ///
+    Toml,
+}
+
+impl MetadataFormat {
+    /// The extension of metadata files written in this format, without a leading dot.
+    ///
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MetadataFormat::Json => "json",
+            MetadataFormat::Yaml => "yaml",
+            MetadataFormat::Toml => "toml",
+        }
+    }
+
+    /// The MIME type of metadata files written in this format.
+    ///
+    pub fn mimetype(&self) -> &'static str {
+        match self {
+            MetadataFormat::Json => "application/json",
+            MetadataFormat::Yaml => "application/yaml",
+            MetadataFormat::Toml => "application/toml",
+        }
+    }
+}
+
+impl FromStr for MetadataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(MetadataFormat::Json),
+            "yaml" | "yml" => Ok(MetadataFormat::Yaml),
+            "toml" => Ok(MetadataFormat::Toml),
+            _ => Err(format!("Can not convert {} to MetadataFormat", s)),
+        }
+    }
+}
+
+/// An error processing a file, along with the file it failed on.
+///
+/// Errors added as outputs are wrapped in it, so failures can be traced back to their file. It displays as the error
+/// it wraps.
+///
+#[derive(Debug)]
+pub struct FileError {
+    /// The ID chain of the file that failed; empty for the root file.
+    ///
+    pub id_chain: Vec<String>,
+
+    /// The original name of the file that failed, if it's an embedded file.
+    ///
+    pub name: Option<String>,
+
+    /// The error the file failed with.
+    ///
+    pub error: anyhow::Error,
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
 /// Represents the state of a processing operation.
 ///
 /// This is built and modified during processing and is provided with the final processing metadata.json.
@@ -68,6 +237,10 @@ pub struct ProcessState {
     /// a root file. This structure is a tree, where the embedded files are branches and the processed files are leaves.
     ///
     pub id_chain: Vec<String>,
+
+    /// The original names of the embedded files in the `id_chain`, in the same order.
+    ///
+    pub name_chain: Vec<String>,
 }
 
 /// Defines the context for a processing operation.
@@ -89,13 +262,133 @@ pub struct ProcessContext {
     ///
     pub state: ProcessState,
 
-    output_sink: Sender<anyhow::Result<ProcessOutput>>,
+    /// The maximum size of the input file in bytes, if any.
+    ///
+    /// Inputs larger than this are rejected before any processor is run.
+    ///
+    pub max_input_bytes: Option<u64>,
+
+    /// The maximum size in bytes of each file produced by a processor, if any.
+    ///
+    /// Text outputs larger than this are truncated to the limit, and marked as `truncated`. Extracted text is held to
+    /// the limit as it's written, stopping the extraction once it's reached, and the file's metadata records it as
+    /// `rusty.truncated`. Other outputs can't be cut short without corrupting them, so they're failed instead.
+    ///
+    pub max_output_bytes: Option<u64>,
+
+    /// Whether to detect the language of the file's text and include it in the metadata.
+    ///
+    pub detect_language: bool,
+
+    /// The number of characters of the file's text to include in the metadata as a preview, if any.
+    ///
+    pub preview_chars: Option<usize>,
+
+    /// The MIME types of embedded files to keep when processing recursively, if any.
+    ///
+    /// Embedded files with other MIME types are not processed any further.
+    ///
+    pub mimetype_allowlist: Option<Vec<String>>,
+
+    /// Whether embedded files filtered out by the `mimetype_allowlist` are still kept as outputs, or dropped entirely.
+    ///
+    pub keep_filtered: bool,
+
+    /// Whether system and junk files in archives, like `__MACOSX/` entries, `.DS_Store` and Office lock files, are
+    /// extracted rather than skipped.
+    ///
+    pub keep_junk_files: bool,
+
+    /// Whether messages in an mbox are written out with their exact original bytes.
+    ///
+    /// By default, messages are written as parsed, where quoted `>From ` lines are unquoted.
+    ///
+    pub raw_mbox_messages: bool,
+
+    /// Whether the extracted text is gzip compressed.
+    ///
+    pub compress_text: bool,
+
+    /// The line ending to normalize the extracted text to, if any.
+    ///
+    /// By default, the text keeps the line endings it was extracted with.
+    ///
+    pub line_ending: Option<LineEnding>,
+
+    /// The format metadata is written in, JSON by default.
+    ///
+    pub metadata_format: MetadataFormat,
+
+    /// The names of the headers to include in rendered messages, if any, compared case-insensitively.
+    ///
+    /// By default, the `Date`, `Subject`, and address headers are included. Names of headers a message doesn't have
+    /// are ignored.
+    ///
+    pub message_headers: Option<Vec<String>>,
+
+    /// The names of the headers never to include in rendered messages, compared case-insensitively, even if they're in
+    /// `message_headers`.
+    ///
+    /// A name ending with `*` suppresses every header starting with it, like `X-*` for all the `X-` headers.
+    ///
+    pub suppressed_headers: Vec<String>,
+
+    /// Whether attachments of rendered messages are appended to the rendered PDF as additional pages.
+    ///
+    /// Only PDF and image attachments can be appended; other attachments are skipped.
+    ///
+    pub append_pdf_attachments: bool,
+
+    /// Whether the MIME types of embedded files declared with a generic type, like `application/octet-stream`, are
+    /// detected from their contents before they're processed any further.
+    ///
+    pub redetect_generic_mimetypes: bool,
+
+    /// Whether the MIME type sniffed from the file's contents is trusted over the declared one, when they confidently
+    /// disagree.
+    ///
+    /// A file declared as `text/plain` that's actually a zip is then processed as a zip, with the discrepancy logged.
+    ///
+    pub trust_content: bool,
+
+    /// Whether computing the deduplication checksums of files is skipped, for when deduplication isn't needed.
+    ///
+    /// Files aren't read to compute their checksums, and random placeholder IDs are used in their place instead.
+    ///
+    pub skip_checksum: bool,
+
+    /// The dedupe strategies to use for files of the MIME types, in place of the ones chosen for them by default.
+    ///
+    pub dedupe_strategies: HashMap<String, DedupeStrategy>,
+
+    /// The ID of the conversation thread of the file, if it's a message in a thread.
+    ///
+    /// This only applies to the file itself, so it isn't cloned into the contexts of embedded files.
+    ///
+    pub thread_id: Option<String>,
+
+    /// Whether the text of the file was truncated for exceeding `max_output_bytes`, recorded in its metadata as
+    /// `rusty.truncated`.
+    ///
+    /// It's set by the processor once the text is extracted, and only applies to the file itself, so it isn't cloned
+    /// into the contexts of embedded files.
+    ///
+    pub text_truncated: bool,
+
+    /// The templates for the names of the files produced from the file.
+    ///
+    /// It's shared between the contexts of embedded files, as it's the same for all of them. See
+    /// [`OutputNameTemplates`] for more information.
+    ///
+    pub output_names: Arc<OutputNameTemplates>,
+
+    output_sink: Arc<dyn OutputSink>,
 }
 
 impl ProcessContext {
     /// Creates a new ProcessContext with the given MIME type.
     ///
-    /// Clones all other fields from the current ProcessContext.
+    /// Clones all other fields from the current ProcessContext, except for `thread_id` and `text_truncated`.
     ///
     pub fn new_clone(&self, mimetype: String) -> Self {
         Self {
@@ -103,14 +396,96 @@ impl ProcessContext {
             types: self.types.clone(),
             output_sink: self.output_sink.clone(),
             state: self.state.clone(),
+            max_input_bytes: self.max_input_bytes,
+            max_output_bytes: self.max_output_bytes,
+            detect_language: self.detect_language,
+            preview_chars: self.preview_chars,
+            mimetype_allowlist: self.mimetype_allowlist.clone(),
+            keep_filtered: self.keep_filtered,
+            keep_junk_files: self.keep_junk_files,
+            raw_mbox_messages: self.raw_mbox_messages,
+            compress_text: self.compress_text,
+            line_ending: self.line_ending,
+            metadata_format: self.metadata_format,
+            message_headers: self.message_headers.clone(),
+            suppressed_headers: self.suppressed_headers.clone(),
+            append_pdf_attachments: self.append_pdf_attachments,
+            redetect_generic_mimetypes: self.redetect_generic_mimetypes,
+            trust_content: self.trust_content,
+            skip_checksum: self.skip_checksum,
+            dedupe_strategies: self.dedupe_strategies.clone(),
+            thread_id: None,
+            text_truncated: false,
+            output_names: self.output_names.clone(),
         }
     }
 
-    /// Adds an metadata.json to be sent through the metadata.json transfer channel created by the caller of the processing operation.
+    /// Returns the name of an output of the given type produced from the file.
+    ///
+    /// The name is resolved from the `output_names` templates against the original name of the file, falling back
+    /// to the `default_name`.
+    ///
+    pub fn output_name(&self, process_type: ProcessType, default_name: &str) -> String {
+        let original_name = self.state.name_chain.last().map(String::as_str);
+        self.output_names.resolve(&process_type, original_name, default_name)
+    }
+
+    /// Computes the deduplication checksum of a file of the given MIME type from its content.
+    ///
+    /// If `skip_checksum` is set, a random placeholder ID is returned instead, without reading the content. The
+    /// strategy in `dedupe_strategies` for the MIME type is used if there's one.
+    ///
+    pub async fn checksum(&self, content: &mut (impl AsyncRead + Unpin), mimetype: &str) -> anyhow::Result<String> {
+        if self.skip_checksum {
+            return Ok(uuid::Uuid::new_v4().simple().to_string());
+        }
+        match self.dedupe_strategies.get(mimetype) {
+            Some(strategy) => dedupe_checksum_with_strategy(content, *strategy).await,
+            None => dedupe_checksum(content, mimetype).await,
+        }
+    }
+
+    /// Computes the deduplication checksum of the file at the path, of the given MIME type.
+    ///
+    /// See [`ProcessContext::checksum`] for more information.
+    ///
+    pub async fn checksum_from_path(&self, path: impl AsRef<Path>, mimetype: &str) -> anyhow::Result<String> {
+        match self.skip_checksum {
+            true => self.checksum(&mut tokio::io::empty(), mimetype).await,
+            false => self.checksum(&mut tokio::fs::File::open(path).await?, mimetype).await,
+        }
+    }
+
+    /// Whether embedded files of the given MIME type pass the `mimetype_allowlist`.
+    ///
+    /// All MIME types are allowed if there isn't an allowlist.
+    ///
+    pub fn is_mimetype_allowed(&self, mimetype: impl AsRef<str>) -> bool {
+        self.mimetype_allowlist.as_ref()
+            .is_none_or(|allowlist| allowlist.iter().any(|allowed| allowed == mimetype.as_ref()))
+    }
+
+    /// Adds an metadata.json to be sent to the output sink given by the caller of the processing operation.
+    ///
+    /// Files produced by processors are held to `max_output_bytes` first, if set. Errors are wrapped in a
+    /// [`FileError`] for the current file.
     ///
     pub async fn add_output(&self, result: anyhow::Result<ProcessOutput>) -> anyhow::Result<()> {
+        let result = match (result, self.max_output_bytes) {
+            (Ok(ProcessOutput::Processed(state, data)), Some(max_output_bytes)) => {
+                limit_output_size(data, max_output_bytes).await.map(|data| ProcessOutput::Processed(state, data))
+            },
+            (result, _) => result,
+        };
+        let result = result.map_err(|error| match error.is::<FileError>() {
+            true => error,
+            false => FileError {
+                id_chain: self.state.id_chain.clone(),
+                name: self.state.name_chain.last().cloned(),
+                error,
+            }.into(),
+        });
         self.output_sink.send(result).await
-            .map_err(|e| anyhow!(e))
     }
 
     /// Returns the current ID chain.
@@ -128,31 +503,75 @@ impl ProcessContext {
 pub struct ProcessContextBuilder {
     mimetype: String,
     types: Vec<ProcessType>,
-    output_sink: Sender<anyhow::Result<ProcessOutput>>,
+    output_sink: Arc<dyn OutputSink>,
     state: ProcessState,
+    max_input_bytes: Option<u64>,
+    max_output_bytes: Option<u64>,
+    detect_language: bool,
+    preview_chars: Option<usize>,
+    mimetype_allowlist: Option<Vec<String>>,
+    keep_filtered: bool,
+    keep_junk_files: bool,
+    raw_mbox_messages: bool,
+    compress_text: bool,
+    line_ending: Option<LineEnding>,
+    metadata_format: MetadataFormat,
+    message_headers: Option<Vec<String>>,
+    suppressed_headers: Vec<String>,
+    append_pdf_attachments: bool,
+    redetect_generic_mimetypes: bool,
+    trust_content: bool,
+    skip_checksum: bool,
+    dedupe_strategies: HashMap<String, DedupeStrategy>,
+    thread_id: Option<String>,
+    text_truncated: bool,
+    output_names: Arc<OutputNameTemplates>,
 }
 
 impl ProcessContextBuilder {
-    /// Creates a new ProcessContextBuilder with the given MIME type, types of files to process, and metadata.json transfer channel.
+    /// Creates a new ProcessContextBuilder with the given MIME type, types of files to process, and metadata.json sink.
     ///
     /// # Arguments
     ///
     /// * `mimetype` - The MIME type of the file to process.
     /// * `types` - The types of metadata.json to generate.
-    /// * `output_sink` - The metadata.json transfer channel created by the caller of the processing operation.
+    /// * `output_sink` - The metadata.json sink given by the caller of the processing operation, usually the sending
+    ///   half of a channel.
     ///
     pub fn new(
         mimetype: impl Into<String>,
         types: Vec<ProcessType>,
-        output_sink: Sender<anyhow::Result<ProcessOutput>>,
+        output_sink: impl OutputSink + 'static,
     ) -> Self {
         ProcessContextBuilder {
             mimetype: mimetype.into(),
             types,
-            output_sink,
+            output_sink: Arc::new(output_sink),
             state: ProcessState {
                 id_chain: Vec::new(),
-            }
+                name_chain: Vec::new(),
+            },
+            max_input_bytes: None,
+            max_output_bytes: None,
+            detect_language: false,
+            preview_chars: None,
+            mimetype_allowlist: None,
+            keep_filtered: false,
+            keep_junk_files: false,
+            raw_mbox_messages: false,
+            compress_text: false,
+            line_ending: None,
+            metadata_format: MetadataFormat::default(),
+            message_headers: None,
+            suppressed_headers: Vec::new(),
+            append_pdf_attachments: false,
+            redetect_generic_mimetypes: false,
+            trust_content: false,
+            skip_checksum: false,
+            dedupe_strategies: HashMap::new(),
+            thread_id: None,
+            text_truncated: false,
+            output_names: Arc::new(OutputNameTemplates::default()),
         }
     }
 
@@ -179,6 +598,211 @@ impl ProcessContextBuilder {
         self
     }
 
+    /// Sets the name chain.
+    ///
+    /// See `ProcessState.name_chain` for more information.
+    ///
+    pub fn name_chain(mut self, name_chain: Vec<String>) -> Self {
+        self.state.name_chain = name_chain;
+        self
+    }
+
+    /// Sets the sink the outputs are sent to.
+    ///
+    /// See [`ProcessContextBuilder::new`] for more information.
+    ///
+    pub fn output_sink(mut self, output_sink: impl OutputSink + 'static) -> Self {
+        self.output_sink = Arc::new(output_sink);
+        self
+    }
+
+    /// Sets the maximum size of the input file in bytes.
+    ///
+    /// See `ProcessContext.max_input_bytes` for more information.
+    ///
+    pub fn max_input_bytes(mut self, max_input_bytes: Option<u64>) -> Self {
+        self.max_input_bytes = max_input_bytes;
+        self
+    }
+
+    /// Sets the maximum size in bytes of each file produced by a processor.
+    ///
+    /// See `ProcessContext.max_output_bytes` for more information.
+    ///
+    pub fn max_output_bytes(mut self, max_output_bytes: Option<u64>) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
+    }
+
+    /// Sets whether to detect the language of the file's text.
+    ///
+    /// See `ProcessContext.detect_language` for more information.
+    ///
+    pub fn detect_language(mut self, detect_language: bool) -> Self {
+        self.detect_language = detect_language;
+        self
+    }
+
+    /// Sets the number of characters of the file's text to include in the metadata as a preview.
+    ///
+    pub fn preview_chars(mut self, preview_chars: Option<usize>) -> Self {
+        self.preview_chars = preview_chars;
+        self
+    }
+
+    /// Sets the MIME types of embedded files to keep when processing recursively.
+    ///
+    /// See `ProcessContext.mimetype_allowlist` for more information.
+    ///
+    pub fn mimetype_allowlist(mut self, mimetype_allowlist: Option<Vec<String>>) -> Self {
+        self.mimetype_allowlist = mimetype_allowlist;
+        self
+    }
+
+    /// Sets whether embedded files filtered out by the MIME type allowlist are kept.
+    ///
+    /// See `ProcessContext.keep_filtered` for more information.
+    ///
+    pub fn keep_filtered(mut self, keep_filtered: bool) -> Self {
+        self.keep_filtered = keep_filtered;
+        self
+    }
+
+    /// Sets whether system and junk files in archives are extracted.
+    ///
+    /// See `ProcessContext.keep_junk_files` for more information.
+    ///
+    pub fn keep_junk_files(mut self, keep_junk_files: bool) -> Self {
+        self.keep_junk_files = keep_junk_files;
+        self
+    }
+
+    /// Sets whether messages in an mbox are written out with their exact original bytes.
+    ///
+    /// See `ProcessContext.raw_mbox_messages` for more information.
+    ///
+    pub fn raw_mbox_messages(mut self, raw_mbox_messages: bool) -> Self {
+        self.raw_mbox_messages = raw_mbox_messages;
+        self
+    }
+
+    /// Sets whether the extracted text is gzip compressed.
+    ///
+    /// See `ProcessContext.compress_text` for more information.
+    ///
+    pub fn compress_text(mut self, compress_text: bool) -> Self {
+        self.compress_text = compress_text;
+        self
+    }
+
+    /// Sets the line ending to normalize the extracted text to.
+    ///
+    /// See `ProcessContext.line_ending` for more information.
+    ///
+    pub fn line_ending(mut self, line_ending: Option<LineEnding>) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Sets the format metadata is written in.
+    ///
+    /// See `ProcessContext.metadata_format` for more information.
+    ///
+    pub fn metadata_format(mut self, metadata_format: MetadataFormat) -> Self {
+        self.metadata_format = metadata_format;
+        self
+    }
+
+    /// Sets the names of the headers to include in rendered messages.
+    ///
+    /// See `ProcessContext.message_headers` for more information.
+    ///
+    pub fn message_headers(mut self, message_headers: Option<Vec<String>>) -> Self {
+        self.message_headers = message_headers;
+        self
+    }
+
+    /// Sets the names of the headers never to include in rendered messages.
+    ///
+    /// See `ProcessContext.suppressed_headers` for more information.
+    ///
+    pub fn suppressed_headers(mut self, suppressed_headers: Vec<String>) -> Self {
+        self.suppressed_headers = suppressed_headers;
+        self
+    }
+
+    /// Sets whether attachments of rendered messages are appended to the rendered PDF.
+    ///
+    /// See `ProcessContext.append_pdf_attachments` for more information.
+    ///
+    pub fn append_pdf_attachments(mut self, append_pdf_attachments: bool) -> Self {
+        self.append_pdf_attachments = append_pdf_attachments;
+        self
+    }
+
+    /// Sets whether the MIME types of embedded files declared with a generic type are detected from their contents.
+    ///
+    /// See `ProcessContext.redetect_generic_mimetypes` for more information.
+    ///
+    pub fn redetect_generic_mimetypes(mut self, redetect_generic_mimetypes: bool) -> Self {
+        self.redetect_generic_mimetypes = redetect_generic_mimetypes;
+        self
+    }
+
+    /// Sets whether the MIME type sniffed from the file's contents is trusted over the declared one.
+    ///
+    /// See `ProcessContext.trust_content` for more information.
+    ///
+    pub fn trust_content(mut self, trust_content: bool) -> Self {
+        self.trust_content = trust_content;
+        self
+    }
+
+    /// Sets whether computing the deduplication checksums of files is skipped.
+    ///
+    /// See `ProcessContext.skip_checksum` for more information.
+    ///
+    pub fn skip_checksum(mut self, skip_checksum: bool) -> Self {
+        self.skip_checksum = skip_checksum;
+        self
+    }
+
+    /// Sets the dedupe strategies to use for files of the MIME types.
+    ///
+    /// See `ProcessContext.dedupe_strategies` for more information.
+    ///
+    pub fn dedupe_strategies(mut self, dedupe_strategies: HashMap<String, DedupeStrategy>) -> Self {
+        self.dedupe_strategies = dedupe_strategies;
+        self
+    }
+
+    /// Sets the ID of the conversation thread of the file.
+    ///
+    /// See `ProcessContext.thread_id` for more information.
+    ///
+    pub fn thread_id(mut self, thread_id: Option<String>) -> Self {
+        self.thread_id = thread_id;
+        self
+    }
+
+    /// Sets whether the text of the file was truncated.
+    ///
+    /// See `ProcessContext.text_truncated` for more information.
+    ///
+    pub fn text_truncated(mut self, text_truncated: bool) -> Self {
+        self.text_truncated = text_truncated;
+        self
+    }
+
+    /// Sets the templates for the names of the files produced from the file.
+    ///
+    /// See `ProcessContext.output_names` for more information.
+    ///
+    pub fn output_names(mut self, output_names: OutputNameTemplates) -> Self {
+        self.output_names = Arc::new(output_names);
+        self
+    }
+
     /// Build the ProcessContext.
     ///
     pub fn build(self) -> ProcessContext {
@@ -187,6 +811,27 @@ impl ProcessContextBuilder {
             types: self.types,
             output_sink: self.output_sink,
             state: self.state,
+            max_input_bytes: self.max_input_bytes,
+            max_output_bytes: self.max_output_bytes,
+            detect_language: self.detect_language,
+            preview_chars: self.preview_chars,
+            mimetype_allowlist: self.mimetype_allowlist,
+            keep_filtered: self.keep_filtered,
+            keep_junk_files: self.keep_junk_files,
+            raw_mbox_messages: self.raw_mbox_messages,
+            compress_text: self.compress_text,
+            line_ending: self.line_ending,
+            metadata_format: self.metadata_format,
+            message_headers: self.message_headers,
+            suppressed_headers: self.suppressed_headers,
+            append_pdf_attachments: self.append_pdf_attachments,
+            redetect_generic_mimetypes: self.redetect_generic_mimetypes,
+            trust_content: self.trust_content,
+            skip_checksum: self.skip_checksum,
+            dedupe_strategies: self.dedupe_strategies,
+            thread_id: self.thread_id,
+            text_truncated: self.text_truncated,
+            output_names: self.output_names,
         }
     }
 }
@@ -198,6 +843,27 @@ impl From<ProcessContext> for ProcessContextBuilder {
             types: context.types,
             output_sink: context.output_sink,
             state: context.state,
+            max_input_bytes: context.max_input_bytes,
+            max_output_bytes: context.max_output_bytes,
+            detect_language: context.detect_language,
+            preview_chars: context.preview_chars,
+            mimetype_allowlist: context.mimetype_allowlist,
+            keep_filtered: context.keep_filtered,
+            keep_junk_files: context.keep_junk_files,
+            raw_mbox_messages: context.raw_mbox_messages,
+            compress_text: context.compress_text,
+            line_ending: context.line_ending,
+            metadata_format: context.metadata_format,
+            message_headers: context.message_headers,
+            suppressed_headers: context.suppressed_headers,
+            append_pdf_attachments: context.append_pdf_attachments,
+            redetect_generic_mimetypes: context.redetect_generic_mimetypes,
+            trust_content: context.trust_content,
+            skip_checksum: context.skip_checksum,
+            dedupe_strategies: context.dedupe_strategies,
+            thread_id: context.thread_id,
+            text_truncated: context.text_truncated,
+            output_names: context.output_names,
         }
     }
 }
@@ -207,6 +873,7 @@ impl From<ProcessContext> for ProcessContextBuilder {
 /// It can be either a new file or an embedded file.
 ///
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ProcessOutput {
     /// A newly created file as a result of processing the original file.
     ///
@@ -214,7 +881,9 @@ pub enum ProcessOutput {
 
     /// A file discovered during the processing of the original file.
     ///
-    Embedded(ProcessState, ProcessOutputData, Sender<anyhow::Result<ProcessOutput>>),
+    /// The context the file was discovered in is provided to process the file recursively.
+    ///
+    Embedded(ProcessState, ProcessOutputData, ProcessContext),
 }
 
 /// Data associated with the file created.
@@ -242,6 +911,10 @@ pub struct ProcessOutputData {
     /// Deduplication ID of the metadata.json file.
     ///
     pub checksum: String,
+
+    /// Whether the file was truncated for exceeding `ProcessContext.max_output_bytes`.
+    ///
+    pub truncated: bool,
 }
 
 impl ProcessOutput {
@@ -269,12 +942,15 @@ impl ProcessOutput {
                 mimetype: mimetype.into(),
                 types: ctx.types.clone(),
                 checksum: checksum.into(),
+                truncated: false,
             }
         )
     }
 
     /// Creates a new ProcessOutput representing an embedded file.
     ///
+    /// If the name has no extension, the one conventionally used for the MIME type is appended.
+    ///
     /// # Arguments
     ///
     /// * `ctx` - The ProcessContext of the processing operation.
@@ -289,16 +965,197 @@ impl ProcessOutput {
         mimetype: impl Into<String>,
         checksum: impl Into<String>,
     ) -> Self {
+        let mimetype = mimetype.into();
         Self::Embedded(
             ctx.state.clone(),
             ProcessOutputData {
-                name: name.into(),
+                name: with_extension(name.into(), &mimetype),
                 path,
-                mimetype: mimetype.into(),
+                mimetype,
                 types: ctx.types.clone(),
                 checksum: checksum.into(),
+                truncated: false,
             },
-            ctx.output_sink.clone(),
+            ctx.clone(),
         )
     }
 }
+
+/// Truncates a text output larger than `max_output_bytes` to the limit, or fails any other output that's larger.
+///
+/// Processors writing text hold it to the limit as it's written, so this only cuts the outputs of the others.
+///
+async fn limit_output_size(mut data: ProcessOutputData, max_output_bytes: u64) -> anyhow::Result<ProcessOutputData> {
+    let size = tokio::fs::metadata(&data.path).await?.len();
+    if size <= max_output_bytes {
+        return Ok(data);
+    }
+    if !data.mimetype.starts_with("text/") {
+        return Err(anyhow!(
+            "output {} of {} bytes exceeds the maximum of {} bytes", data.name, size, max_output_bytes
+        ));
+    }
+
+    let len = truncate_text(&data.path, max_output_bytes).await?;
+    warn!("Truncated output {} of {} bytes to {} bytes", data.name, size, len);
+    data.truncated = true;
+    Ok(data)
+}
+
+/// Truncates the text file to at most `len` bytes, and returns its new length.
+///
+/// Text is cut at the last character boundary within the limit, so it stays valid UTF-8.
+///
+pub(crate) async fn truncate_text(path: &Path, len: u64) -> std::io::Result<u64> {
+    let mut file = tokio::fs::OpenOptions::new().read(true).write(true).open(path).await?;
+    let len = len.min(file.metadata().await?.len());
+    let start = len.saturating_sub(4);
+    let mut tail = vec![0; (len - start) as usize];
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    file.read_exact(&mut tail).await?;
+
+    // A character is only kept if all of its bytes are, so the last one is dropped if it's been cut
+    let len = match tail.iter().rposition(|byte| byte & 0xC0 != 0x80) {
+        Some(lead) if lead + utf8_width(tail[lead]) > tail.len() => start + lead as u64,
+        _ => len,
+    };
+    file.set_len(len).await?;
+    Ok(len)
+}
+
+/// Returns the number of bytes of the UTF-8 character starting with the lead byte.
+///
+fn utf8_width(lead: u8) -> usize {
+    match lead {
+        0xF0.. => 4,
+        0xE0.. => 3,
+        0xC0.. => 2,
+        _ => 1,
+    }
+}
+
+/// Appends the extension of the MIME type to the name if the name doesn't have one.
+///
+fn with_extension(name: String, mimetype: &str) -> String {
+    if Path::new(&name).extension().is_some() {
+        return name;
+    }
+    match crate::mimetype_to_extension(mimetype) {
+        Some(extension) => format!("{}.{}", name, extension),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::ReadBuf;
+
+    use super::*;
+
+    /// Reader counting the bytes read from it.
+    ///
+    struct CountingReader<R> {
+        inner: R,
+        count: usize,
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            let filled = buf.filled().len();
+            let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+            self.count += buf.filled().len() - filled;
+            poll
+        }
+    }
+
+    async fn checksum_counting_reads(skip_checksum: bool) -> anyhow::Result<(String, usize)> {
+        let (output_sink, _) = tokio::sync::mpsc::channel(1);
+        let ctx = ProcessContextBuilder::new("text/plain", vec![], output_sink)
+            .skip_checksum(skip_checksum)
+            .build();
+        let mut reader = CountingReader { inner: &b"contents of the file"[..], count: 0 };
+
+        let checksum = ctx.checksum(&mut reader, "text/plain").await?;
+        Ok((checksum, reader.count))
+    }
+
+    #[tokio::test]
+    async fn test_checksum() -> anyhow::Result<()> {
+        let (checksum, count) = checksum_counting_reads(false).await?;
+
+        assert_eq!(checksum, dedupe_checksum(&mut &b"contents of the file"[..], "text/plain").await?);
+        assert_eq!(count, 20);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checksum_skipped() -> anyhow::Result<()> {
+        let (checksum, count) = checksum_counting_reads(true).await?;
+        let (other_checksum, _) = checksum_counting_reads(true).await?;
+
+        assert_eq!(count, 0);
+        assert_eq!(checksum.len(), 32);
+        assert_ne!(checksum, other_checksum);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_thread_id() {
+        let (output_sink, _) = tokio::sync::mpsc::channel(1);
+        let ctx = ProcessContextBuilder::new("message/rfc822", vec![], output_sink)
+            .thread_id(Some("<root@example.com>".to_string()))
+            .build();
+
+        assert_eq!(ctx.new_clone("image/png".to_string()).thread_id, None);
+        assert_eq!(ProcessContextBuilder::from(ctx).build().thread_id.as_deref(), Some("<root@example.com>"));
+    }
+
+    #[test]
+    fn test_line_ending_normalize() {
+        assert_eq!(LineEnding::Lf.normalize(b"a\rb\r\n\rc\n"), b"a\nb\n\nc\n");
+        assert_eq!(LineEnding::Crlf.normalize(b"a\rb\r\nc\n"), b"a\r\nb\r\nc\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_limit_output_size_at_char_boundary() -> anyhow::Result<()> {
+        let path = test_utils::temp_path()?;
+        std::fs::write(&path, "naïve café")?;
+        let data = ProcessOutputData {
+            name: "extracted.txt".to_string(),
+            path,
+            mimetype: "text/plain".to_string(),
+            types: vec![],
+            checksum: "checksum".to_string(),
+            truncated: false,
+        };
+
+        let data = limit_output_size(data, 3).await?;
+
+        assert!(data.truncated);
+        assert_eq!(std::fs::read_to_string(&data.path)?, "na");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_truncate_text() -> anyhow::Result<()> {
+        for (len, expected) in [(0, ""), (3, "na"), (4, "naï"), (10, "naïve caf"), (12, "naïve café"), (20, "naïve café")] {
+            let path = test_utils::temp_path()?;
+            std::fs::write(&path, "naïve café")?;
+
+            assert_eq!(truncate_text(&path, len).await?, expected.len() as u64);
+            assert_eq!(std::fs::read_to_string(&path)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_extension() {
+        assert_eq!(with_extension("invoice".to_string(), "application/pdf"), "invoice.pdf");
+        assert_eq!(with_extension("scan".to_string(), "image/jpeg"), "scan.jpg");
+        assert_eq!(with_extension("invoice.pdf".to_string(), "image/jpeg"), "invoice.pdf");
+        assert_eq!(with_extension("blob".to_string(), "application/x-unknown"), "blob");
+    }
+}
@@ -0,0 +1,211 @@
+use std::path::PathBuf;
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use log::warn;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::processing::{ProcessContextBuilder, processor, ProcessOutput, ProcessOutputData, ProcessState, ProcessType};
+use crate::streaming::ByteStream;
+
+/// An output of [`process_outputs`].
+///
+/// This is the output type of the streaming API, rather than [`ProcessOutput`]. Unlike a [`ProcessOutput`], embedded
+/// files don't carry the context they were discovered in, which holds the sink of the outputs open and would keep the
+/// stream from ending. Converting a [`ProcessOutput`] into it drops that context.
+///
+#[derive(Debug)]
+pub enum StreamedOutput {
+    /// A file created from processing the original file.
+    ///
+    Processed(ProcessState, ProcessOutputData),
+
+    /// A file discovered during the processing of the original file.
+    ///
+    Embedded(ProcessState, ProcessOutputData),
+}
+
+impl From<ProcessOutput> for StreamedOutput {
+    fn from(output: ProcessOutput) -> Self {
+        match output {
+            ProcessOutput::Processed(state, data) => StreamedOutput::Processed(state, data),
+            ProcessOutput::Embedded(state, data, _) => StreamedOutput::Embedded(state, data),
+        }
+    }
+}
+
+/// Processes a file and returns its outputs as a stream.
+///
+/// The processing operation runs as a background task, sending each output through the returned stream as it's
+/// produced. The stream ends once processing finishes. If processing fails, the error is the last item of the stream.
+///
+/// Embedded files are not processed recursively; they're yielded as [`StreamedOutput::Embedded`], without the context
+/// needed to process them.
+///
+/// Must be called from within a tokio runtime.
+///
+/// # Arguments
+///
+/// * `path` - The path to the file to process.
+/// * `mimetype` - The MIME type of the file.
+/// * `types` - The types of output to generate.
+///
+pub fn process_outputs(
+    path: impl Into<PathBuf>,
+    mimetype: impl Into<String>,
+    types: Vec<ProcessType>,
+) -> impl Stream<Item = anyhow::Result<StreamedOutput>> {
+    let (output_sink, outputs) = tokio::sync::mpsc::channel(100);
+    let ctx = ProcessContextBuilder::new(mimetype, types, output_sink.clone()).build();
+    let path = path.into();
+
+    tokio::spawn(async move {
+        if let Err(err) = processor().process(ctx, path).await {
+            let _ = output_sink.send(Err(err.into())).await;
+        }
+    });
+
+    ReceiverStream::new(outputs).map(|output| output.map(StreamedOutput::from))
+}
+
+/// Processes the metadata of a file, and of its embedded files when recursing, as newline-delimited JSON.
+///
+/// Each line is a JSON object of a single file, with the file's `id_chain` and its `metadata`. The root file has an
+/// empty `id_chain`. Embedded files are only used to discover more files, and aren't part of the stream themselves.
+///
+/// Must be called from within a tokio runtime.
+///
+/// # Arguments
+///
+/// * `path` - The path to the file to process.
+/// * `mimetype` - The MIME type of the file.
+/// * `recurse` - Whether to include the metadata of embedded files.
+///
+pub fn process_metadata_ndjson(
+    path: impl Into<PathBuf>,
+    mimetype: impl Into<String>,
+    recurse: bool,
+) -> ByteStream {
+    let types = match recurse {
+        true => vec![ProcessType::Metadata, ProcessType::Embedded],
+        false => vec![ProcessType::Metadata],
+    };
+    let (output_sink, mut outputs) = tokio::sync::mpsc::channel(100);
+    let ctx = ProcessContextBuilder::new(mimetype, types, output_sink.clone()).build();
+    let path = path.into();
+
+    tokio::spawn(async move {
+        if let Err(err) = processor().process(ctx, path).await {
+            let _ = output_sink.send(Err(err.into())).await;
+        }
+    });
+
+    Box::pin(stream! {
+        while let Some(output) = outputs.recv().await {
+            match output {
+                Ok(ProcessOutput::Processed(state, data)) => yield metadata_line(state, data),
+                Ok(ProcessOutput::Embedded(mut state, data, ctx)) => {
+                    state.id_chain.push(data.checksum);
+                    state.name_chain.push(data.name);
+                    let ctx = ProcessContextBuilder::from(ctx)
+                        .mimetype(data.mimetype)
+                        .id_chain(state.id_chain)
+                        .name_chain(state.name_chain)
+                        .build();
+
+                    // The embedded file is removed once the task finishes processing it
+                    let path = data.path;
+                    tokio::spawn(async move {
+                        if let Err(err) = processor().process(ctx.clone(), path.to_path_buf()).await {
+                            warn!("Error processing: {}", err);
+                            let _ = ctx.add_output(Err(err.into())).await;
+                        }
+                    });
+                },
+                Err(err) => yield Err(err),
+            }
+        }
+    })
+}
+
+/// Creates the newline-terminated JSON line of a metadata output.
+///
+fn metadata_line(state: ProcessState, data: ProcessOutputData) -> anyhow::Result<Vec<u8>> {
+    let metadata = json::parse(&std::fs::read_to_string(&data.path)?)?;
+    let line = json::object! {
+        "id_chain": state.id_chain,
+        "metadata": metadata,
+    };
+    Ok(format!("{}\n", line.dump()).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::streaming::stream_to_bytes;
+
+    use super::*;
+
+    #[cfg(feature = "pdf")]
+    #[tokio::test]
+    async fn test_process_outputs() -> anyhow::Result<()> {
+        let path = "../resources/rfc822/headers-small.eml";
+        let types = vec![ProcessType::Text, ProcessType::Metadata, ProcessType::Pdf];
+
+        let outputs: Vec<anyhow::Result<StreamedOutput>> = process_outputs(path, "message/rfc822", types)
+            .collect()
+            .await;
+
+        let mut names = vec![];
+        for output in outputs {
+            match output? {
+                StreamedOutput::Processed(_, data) => names.push(data.name),
+                StreamedOutput::Embedded(_, data) => panic!("Unexpected embedded output {}", data.name),
+            }
+        }
+        names.sort();
+
+        assert_eq!(names, vec!["extracted.txt", "metadata.json", "rendered.pdf"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_outputs_embedded() -> anyhow::Result<()> {
+        let path = "../resources/rfc822/attachments.eml";
+
+        // Holding the sink open in the outputs would keep the stream from ending
+        let outputs = process_outputs(path, "message/rfc822", vec![ProcessType::Embedded]).collect::<Vec<_>>();
+        let outputs = tokio::time::timeout(Duration::from_secs(30), outputs).await?;
+
+        let mut names = vec![];
+        for output in outputs {
+            match output? {
+                StreamedOutput::Embedded(_, data) => names.push(data.name),
+                StreamedOutput::Processed(_, data) => panic!("Unexpected processed output {}", data.name),
+            }
+        }
+        assert!(!names.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_metadata_ndjson() -> anyhow::Result<()> {
+        let path = "../resources/mbox/ubuntu-no-small.mbox";
+
+        let ndjson = stream_to_bytes(process_metadata_ndjson(path, "application/mbox", true)).await?;
+
+        let mut id_chain_lengths = vec![];
+        for line in String::from_utf8(ndjson)?.lines() {
+            let line = json::parse(line)?;
+            assert!(line["id_chain"].is_array());
+            assert!(line["metadata"].is_object());
+            id_chain_lengths.push(line["id_chain"].len());
+        }
+        id_chain_lengths.sort();
+
+        // The mbox, its two messages, and the attachment of one of them
+        assert_eq!(id_chain_lengths, vec![0, 1, 1, 2]);
+        Ok(())
+    }
+}
@@ -1,18 +1,27 @@
 use std::fmt::{Debug, Display, Formatter};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use anyhow::anyhow;
 use async_trait::async_trait;
-use futures::future::try_join_all;
+use futures::future::join_all;
 use lazy_static::lazy_static;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use tempfile::{NamedTempFile, TempPath};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
-use identify::deduplication::dedupe_checksum_from_path;
+use identify::mimetype::{identify_mimetype, sniff_contradicting_mimetype};
+use services::{config, external_extractors, MissingDependency, pdf_password};
 
-use crate::processing::{ProcessContext, ProcessType};
+use crate::processing::{OutputSink, ProcessContext, ProcessContextBuilder, ProcessOutput, ProcessType};
+use crate::processing::gzip::{decompress_single_member, GZIP_MIMETYPE};
 
 lazy_static! {
     static ref PROCESSOR: Processor = Processor;
+    static ref PROCESSOR_PERMITS: Arc<Semaphore> = Arc::new(Semaphore::new(max_concurrent_processors()));
 }
 
 /// Returns a reference to the global processor instance.
@@ -29,6 +38,22 @@ pub enum ProcessingError {
     ///
     UnsupportedMimeType(String),
 
+    /// The input file is larger than the configured limit.
+    ///
+    InputTooLarge {
+        /// The size of the input file in bytes.
+        ///
+        size: u64,
+
+        /// The maximum allowed size in bytes.
+        ///
+        limit: u64,
+    },
+
+    /// An external program a processor runs isn't installed.
+    ///
+    MissingDependency(String),
+
     /// An unexpected error occurred.
     ///
     Unexpected(anyhow::Error),
@@ -38,11 +63,35 @@ impl Display for ProcessingError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::UnsupportedMimeType(mimetype) => write!(f, "Unsupported MIME type: {}", mimetype),
+            Self::InputTooLarge { size, limit } => write!(f, "Input too large: {} bytes exceeds limit of {} bytes", size, limit),
+            Self::MissingDependency(name) => write!(f, "Missing dependency: {} is not installed", name),
             Self::Unexpected(err) => write!(f, "Unexpected error: {}", err),
         }
     }
 }
 
+impl std::error::Error for ProcessingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unexpected(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl ProcessingError {
+    /// A stable code identifying the kind of error, for consumers to match on, e.g. to map it to an HTTP status.
+    ///
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::UnsupportedMimeType(_) => "unsupported_mimetype",
+            Self::InputTooLarge { .. } => "input_too_large",
+            Self::MissingDependency(_) => "missing_dependency",
+            Self::Unexpected(_) => "unexpected",
+        }
+    }
+}
+
 /// Process is a trait that defines the interface for process data from a file or as raw bytes.
 ///
 /// Process implementations are required to be thread safe.
@@ -68,6 +117,12 @@ pub(crate) trait Process: Send + Sync {
     /// Returns the name of the processor.
     ///
     fn name(&self) -> &'static str;
+
+    /// Returns the MIME type of the files the processor outputs, with the options of the context.
+    ///
+    /// Embedded processors output files of whatever type was embedded, so they report `application/octet-stream`.
+    ///
+    fn output_mimetype(&self, ctx: &ProcessContext) -> &'static str;
 }
 
 
@@ -91,26 +146,197 @@ impl Processor {
     /// * `ctx` - Context of the processing operation.
     /// * `stream` - Stream of data in `bytes::Bytes` of the content to process.
     ///
+    /// Gzip files wrapping a single file are decompressed, and the file they wrap is processed as its own MIME type;
+    /// see [`Processor::unwrap_gzip`].
+    ///
+    /// With `ProcessContext.trust_content`, the file is processed as the MIME type sniffed from its contents if it
+    /// contradicts the declared one; see [`Processor::route_by_content`].
+    ///
     pub async fn process(
         &self,
         ctx: ProcessContext,
         input_path: PathBuf,
     ) -> Result<(), ProcessingError> {
-        let checksum = dedupe_checksum_from_path(&input_path, &ctx.mimetype).await
+        let ctx = match ctx.trust_content {
+            true => self.route_by_content(ctx, &input_path).await,
+            false => ctx,
+        };
+
+        // The decompressed file is removed once it's been processed
+        let (ctx, input_path, _decompressed) = match ctx.mimetype.as_str() {
+            GZIP_MIMETYPE => self.unwrap_gzip(ctx, input_path).await,
+            _ => (ctx, input_path, None),
+        };
+
+        let size = std::fs::metadata(&input_path)
+            .map_err(|err| ProcessingError::Unexpected(err.into()))?
+            .len();
+        if let Some(limit) = ctx.max_input_bytes {
+            if size > limit {
+                return Err(ProcessingError::InputTooLarge { size, limit });
+            }
+        }
+
+        let checksum = ctx.checksum_from_path(&input_path, &ctx.mimetype).await
             .map_err(ProcessingError::Unexpected)?;
 
-        let mut futures = vec![];
-        for processor in self.determine_processors(&ctx.mimetype, &ctx.types) {
-            let inner_ctx = ctx.clone();
-            let input_path_ref = &input_path;
-            let checksum = &checksum;
+        // Encrypted PDFs that can't be opened would make the extractors fail or stall, so they aren't run at all
+        if ctx.mimetype == "application/pdf" {
+            if let Err(err) = crate::pdf::encryption::check_encryption(&input_path, pdf_password()).await {
+                return ctx.add_output(Err(err)).await.map_err(ProcessingError::Unexpected);
+            }
+        }
 
-            futures.push(async move {
-                processor.process(inner_ctx, input_path_ref, temp_path()?, checksum).await
-            });
+        // Empty files have nothing to extract, so avoid running tools that may fail on them
+        if size == 0 {
+            let processors = self.empty_processors(&ctx.types);
+            return self.run_processors(ctx, processors, &input_path, &checksum, &PROCESSOR_PERMITS).await;
+        }
+
+        // The metadata records whether the text was truncated, so the text is extracted first when it may be
+        let mut types = ctx.types.clone();
+        let text_truncated = match ctx.max_output_bytes.is_some() && types.contains(&ProcessType::Metadata) {
+            true => {
+                types.retain(|process_type| *process_type != ProcessType::Text);
+                self.extract_text(&ctx, &input_path, &checksum).await?
+            },
+            false => false,
+        };
+        let ctx = ProcessContextBuilder::from(ctx).text_truncated(text_truncated).build();
+        let processors = self.determine_processors(&ctx.mimetype, &types);
+        self.run_processors(ctx, processors, &input_path, &checksum, &PROCESSOR_PERMITS).await
+    }
+
+    /// Runs the text processor on its own, ahead of the other processors, and returns whether it truncated the text.
+    ///
+    async fn extract_text(&self, ctx: &ProcessContext, input_path: &Path, checksum: &str) -> Result<bool, ProcessingError> {
+        if !ctx.types.contains(&ProcessType::Text) {
+            return Ok(false);
+        }
+
+        let sink = Arc::new(TruncationObservingSink { inner: ctx.output_sink.clone(), truncated: AtomicBool::new(false) });
+        let text_ctx = ProcessContextBuilder::from(ctx.clone()).output_sink(sink.clone()).build();
+        let processors = self.determine_processors(&ctx.mimetype, &[ProcessType::Text]);
+        self.run_processors(text_ctx, processors, input_path, checksum, &PROCESSOR_PERMITS).await?;
+        Ok(sink.truncated.load(Ordering::SeqCst))
+    }
+
+    /// Returns the context to process the file with as the MIME type sniffed from its contents, if it confidently
+    /// contradicts the declared MIME type.
+    ///
+    /// The file is processed as its declared MIME type if its contents fail to be sniffed.
+    ///
+    async fn route_by_content(&self, ctx: ProcessContext, input_path: &Path) -> ProcessContext {
+        match sniff_contradicting_mimetype(input_path, &ctx.mimetype).await {
+            Ok(Some(mimetype)) => {
+                warn!("File declared as {} has the contents of {}, processing it as {}", ctx.mimetype, mimetype, mimetype);
+                ProcessContextBuilder::from(ctx).mimetype(mimetype).build()
+            },
+            Ok(None) => ctx,
+            Err(err) => {
+                warn!("Processing file as the declared {}, as its contents failed to be sniffed: {}", ctx.mimetype, err);
+                ctx
+            },
+        }
+    }
+
+    /// Decompresses a gzip file wrapping a single file and identifies the MIME type of the file it wraps.
+    ///
+    /// Returns the context and path to process the wrapped file with, along with the decompressed file to keep until
+    /// it's processed. The gzip file is processed as it is if it wraps more than one file, fails to be decompressed, or
+    /// the MIME type of the file it wraps can't be identified.
+    ///
+    async fn unwrap_gzip(&self, ctx: ProcessContext, input_path: PathBuf) -> (ProcessContext, PathBuf, Option<TempPath>) {
+        let max_input_bytes = ctx.max_input_bytes;
+        let path = input_path.clone();
+        let decompressed = tokio::task::spawn_blocking(move || decompress_single_member(&path, max_input_bytes)).await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result);
+
+        let decompressed = match decompressed {
+            Ok(Some(decompressed)) => decompressed,
+            Ok(None) => return (ctx, input_path, None),
+            Err(err) => {
+                warn!("Processing gzip file as it is, as it failed to be decompressed: {}", err);
+                return (ctx, input_path, None);
+            },
+        };
+
+        match identify_mimetype(&decompressed).await {
+            Ok(Some(mimetype)) => {
+                info!("Processing gzip file as the {} file it wraps", mimetype);
+                let ctx = ProcessContextBuilder::from(ctx).mimetype(mimetype).build();
+                (ctx, decompressed.to_path_buf(), Some(decompressed))
+            },
+            Ok(None) => (ctx, input_path, None),
+            Err(err) => {
+                warn!("Processing gzip file as it is, as the file it wraps failed to be identified: {}", err);
+                (ctx, input_path, None)
+            },
+        }
+    }
+
+    /// Returns whether any processor, of any type of output, would run for a file with the given MIME type.
+    ///
+    /// This is cheap to check, and can be used to reject unsupported files before processing them. As text and metadata
+    /// fall back to being extracted by tika, any well-formed `type/subtype` MIME type is supported.
+    ///
+    pub fn is_supported(&self, mimetype: &str) -> bool {
+        let is_mimetype = mimetype.split_once('/')
+            .is_some_and(|(type_, subtype)| !type_.is_empty() && !subtype.is_empty() && !subtype.contains('/'));
+        is_mimetype && !self.determine_processors(mimetype, ProcessType::all()).is_empty()
+    }
+
+    /// Runs the processors concurrently, isolating each processor's failure from the others.
+    ///
+    /// Each processor holds one of the `permits` while running, bounding how many processors (and the subprocesses
+    /// they spawn) run at once across all files being processed. It's released while the processor waits for an
+    /// output to be taken; see [`PermitReleasingSink`].
+    ///
+    /// Outputs of successful processors still reach the output sink, while the error of each failed processor is sent
+    /// to the output sink as an error output. A processor failing because a program it runs isn't installed fails
+    /// processing with [`ProcessingError::MissingDependency`] instead, once the other processors finish.
+    ///
+    async fn run_processors(
+        &self,
+        ctx: ProcessContext,
+        processors: Vec<Box<dyn Process>>,
+        input_path: &Path,
+        checksum: &str,
+        permits: &Arc<Semaphore>,
+    ) -> Result<(), ProcessingError> {
+        let futures = processors.into_iter().map(|processor| {
+            let mut inner_ctx = ctx.clone();
+            async move {
+                let result = async {
+                    let sink = Arc::new(PermitReleasingSink::acquire(inner_ctx.output_sink.clone(), permits.clone()).await?);
+                    inner_ctx.output_sink = sink.clone();
+                    let result = processor.process(inner_ctx, input_path, temp_path()?, checksum).await;
+                    sink.finish();
+                    result
+                }.await;
+                (processor.name(), result)
+            }
+        });
+
+        let mut missing_dependency = None;
+        for (name, result) in join_all(futures).await {
+            if let Err(err) = result {
+                let missing = err.chain()
+                    .find_map(|err| err.downcast_ref::<MissingDependency>())
+                    .map(|missing| missing.name.clone());
+                match missing {
+                    Some(name) => missing_dependency = missing_dependency.or(Some(name)),
+                    None => ctx.add_output(Err(anyhow!("{} processor failed: {:#}", name, err))).await
+                        .map_err(ProcessingError::Unexpected)?,
+                }
+            }
+        }
+
+        match missing_dependency {
+            Some(name) => Err(ProcessingError::MissingDependency(name)),
+            None => Ok(()),
         }
-        try_join_all(futures).await.map_err(ProcessingError::Unexpected)?;
-        Ok(())
     }
 
     fn determine_processors(&self, mimetype: &str, types: &[ProcessType]) -> Vec<Box<dyn Process>> {
@@ -131,16 +357,37 @@ impl Processor {
                 processors.push(processor);
             }
         }
-        if types.contains(&ProcessType::Embedded) {
+        if types.contains(&ProcessType::Embedded) || types.contains(&ProcessType::Attachments) {
             if let Some(processor) = self.embedded_processor(mimetype) {
                 processors.push(processor);
             }
         }
+        if types.contains(&ProcessType::Ocr) {
+            if let Some(processor) = self.ocr_processor(mimetype) {
+                processors.push(processor);
+            }
+        }
 
         processors
     }
 
+    fn empty_processors(&self, types: &[ProcessType]) -> Vec<Box<dyn Process>> {
+        match types.contains(&ProcessType::Metadata) {
+            true => vec![Box::<crate::metadata::EmptyMetadataProcessor>::default()],
+            false => vec![],
+        }
+    }
+
     fn text_processor(&self, mimetype: &str) -> Option<Box<dyn Process>> {
+        if external_extractors().get(mimetype).is_some() {
+            return Some(Box::<crate::text::DefaultTextProcessor>::default());
+        }
+
+        #[cfg(feature = "transcription")]
+        if let Some(processor) = crate::transcription::transcription_processor(mimetype, services::transcriber()) {
+            return Some(processor);
+        }
+
         match mimetype {
             "text/plain " |
             "text/css" |
@@ -153,10 +400,15 @@ impl Processor {
         }
     }
 
-    fn metadata_processor(&self, _mimetype: &str) -> Option<Box<dyn Process>> {
-        Some(Box::<crate::metadata::DefaultMetadataProcessor>::default())
+    fn metadata_processor(&self, mimetype: &str) -> Option<Box<dyn Process>> {
+        match mimetype {
+            "text/calendar" => Some(Box::<crate::metadata::IcalMetadataProcessor>::default()),
+
+            _ => Some(Box::<crate::metadata::DefaultMetadataProcessor>::default()),
+        }
     }
 
+    #[cfg(feature = "pdf")]
     fn pdf_processor(&self, mimetype: &str) -> Option<Box<dyn Process>> {
         match mimetype {
             "message/rfc822" => Some(Box::<crate::pdf::Rfc822PdfProcessor>::default()),
@@ -165,15 +417,115 @@ impl Processor {
         }
     }
 
+    /// Rendering PDFs is compiled out without the `pdf` feature, so nothing is rendered.
+    ///
+    #[cfg(not(feature = "pdf"))]
+    fn pdf_processor(&self, _: &str) -> Option<Box<dyn Process>> {
+        None
+    }
+
     fn embedded_processor(&self, mimetype: &str) -> Option<Box<dyn Process>> {
         match mimetype {
             "application/zip" => Some(Box::<crate::embedded::ZipEmbeddedProcessor>::default()),
             "application/mbox" => Some(Box::<crate::embedded::MboxEmbeddedProcessor>::default()),
+            "application/pdf" => Some(Box::<crate::embedded::PdfEmbeddedProcessor>::default()),
             "message/rfc822" => Some(Box::<crate::embedded::Rfc822EmbeddedProcessor>::default()),
+            "application/ms-tnef" |
+            "application/vnd.ms-tnef" => Some(Box::<crate::embedded::TnefEmbeddedProcessor>::default()),
 
             _ => None
         }
     }
+
+    fn ocr_processor(&self, mimetype: &str) -> Option<Box<dyn Process>> {
+        match mimetype {
+            #[cfg(feature = "pdf")]
+            "application/pdf" => Some(Box::<crate::ocr::PdfOcrProcessor>::default()),
+
+            "image/png" |
+            "image/jpeg" |
+            "image/tiff" |
+            "image/gif" |
+            "image/bmp" |
+            "image/webp" => Some(Box::<crate::ocr::ImageOcrProcessor>::default()),
+
+            _ => None
+        }
+    }
+}
+
+/// The maximum number of processors to run at once, configured by `MAX_CONCURRENT_PROCESSORS`.
+///
+/// Defaults to the number of CPUs.
+///
+fn max_concurrent_processors() -> usize {
+    config().get("MAX_CONCURRENT_PROCESSORS")
+        .and_then(|max| max.parse().ok())
+        .filter(|max| *max > 0)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, NonZeroUsize::get))
+}
+
+/// Output sink of a running processor, releasing the processor's permit while it waits for an output to be taken.
+///
+/// A processor waiting on the consumer of its outputs then doesn't keep other processors from running, such as the
+/// ones processing the embedded files it already sent, which the consumer may be waiting on in turn. The permit is
+/// taken back once the output is sent, unless the processor has finished by then.
+///
+#[derive(Debug)]
+struct PermitReleasingSink {
+    inner: Arc<dyn OutputSink>,
+    permits: Arc<Semaphore>,
+    permit: Mutex<Option<OwnedSemaphorePermit>>,
+    finished: AtomicBool,
+}
+
+impl PermitReleasingSink {
+    async fn acquire(inner: Arc<dyn OutputSink>, permits: Arc<Semaphore>) -> anyhow::Result<Self> {
+        let permit = permits.clone().acquire_owned().await?;
+        Ok(Self { inner, permits, permit: Mutex::new(Some(permit)), finished: AtomicBool::new(false) })
+    }
+
+    /// Releases the permit for good, once the processor has finished.
+    ///
+    fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+        self.permit.lock().unwrap_or_else(PoisonError::into_inner).take();
+    }
+}
+
+#[async_trait]
+impl OutputSink for PermitReleasingSink {
+    async fn send(&self, result: anyhow::Result<ProcessOutput>) -> anyhow::Result<()> {
+        let released = self.permit.lock().unwrap_or_else(PoisonError::into_inner).take().is_some();
+        let sent = self.inner.send(result).await;
+
+        if released {
+            let permit = self.permits.clone().acquire_owned().await?;
+            let mut held = self.permit.lock().unwrap_or_else(PoisonError::into_inner);
+            if !self.finished.load(Ordering::SeqCst) {
+                *held = Some(permit);
+            }
+        }
+        sent
+    }
+}
+
+/// Output sink of the text processor, noting whether any of the text it sends was truncated.
+///
+#[derive(Debug)]
+struct TruncationObservingSink {
+    inner: Arc<dyn OutputSink>,
+    truncated: AtomicBool,
+}
+
+#[async_trait]
+impl OutputSink for TruncationObservingSink {
+    async fn send(&self, result: anyhow::Result<ProcessOutput>) -> anyhow::Result<()> {
+        if let Ok(ProcessOutput::Processed(_, data)) = &result {
+            self.truncated.fetch_or(data.truncated, Ordering::SeqCst);
+        }
+        self.inner.send(result).await
+    }
 }
 
 /// Creates a temporary file and returns its path.
@@ -181,4 +533,380 @@ impl Processor {
 #[inline]
 fn temp_path() -> std::io::Result<TempPath> {
     Ok(NamedTempFile::new()?.into_temp_path())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use services::ExternalExtractor;
+
+    use crate::processing::{MetadataFormat, ProcessContextBuilder, ProcessOutput};
+
+    use super::*;
+
+    /// Processor stub that either outputs a file with the given name or fails.
+    ///
+    struct StubProcessor {
+        name: &'static str,
+        output_name: Option<&'static str>,
+    }
+
+    #[async_trait]
+    impl Process for StubProcessor {
+        async fn process(
+            &self,
+            ctx: ProcessContext,
+            _: &Path,
+            output_path: TempPath,
+            checksum: &str,
+        ) -> anyhow::Result<()> {
+            match self.output_name {
+                Some(output_name) => {
+                    let output = ProcessOutput::processed(&ctx, output_name, output_path, "text/plain", checksum);
+                    ctx.add_output(Ok(output)).await
+                },
+                None => Err(anyhow!("failed to render")),
+            }
+        }
+
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+            "text/plain"
+        }
+    }
+
+    /// Processor stub that runs a program that isn't installed.
+    ///
+    struct MissingProgramProcessor;
+
+    #[async_trait]
+    impl Process for MissingProgramProcessor {
+        async fn process(&self, _: ProcessContext, input_path: &Path, output_path: TempPath, _: &str) -> anyhow::Result<()> {
+            ExternalExtractor::parse("programthatdoesntexist {input}")?.text_into_file(input_path, output_path).await
+        }
+
+        fn name(&self) -> &'static str {
+            "Missing Program"
+        }
+
+        fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+            "text/plain"
+        }
+    }
+
+    /// Processor stub that counts how many instances of it are running at once.
+    ///
+    struct CountingProcessor {
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Process for CountingProcessor {
+        async fn process(&self, _: ProcessContext, _: &Path, _: TempPath, _: &str) -> anyhow::Result<()> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn name(&self) -> &'static str {
+            "Counting"
+        }
+
+        fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+            "text/plain"
+        }
+    }
+
+    const INPUT_PATH: &str = "../resources/zip/testzip.zip";
+
+    fn context_with_limit(max_input_bytes: Option<u64>) -> ProcessContext {
+        let (output_sink, _) = tokio::sync::mpsc::channel(10);
+        ProcessContextBuilder::new("application/zip", vec![], output_sink)
+            .max_input_bytes(max_input_bytes)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_process_under_size_limit() {
+        let size = std::fs::metadata(INPUT_PATH).unwrap().len();
+        let ctx = context_with_limit(Some(size));
+
+        let result = processor().process(ctx, PathBuf::from(INPUT_PATH)).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_process_over_size_limit() {
+        let size = std::fs::metadata(INPUT_PATH).unwrap().len();
+        let ctx = context_with_limit(Some(size - 1));
+
+        let result = processor().process(ctx, PathBuf::from(INPUT_PATH)).await;
+
+        match result {
+            Err(ProcessingError::InputTooLarge { size: actual_size, limit }) => {
+                assert_eq!(actual_size, size);
+                assert_eq!(limit, size - 1);
+            },
+            _ => panic!("expected input too large error"),
+        }
+    }
+
+    async fn embedded_names(path: &str, mimetype: &str, trust_content: bool) -> anyhow::Result<Vec<String>> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new(mimetype, vec![ProcessType::Embedded], output_sink)
+            .trust_content(trust_content)
+            .build();
+
+        processor().process(ctx, PathBuf::from(path)).await.map_err(|err| anyhow!("{}", err))?;
+
+        let mut names = vec![];
+        while let Some(output) = outputs.recv().await {
+            if let ProcessOutput::Embedded(_, data, _) = output? {
+                names.push(data.name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    #[tokio::test]
+    async fn test_process_trust_content() -> anyhow::Result<()> {
+        let path = "../resources/text/zip-labeled-as-text.txt";
+
+        assert!(embedded_names(path, "text/plain", false).await?.is_empty());
+        assert_eq!(embedded_names(path, "text/plain", true).await?, vec!["PA280040.JPG", "PA280041.JPG"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_isolates_processor_failures() -> anyhow::Result<()> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("message/rfc822", vec![], output_sink).build();
+        let processors: Vec<Box<dyn Process>> = vec![
+            Box::new(StubProcessor { name: "Text", output_name: Some("extracted.txt") }),
+            Box::new(StubProcessor { name: "Metadata", output_name: Some("metadata.json") }),
+            Box::new(StubProcessor { name: "PDF", output_name: None }),
+        ];
+
+        let permits = Arc::new(Semaphore::new(3));
+        let result = processor().run_processors(ctx, processors, Path::new(INPUT_PATH), "checksum", &permits).await;
+        assert!(result.is_ok());
+
+        let mut names = vec![];
+        let mut errors = vec![];
+        while let Some(output) = outputs.recv().await {
+            match output {
+                Ok(ProcessOutput::Processed(_, data)) => names.push(data.name),
+                Ok(ProcessOutput::Embedded(_, _, _)) => panic!("expected processed output"),
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+        names.sort();
+
+        assert_eq!(names, vec!["extracted.txt", "metadata.json"]);
+        assert_eq!(errors, vec!["PDF processor failed: failed to render"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_missing_dependency() {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("message/rfc822", vec![], output_sink).build();
+        let processors: Vec<Box<dyn Process>> = vec![
+            Box::new(MissingProgramProcessor),
+            Box::new(StubProcessor { name: "Metadata", output_name: Some("metadata.json") }),
+        ];
+
+        let permits = Arc::new(Semaphore::new(2));
+        let result = processor().run_processors(ctx, processors, Path::new(INPUT_PATH), "checksum", &permits).await;
+
+        match result {
+            Err(ProcessingError::MissingDependency(name)) => assert_eq!(name, "programthatdoesntexist"),
+            _ => panic!("expected missing dependency error"),
+        }
+        assert!(matches!(outputs.recv().await, Some(Ok(ProcessOutput::Processed(_, _)))));
+        assert!(outputs.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_process_limits_concurrent_processors() {
+        let (output_sink, _) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("message/rfc822", vec![], output_sink).build();
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let processors: Vec<Box<dyn Process>> = (0..8)
+            .map(|_| Box::new(CountingProcessor { running: running.clone(), max_running: max_running.clone() }) as Box<dyn Process>)
+            .collect();
+
+        let permits = Arc::new(Semaphore::new(2));
+        let result = processor().run_processors(ctx, processors, Path::new(INPUT_PATH), "checksum", &permits).await;
+        assert!(result.is_ok());
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_output_mimetype() {
+        let (output_sink, _) = tokio::sync::mpsc::channel(1);
+        let ctx = ProcessContextBuilder::new("application/pdf", vec![], output_sink).build();
+        let output_mimetype = |processor: Option<Box<dyn Process>>| processor.map(|processor| processor.output_mimetype(&ctx));
+
+        assert_eq!(output_mimetype(processor().text_processor("application/pdf")), Some("text/plain"));
+        let compressed_ctx = ProcessContextBuilder::from(ctx.clone()).compress_text(true).build();
+        assert_eq!(
+            processor().text_processor("application/pdf").map(|processor| processor.output_mimetype(&compressed_ctx)),
+            Some("application/gzip"),
+        );
+        assert_eq!(output_mimetype(processor().metadata_processor("application/pdf")), Some("application/json"));
+        assert_eq!(output_mimetype(processor().metadata_processor("text/calendar")), Some("application/json"));
+        let yaml_ctx = ProcessContextBuilder::from(ctx.clone()).metadata_format(MetadataFormat::Yaml).build();
+        assert_eq!(
+            processor().metadata_processor("application/pdf").map(|processor| processor.output_mimetype(&yaml_ctx)),
+            Some("application/yaml"),
+        );
+        let toml_ctx = ProcessContextBuilder::from(ctx.clone()).metadata_format(MetadataFormat::Toml).build();
+        assert_eq!(
+            processor().empty_processors(&[ProcessType::Metadata]).iter().map(|processor| processor.output_mimetype(&toml_ctx)).collect::<Vec<_>>(),
+            vec!["application/toml"],
+        );
+        assert_eq!(
+            processor().empty_processors(&[ProcessType::Metadata]).iter().map(|processor| processor.output_mimetype(&ctx)).collect::<Vec<_>>(),
+            vec!["application/json"],
+        );
+        #[cfg(feature = "pdf")]
+        assert_eq!(output_mimetype(processor().pdf_processor("message/rfc822")), Some("application/pdf"));
+        for mimetype in ["application/zip", "application/mbox", "application/pdf", "message/rfc822", "application/vnd.ms-tnef"] {
+            assert_eq!(output_mimetype(processor().embedded_processor(mimetype)), Some("application/octet-stream"));
+        }
+        assert_eq!(output_mimetype(processor().ocr_processor("image/png")), Some("text/plain"));
+        #[cfg(feature = "pdf")]
+        assert_eq!(output_mimetype(processor().ocr_processor("application/pdf")), Some("text/plain"));
+    }
+
+    #[test]
+    fn test_is_supported() {
+        assert!(processor().is_supported("application/pdf"));
+        assert!(processor().is_supported("application/mbox"));
+        assert!(processor().is_supported("text/calendar"));
+    }
+
+    #[test]
+    fn test_is_supported_unsupported() {
+        assert!(!processor().is_supported(""));
+        assert!(!processor().is_supported("pdf"));
+        assert!(!processor().is_supported("application/"));
+        assert!(!processor().is_supported("application/pdf/extra"));
+    }
+
+    #[test]
+    fn test_determine_ocr_processors() {
+        let names = |mimetype| processor().determine_processors(mimetype, &[ProcessType::Ocr]).iter()
+            .map(|processor| processor.name())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names("image/png"), vec!["Image OCR"]);
+        assert!(names("text/plain").is_empty());
+        assert!(!processor().determine_processors("image/png", ProcessType::all()).iter()
+            .any(|processor| processor.name() == "Image OCR"));
+    }
+
+    #[cfg(not(feature = "pdf"))]
+    #[tokio::test]
+    async fn test_process_pdf_disabled() -> anyhow::Result<()> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("message/rfc822", vec![ProcessType::Pdf], output_sink).build();
+
+        let result = processor().process(ctx, PathBuf::from("../resources/rfc822/headers-small.eml")).await;
+
+        assert!(result.is_ok());
+        assert!(outputs.recv().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_gzip_wrapped_message() -> anyhow::Result<()> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/gzip", vec![ProcessType::Text], output_sink).build();
+
+        let result = processor().process(ctx, PathBuf::from("../resources/gzip/plain-text.eml.gz")).await;
+        assert!(result.is_ok());
+
+        let Some(Ok(ProcessOutput::Processed(_, data))) = outputs.recv().await else {
+            panic!("expected processed output");
+        };
+        assert_eq!(data.name, "extracted.txt");
+        let text = std::fs::read_to_string(&data.path)?;
+        assert!(text.contains("Widgets"), "unexpected text: {}", text);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_empty_input() -> anyhow::Result<()> {
+        let input = NamedTempFile::new()?;
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/pdf", ProcessType::all().to_vec(), output_sink).build();
+
+        let result = processor().process(ctx, input.path().to_path_buf()).await;
+        assert!(result.is_ok());
+
+        let mut names = vec![];
+        while let Some(output) = outputs.recv().await {
+            match output? {
+                ProcessOutput::Processed(_, data) => {
+                    let metadata = json::parse(&std::fs::read_to_string(&data.path)?)?;
+                    assert_eq!(metadata["content_length"], "0");
+                    assert_eq!(metadata["raw"]["Content-Length"], "0");
+                    names.push(data.name);
+                },
+                ProcessOutput::Embedded(_, _, _) => panic!("expected processed output"),
+            }
+        }
+
+        assert_eq!(names, vec!["metadata.json"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_encrypted_pdf() {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/pdf", ProcessType::all().to_vec(), output_sink).build();
+
+        let result = processor().process(ctx, PathBuf::from("../resources/pdf/encrypted.pdf")).await;
+        assert!(result.is_ok());
+
+        let mut errors = vec![];
+        while let Some(output) = outputs.recv().await {
+            errors.push(output.unwrap_err().to_string());
+        }
+
+        assert_eq!(errors, vec!["PDF is encrypted and no password is configured"]);
+    }
+
+    #[test]
+    fn test_processing_error_code() {
+        use std::error::Error;
+
+        let errors = [
+            (ProcessingError::UnsupportedMimeType("application/x-unknown".to_string()), "unsupported_mimetype"),
+            (ProcessingError::InputTooLarge { size: 2, limit: 1 }, "input_too_large"),
+            (ProcessingError::MissingDependency("wkhtmltopdf".to_string()), "missing_dependency"),
+            (ProcessingError::Unexpected(anyhow!("something broke")), "unexpected"),
+        ];
+
+        for (err, code) in errors {
+            assert_eq!(err.error_code(), code);
+            assert_eq!(err.source().is_some(), code == "unexpected");
+        }
+    }
+}
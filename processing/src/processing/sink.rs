@@ -0,0 +1,85 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use tokio::sync::mpsc::Sender;
+
+use crate::processing::ProcessOutput;
+
+/// Destination of the outputs of a processing operation, such as a channel read by the caller.
+///
+/// The sink is shared between the contexts of embedded files, so it's only dropped once all of them are done.
+///
+#[async_trait]
+pub trait OutputSink: Debug + Send + Sync {
+    /// Sends the result of processing, either an output or the error that prevented it from being produced.
+    ///
+    /// Returns an error if the sink can't accept any more outputs.
+    ///
+    async fn send(&self, result: anyhow::Result<ProcessOutput>) -> anyhow::Result<()>;
+}
+
+/// The default sink, sending outputs through a channel created by the caller of the processing operation.
+///
+#[async_trait]
+impl OutputSink for Sender<anyhow::Result<ProcessOutput>> {
+    async fn send(&self, result: anyhow::Result<ProcessOutput>) -> anyhow::Result<()> {
+        Sender::send(self, result).await
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+/// A shared sink, for the caller to keep a handle to a sink given to a processing operation.
+///
+#[async_trait]
+impl<S: OutputSink + ?Sized> OutputSink for Arc<S> {
+    async fn send(&self, result: anyhow::Result<ProcessOutput>) -> anyhow::Result<()> {
+        self.as_ref().send(result).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use test_utils::temp_path;
+
+    use crate::embedded::MboxEmbeddedProcessor;
+    use crate::processing::{Process, ProcessContextBuilder};
+
+    use super::*;
+
+    /// Sink collecting the outputs sent to it.
+    ///
+    #[derive(Debug, Default)]
+    struct VecSink(Mutex<Vec<anyhow::Result<ProcessOutput>>>);
+
+    #[async_trait]
+    impl OutputSink for VecSink {
+        async fn send(&self, result: anyhow::Result<ProcessOutput>) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(result);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_output_sink() -> anyhow::Result<()> {
+        let sink = Arc::new(VecSink::default());
+        let ctx = ProcessContextBuilder::new("application/mbox", vec![], sink.clone()).build();
+        let path = Path::new("../resources/mbox/ubuntu-no-small.mbox");
+
+        MboxEmbeddedProcessor.process(ctx, path, temp_path()?, "checksum").await?;
+
+        let outputs = sink.0.lock().unwrap();
+        assert_eq!(outputs.len(), 2);
+        for output in outputs.iter() {
+            let Ok(ProcessOutput::Embedded(_, data, _)) = output else {
+                panic!("Expected embedded output");
+            };
+            assert_eq!(data.mimetype, "message/rfc822");
+        }
+        Ok(())
+    }
+}
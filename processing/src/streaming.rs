@@ -0,0 +1,432 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+
+use anyhow::anyhow;
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tempfile::TempPath;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::ReceiverStream;
+
+use identify::deduplication::BlockMd5;
+
+/// A stream of chunks of bytes.
+///
+pub type ByteStream = Pin<Box<dyn Stream<Item = anyhow::Result<Vec<u8>>> + Send>>;
+
+/// The number of chunks each branch of a [`tee`] buffers ahead of its consumer.
+///
+const TEE_BUFFER_CHUNKS: usize = 16;
+
+/// The maximum size of the chunks read by [`read_to_stream`].
+///
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// An algorithm for the digests computed by [`read_to_stream_hashed`].
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// The checksum files deduplicated by their content get, so it can be computed without reading them again.
+    ///
+    /// It's the MD5 of the bytes in 1 MB blocks, the last one padded with zeros; see [`BlockMd5`].
+    ///
+    #[default]
+    Dedupe,
+
+    /// MD5 of the bytes as they are.
+    ///
+    Md5,
+
+    /// SHA-256.
+    ///
+    Sha256,
+}
+
+/// The state of a digest being computed.
+///
+enum Hasher {
+    Dedupe(BlockMd5),
+    Md5(md5::Context),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Dedupe => Hasher::Dedupe(BlockMd5::new()),
+            HashAlgorithm::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Dedupe(hasher) => hasher.update(bytes),
+            Hasher::Md5(ctx) => ctx.consume(bytes),
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Dedupe(hasher) => hasher.compute(),
+            Hasher::Md5(ctx) => format!("{:x}", ctx.compute()),
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+/// Collects all chunks of the stream into a single buffer.
+///
+/// Returns the first error of the stream, if any.
+///
+pub async fn stream_to_bytes(mut stream: ByteStream) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    while let Some(chunk) = stream.next().await {
+        bytes.extend(chunk?);
+    }
+    Ok(bytes)
+}
+
+/// Writes all chunks of the stream to a new temporary file, returning its path.
+///
+/// Returns the first error of the stream, if any; the temporary file is removed then. See [`services::stream_to_file`],
+/// which downloads are written with too.
+///
+pub async fn stream_to_file(stream: ByteStream) -> anyhow::Result<TempPath> {
+    services::stream_to_file(stream).await
+}
+
+/// Consumes the stream, counting its bytes without retaining them.
+///
+/// Returns the first error of the stream, if any.
+///
+pub async fn stream_len(mut stream: ByteStream) -> anyhow::Result<usize> {
+    let mut len = 0;
+    while let Some(chunk) = stream.next().await {
+        len += chunk?.len();
+    }
+    Ok(len)
+}
+
+/// Reads the source into a stream of chunks of bytes, as they're read.
+///
+/// A read error is the last item of the stream.
+///
+pub fn read_to_stream<R>(mut source: R) -> ByteStream
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    Box::pin(stream! {
+        loop {
+            let mut chunk = vec![0; READ_CHUNK_SIZE];
+            match source.read(&mut chunk).await {
+                Ok(0) => break,
+                Ok(len) => {
+                    chunk.truncate(len);
+                    yield Ok(chunk);
+                },
+                Err(err) => {
+                    yield Err(err.into());
+                    break;
+                },
+            }
+        }
+    })
+}
+
+/// Reads the files at the paths into a single stream of chunks of bytes, one file after the other in order.
+///
+/// Each file is only opened once the previous one has been read to its end. An error opening or reading a file is the
+/// last item of the stream, so the files after it aren't read.
+///
+pub fn paths_to_stream(paths: Vec<PathBuf>) -> ByteStream {
+    Box::pin(stream! {
+        for path in paths {
+            let file = match tokio::fs::File::open(&path).await {
+                Ok(file) => file,
+                Err(err) => {
+                    yield Err(anyhow!("failed to open {}: {}", path.display(), err));
+                    return;
+                },
+            };
+
+            let mut chunks = read_to_stream(file);
+            while let Some(chunk) = chunks.next().await {
+                match chunk {
+                    Ok(chunk) => yield Ok(chunk),
+                    Err(err) => {
+                        yield Err(anyhow!("failed to read {}: {}", path.display(), err));
+                        return;
+                    },
+                }
+            }
+        }
+    })
+}
+
+/// Wraps the stream to report its progress, calling `progress` with the cumulative number of bytes as each chunk passes
+/// through.
+///
+/// Errors pass through without being reported.
+///
+pub fn with_progress(mut stream: ByteStream, progress: impl Fn(usize) + Send + 'static) -> ByteStream {
+    Box::pin(stream! {
+        let mut total = 0;
+        while let Some(chunk) = stream.next().await {
+            if let Ok(chunk) = &chunk {
+                total += chunk.len();
+                progress(total);
+            }
+            yield chunk;
+        }
+    })
+}
+
+/// Reads the source into a stream of chunks of bytes, computing the digest of its bytes along the way.
+///
+/// The returned future resolves with the hex digest once the stream has been read to its end, so the bytes are only
+/// read once. It fails if the stream fails, or is dropped before it's read to its end.
+///
+pub fn read_to_stream_hashed<R>(
+    source: R,
+    algorithm: HashAlgorithm,
+) -> (ByteStream, impl Future<Output = anyhow::Result<String>>)
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let (digest_sink, digest) = oneshot::channel();
+    let mut chunks = read_to_stream(source);
+
+    let stream: ByteStream = Box::pin(stream! {
+        let mut hasher = Hasher::new(algorithm);
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    hasher.update(&chunk);
+                    yield Ok(chunk);
+                },
+                Err(err) => {
+                    // The digest fails before the error is yielded, as the stream may not be polled any further
+                    let _ = digest_sink.send(Err(format!("{:#}", err)));
+                    yield Err(err);
+                    return;
+                },
+            }
+        }
+        let _ = digest_sink.send(Ok(hasher.finalize()));
+    });
+
+    let digest = async move {
+        digest.await
+            .map_err(|_| anyhow!("stream was dropped before it was read to its end"))?
+            .map_err(|err| anyhow!(err))
+    };
+    (stream, digest)
+}
+
+/// Duplicates the stream into two branches that each receive all chunks of the stream.
+///
+/// The stream is read by a background task, so this must be called from within a tokio runtime.
+///
+/// # Backpressure
+///
+/// Each branch buffers at most a fixed number of chunks, and the stream is only read further once both branches
+/// have room, so the branches advance at the pace of the slower consumer. As a result, both branches must be consumed
+/// concurrently; reading one to its end before starting the other stalls once the buffer of the other is full.
+/// If a branch is dropped, the other keeps receiving chunks.
+///
+/// An error of the stream is sent to both branches, ending them.
+///
+pub fn tee(mut stream: ByteStream) -> (ByteStream, ByteStream) {
+    let (first_sink, first) = tokio::sync::mpsc::channel(TEE_BUFFER_CHUNKS);
+    let (second_sink, second) = tokio::sync::mpsc::channel(TEE_BUFFER_CHUNKS);
+
+    tokio::spawn(async move {
+        let mut sinks: Vec<Sender<anyhow::Result<Vec<u8>>>> = vec![first_sink, second_sink];
+        while let Some(chunk) = stream.next().await {
+            let (chunk, is_err) = match chunk {
+                Ok(chunk) => (Ok(chunk), false),
+                Err(err) => (Err(format!("{:#}", err)), true),
+            };
+
+            let mut open = vec![];
+            for sink in sinks {
+                let item = chunk.clone().map_err(|err| anyhow!(err));
+                if sink.send(item).await.is_ok() {
+                    open.push(sink);
+                }
+            }
+            sinks = open;
+
+            if is_err || sinks.is_empty() {
+                break;
+            }
+        }
+    });
+
+    (Box::pin(ReceiverStream::new(first)), Box::pin(ReceiverStream::new(second)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use identify::deduplication::dedupe_checksum;
+    use test_utils::random_byte_stream;
+
+    use super::*;
+
+    fn byte_stream(len: usize) -> (Vec<u8>, ByteStream) {
+        let (bytes, stream) = random_byte_stream(len, 1000);
+        (bytes, Box::pin(stream.map(Ok)))
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_bytes() -> anyhow::Result<()> {
+        let (bytes, stream) = byte_stream(10_500);
+
+        assert_eq!(stream_to_bytes(stream).await?, bytes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_len() -> anyhow::Result<()> {
+        let (_, stream) = byte_stream(10_500);
+
+        assert_eq!(stream_len(stream).await?, 10_500);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_file() -> anyhow::Result<()> {
+        let (bytes, stream) = byte_stream(10_500);
+
+        let path = stream_to_file(stream).await?;
+
+        assert_eq!(std::fs::read(&path)?, bytes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_bytes_error() {
+        let stream: ByteStream = Box::pin(futures::stream::iter(vec![Ok(vec![1]), Err(anyhow!("read failed"))]));
+
+        assert!(stream_to_bytes(stream).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_paths_to_stream() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut paths = vec![];
+        let mut expected = vec![];
+        for (name, len) in [("part-3", 70_000), ("part-1", 10), ("part-2", 0)] {
+            let (bytes, _) = byte_stream(len);
+            let path = dir.path().join(name);
+            std::fs::write(&path, &bytes)?;
+            paths.push(path);
+            expected.extend(bytes);
+        }
+
+        assert_eq!(stream_to_bytes(paths_to_stream(paths)).await?, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paths_to_stream_missing_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("first");
+        std::fs::write(&first, b"first")?;
+        let paths = vec![first, dir.path().join("missing"), dir.path().join("first")];
+
+        let chunks: Vec<_> = paths_to_stream(paths).collect().await;
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].as_ref().unwrap(), b"first");
+        assert!(chunks[1].as_ref().unwrap_err().to_string().starts_with("failed to open"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_with_progress() -> anyhow::Result<()> {
+        let (bytes, stream) = byte_stream(10_500);
+        let reported = Arc::new(Mutex::new(vec![]));
+
+        let progress = reported.clone();
+        let stream = with_progress(stream, move |total| progress.lock().unwrap().push(total));
+
+        assert_eq!(stream_to_bytes(stream).await?, bytes);
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 11);
+        assert!(reported.windows(2).all(|totals| totals[0] < totals[1]));
+        assert_eq!(reported.last(), Some(&10_500));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_to_stream_hashed() -> anyhow::Result<()> {
+        // Spanning more than one block of the dedupe checksum, the last one partially filled
+        for len in [0, 200_500, 2_500_000] {
+            let (bytes, _) = byte_stream(len);
+
+            for algorithm in [HashAlgorithm::Dedupe, HashAlgorithm::Md5, HashAlgorithm::Sha256] {
+                let (stream, digest) = read_to_stream_hashed(std::io::Cursor::new(bytes.clone()), algorithm);
+
+                assert_eq!(stream_to_bytes(stream).await?, bytes);
+                let expected = match algorithm {
+                    HashAlgorithm::Dedupe => dedupe_checksum(&mut bytes.as_slice(), "application/pdf").await?,
+                    HashAlgorithm::Md5 => format!("{:x}", md5::compute(&bytes)),
+                    HashAlgorithm::Sha256 => format!("{:x}", Sha256::digest(&bytes)),
+                };
+                assert_eq!(digest.await?, expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_to_stream_hashed_dropped() {
+        let (stream, digest) = read_to_stream_hashed(std::io::Cursor::new(vec![1; 10]), HashAlgorithm::Md5);
+        drop(stream);
+
+        assert!(digest.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tee() -> anyhow::Result<()> {
+        let (bytes, stream) = byte_stream(100_500);
+
+        let (first, second) = tee(stream);
+        let (first, second) = tokio::join!(stream_to_bytes(first), stream_to_bytes(second));
+
+        assert_eq!(first?, bytes);
+        assert_eq!(second?, bytes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tee_dropped_branch() -> anyhow::Result<()> {
+        let (bytes, stream) = byte_stream(100_500);
+
+        let (first, second) = tee(stream);
+        drop(second);
+
+        assert_eq!(stream_to_bytes(first).await?, bytes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tee_error() {
+        let stream: ByteStream = Box::pin(futures::stream::iter(vec![Ok(vec![1]), Err(anyhow!("read failed"))]));
+
+        let (first, second) = tee(stream);
+        let (first, second) = tokio::join!(stream_to_bytes(first), stream_to_bytes(second));
+
+        assert_eq!(first.unwrap_err().to_string(), "read failed");
+        assert_eq!(second.unwrap_err().to_string(), "read failed");
+    }
+}
@@ -0,0 +1,109 @@
+use std::io::{Error, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::AsyncWrite;
+
+/// Writer passing at most `limit` bytes on to the inner writer, and failing any write past that.
+///
+/// Failing the write stops whatever is producing the output, like the command whose stdout is being read, so no more
+/// of it is produced than is kept.
+///
+#[derive(Debug)]
+pub(crate) struct LimitedWriter<W> {
+    inner: W,
+    limit: u64,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<W> LimitedWriter<W> {
+    pub(crate) fn new(inner: W, limit: u64) -> Self {
+        Self { inner, limit, remaining: limit, exceeded: false }
+    }
+
+    /// Returns whether more than `limit` bytes were written, and so the output is missing the rest.
+    ///
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    /// Returns the number of bytes of the buffer that may be written, failing if there's no room left for any.
+    ///
+    fn allowed(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 && !buf.is_empty() {
+            self.exceeded = true;
+            return Err(Error::other(format!("output exceeds the maximum of {} bytes", self.limit)));
+        }
+        Ok(buf.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX)))
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let allowed = self.allowed(buf)?;
+        let written = self.inner.write(&buf[..allowed])?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for LimitedWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let allowed = self.allowed(buf)?;
+        let poll = Pin::new(&mut self.inner).poll_write(cx, &buf[..allowed]);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.remaining -= written as u64;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut writer = LimitedWriter::new(vec![], 8);
+
+        assert!(Write::write_all(&mut writer, b"Quarterly").is_err());
+        assert!(writer.exceeded());
+        assert_eq!(writer.inner, b"Quarterl");
+    }
+
+    #[test]
+    fn test_write_within_limit() -> anyhow::Result<()> {
+        let mut writer = LimitedWriter::new(vec![], 9);
+
+        Write::write_all(&mut writer, b"Quarterly")?;
+        Write::write_all(&mut writer, b"")?;
+
+        assert!(!writer.exceeded());
+        assert_eq!(writer.inner, b"Quarterly");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_poll_write() {
+        let mut writer = LimitedWriter::new(vec![], 8);
+
+        assert!(AsyncWriteExt::write_all(&mut writer, b"Quarterly").await.is_err());
+        assert!(writer.exceeded());
+        assert_eq!(writer.inner, b"Quarterl");
+    }
+}
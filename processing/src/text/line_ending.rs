@@ -0,0 +1,109 @@
+use std::io::{Error, ErrorKind, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::AsyncWrite;
+
+use crate::processing::LineEnding;
+
+/// Writer normalizing the line endings of the text written to it before passing it on to the inner writer.
+///
+/// The text is normalized chunk by chunk as it's written, so it's never held in memory as a whole.
+///
+#[derive(Debug)]
+pub(crate) struct LineEndingWriter<W> {
+    inner: W,
+    line_ending: LineEnding,
+    after_cr: bool,
+    pending: Vec<u8>,
+}
+
+impl<W> LineEndingWriter<W> {
+    pub(crate) fn new(inner: W, line_ending: LineEnding) -> Self {
+        Self { inner, line_ending, after_cr: false, pending: vec![] }
+    }
+
+    /// Returns the inner writer.
+    ///
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> LineEndingWriter<W> {
+    /// Writes the normalized text still pending to the inner writer.
+    ///
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while !self.pending.is_empty() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if written == 0 {
+                return Poll::Ready(Err(Error::from(ErrorKind::WriteZero)));
+            }
+            self.pending.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: Write> Write for LineEndingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut normalized = Vec::with_capacity(buf.len());
+        self.line_ending.normalize_into(buf, &mut self.after_cr, &mut normalized);
+        self.inner.write_all(&normalized)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for LineEndingWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        // The chunk is accepted once the previous one is written, and is written by the next write or flush
+        ready!(self.poll_write_pending(cx))?;
+        let this = &mut *self;
+        this.line_ending.normalize_into(buf, &mut this.after_cr, &mut this.pending);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_write_pending(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_write_pending(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[test]
+    fn test_write_split_crlf() -> anyhow::Result<()> {
+        let mut writer = LineEndingWriter::new(vec![], LineEnding::Lf);
+
+        Write::write_all(&mut writer, b"Quarterly\r")?;
+        Write::write_all(&mut writer, b"\nreview\r")?;
+
+        assert_eq!(writer.into_inner(), b"Quarterly\nreview\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_poll_write_split_crlf() -> anyhow::Result<()> {
+        let mut writer = LineEndingWriter::new(vec![], LineEnding::Crlf);
+
+        AsyncWriteExt::write_all(&mut writer, b"Quarterly\r").await?;
+        AsyncWriteExt::write_all(&mut writer, b"\nreview\n").await?;
+        AsyncWriteExt::flush(&mut writer).await?;
+
+        assert_eq!(writer.into_inner(), b"Quarterly\r\nreview\r\n");
+        Ok(())
+    }
+}
@@ -1,15 +1,113 @@
+use std::io::Write;
 use std::path::Path;
 
 use async_trait::async_trait;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use log::warn;
 use tempfile::TempPath;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-use services::tika;
+use services::{external_extractors, ExternalExtractor, tika};
 
-use crate::processing::{Process, ProcessContext, ProcessOutput};
+use crate::processing::{Process, ProcessContext, ProcessOutput, ProcessOutputData, ProcessType, truncate_text};
 
+use self::limit::LimitedWriter;
+use self::line_ending::LineEndingWriter;
+
+mod limit;
+mod line_ending;
+
+/// Text processor extracting text with the external extractor registered for the MIME type, or tika if there's none.
+///
 #[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DefaultTextProcessor;
 
+impl DefaultTextProcessor {
+    /// Extracts the text of the input file into the writer, normalizing its line endings if configured.
+    ///
+    async fn text_into_writer<W>(
+        &self,
+        ctx: &ProcessContext,
+        extractor: &Option<ExternalExtractor>,
+        input_path: &Path,
+        writer: W,
+    ) -> anyhow::Result<W>
+    where
+        W: Write + Send,
+    {
+        let Some(line_ending) = ctx.line_ending else {
+            return match extractor {
+                Some(extractor) => extractor.text_into_writer(input_path, writer).await,
+                None => tika()?.text_into_writer(input_path, writer).await,
+            };
+        };
+
+        let writer = LineEndingWriter::new(writer, line_ending);
+        let writer = match extractor {
+            Some(extractor) => extractor.text_into_writer(input_path, writer).await?,
+            None => tika()?.text_into_writer(input_path, writer).await?,
+        };
+        Ok(writer.into_inner())
+    }
+
+    /// Extracts the text of the input file into the async writer, as it's produced, normalizing its line endings if
+    /// configured.
+    ///
+    async fn text_into_async_writer<W>(
+        &self,
+        ctx: &ProcessContext,
+        extractor: &Option<ExternalExtractor>,
+        input_path: &Path,
+        writer: W,
+    ) -> anyhow::Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let Some(line_ending) = ctx.line_ending else {
+            return match extractor {
+                Some(extractor) => extractor.text_into_async_writer(input_path, writer).await.map(|_| ()),
+                None => tika()?.text_into_async_writer(input_path, writer).await.map(|_| ()),
+            };
+        };
+
+        let writer = LineEndingWriter::new(writer, line_ending);
+        match extractor {
+            Some(extractor) => extractor.text_into_async_writer(input_path, writer).await.map(|_| ()),
+            None => tika()?.text_into_async_writer(input_path, writer).await.map(|_| ()),
+        }
+    }
+
+    /// Extracts the text of the input file into the output file, held to `ctx.max_output_bytes`.
+    ///
+    /// Returns whether the text was truncated.
+    ///
+    async fn text_into_file(
+        &self,
+        ctx: &ProcessContext,
+        extractor: &Option<ExternalExtractor>,
+        input_path: &Path,
+        output_path: &Path,
+    ) -> anyhow::Result<bool> {
+        let max_output_bytes = ctx.max_output_bytes.unwrap_or(u64::MAX);
+        let mut file = LimitedWriter::new(tokio::fs::File::create(output_path).await?, max_output_bytes);
+        let result = self.text_into_async_writer(ctx, extractor, input_path, &mut file).await;
+
+        // Extraction is stopped once the file reaches its limit, which isn't an error, as the text is truncated
+        let truncated = file.exceeded();
+        if !truncated {
+            result?;
+        }
+        file.flush().await?;
+
+        if truncated {
+            let len = truncate_text(output_path, max_output_bytes).await?;
+            warn!("Truncated extracted text to {} bytes", len);
+        }
+        Ok(truncated)
+    }
+}
+
 #[async_trait]
 impl Process for DefaultTextProcessor {
     async fn process(
@@ -19,13 +117,194 @@ impl Process for DefaultTextProcessor {
         output_path: TempPath,
         checksum: &str,
     ) -> anyhow::Result<()> {
-        tika().text_into_file(input_path, &output_path).await?;
+        let extractor = external_extractors().get(&ctx.mimetype);
+        let output = if ctx.compress_text {
+            // Compressed text can't be cut short, so it fails once it exceeds the limit
+            let file = std::fs::File::create(&output_path)?;
+            let file = LimitedWriter::new(file, ctx.max_output_bytes.unwrap_or(u64::MAX));
+            let encoder = GzEncoder::new(file, Compression::default());
+            let encoder = self.text_into_writer(&ctx, &extractor, input_path, encoder).await?;
+            encoder.finish()?;
+            let name = format!("{}.gz", ctx.output_name(ProcessType::Text, "extracted.txt"));
+            ProcessOutput::processed(&ctx, name, output_path, self.output_mimetype(&ctx), checksum)
+        } else {
+            let truncated = self.text_into_file(&ctx, &extractor, input_path, &output_path).await?;
+            ProcessOutput::Processed(ctx.state.clone(), ProcessOutputData {
+                name: ctx.output_name(ProcessType::Text, "extracted.txt"),
+                path: output_path,
+                mimetype: self.output_mimetype(&ctx).to_string(),
+                types: ctx.types.clone(),
+                checksum: checksum.to_string(),
+                truncated,
+            })
+        };
 
-        let output = ProcessOutput::processed(&ctx, "extracted.txt", output_path, "text/plain", checksum);
         ctx.add_output(Ok(output)).await
     }
 
     fn name(&self) -> &'static str {
         "Default Text"
     }
-}
\ No newline at end of file
+
+    /// Text is output gzipped with `ProcessContext.compress_text`.
+    ///
+    fn output_mimetype(&self, ctx: &ProcessContext) -> &'static str {
+        match ctx.compress_text {
+            true => "application/gzip",
+            false => "text/plain",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use flate2::read::GzDecoder;
+    use test_utils::temp_path;
+
+    use crate::processing::{LineEnding, ProcessContextBuilder};
+
+    use super::*;
+
+    async fn process_text(compress_text: bool) -> anyhow::Result<ProcessOutputData> {
+        process_text_of("message/rfc822", compress_text).await
+    }
+
+    async fn process_text_of(mimetype: &str, compress_text: bool) -> anyhow::Result<ProcessOutputData> {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new(mimetype, vec![], output_sink)
+            .compress_text(compress_text)
+            .build();
+        let path = PathBuf::from("../resources/rfc822/headers-small.eml");
+
+        DefaultTextProcessor.process(ctx, &path, temp_path()?, "checksum").await?;
+
+        match outputs.recv().await {
+            Some(Ok(ProcessOutput::Processed(_, data))) => Ok(data),
+            _ => panic!("Expected processed output"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_compressed() -> anyhow::Result<()> {
+        let plain = process_text(false).await?;
+        let compressed = process_text(true).await?;
+
+        assert_eq!(compressed.name, "extracted.txt.gz");
+        assert_eq!(compressed.mimetype, "application/gzip");
+
+        let mut decompressed = String::new();
+        GzDecoder::new(std::fs::File::open(&compressed.path)?).read_to_string(&mut decompressed)?;
+        assert_eq!(decompressed, std::fs::read_to_string(&plain.path)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_external_extractor() -> anyhow::Result<()> {
+        external_extractors().register("application/x-rusty-test", "cat {input}")?;
+
+        let data = process_text_of("application/x-rusty-test", false).await?;
+
+        assert_eq!(data.name, "extracted.txt");
+        assert_eq!(
+            std::fs::read_to_string(&data.path)?,
+            std::fs::read_to_string("../resources/rfc822/headers-small.eml")?,
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_line_ending() -> anyhow::Result<()> {
+        external_extractors().register("application/x-rusty-crlf-test", "cat {input}")?;
+        let path = PathBuf::from("../resources/text/crlf.txt");
+
+        for (line_ending, expected) in [
+            (None, "Quarterly report\r\n\r\nRevenue grew in every region.\r\nCosts were flat.\nHeadcount is unchanged.\r\n"),
+            (Some(LineEnding::Lf), "Quarterly report\n\nRevenue grew in every region.\nCosts were flat.\nHeadcount is unchanged.\n"),
+            (Some(LineEnding::Crlf), "Quarterly report\r\n\r\nRevenue grew in every region.\r\nCosts were flat.\r\nHeadcount is unchanged.\r\n"),
+        ] {
+            let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+            let ctx = ProcessContextBuilder::new("application/x-rusty-crlf-test", vec![], output_sink)
+                .line_ending(line_ending)
+                .build();
+
+            DefaultTextProcessor.process(ctx, &path, temp_path()?, "checksum").await?;
+
+            let Some(Ok(ProcessOutput::Processed(_, data))) = outputs.recv().await else {
+                panic!("Expected processed output");
+            };
+            assert_eq!(std::fs::read_to_string(&data.path)?, expected);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_max_output_bytes() -> anyhow::Result<()> {
+        external_extractors().register("application/x-rusty-large-test", "cat {input}")?;
+        let path = PathBuf::from("../resources/text/crlf.txt");
+
+        for (max_output_bytes, expected, truncated) in [
+            (None, std::fs::read_to_string(&path)?, false),
+            (Some(1024), std::fs::read_to_string(&path)?, false),
+            (Some(16), "Quarterly report".to_string(), true),
+        ] {
+            let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+            let ctx = ProcessContextBuilder::new("application/x-rusty-large-test", vec![], output_sink)
+                .max_output_bytes(max_output_bytes)
+                .build();
+
+            DefaultTextProcessor.process(ctx, &path, temp_path()?, "checksum").await?;
+
+            let Some(Ok(ProcessOutput::Processed(_, data))) = outputs.recv().await else {
+                panic!("Expected processed output");
+            };
+            assert_eq!(std::fs::read_to_string(&data.path)?, expected);
+            assert_eq!(data.truncated, truncated);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_max_output_bytes_compressed() -> anyhow::Result<()> {
+        external_extractors().register("application/x-rusty-large-gzip-test", "cat {input}")?;
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("application/x-rusty-large-gzip-test", vec![], output_sink)
+            .compress_text(true)
+            .max_output_bytes(Some(16))
+            .build();
+
+        let result = DefaultTextProcessor.process(ctx, Path::new("../resources/text/crlf.txt"), temp_path()?, "checksum").await;
+
+        assert!(result.is_err());
+        assert!(outputs.recv().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_max_output_bytes_stops_extraction() -> anyhow::Result<()> {
+        external_extractors().register("application/x-rusty-endless-test", "yes quarterly")?;
+
+        for line_ending in [None, Some(LineEnding::Crlf)] {
+            let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+            let ctx = ProcessContextBuilder::new("application/x-rusty-endless-test", vec![], output_sink)
+                .max_output_bytes(Some(16))
+                .line_ending(line_ending)
+                .build();
+
+            DefaultTextProcessor.process(ctx, Path::new("../resources/text/crlf.txt"), temp_path()?, "checksum").await?;
+
+            let Some(Ok(ProcessOutput::Processed(_, data))) = outputs.recv().await else {
+                panic!("Expected processed output");
+            };
+            let expected = match line_ending {
+                Some(_) => "quarterly\r\nquart",
+                None => "quarterly\nquarte",
+            };
+            assert_eq!(std::fs::read_to_string(&data.path)?, expected);
+            assert!(data.truncated);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use tempfile::TempPath;
+
+use services::Transcriber;
+
+use crate::processing::{Process, ProcessContext, ProcessOutput, ProcessType};
+
+/// Whether the MIME type is of an audio or video file, whose speech can be transcribed.
+///
+pub(crate) fn is_transcribable(mimetype: &str) -> bool {
+    mimetype.starts_with("audio/") || mimetype.starts_with("video/")
+}
+
+/// Returns a processor transcribing the file with the transcriber, if it's an audio or video file and the transcriber
+/// has a speech-to-text command configured.
+///
+pub(crate) fn transcription_processor(
+    mimetype: &str,
+    transcriber: &'static Transcriber,
+) -> Option<Box<dyn Process>> {
+    match is_transcribable(mimetype) && transcriber.is_configured() {
+        true => Some(Box::new(TranscriptionProcessor { transcriber })),
+        false => None,
+    }
+}
+
+/// Text processor for audio and video files, transcribing their speech with the speech-to-text command of its
+/// transcriber.
+///
+/// It's created by [`transcription_processor`] with the global transcriber; see [`services::Transcriber`] for how its
+/// command is configured.
+///
+#[derive(Debug, Clone)]
+pub struct TranscriptionProcessor {
+    transcriber: &'static Transcriber,
+}
+
+#[async_trait]
+impl Process for TranscriptionProcessor {
+    async fn process(
+        &self,
+        ctx: ProcessContext,
+        input_path: &Path,
+        output_path: TempPath,
+        checksum: &str,
+    ) -> anyhow::Result<()> {
+        self.transcriber.transcribe_into_file(input_path, &output_path).await?;
+
+        let name = ctx.output_name(ProcessType::Text, "transcript.txt");
+        let output = ProcessOutput::processed(&ctx, name, output_path, "text/plain", checksum);
+        ctx.add_output(Ok(output)).await
+    }
+
+    fn name(&self) -> &'static str {
+        "Transcription"
+    }
+
+    fn output_mimetype(&self, _: &ProcessContext) -> &'static str {
+        "text/plain"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    use tempfile::NamedTempFile;
+    use test_utils::temp_path;
+
+    use crate::processing::ProcessContextBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_is_transcribable() {
+        assert!(is_transcribable("audio/mpeg"));
+        assert!(is_transcribable("video/mp4"));
+        assert!(!is_transcribable("image/png"));
+    }
+
+    /// A transcriber of its own for a test, leaving the global one untouched.
+    ///
+    fn local_transcriber() -> &'static Transcriber {
+        Box::leak(Box::default())
+    }
+
+    #[test]
+    fn test_transcription_processor() -> anyhow::Result<()> {
+        let transcriber = local_transcriber();
+        assert!(transcription_processor("audio/mpeg", transcriber).is_none());
+
+        transcriber.configure("echo Hello from the voicemail")?;
+        assert!(transcription_processor("audio/mpeg", transcriber).is_some_and(|p| p.name() == "Transcription"));
+        assert!(transcription_processor("video/mp4", transcriber).is_some());
+        assert!(transcription_processor("image/png", transcriber).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process() -> anyhow::Result<()> {
+        // A fake speech-to-text command, transcribing every file the same
+        let mut command = NamedTempFile::new()?;
+        command.write_all(b"#!/bin/sh\necho \"Transcript of $(basename \"$1\")\"\n")?;
+        let command = command.into_temp_path();
+        std::fs::set_permissions(&command, std::fs::Permissions::from_mode(0o755))?;
+        let transcriber = local_transcriber();
+        transcriber.configure(&format!("{} {{input}}", command.display()))?;
+
+        let mut voicemail = NamedTempFile::new()?;
+        voicemail.write_all(b"not really audio")?;
+        let path = PathBuf::from(voicemail.path());
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+
+        let (output_sink, mut output_rx) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new("audio/mpeg", vec![ProcessType::Text], output_sink).build();
+        TranscriptionProcessor { transcriber }.process(ctx, &path, temp_path()?, "checksum").await?;
+
+        let output = output_rx.recv().await.expect("expected an output")?;
+        let ProcessOutput::Processed(_, data) = output else {
+            panic!("expected processed output");
+        };
+        assert_eq!(data.name, "transcript.txt");
+        assert_eq!(data.mimetype, "text/plain");
+        assert_eq!(std::fs::read_to_string(&data.path)?, format!("Transcript of {}\n", file_name));
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+// Resolves the features of the workspace with cargo itself, so it's kept in a test binary of its own
+use std::process::Command;
+
+/// Building without default features must compile the PDF and transcription services out of `services` too, so no
+/// crate `processing` depends on may turn the default features of `services` back on.
+///
+#[test]
+fn test_no_default_features_disable_services_features() -> anyhow::Result<()> {
+    let output = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["tree", "-e", "features", "-p", "processing", "--no-default-features", "-i", "services"])
+        .output()?;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let tree = String::from_utf8(output.stdout)?;
+    for feature in ["default", "pdf", "transcription"] {
+        assert!(!tree.contains(&format!("services feature \"{}\"", feature)), "{}", tree);
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+// Replaces the global allocator, so it's kept in a test binary of its own
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use bytesize::MB;
+
+use processing::processing::{ProcessContextBuilder, processor, ProcessType};
+
+/// Allocator tracking the peak number of bytes allocated by the current thread while tracking is enabled.
+///
+struct PeakAllocator;
+
+thread_local! {
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if TRACKING.with(Cell::get) {
+            let allocated = ALLOCATED.with(|a| { a.set(a.get() + layout.size() as isize); a.get() });
+            PEAK.with(|peak| peak.set(peak.get().max(allocated)));
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if TRACKING.with(Cell::get) {
+            ALLOCATED.with(|a| a.set(a.get() - layout.size() as isize));
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+#[tokio::test]
+async fn test_process_large_mbox_memory_bounded() -> anyhow::Result<()> {
+    let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+    let ctx = ProcessContextBuilder::new("application/mbox", vec![ProcessType::Embedded], output_sink).build();
+
+    // The test runtime is single threaded, so all processing allocations are made on this thread
+    TRACKING.with(|tracking| tracking.set(true));
+    let processing = tokio::spawn(processor().process(ctx, "../resources/mbox/ubuntu-no.mbox".into()));
+    let mut output_count = 0;
+    while let Some(output) = outputs.recv().await {
+        output?;
+        output_count += 1;
+    }
+    processing.await??;
+    TRACKING.with(|tracking| tracking.set(false));
+
+    let peak = PEAK.with(Cell::get);
+    assert_eq!(output_count, 344);
+    // A single message and the checksum's read buffer, regardless of the size of the mailbox
+    assert!(peak < 2 * MB as isize, "peak allocation of {} bytes", peak);
+    Ok(())
+}
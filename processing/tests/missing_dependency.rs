@@ -0,0 +1,24 @@
+// Clears the PATH so none of the external programs can be found, so it's kept in a test binary of its own
+#![cfg(feature = "pdf")]
+
+use std::path::PathBuf;
+
+use tempfile::TempDir;
+
+use processing::processing::{processor, ProcessContextBuilder, ProcessingError, ProcessType};
+
+#[tokio::test]
+async fn test_process_rfc822_pdf_without_wkhtmltopdf() -> anyhow::Result<()> {
+    let empty_dir = TempDir::new()?;
+    std::env::set_var("PATH", empty_dir.path());
+    let (output_sink, _outputs) = tokio::sync::mpsc::channel(10);
+    let ctx = ProcessContextBuilder::new("message/rfc822", vec![ProcessType::Pdf], output_sink).build();
+
+    let result = processor().process(ctx, PathBuf::from("../resources/rfc822/plain-text.eml")).await;
+
+    match result {
+        Err(ProcessingError::MissingDependency(name)) => assert_eq!(name, "wkhtmltopdf"),
+        result => panic!("expected wkhtmltopdf to be missing, got {:?}", result),
+    }
+    Ok(())
+}
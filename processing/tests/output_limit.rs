@@ -0,0 +1,44 @@
+// Points the Tika service at a mock server before its first use, so it's kept in a test binary of its own
+
+use std::path::PathBuf;
+
+use httpmock::Method::PUT;
+use httpmock::MockServer;
+
+use processing::processing::{processor, ProcessContextBuilder, ProcessOutput, ProcessType};
+use services::external_extractors;
+
+#[tokio::test]
+async fn test_process_truncated_text_in_metadata() -> anyhow::Result<()> {
+    let server = MockServer::start_async().await;
+    server.mock_async(|when, then| {
+        when.method(PUT).path("/meta");
+        then.status(200).body(r#"{"Content-Type":"text/plain"}"#);
+    }).await;
+    std::env::set_var("TIKA_BACKEND", "server");
+    std::env::set_var("TIKA_HOST", server.host());
+    std::env::set_var("TIKA_PORT", server.port().to_string());
+    external_extractors().register("application/x-rusty-endless-test", "yes quarterly")?;
+    external_extractors().register("application/x-rusty-short-test", "cat {input}")?;
+
+    for (mimetype, truncated) in [("application/x-rusty-endless-test", true), ("application/x-rusty-short-test", false)] {
+        let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+        let ctx = ProcessContextBuilder::new(mimetype, vec![ProcessType::Text, ProcessType::Metadata], output_sink)
+            .max_output_bytes(Some(1024))
+            .build();
+
+        processor().process(ctx, PathBuf::from("../resources/text/crlf.txt")).await?;
+
+        let Some(Ok(ProcessOutput::Processed(_, text))) = outputs.recv().await else {
+            panic!("expected the text first");
+        };
+        let Some(Ok(ProcessOutput::Processed(_, metadata))) = outputs.recv().await else {
+            panic!("expected the metadata after the text");
+        };
+        assert_eq!(text.truncated, truncated);
+        assert!(std::fs::metadata(&text.path)?.len() <= 1024);
+        let metadata = json::parse(&std::fs::read_to_string(&metadata.path)?)?;
+        assert_eq!(metadata["rusty.truncated"].as_bool(), truncated.then_some(true));
+    }
+    Ok(())
+}
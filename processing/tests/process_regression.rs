@@ -1,3 +1,6 @@
+// The expected outputs include rendered PDFs
+#![cfg(feature = "pdf")]
+
 use std::{fs, path};
 use anyhow::anyhow;
 
@@ -0,0 +1,42 @@
+// Points the Tika service at a mock server before its first use, so it's kept in a test binary of its own
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+use httpmock::Method::PUT;
+use httpmock::MockServer;
+use zip::ZipArchive;
+
+use processing::process_metadata_only;
+
+#[tokio::test]
+async fn test_process_thread_ids() -> anyhow::Result<()> {
+    let server = MockServer::start_async().await;
+    server.mock_async(|when, then| {
+        when.method(PUT).path("/meta");
+        then.status(200).body(r#"{"Content-Type":"message/rfc822"}"#);
+    }).await;
+    std::env::set_var("TIKA_BACKEND", "server");
+    std::env::set_var("TIKA_HOST", server.host());
+    std::env::set_var("TIKA_PORT", server.port().to_string());
+
+    let archive = process_metadata_only(PathBuf::from("../resources/mbox/reply-chain.mbox"), "application/mbox", true).await?;
+
+    let mut archive = ZipArchive::new(archive)?;
+    let mut thread_sizes = HashMap::new();
+    for i in 0..archive.len() {
+        let mut content = String::new();
+        archive.by_index(i)?.read_to_string(&mut content)?;
+        if let Some(thread_id) = json::parse(&content)?["rusty.thread_id"].as_str() {
+            *thread_sizes.entry(thread_id.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    // Both replies share the thread ID of the message they reply to, unlike the unrelated message
+    assert_eq!(thread_sizes, HashMap::from([
+        ("root@example.com".to_string(), 3),
+        ("unrelated@example.com".to_string(), 1),
+    ]));
+    Ok(())
+}
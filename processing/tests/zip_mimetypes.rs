@@ -0,0 +1,43 @@
+// Selects the infer MIME sniffer before its first use, as xdg-mime may not be installed, so it's kept in a test binary
+// of its own
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use tempfile::NamedTempFile;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use processing::processing::{processor, ProcessContextBuilder, ProcessOutput, ProcessType};
+
+#[tokio::test]
+async fn test_process_zip_entries_typed_by_contents() -> anyhow::Result<()> {
+    std::env::set_var("MIME_SNIFFER", "infer");
+    let pdf = std::fs::read("../resources/pdf/zugferd-invoice.pdf")?;
+    let zip = std::fs::read("../resources/zip/testzip.zip")?;
+    let zip_file = NamedTempFile::new()?;
+    let mut writer = ZipWriter::new(zip_file.reopen()?);
+    for (name, content) in [("scan", &pdf), ("notes.txt", &pdf), ("report.pdf", &zip)] {
+        writer.start_file(name, FileOptions::default())?;
+        writer.write_all(content)?;
+    }
+    writer.finish()?;
+
+    let (output_sink, mut outputs) = tokio::sync::mpsc::channel(10);
+    let ctx = ProcessContextBuilder::new("application/zip", vec![ProcessType::Embedded], output_sink).build();
+    processor().process(ctx, PathBuf::from(zip_file.path())).await?;
+
+    let mut mimetypes = HashMap::new();
+    while let Ok(output) = outputs.try_recv() {
+        if let ProcessOutput::Embedded(_, data, _) = output? {
+            mimetypes.insert(data.name, data.mimetype);
+        }
+    }
+    assert_eq!(mimetypes, HashMap::from([
+        ("scan.pdf".to_string(), "application/pdf".to_string()),
+        ("notes.txt".to_string(), "application/pdf".to_string()),
+        ("report.pdf".to_string(), "application/zip".to_string()),
+    ]));
+    Ok(())
+}
@@ -1,25 +1,133 @@
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
 
+use anyhow::anyhow;
 use bytesize::MB;
+use tempfile::{SpooledTempFile, TempDir};
+use zip::CompressionMethod;
+use zip::write::FileOptions;
+
+/// MIME types of content that's already compressed, which gains little from being compressed again.
+///
+const COMPRESSED_MIMETYPES: [&str; 13] = [
+    "application/gzip",
+    "application/pdf",
+    "application/vnd.rar",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-rar-compressed",
+    "application/x-xz",
+    "application/zip",
+    "application/zstd",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+];
+
+/// The compression level used for text, which compresses well.
+///
+const MAX_DEFLATE_LEVEL: i32 = 9;
+
+/// How the entries of an archive are compressed.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompressionPolicy {
+    /// Entries are compressed according to their MIME type.
+    ///
+    /// Content that's already compressed, like JPEGs, PDFs, and Office Open XML documents, is stored without being
+    /// compressed again, text is deflated with the maximum compression level, and everything else, including entries
+    /// of unknown MIME type, is deflated with the default level.
+    ///
+    #[default]
+    ByMimetype,
+
+    /// All entries are stored without compression.
+    ///
+    Stored,
+
+    /// All entries are deflated, with the given compression level or the default level.
+    ///
+    Deflated(Option<i32>),
+}
+
+impl CompressionPolicy {
+    /// The options to write an entry of the given MIME type with.
+    ///
+    pub fn file_options(&self, mimetype: Option<&str>) -> FileOptions {
+        let (method, level) = match self {
+            CompressionPolicy::Stored => (CompressionMethod::Stored, None),
+            CompressionPolicy::Deflated(level) => (CompressionMethod::Deflated, *level),
+            CompressionPolicy::ByMimetype => match mimetype {
+                Some(mimetype) if is_compressed(mimetype) => (CompressionMethod::Stored, None),
+                Some(mimetype) if is_text(mimetype) => (CompressionMethod::Deflated, Some(MAX_DEFLATE_LEVEL)),
+                _ => (CompressionMethod::Deflated, None),
+            },
+        };
+        FileOptions::default().compression_method(method).compression_level(level)
+    }
+}
+
+fn is_compressed(mimetype: &str) -> bool {
+    COMPRESSED_MIMETYPES.contains(&mimetype)
+        || mimetype.starts_with("application/vnd.openxmlformats-officedocument.")
+        || mimetype.starts_with("audio/")
+        || mimetype.starts_with("video/")
+}
+
+fn is_text(mimetype: &str) -> bool {
+    mimetype.starts_with("text/")
+        || mimetype.ends_with("+json")
+        || mimetype.ends_with("+xml")
+        || mimetype.ends_with("+yaml")
+        || [
+            "application/json",
+            "application/mbox",
+            "application/toml",
+            "application/xml",
+            "application/yaml",
+            "message/rfc822",
+        ].contains(&mimetype)
+}
 
 /// A builder for creating an archive.
 ///
-/// This builder eagerly writes the contents to an archive.
+/// This builder eagerly writes the contents to an archive, usually a file. See [`StreamingArchiveWriter`] to write the
+/// archive to a destination that can't seek.
 ///
-pub struct ArchiveBuilder {
-    zipper: zip::ZipWriter<File>,
+pub struct ArchiveBuilder<W: Write + Seek = File> {
+    zipper: zip::ZipWriter<W>,
+    compression: CompressionPolicy,
+    deterministic: bool,
 }
 
-impl ArchiveBuilder {
+impl<W: Write + Seek> ArchiveBuilder<W> {
     /// Create a new archive builder.
     ///
-    pub fn new(file: File) -> anyhow::Result<Self> {
+    pub fn new(file: W) -> anyhow::Result<Self> {
         let zipper = zip::ZipWriter::new(file);
 
-        Ok(Self { zipper })
+        Ok(Self { zipper, compression: CompressionPolicy::default(), deterministic: false })
+    }
+
+    /// Sets how the entries of the archive are compressed.
+    ///
+    pub fn compression(mut self, compression: CompressionPolicy) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets whether entries are written with a fixed modification time, the earliest a zip archive supports, rather
+    /// than the current time.
+    ///
+    /// Archives built from the same files in the same order are then identical byte for byte.
+    ///
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
     }
 
     /// Add a file to the archive.
@@ -30,8 +138,29 @@ impl ArchiveBuilder {
     /// * `zip_path` - The path to the file in the archive.
     ///
     pub fn push(&mut self, input_path: impl AsRef<Path>, zip_path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.push_with_mimetype(input_path, zip_path, None)
+    }
+
+    /// Add a file of the given MIME type to the archive, compressing it according to the compression policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_path` - The path to the file to add to the archive.
+    /// * `zip_path` - The path to the file in the archive.
+    /// * `mimetype` - The MIME type of the file, if known.
+    ///
+    pub fn push_with_mimetype(
+        &mut self,
+        input_path: impl AsRef<Path>,
+        zip_path: impl AsRef<Path>,
+        mimetype: Option<&str>,
+    ) -> anyhow::Result<()> {
         let zip_path_str = zip_path.as_ref().to_string_lossy();
-        self.zipper.start_file(zip_path_str, Default::default())?;
+        let mut options = self.compression.file_options(mimetype);
+        if self.deterministic {
+            options = options.last_modified_time(zip::DateTime::default());
+        }
+        self.zipper.start_file(zip_path_str, options)?;
 
         let path = input_path.as_ref();
         self.write_file(path)?;
@@ -41,7 +170,7 @@ impl ArchiveBuilder {
 
     /// Build the archive.
     ///
-    pub fn build(&mut self) -> anyhow::Result<std::fs::File> {
+    pub fn build(&mut self) -> anyhow::Result<W> {
         Ok(self.zipper.finish()?)
     }
 
@@ -58,4 +187,395 @@ impl ArchiveBuilder {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// A builder for creating an archive from files staged in a directory.
+///
+/// Pushed files are hard-linked into the staging directory, or copied if they can't be linked, and the archive is only
+/// written once it's built. Unlike [`ArchiveBuilder`], files can be pushed concurrently from multiple threads.
+///
+pub struct StagingArchiveBuilder {
+    file: File,
+    staging: TempDir,
+    zip_paths: Mutex<Vec<(PathBuf, Option<String>)>>,
+    compression: CompressionPolicy,
+}
+
+impl StagingArchiveBuilder {
+    /// Create a new staging archive builder, writing the archive to `file` when built.
+    ///
+    pub fn new(file: File) -> anyhow::Result<Self> {
+        Ok(Self {
+            file,
+            staging: TempDir::new()?,
+            zip_paths: Mutex::new(vec![]),
+            compression: CompressionPolicy::default(),
+        })
+    }
+
+    /// Sets how the entries of the archive are compressed.
+    ///
+    pub fn compression(mut self, compression: CompressionPolicy) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Stage a file to add to the archive.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_path` - The path to the file to add to the archive.
+    /// * `zip_path` - The path to the file in the archive, which must be relative and can't contain `..`.
+    ///
+    pub fn push(&self, input_path: impl AsRef<Path>, zip_path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.push_with_mimetype(input_path, zip_path, None)
+    }
+
+    /// Stage a file of the given MIME type to add to the archive, compressing it according to the compression policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_path` - The path to the file to add to the archive.
+    /// * `zip_path` - The path to the file in the archive, which must be relative and can't contain `..`.
+    /// * `mimetype` - The MIME type of the file, if known.
+    ///
+    pub fn push_with_mimetype(
+        &self,
+        input_path: impl AsRef<Path>,
+        zip_path: impl AsRef<Path>,
+        mimetype: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let zip_path = zip_path.as_ref();
+        if !zip_path.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(anyhow!("invalid archive entry path {:?}", zip_path));
+        }
+
+        let staged_path = self.staging.path().join(zip_path);
+        if let Some(parent) = staged_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if let Err(err) = std::fs::hard_link(&input_path, &staged_path) {
+            if err.kind() == ErrorKind::AlreadyExists {
+                return Err(anyhow!("duplicate archive entry path {:?}", zip_path));
+            }
+            std::fs::copy(&input_path, &staged_path)?;
+        }
+
+        self.zip_paths.lock()
+            .map_err(|_| anyhow!("staging archive builder poisoned"))?
+            .push((zip_path.to_path_buf(), mimetype.map(str::to_string)));
+        Ok(())
+    }
+
+    /// Build the archive from the staged files, in the order they were pushed.
+    ///
+    pub fn build(self) -> anyhow::Result<File> {
+        let zip_paths = self.zip_paths.into_inner().map_err(|_| anyhow!("staging archive builder poisoned"))?;
+
+        let mut builder = ArchiveBuilder::new(self.file)?.compression(self.compression);
+        for (zip_path, mimetype) in zip_paths {
+            builder.push_with_mimetype(self.staging.path().join(&zip_path), &zip_path, mimetype.as_deref())?;
+        }
+        builder.build()
+    }
+}
+
+/// The size of the entry being written by a [`StreamingArchiveWriter`] above which it's buffered in a temporary file
+/// rather than in memory.
+///
+const STREAMING_BUFFER_SIZE: usize = MB as usize;
+
+/// The bytes of an archive committed by a [`StreamingArchiveWriter`], shared with the writer.
+///
+#[derive(Debug, Clone, Default)]
+pub struct CommittedBytes(Arc<Mutex<Vec<SpooledTempFile>>>);
+
+impl CommittedBytes {
+    /// Takes the bytes committed since they were last taken, in order and rewound to be read from the start.
+    ///
+    /// The bytes of a large entry are kept in a temporary file, so they should be copied out in chunks rather than
+    /// read into memory.
+    ///
+    pub fn take(&self) -> Vec<SpooledTempFile> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn push(&self, bytes: SpooledTempFile) {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).push(bytes);
+    }
+}
+
+/// A writer of an archive that commits its bytes as soon as they're final, to stream the archive to a destination
+/// that can't seek.
+///
+/// Once an entry is written, its header is updated by seeking back to it, so the bytes of the entry being written are
+/// buffered until the writer seeks back to the end of the archive. Only the entry being written is buffered, spilling
+/// over to a temporary file once it's large, and the rest of the archive is committed once it's built with
+/// [`StreamingArchiveWriter::commit`].
+///
+#[derive(Debug)]
+pub struct StreamingArchiveWriter {
+    buffer: SpooledTempFile,
+    buffer_start: u64,
+    buffer_len: u64,
+    position: u64,
+    committed: CommittedBytes,
+}
+
+impl Default for StreamingArchiveWriter {
+    fn default() -> Self {
+        Self {
+            buffer: SpooledTempFile::new(STREAMING_BUFFER_SIZE),
+            buffer_start: 0,
+            buffer_len: 0,
+            position: 0,
+            committed: CommittedBytes::default(),
+        }
+    }
+}
+
+impl StreamingArchiveWriter {
+    /// Create a new streaming archive writer.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a handle to take the committed bytes from, which stays valid once the writer is moved.
+    ///
+    pub fn committed_bytes(&self) -> CommittedBytes {
+        self.committed.clone()
+    }
+
+    /// Commits all buffered bytes, which can't be written to any more.
+    ///
+    pub fn commit(&mut self) -> std::io::Result<()> {
+        let mut bytes = std::mem::replace(&mut self.buffer, SpooledTempFile::new(STREAMING_BUFFER_SIZE));
+        bytes.rewind()?;
+        self.buffer_start += self.buffer_len;
+        self.buffer_len = 0;
+        self.position = self.buffer_start;
+        self.committed.push(bytes);
+        Ok(())
+    }
+
+    fn end(&self) -> u64 {
+        self.buffer_start + self.buffer_len
+    }
+}
+
+impl Write for StreamingArchiveWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The position in the buffer is kept in step with the position of the writer
+        let written = self.buffer.write(buf)?;
+        self.position += written as u64;
+        self.buffer_len = self.buffer_len.max(self.position - self.buffer_start);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.buffer.flush()
+    }
+}
+
+impl Seek for StreamingArchiveWriter {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.end().checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let position = position
+            .filter(|position| (self.buffer_start..=self.end()).contains(position))
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "can't seek outside of the buffered bytes"))?;
+
+        // Returning to the end after updating the header of an entry means the entry is finished
+        if position == self.end() && self.position < position {
+            self.commit()?;
+        } else {
+            self.buffer.seek(SeekFrom::Start(position - self.buffer_start))?;
+            self.position = position;
+        }
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom};
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn read_committed(committed: &CommittedBytes) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        for mut committed in committed.take() {
+            committed.read_to_end(&mut bytes)?;
+        }
+        Ok(bytes)
+    }
+
+    fn archive_names(mut file: File) -> anyhow::Result<Vec<String>> {
+        file.seek(SeekFrom::Start(0))?;
+        let archive = zip::ZipArchive::new(file)?;
+        let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn test_staging_archive_builder() -> anyhow::Result<()> {
+        let mut input = NamedTempFile::new()?;
+        input.write_all(b"contents")?;
+
+        let builder = StagingArchiveBuilder::new(tempfile::tempfile()?)?;
+        builder.push(input.path(), "a/first.txt")?;
+        builder.push(input.path(), "second.txt")?;
+
+        assert_eq!(archive_names(builder.build()?)?, vec!["a/first.txt", "second.txt"]);
+        Ok(())
+    }
+
+    fn compression_methods(mut file: File) -> anyhow::Result<Vec<(String, CompressionMethod)>> {
+        file.seek(SeekFrom::Start(0))?;
+        let mut archive = zip::ZipArchive::new(file)?;
+        let mut methods = vec![];
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i)?;
+            methods.push((entry.name().to_string(), entry.compression()));
+        }
+        Ok(methods)
+    }
+
+    #[test]
+    fn test_archive_builder_compression_by_mimetype() -> anyhow::Result<()> {
+        let mut input = NamedTempFile::new()?;
+        input.write_all(b"contents contents contents")?;
+
+        let mut builder = ArchiveBuilder::new(tempfile::tempfile()?)?;
+        builder.push_with_mimetype(input.path(), "photo.jpg", Some("image/jpeg"))?;
+        builder.push_with_mimetype(input.path(), "extracted.txt", Some("text/plain"))?;
+        builder.push(input.path(), "unknown")?;
+
+        assert_eq!(compression_methods(builder.build()?)?, vec![
+            ("photo.jpg".to_string(), CompressionMethod::Stored),
+            ("extracted.txt".to_string(), CompressionMethod::Deflated),
+            ("unknown".to_string(), CompressionMethod::Deflated),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_text() {
+        for mimetype in ["text/plain", "application/json", "application/yaml", "application/toml", "application/ld+json"] {
+            assert!(is_text(mimetype), "{}", mimetype);
+        }
+        assert!(!is_text("application/octet-stream"));
+    }
+
+    #[test]
+    fn test_staging_archive_builder_compression_override() -> anyhow::Result<()> {
+        let mut input = NamedTempFile::new()?;
+        input.write_all(b"contents contents contents")?;
+
+        let builder = StagingArchiveBuilder::new(tempfile::tempfile()?)?.compression(CompressionPolicy::Stored);
+        builder.push_with_mimetype(input.path(), "extracted.txt", Some("text/plain"))?;
+
+        assert_eq!(
+            compression_methods(builder.build()?)?,
+            vec![("extracted.txt".to_string(), CompressionMethod::Stored)],
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_builder_deterministic() -> anyhow::Result<()> {
+        let mut input = NamedTempFile::new()?;
+        input.write_all(b"contents")?;
+
+        let mut builder = ArchiveBuilder::new(tempfile::tempfile()?)?.deterministic(true);
+        builder.push(input.path(), "entry.txt")?;
+
+        let mut archive = zip::ZipArchive::new(builder.build()?)?;
+        let last_modified = archive.by_index(0)?.last_modified();
+        assert_eq!((last_modified.year(), last_modified.month(), last_modified.day()), (1980, 1, 1));
+        assert_eq!((last_modified.hour(), last_modified.minute(), last_modified.second()), (0, 0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_archive_writer() -> anyhow::Result<()> {
+        let mut input = NamedTempFile::new()?;
+        input.write_all(&[7; 10_000])?;
+
+        let writer = StreamingArchiveWriter::new();
+        let committed = writer.committed_bytes();
+        let mut builder = ArchiveBuilder::new(writer)?;
+        builder.push(input.path(), "first.bin")?;
+        assert!(read_committed(&committed)?.is_empty());
+
+        // Starting the second entry finishes the first, which is then committed
+        builder.push(input.path(), "second.bin")?;
+        let mut bytes = read_committed(&committed)?;
+        assert!(!bytes.is_empty());
+
+        builder.build()?.commit()?;
+        bytes.extend(read_committed(&committed)?);
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+        assert_eq!(archive.len(), 2);
+        for i in 0..archive.len() {
+            let mut content = vec![];
+            archive.by_index(i)?.read_to_end(&mut content)?;
+            assert_eq!(content, vec![7; 10_000]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_archive_writer_seek_committed() -> anyhow::Result<()> {
+        let mut writer = StreamingArchiveWriter::new();
+        writer.write_all(b"header")?;
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(b"H")?;
+        writer.seek(SeekFrom::End(0))?;
+
+        assert_eq!(read_committed(&writer.committed_bytes())?, b"Header");
+        assert!(writer.seek(SeekFrom::Start(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_archive_writer_large_entry() -> anyhow::Result<()> {
+        let mut writer = StreamingArchiveWriter::new();
+        writer.write_all(&vec![7; 2 * STREAMING_BUFFER_SIZE])?;
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(b"H")?;
+        writer.seek(SeekFrom::End(0))?;
+
+        let committed = writer.committed_bytes().take();
+        assert_eq!(committed.len(), 1);
+        assert!(committed[0].is_rolled());
+
+        let mut bytes = vec![];
+        for mut committed in committed {
+            committed.read_to_end(&mut bytes)?;
+        }
+        assert_eq!(bytes.len(), 2 * STREAMING_BUFFER_SIZE);
+        assert_eq!(&bytes[..2], b"H\x07");
+        Ok(())
+    }
+
+    #[test]
+    fn test_staging_archive_builder_invalid_paths() -> anyhow::Result<()> {
+        let input = NamedTempFile::new()?;
+        let builder = StagingArchiveBuilder::new(tempfile::tempfile()?)?;
+
+        assert!(builder.push(input.path(), "../escaped.txt").is_err());
+        assert!(builder.push(input.path(), "/absolute.txt").is_err());
+        builder.push(input.path(), "entry.txt")?;
+        assert!(builder.push(input.path(), "entry.txt").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
+
+use crate::{external_extractors, TikaBackend};
+
+/// An external program needed for processing that isn't installed.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingDependency {
+    /// The name of the program, as it's run.
+    ///
+    pub name: String,
+}
+
+impl MissingDependency {
+    /// Creates a new `MissingDependency` for the program.
+    ///
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl fmt::Display for MissingDependency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not installed or isn't on the PATH", self.name)
+    }
+}
+
+impl std::error::Error for MissingDependency {}
+
+/// Checks that the external programs processing runs are installed, returning the ones that aren't.
+///
+/// Useful to call at startup, rather than finding out when a file fails to be processed.
+///
+pub fn check_dependencies() -> Vec<MissingDependency> {
+    missing_programs(required_programs())
+}
+
+/// Returns the path of the program, searching the `PATH` unless it's given as a path itself.
+///
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return is_executable(path).then(|| path.to_path_buf());
+    }
+
+    std::env::var_os("PATH").and_then(|paths|
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|path| is_executable(path))
+    )
+}
+
+/// The programs run by the services, given the configured Tika backend, external extractors, and speech-to-text
+/// command.
+///
+fn required_programs() -> Vec<String> {
+    let mut programs = vec![crate::xdg_mime::PROGRAM, crate::tesseract::PROGRAM];
+    #[cfg(feature = "pdf")]
+    programs.extend([crate::html_to_pdf::PROGRAM, crate::pdf_to_image::PROGRAM]);
+    if TikaBackend::default() == TikaBackend::Cli {
+        programs.push(crate::tika::JAVA_PROGRAM);
+    }
+
+    let mut programs: Vec<String> = programs.into_iter().map(str::to_string).collect();
+    programs.extend(external_extractors().programs());
+    #[cfg(feature = "transcription")]
+    programs.extend(crate::transcriber().program());
+    programs
+}
+
+fn missing_programs(programs: impl IntoIterator<Item=impl Into<String>>) -> Vec<MissingDependency> {
+    programs.into_iter()
+        .map(Into::into)
+        .filter(|program| find_program(program).is_none())
+        .map(MissingDependency::new)
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_program() {
+        assert!(find_program("sh").is_some());
+        assert!(find_program("/bin/sh").is_some());
+        assert!(find_program("programthatdoesntexist").is_none());
+        assert!(find_program("/bin/programthatdoesntexist").is_none());
+    }
+
+    #[test]
+    fn test_missing_programs() {
+        assert_eq!(
+            missing_programs(["sh", "programthatdoesntexist", "cat"]),
+            vec![MissingDependency::new("programthatdoesntexist")],
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::anyhow;
+use futures::{Stream, StreamExt};
+use log::info;
+use tempfile::{NamedTempFile, TempPath};
+use tokio::io::AsyncWriteExt;
+
+use crate::{http_client, HttpClientConfig};
+
+/// A file downloaded into a temporary file, deleted once it's dropped.
+///
+#[derive(Debug)]
+pub struct Download {
+    /// The path of the temporary file.
+    ///
+    pub path: TempPath,
+
+    /// The MIME type of the file from the `Content-Type` of the response, without its parameters, if any.
+    ///
+    pub mimetype: Option<String>,
+}
+
+/// Whether the input is an `http` or `https` URL rather than a path.
+///
+pub fn is_url(input: &str) -> bool {
+    let input = input.to_ascii_lowercase();
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Downloads the file at the URL into a temporary file.
+///
+/// The response body is streamed into the file as it's received, so large files aren't held in memory. Responses with
+/// an error status fail the download.
+///
+pub async fn download(url: &str, client_config: &HttpClientConfig) -> anyhow::Result<Download> {
+    info!("Downloading {}", url);
+
+    let response = http_client(client_config)?.get(url).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(anyhow!("Failed to download {}: {}", url, status));
+    }
+    let mimetype = response.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mimetype| mimetype.trim().to_ascii_lowercase())
+        .filter(|mimetype| !mimetype.is_empty());
+
+    let path = stream_to_file(response.bytes_stream()).await?;
+
+    Ok(Download { path, mimetype })
+}
+
+/// Writes all chunks of the stream to a new temporary file, returning its path.
+///
+/// Returns the first error of the stream, if any; the temporary file is removed then.
+///
+pub async fn stream_to_file<S, B, E>(stream: S) -> anyhow::Result<TempPath>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<anyhow::Error>,
+{
+    stream_into_file(stream, NamedTempFile::new()?.into_temp_path()).await
+}
+
+/// Writes all chunks of the stream to the temporary file, which is removed if the stream or the write fails.
+///
+async fn stream_into_file<S, B, E>(stream: S, path: TempPath) -> anyhow::Result<TempPath>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<anyhow::Error>,
+{
+    let mut stream = std::pin::pin!(stream);
+    let mut file = tokio::fs::File::create(&path).await?;
+    while let Some(chunk) = stream.next().await {
+        file.write_all(chunk.map_err(Into::into)?.as_ref()).await?;
+    }
+    file.flush().await?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://example.com/message.eml"));
+        assert!(is_url("HTTP://example.com/message.eml"));
+        assert!(!is_url("ftp://example.com/message.eml"));
+        assert!(!is_url("../resources/rfc822/attachments.eml"));
+    }
+
+    #[tokio::test]
+    async fn test_download() -> anyhow::Result<()> {
+        let server = MockServer::start_async().await;
+        server.mock_async(|when, then| {
+            when.method(GET).path("/message.eml");
+            then.status(200).header("Content-Type", "Message/RFC822; charset=utf-8").body("Subject: Hello");
+        }).await;
+
+        let download = download(&server.url("/message.eml"), &HttpClientConfig::default()).await?;
+
+        assert_eq!(download.mimetype.as_deref(), Some("message/rfc822"));
+        assert_eq!(std::fs::read(&download.path)?, b"Subject: Hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_file() -> anyhow::Result<()> {
+        let stream = futures::stream::iter(vec![Ok::<_, anyhow::Error>(b"Subject: ".to_vec()), Ok(b"Hello".to_vec())]);
+
+        let path = stream_to_file(stream).await?;
+
+        assert_eq!(std::fs::read(&path)?, b"Subject: Hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_file_error() -> anyhow::Result<()> {
+        let stream = futures::stream::iter(vec![Ok(b"Subject: ".to_vec()), Err(anyhow!("connection reset"))]);
+        let path = NamedTempFile::new()?.into_temp_path();
+        let path_buf = path.to_path_buf();
+
+        assert!(stream_into_file(stream, path).await.is_err());
+        assert!(!path_buf.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_error_status() {
+        let server = MockServer::start_async().await;
+        server.mock_async(|when, then| {
+            when.method(GET).path("/missing.eml");
+            then.status(404);
+        }).await;
+
+        let result = download(&server.url("/missing.eml"), &HttpClientConfig::default()).await;
+
+        assert!(result.is_err());
+    }
+}
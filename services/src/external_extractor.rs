@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::RwLock;
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{config, no_writer, stream_command};
+
+/// The placeholder in a command template replaced by the path of the input file.
+///
+pub const INPUT_PLACEHOLDER: &str = "{input}";
+
+lazy_static! {
+    static ref EXTERNAL_EXTRACTORS: ExternalExtractors = ExternalExtractors::from_config();
+}
+
+/// Returns the singleton registry of external extractors.
+///
+/// It's initialized with the extractors configured by `EXTERNAL_EXTRACTORS`; see [`ExternalExtractors::parse`] for
+/// its format.
+///
+pub fn external_extractors() -> &'static ExternalExtractors {
+    &EXTERNAL_EXTRACTORS
+}
+
+/// An external command extracting the text of a file, written to its stdout.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalExtractor {
+    program: String,
+    args: Vec<String>,
+}
+
+impl ExternalExtractor {
+    /// Parses a command template, like `dwg2txt --utf8 {input}`.
+    ///
+    /// The template is split on whitespace, with the first word being the program to run. Each `{input}` is replaced
+    /// by the path of the input file, and if there's none, the input file is streamed into stdin instead.
+    ///
+    pub fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut words = template.split_whitespace().map(str::to_string);
+        let program = words.next().ok_or(anyhow!("empty external extractor command"))?;
+        Ok(Self { program, args: words.collect() })
+    }
+
+    /// Returns the program the command runs.
+    ///
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    /// Extracts the text of the input file into the output file.
+    ///
+    pub async fn text_into_file(&self, input_path: impl AsRef<Path>, output_path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let output_file = tokio::fs::File::create(output_path.as_ref()).await?;
+        self.text_into_async_writer(input_path, output_file).await?;
+        Ok(())
+    }
+
+    /// Extracts the text of the input file into the asynchronous writer, as it's produced by the command.
+    ///
+    /// The writer is flushed and returned once all the text has been written. If writing fails, the command's output
+    /// is no longer read, so it's stopped rather than left to produce the rest of the text.
+    ///
+    pub async fn text_into_async_writer<W>(&self, input_path: impl AsRef<Path>, mut writer: W) -> anyhow::Result<W>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        self.run(input_path.as_ref(), &mut writer).await?;
+        writer.flush().await?;
+
+        Ok(writer)
+    }
+
+    /// Extracts the text of the input file into the writer.
+    ///
+    /// The writer is flushed and returned once all the text has been written.
+    ///
+    pub async fn text_into_writer<W>(&self, input_path: impl AsRef<Path>, mut writer: W) -> anyhow::Result<W>
+    where
+        W: Write + Send,
+    {
+        let mut output = vec![];
+        self.run(input_path.as_ref(), &mut output).await?;
+        writer.write_all(&output)?;
+        writer.flush()?;
+
+        Ok(writer)
+    }
+
+    async fn run<W>(&self, input_path: &Path, output: W) -> anyhow::Result<()>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        info!("Using {} to extract text", self.program);
+
+        let path = input_path.to_string_lossy();
+        let args: Vec<String> = self.args.iter().map(|arg| arg.replace(INPUT_PLACEHOLDER, &path)).collect();
+        let input = match self.args.iter().any(|arg| arg.contains(INPUT_PLACEHOLDER)) {
+            true => None,
+            false => Some(tokio::fs::File::open(input_path).await?),
+        };
+
+        stream_command(&self.program, args, input, Some(output), no_writer()).await
+            .map_err(|err| err.with_message(format!("External extractor {} failed", self.program)))?;
+        Ok(())
+    }
+}
+
+/// A registry of external extractors by the MIME type of files they extract the text of.
+///
+#[derive(Debug, Default)]
+pub struct ExternalExtractors {
+    extractors: RwLock<HashMap<String, ExternalExtractor>>,
+}
+
+impl ExternalExtractors {
+    /// Parses the registry from a list of `mimetype=template` entries separated by `;`, like
+    /// `application/acad=dwg2txt {input};image/vnd.dxf=dxf2txt`.
+    ///
+    /// See [`ExternalExtractor::parse`] for the format of the command templates.
+    ///
+    pub fn parse(entries: &str) -> anyhow::Result<Self> {
+        let extractors = Self::default();
+        for entry in entries.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (mimetype, template) = entry.split_once('=')
+                .ok_or(anyhow!("invalid external extractor entry {:?}", entry))?;
+            extractors.register(mimetype.trim(), template)?;
+        }
+        Ok(extractors)
+    }
+
+    fn from_config() -> Self {
+        let entries = config().get("EXTERNAL_EXTRACTORS").unwrap_or_default();
+        Self::parse(&entries).unwrap_or_else(|err| {
+            warn!("Ignoring EXTERNAL_EXTRACTORS: {}", err);
+            Self::default()
+        })
+    }
+
+    /// Registers the command template extracting the text of files of the MIME type, replacing any registered before.
+    ///
+    pub fn register(&self, mimetype: impl Into<String>, template: &str) -> anyhow::Result<()> {
+        let extractor = ExternalExtractor::parse(template)?;
+        self.extractors.write()
+            .map_err(|_| anyhow!("external extractors poisoned"))?
+            .insert(mimetype.into(), extractor);
+        Ok(())
+    }
+
+    /// Returns the extractor registered for the MIME type, if any.
+    ///
+    pub fn get(&self, mimetype: &str) -> Option<ExternalExtractor> {
+        self.extractors.read().ok()?.get(mimetype).cloned()
+    }
+
+    /// Returns the programs run by the registered extractors, without duplicates.
+    ///
+    pub fn programs(&self) -> Vec<String> {
+        let mut programs: Vec<String> = self.extractors.read()
+            .map(|extractors| extractors.values().map(|extractor| extractor.program.clone()).collect())
+            .unwrap_or_default();
+        programs.sort();
+        programs.dedup();
+        programs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let extractors = ExternalExtractors::parse("application/acad = dwg2txt --utf8 {input}; image/vnd.dxf=dxf2txt")?;
+
+        assert_eq!(extractors.get("application/acad"), Some(ExternalExtractor {
+            program: "dwg2txt".to_string(),
+            args: vec!["--utf8".to_string(), "{input}".to_string()],
+        }));
+        assert_eq!(extractors.get("image/vnd.dxf"), Some(ExternalExtractor {
+            program: "dxf2txt".to_string(),
+            args: vec![],
+        }));
+        assert_eq!(extractors.get("application/pdf"), None);
+        assert_eq!(extractors.programs(), vec!["dwg2txt", "dxf2txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(ExternalExtractors::parse("application/acad").is_err());
+        assert!(ExternalExtractors::parse("application/acad= ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_text_into_file() -> anyhow::Result<()> {
+        let mut input = NamedTempFile::new()?;
+        input.write_all(b"extracted text")?;
+        let output = NamedTempFile::new()?;
+
+        for template in ["cat {input}", "cat"] {
+            ExternalExtractor::parse(template)?.text_into_file(input.path(), output.path()).await?;
+            assert_eq!(std::fs::read_to_string(output.path())?, "extracted text");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_text_into_writer_failure() -> anyhow::Result<()> {
+        let input = NamedTempFile::new()?;
+
+        let result = ExternalExtractor::parse("false")?.text_into_writer(input.path(), vec![]).await;
+
+        assert!(result.is_err());
+        Ok(())
+    }
+}
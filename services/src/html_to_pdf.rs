@@ -4,10 +4,15 @@ use lazy_static::lazy_static;
 use tokio::io::{AsyncRead, AsyncWrite};
 use crate::{stream_command, trim_to_string};
 
-const PROGRAM: &str = "wkhtmltopdf";
+pub(crate) const PROGRAM: &str = "wkhtmltopdf";
 
-const DEFAULT_ARGS: [&str; 15] = [
-    "--quiet",
+/// The arguments of every call to the `HtmlToPdf` CLI tool.
+///
+/// Progress output is silenced, but warnings and errors are kept on stderr so failed renders can be diagnosed.
+///
+const DEFAULT_ARGS: [&str; 16] = [
+    "--log-level",
+    "warn",
     "--encoding",
     "utf-8",
     "--disable-external-links",
@@ -66,7 +71,7 @@ impl HtmlToPdf {
     /// # Returns
     ///
     /// * `Ok(HtmlToPdfOutput)` - If the `HtmlToPdf` CLI tool was run successfully.
-    /// * `Err(_)` - If there was an error running the `PdfToImage` CLI tool.
+    /// * `Err(_)` - If there was an error running the `HtmlToPdf` CLI tool, including its stderr if it ran.
     ///
     pub async fn run<R, W>(&self, mut input: R, mut output: W) -> anyhow::Result<HtmlToPdfOutput>
     where
@@ -120,4 +125,15 @@ mod tests {
         assert_eq!(output.error, "");
         assert_ne!(pdf.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_html_to_pdf_error() {
+        let input = br#"<html><body><img src="file:///nonexistent.png"></body></html>"#.to_vec();
+        let mut pdf = vec![];
+
+        let result = html_to_pdf().run(input.as_ref(), &mut pdf).await;
+
+        let error = result.err().expect("expected the render to fail").to_string();
+        assert!(error.contains("Blocked access to file"), "unexpected error: {}", error);
+    }
 }
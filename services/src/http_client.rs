@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use reqwest::{NoProxy, Proxy};
+
+use crate::config;
+
+/// The default timeout of HTTP requests in seconds.
+///
+pub const DEFAULT_HTTP_TIMEOUT_SECS: u64 = 300;
+
+/// Configuration of the HTTP client used for outbound requests.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpClientConfig {
+    /// The proxy to send `http` requests through, if any.
+    ///
+    pub http_proxy: Option<String>,
+
+    /// The proxy to send `https` requests through, if any.
+    ///
+    pub https_proxy: Option<String>,
+
+    /// Comma-separated hosts and domains that bypass the proxies, if any.
+    ///
+    pub no_proxy: Option<String>,
+
+    /// The timeout of each request, from connecting until the response body has been read.
+    ///
+    pub timeout: Duration,
+}
+
+impl Default for HttpClientConfig {
+    /// Reads the configuration from `HTTP_PROXY`, `HTTPS_PROXY`, `NO_PROXY`, and `HTTP_TIMEOUT_SECS`.
+    ///
+    fn default() -> Self {
+        let timeout_secs = config().get("HTTP_TIMEOUT_SECS")
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(DEFAULT_HTTP_TIMEOUT_SECS);
+
+        Self {
+            http_proxy: config().get("HTTP_PROXY"),
+            https_proxy: config().get("HTTPS_PROXY"),
+            no_proxy: config().get("NO_PROXY"),
+            timeout: Duration::from_secs(timeout_secs),
+        }
+    }
+}
+
+/// Builds an HTTP client using the given configuration.
+///
+/// When a proxy is configured, only the configured proxies are used. Otherwise, requests go through the proxies
+/// reqwest finds in the environment, like `http_proxy` and `ALL_PROXY`.
+///
+pub fn http_client(client_config: &HttpClientConfig) -> anyhow::Result<reqwest::Client> {
+    let no_proxy = client_config.no_proxy.as_deref().and_then(NoProxy::from_string);
+
+    let mut builder = reqwest::Client::builder().timeout(client_config.timeout);
+    if client_config.http_proxy.is_some() || client_config.https_proxy.is_some() {
+        builder = builder.no_proxy();
+    }
+    if let Some(http_proxy) = &client_config.http_proxy {
+        builder = builder.proxy(Proxy::http(http_proxy)?.no_proxy(no_proxy.clone()));
+    }
+    if let Some(https_proxy) = &client_config.https_proxy {
+        builder = builder.proxy(Proxy::https(https_proxy)?.no_proxy(no_proxy));
+    }
+
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+
+    use super::*;
+
+    fn proxied_config(proxy: &MockServer, no_proxy: Option<&str>) -> HttpClientConfig {
+        HttpClientConfig {
+            http_proxy: Some(proxy.base_url()),
+            https_proxy: None,
+            no_proxy: no_proxy.map(str::to_string),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_client_proxy() -> anyhow::Result<()> {
+        let proxy = MockServer::start_async().await;
+        let proxied = proxy.mock_async(|when, then| {
+            when.method(GET).path("/tika").header("host", "tika.invalid:9998");
+            then.status(200);
+        }).await;
+
+        let client = http_client(&proxied_config(&proxy, None))?;
+        let response = client.get("http://tika.invalid:9998/tika").send().await?;
+
+        assert_eq!(response.status(), 200);
+        proxied.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_client_no_proxy() -> anyhow::Result<()> {
+        let proxy = MockServer::start_async().await;
+        let server = MockServer::start_async().await;
+        let direct = server.mock_async(|when, then| {
+            when.method(GET).path("/tika");
+            then.status(200);
+        }).await;
+
+        let client = http_client(&proxied_config(&proxy, Some("127.0.0.1,localhost")))?;
+        client.get(server.url("/tika")).send().await?;
+
+        direct.assert_async().await;
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_client_invalid_proxy() {
+        let client_config = HttpClientConfig {
+            http_proxy: Some("http://[::1".to_string()),
+            ..HttpClientConfig::default()
+        };
+
+        assert!(http_client(&client_config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_client_timeout() -> anyhow::Result<()> {
+        let server = MockServer::start_async().await;
+        server.mock_async(|when, then| {
+            when.method(GET).path("/tika");
+            then.status(200).delay(Duration::from_secs(2));
+        }).await;
+
+        let client_config = HttpClientConfig {
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: None,
+            timeout: Duration::from_millis(100),
+        };
+        let result = http_client(&client_config)?.get(server.url("/tika")).send().await;
+
+        assert!(result.is_err_and(|err| err.is_timeout()));
+        Ok(())
+    }
+}
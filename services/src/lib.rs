@@ -6,27 +6,50 @@
 use std::ffi::OsStr;
 use std::fmt;
 use std::fmt::Formatter;
-use std::io::Cursor;
-use std::ops::{Deref, DerefMut};
+use std::io::{Cursor, ErrorKind};
+use std::pin::Pin;
 use std::process::{ExitStatus, Stdio};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
 use anyhow::{anyhow, Error};
-use bytesize::MB;
+use bytesize::{KB, MB};
 use tokio::join;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::process::ChildStdin;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
 
 mod archive_builder;
 mod config;
+mod dependencies;
+mod download;
+mod external_extractor;
+#[cfg(feature = "pdf")]
 mod html_to_pdf;
+mod http_client;
+mod logging;
+#[cfg(feature = "pdf")]
 mod pdf_to_image;
+mod tesseract;
 mod tika;
+#[cfg(feature = "transcription")]
+mod transcriber;
 mod xdg_mime;
 
 pub use archive_builder::*;
 pub use config::*;
+pub use dependencies::*;
+pub use download::*;
+pub use external_extractor::*;
+#[cfg(feature = "pdf")]
 pub use html_to_pdf::*;
+pub use http_client::*;
+pub use logging::*;
+#[cfg(feature = "pdf")]
 pub use pdf_to_image::*;
+pub use tesseract::*;
 pub use tika::*;
+#[cfg(feature = "transcription")]
+pub use transcriber::*;
 pub use xdg_mime::*;
 
 /// Defines a closure that logs an error if the [`anyhow::Result`] passed in is an error.
@@ -43,9 +66,22 @@ macro_rules! log_err {
     );
 }
 
+/// The default size of the buffer used to transfer data between a command's I/O streams.
+///
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = MB as usize;
+
+/// The default maximum number of bytes of a command's stderr captured in a [`CommandError`].
+///
+/// Can be configured with `COMMAND_STDERR_CAP`.
+///
+pub(crate) const DEFAULT_STDERR_CAP: usize = 64 * KB as usize;
+
+/// How often to check whether a command has read all of its input.
+///
+const INPUT_READ_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
 pub(crate) fn no_reader() -> Option<Cursor<Vec<u8>>> { None }
 
-#[allow(dead_code)]
 pub(crate) fn no_writer() -> Option<Vec<u8>> { None }
 
 /// Error type for when a command execution fails.
@@ -59,7 +95,9 @@ pub enum CommandError<E = Error> {
     /// When the command fails after exiting, such as if the child exits with a non-zero status
     /// or the I/O streams encountered a problem during execution.
     ///
-    PostExit(ExitStatus, E),
+    /// The stderr of the command is captured as well, truncated to the first `COMMAND_STDERR_CAP` bytes.
+    ///
+    PostExit(ExitStatus, E, String),
 }
 
 impl CommandError {
@@ -75,24 +113,39 @@ impl CommandError {
     ///
     /// Useful to pass in as a function handler to mapping functions (i.e. `map_err`).
     ///
-    pub fn post_exit(status: ExitStatus, err: impl Into<Error>) -> Self {
-        CommandError::PostExit(status, err.into())
+    pub fn post_exit(status: ExitStatus, err: impl Into<Error>, stderr: impl Into<String>) -> Self {
+        CommandError::PostExit(status, err.into(), stderr.into())
+    }
+
+    /// Converts the error into an [`Error`] described by `message`.
+    ///
+    /// A [`MissingDependency`] is passed through as it is, so callers can still tell it apart.
+    ///
+    pub(crate) fn with_message(self, message: impl fmt::Display) -> Error {
+        match self {
+            CommandError::PreExit(err) if err.is::<MissingDependency>() => err,
+            err => anyhow!("{}: {}", message, err),
+        }
     }
 }
 
 impl fmt::Display for CommandError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let (code, error) = match self {
-            CommandError::PreExit(err) => ("".to_string(), err),
-            CommandError::PostExit(status, err) => (
+        let (code, error, stderr) = match self {
+            CommandError::PreExit(err) => ("".to_string(), err, ""),
+            CommandError::PostExit(status, err, stderr) => (
                 status.code()
                     .map(|code| format!(" (code {})", code))
                     .unwrap_or("".to_string()),
-                err
+                err,
+                stderr.as_str(),
             )
         };
 
-        write!(f, "{}{}", error, code)
+        match stderr {
+            "" => write!(f, "{}{}", error, code),
+            stderr => write!(f, "{}{}: {}", error, code, stderr),
+        }
     }
 }
 
@@ -100,12 +153,82 @@ impl std::error::Error for CommandError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         let error = match self {
             CommandError::PreExit(err) => err,
-            CommandError::PostExit(_, err) => err,
+            CommandError::PostExit(_, err, _) => err,
         };
         Some(error.as_ref())
     }
 }
 
+/// Writer capturing the first `cap` bytes written to it, while forwarding all writes to an optional inner writer.
+///
+struct CapturingWriter<W> {
+    inner: Option<W>,
+    captured: Vec<u8>,
+    cap: usize,
+    truncated: bool,
+}
+
+impl<W> CapturingWriter<W> {
+    fn new(inner: Option<W>, cap: usize) -> Self {
+        Self { inner, captured: vec![], cap, truncated: false }
+    }
+
+    fn capture(&mut self, bytes: &[u8]) {
+        let remaining = self.cap - self.captured.len();
+        if bytes.len() > remaining {
+            self.truncated = true;
+        }
+        self.captured.extend_from_slice(&bytes[..bytes.len().min(remaining)]);
+    }
+
+    /// Returns the captured bytes as a trimmed string, marking whether it was truncated.
+    ///
+    fn into_string(self) -> String {
+        let captured = trim_to_string(&self.captured);
+        if self.truncated {
+            format!("{}... (truncated)", captured)
+        } else {
+            captured
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CapturingWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let written = match self.inner.as_mut() {
+            Some(inner) => match Pin::new(inner).poll_write(cx, buf) {
+                Poll::Ready(Ok(n)) => n,
+                other => return other,
+            },
+            None => buf.len(),
+        };
+        self.capture(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.inner.as_mut() {
+            Some(inner) => Pin::new(inner).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.inner.as_mut() {
+            Some(inner) => Pin::new(inner).poll_shutdown(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+/// The maximum number of bytes of a command's stderr to capture.
+///
+fn stderr_cap() -> usize {
+    config().get("COMMAND_STDERR_CAP")
+        .and_then(|cap| cap.parse().ok())
+        .unwrap_or(DEFAULT_STDERR_CAP)
+}
+
 fn trim_to_string(value: &[u8]) -> String {
     String::from_utf8_lossy(value)
         .replace('\u{0}', "")
@@ -113,18 +236,34 @@ fn trim_to_string(value: &[u8]) -> String {
         .to_string()
 }
 
-async fn transfer<R, W>(reader: Option<R>, writer: Option<W>) -> anyhow::Result<()>
+async fn transfer<R, W>(reader: Option<R>, writer: Option<W>, buffer_size: usize) -> anyhow::Result<()>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    if let (Some(mut reader), Some(mut writer)) = (reader, writer) {
-        let mut buf = Box::new([0; MB as usize]);
-        while reader.read(buf.deref_mut()).await? > 0 {
-            if writer.write(buf.deref()).await? == 0 {
-                return Err(anyhow!("writer closed unexpectedly"));
+    if let (Some(mut reader), Some(writer)) = (reader, writer) {
+        let mut writer = BufWriter::with_capacity(buffer_size, writer);
+        let mut buf = vec![0; buffer_size];
+        loop {
+            let bytes_read = reader.read(&mut buf).await?;
+            if bytes_read == 0 {
+                break;
+            }
+
+            let mut bytes_written = 0;
+            while bytes_written < bytes_read {
+                match writer.write(&buf[bytes_written..bytes_read]).await? {
+                    0 => return Err(anyhow!("writer closed unexpectedly")),
+                    n => bytes_written += n,
+                }
             }
         }
+
+        // Buffered data that the inner writer refuses surfaces as `WriteZero` when flushing
+        writer.flush().await.map_err(|err| match err.kind() {
+            ErrorKind::WriteZero => anyhow!("writer closed unexpectedly"),
+            _ => err.into(),
+        })?;
     }
     Ok(())
 }
@@ -150,7 +289,8 @@ where
 /// 2. The command finished, but an I/O error occurred while streaming, so the exit status and error will be populated
 /// 2. The command finished, but the exit status was non-zero, so the exit status and error will be populated
 ///
-/// For all errors that have an exit status, the `error` [`AsyncWrite`] passed to the function will have the `stderr` from the command.
+/// For all errors that have an exit status, the `error` [`AsyncWrite`] passed to the function will have the `stderr` from the command,
+/// and the [`CommandError`] will have it captured as well.
 ///
 pub(crate) async fn stream_command<R, W, E>(
     program: impl AsRef<str>,
@@ -164,34 +304,149 @@ pub(crate) async fn stream_command<R, W, E>(
         W: AsyncWrite + Unpin,
         E: AsyncWrite + Unpin,
 {
-    let mut proc = tokio::process::Command::new(program.as_ref())
-        .args(arguments)
+    stream_command_with_buffer_size(program, arguments, input, output, error, DEFAULT_BUFFER_SIZE).await
+}
+
+/// Run a command and return the exit status, using a transfer buffer of `buffer_size` bytes for each I/O stream.
+///
+/// See [`stream_command`] for more information.
+///
+pub(crate) async fn stream_command_with_buffer_size<R, W, E>(
+    program: impl AsRef<str>,
+    arguments: impl IntoIterator<Item=impl AsRef<OsStr>>,
+    input: Option<R>,
+    output: Option<W>,
+    error: Option<E>,
+    buffer_size: usize,
+) -> Result<ExitStatus, CommandError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+        E: AsyncWrite + Unpin,
+{
+    let mut command = tokio::process::Command::new(program.as_ref());
+    command.args(arguments);
+    run_command(command, program.as_ref(), input, output, error, buffer_size).await
+}
+
+/// Run a command with the environment variables added to its environment, and return the exit status.
+///
+/// Unlike arguments, the environment of a process can't be read by other users, so it's suited for secrets.
+///
+/// See [`stream_command`] for more information.
+///
+pub(crate) async fn stream_command_with_envs<R, W, E>(
+    program: impl AsRef<str>,
+    arguments: impl IntoIterator<Item=impl AsRef<OsStr>>,
+    envs: impl IntoIterator<Item=(impl AsRef<OsStr>, impl AsRef<OsStr>)>,
+    input: Option<R>,
+    output: Option<W>,
+    error: Option<E>,
+) -> Result<ExitStatus, CommandError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+        E: AsyncWrite + Unpin,
+{
+    let mut command = tokio::process::Command::new(program.as_ref());
+    command.args(arguments).envs(envs);
+    run_command(command, program.as_ref(), input, output, error, DEFAULT_BUFFER_SIZE).await
+}
+
+async fn run_command<R, W, E>(
+    mut command: tokio::process::Command,
+    program: &str,
+    input: Option<R>,
+    output: Option<W>,
+    error: Option<E>,
+    buffer_size: usize,
+) -> Result<ExitStatus, CommandError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+        E: AsyncWrite + Unpin,
+{
+    let mut proc = command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .map_err(CommandError::pre_exit)?;
-
-    let writing = transfer(input, proc.stdin.take());
-    let reading = transfer(proc.stdout.take(), output);
-    let erroring = transfer(proc.stderr.take(), error);
+        .map_err(|err| match err.kind() {
+            ErrorKind::NotFound => CommandError::pre_exit(MissingDependency::new(program)),
+            _ => CommandError::pre_exit(err),
+        })?;
+
+    // Always drain stderr to capture it, even if the caller doesn't want it written anywhere
+    let mut stderr = CapturingWriter::new(error, stderr_cap());
+    let mut stdin = proc.stdin.take();
+    let writing = async move {
+        if input.is_none() {
+            return Ok(());
+        }
+        transfer(input, stdin.as_mut(), buffer_size).await?;
+        match stdin {
+            Some(stdin) => wait_for_input_read(&stdin).await,
+            None => Ok(()),
+        }
+    };
+    let reading = transfer(proc.stdout.take(), output, buffer_size);
+    let erroring = transfer(proc.stderr.take(), Some(&mut stderr), buffer_size);
 
     // Don't `try_join!` to allow the error buffer to be written to completion
     let (writing_res, reading_res, erroring_res) = join!(writing, reading, erroring);
     let exit_status = proc.wait().await
         .map_err(CommandError::pre_exit)?;
+    let stderr = stderr.into_string();
 
     // Resolve the results after the process finishes to get the `ExitStatus`
-    writing_res.and(reading_res).and(erroring_res)
-        .map_err(|err| CommandError::post_exit(exit_status, err))?;
+    if let Err(err) = writing_res.and(reading_res).and(erroring_res) {
+        return Err(CommandError::post_exit(exit_status, err, stderr));
+    }
 
     if exit_status.success() {
         Ok(exit_status)
     } else {
-        Err(CommandError::post_exit(exit_status, anyhow!("command failed with non-zero exit status")))
+        Err(CommandError::post_exit(exit_status, anyhow!("command failed with non-zero exit status"), stderr))
+    }
+}
+
+/// Waits for the child to read all the input written to its stdin, before closing it.
+///
+/// Input still in the pipe when the child exits would otherwise be dropped silently, so it fails with a broken pipe,
+/// the same as input written after the child exits.
+///
+#[cfg(unix)]
+async fn wait_for_input_read(stdin: &ChildStdin) -> anyhow::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = stdin.as_raw_fd();
+    loop {
+        let mut unread: libc::c_int = 0;
+        // SAFETY: `fd` is the open write end of the pipe to the child's stdin, owned by `stdin`
+        if unsafe { libc::ioctl(fd, libc::FIONREAD, &mut unread) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if unread == 0 {
+            return Ok(());
+        }
+
+        let mut poll_fd = libc::pollfd { fd, events: libc::POLLOUT, revents: 0 };
+        // SAFETY: `poll_fd` is a single valid `pollfd`, and a zero timeout returns immediately
+        if unsafe { libc::poll(&mut poll_fd, 1, 0) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if poll_fd.revents & libc::POLLERR != 0 {
+            return Err(std::io::Error::from_raw_os_error(libc::EPIPE).into());
+        }
+        tokio::time::sleep(INPUT_READ_POLL_INTERVAL).await;
     }
 }
 
+#[cfg(not(unix))]
+async fn wait_for_input_read(_stdin: &ChildStdin) -> anyhow::Result<()> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod test_utils {
     use std::process::Stdio;
@@ -224,8 +479,31 @@ mod test_utils {
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use bytesize::{KB, MB};
+    use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+    use crate::{CapturingWriter, CommandError, MissingDependency, no_reader, no_writer, stream_command, stream_command_with_buffer_size, stream_command_with_envs, transfer, trim_to_string};
+
+    /// A writer that never accepts any bytes.
+    ///
+    struct ZeroWriter;
+
+    impl AsyncWrite for ZeroWriter {
+        fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<std::io::Result<usize>> {
+            Poll::Ready(Ok(0))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
 
-    use crate::{CommandError, stream_command, trim_to_string};
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
 
     fn buffers(data: &[u8]) -> (Cursor<Vec<u8>>, Vec<u8>, Vec<u8>) {
         let input = Cursor::new(data.to_vec());
@@ -270,7 +548,7 @@ mod tests {
         assert!(result.is_err());
         let command_err = result.unwrap_err();
         if let CommandError::PreExit(err) = command_err {
-            assert_eq!(err.to_string(), "No such file or directory (os error 2)");
+            assert_eq!(err.downcast_ref::<MissingDependency>(), Some(&MissingDependency::new("commandthatdoesntexist")));
         } else {
             panic!("expected pre-exit error");
         }
@@ -278,6 +556,56 @@ mod tests {
         assert!(error.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_stream_command_with_small_buffer() {
+        let data: Vec<u8> = (0..3 * MB).map(|i| (i % 251) as u8).collect();
+        let (mut input, mut output, mut error) = buffers(&data);
+
+        let result = stream_command_with_buffer_size(
+            "cat",
+            Vec::<&str>::new(),
+            Some(&mut input),
+            Some(&mut output),
+            Some(&mut error),
+            4 * KB as usize,
+        ).await;
+
+        assert!(result.is_ok());
+        assert_eq!(output.len(), data.len());
+        assert!(output == data, "output is not byte-exact");
+        assert!(error.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stream_command_with_envs() {
+        let (mut input, mut output, mut error) = buffers(b"");
+
+        let result = stream_command_with_envs(
+            "sh",
+            ["-c", "printf %s \"$SECRET\""],
+            [("SECRET", "hunter2")],
+            Some(&mut input),
+            Some(&mut output),
+            Some(&mut error),
+        ).await;
+
+        assert!(result.is_ok());
+        assert_eq!(output, b"hunter2");
+    }
+
+    #[tokio::test]
+    async fn test_transfer_detects_closed_writer() {
+        // Smaller than the input, writes bypass the buffer; larger, the data is buffered until flushed
+        for buffer_size in [4, KB as usize] {
+            let input = Cursor::new(b"hello world".to_vec());
+
+            let result = transfer(Some(input), Some(ZeroWriter), buffer_size).await;
+
+            assert!(result.is_err());
+            assert_eq!(result.unwrap_err().to_string(), "writer closed unexpectedly");
+        }
+    }
+
     #[tokio::test]
     async fn test_stream_command_fails_post_exit_io() {
         let (mut input, mut output, mut error) = buffers(b"hello world");
@@ -292,9 +620,10 @@ mod tests {
 
         assert!(result.is_err());
         let command_err = result.unwrap_err();
-        if let CommandError::PostExit(status, err) = command_err {
+        if let CommandError::PostExit(status, err, stderr) = command_err {
             assert_eq!(status.code(), Some(2));
             assert_eq!(err.to_string(), "Broken pipe (os error 32)");
+            assert_eq!(stderr, "ls: invalid option -- 'e'\nTry 'ls --help' for more information.");
         } else {
             panic!("expected post-exit error");
         }
@@ -310,7 +639,7 @@ mod tests {
 
         let result = stream_command(
             "bash",
-            vec!["-c", "exit 13"],
+            vec!["-c", "echo 'unlucky' >&2; exit 13"],
             Some(&mut input),
             Some(&mut output),
             Some(&mut error),
@@ -318,14 +647,42 @@ mod tests {
 
         assert!(result.is_err());
         let command_err = result.unwrap_err();
-        if let CommandError::PostExit(status, err) = command_err {
+        if let CommandError::PostExit(status, err, stderr) = command_err {
             assert_eq!(status.code(), Some(13));
             assert_eq!(err.to_string(), "command failed with non-zero exit status");
+            assert_eq!(stderr, "unlucky");
         } else {
             panic!("expected post-exit error");
         }
 
         assert!(output.is_empty());
-        assert!(error.is_empty());
+        assert_eq!(trim_to_string(&error), "unlucky");
+    }
+
+    #[tokio::test]
+    async fn test_stream_command_captures_stderr_without_error_writer() {
+        let result = stream_command(
+            "bash",
+            vec!["-c", "echo 'unlucky' >&2; exit 13"],
+            no_reader(),
+            no_writer(),
+            no_writer(),
+        ).await;
+
+        match result {
+            Err(CommandError::PostExit(_, _, stderr)) => assert_eq!(stderr, "unlucky"),
+            _ => panic!("expected post-exit error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capturing_writer_truncates() {
+        let mut forwarded = vec![];
+        let mut writer = CapturingWriter::new(Some(&mut forwarded), 5);
+
+        writer.write_all(b"hello world").await.unwrap();
+
+        assert_eq!(writer.into_string(), "hello... (truncated)");
+        assert_eq!(forwarded, b"hello world");
     }
 }
@@ -0,0 +1,30 @@
+use log::Level;
+
+/// Maps the `--quiet` and `--verbose` flags of a CLI to the level to log at.
+///
+/// Logs at [`Level::Info`] by default. Being quiet only logs errors, and each `--verbose` flag logs one level deeper,
+/// down to [`Level::Trace`]. Being quiet takes precedence over being verbose.
+///
+pub fn log_level(quiet: bool, verbose: u8) -> Level {
+    match (quiet, verbose) {
+        (true, _) => Level::Error,
+        (false, 0) => Level::Info,
+        (false, 1) => Level::Debug,
+        (false, _) => Level::Trace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_level() {
+        assert_eq!(log_level(false, 0), Level::Info);
+        assert_eq!(log_level(false, 1), Level::Debug);
+        assert_eq!(log_level(false, 2), Level::Trace);
+        assert_eq!(log_level(false, 5), Level::Trace);
+        assert_eq!(log_level(true, 0), Level::Error);
+        assert_eq!(log_level(true, 2), Level::Error);
+    }
+}
@@ -1,23 +1,31 @@
 use std::process::ExitStatus;
 
+use anyhow::anyhow;
 use lazy_static::lazy_static;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::{stream_command, trim_to_string};
 
-const PROGRAM: &str = "gs";
+pub(crate) const PROGRAM: &str = "gs";
 
-const DEFAULT_ARGS: [&str; 8] = [
+const DEFAULT_ARGS: [&str; 7] = [
     "-q",             // No program metadata.json to stdout
     "-dNOPAUSE",      // Disable prompt/pause after end of each page
     "-dBATCH",        // Exit after operation exits
     "-dSAFER",        // Activate sandboxing; prevent I/O access outside specified files
-    "-r300",          //
     "-sDEVICE=jpeg",  // Use JPEG image format
     "-sOutputFile=-", // Send metadata.json to stdout
     "-",              // Read input from stdin
 ];
 
+/// The resolution images are rendered at by default, in dots per inch.
+///
+pub const DEFAULT_DPI: u32 = 300;
+
+/// The range of supported resolutions, in dots per inch.
+///
+pub const DPI_RANGE: std::ops::RangeInclusive<u32> = 10..=1200;
+
 /// The type of the singleton instance of the `PdfToImage` service.
 ///
 pub type PdfToImageService = Box<PdfToImage>;
@@ -50,7 +58,7 @@ pub struct PdfToImageOutput {
 pub struct PdfToImage {}
 
 impl PdfToImage {
-    /// Run the `PdfToImage` service.
+    /// Run the `PdfToImage` service, rendering at [`DEFAULT_DPI`].
     ///
     /// # Arguments
     ///
@@ -62,7 +70,64 @@ impl PdfToImage {
     /// * `Ok(PdfToImageOutput)` - If the `PdfToImage` CLI tool was run successfully.
     /// * `Err(_)` - If there was an error running the `PdfToImage` CLI tool.
     ///
-    pub async fn run<R, W>(&self, mut input: R, mut output: W) -> anyhow::Result<PdfToImageOutput>
+    pub async fn run<R, W>(&self, input: R, output: W) -> anyhow::Result<PdfToImageOutput>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.run_with_dpi(input, output, DEFAULT_DPI).await
+    }
+
+    /// Run the `PdfToImage` service, rendering at the given resolution.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input stream to read the PDF from.
+    /// * `output` - The output stream to write the image to.
+    /// * `dpi` - The resolution to render at in dots per inch, within [`DPI_RANGE`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(PdfToImageOutput)` - If the `PdfToImage` CLI tool was run successfully.
+    /// * `Err(_)` - If the DPI is out of range or there was an error running the `PdfToImage` CLI tool.
+    ///
+    pub async fn run_with_dpi<R, W>(&self, input: R, output: W, dpi: u32) -> anyhow::Result<PdfToImageOutput>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.run_pages(input, output, dpi, None).await
+    }
+
+    /// Run the `PdfToImage` service on a single page, rendering at the given resolution.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input stream to read the PDF from.
+    /// * `output` - The output stream to write the image of the page to.
+    /// * `page` - The number of the page to render, starting at 1.
+    /// * `dpi` - The resolution to render at in dots per inch, within [`DPI_RANGE`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(PdfToImageOutput)` - If the `PdfToImage` CLI tool was run successfully.
+    /// * `Err(_)` - If the DPI is out of range or there was an error running the `PdfToImage` CLI tool.
+    ///
+    pub async fn run_page<R, W>(&self, input: R, output: W, page: u32, dpi: u32) -> anyhow::Result<PdfToImageOutput>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.run_pages(input, output, dpi, Some(page)).await
+    }
+
+    async fn run_pages<R, W>(
+        &self,
+        mut input: R,
+        mut output: W,
+        dpi: u32,
+        page: Option<u32>,
+    ) -> anyhow::Result<PdfToImageOutput>
     where
         R: AsyncRead + Unpin,
         W: AsyncWrite + Unpin,
@@ -70,7 +135,7 @@ impl PdfToImage {
         let mut error = vec![];
         let exit_status = stream_command(
             PROGRAM,
-            &DEFAULT_ARGS,
+            Self::args(dpi, page)?,
             Some(&mut input),
             Some(&mut output),
             Some(&mut error),
@@ -81,6 +146,19 @@ impl PdfToImage {
             error: trim_to_string(&error),
         })
     }
+
+    fn args(dpi: u32, page: Option<u32>) -> anyhow::Result<Vec<String>> {
+        if !DPI_RANGE.contains(&dpi) {
+            return Err(anyhow!("DPI {} is outside of the supported range {:?}", dpi, DPI_RANGE));
+        }
+
+        let mut args: Vec<String> = DEFAULT_ARGS.iter().map(|arg| arg.to_string()).collect();
+        args.insert(4, format!("-r{}", dpi));
+        if let Some(page) = page {
+            args.splice(5..5, [format!("-dFirstPage={}", page), format!("-dLastPage={}", page)]);
+        }
+        Ok(args)
+    }
 }
 
 #[cfg(test)]
@@ -102,6 +180,40 @@ mod tests {
         assert_eq!(pdf_to_image().type_id(), TypeId::of::<Box<PdfToImage>>());
     }
 
+    #[test]
+    fn test_args() {
+        let args = PdfToImage::args(72, None).unwrap();
+
+        assert_eq!(args, vec![
+            "-q", "-dNOPAUSE", "-dBATCH", "-dSAFER", "-r72", "-sDEVICE=jpeg", "-sOutputFile=-", "-",
+        ]);
+        assert!(PdfToImage::args(DEFAULT_DPI, None).unwrap().contains(&"-r300".to_string()));
+        assert_eq!(PdfToImage::args(72, Some(2)).unwrap(), vec![
+            "-q", "-dNOPAUSE", "-dBATCH", "-dSAFER", "-r72", "-dFirstPage=2", "-dLastPage=2", "-sDEVICE=jpeg",
+            "-sOutputFile=-", "-",
+        ]);
+    }
+
+    #[test]
+    fn test_args_out_of_range() {
+        assert!(PdfToImage::args(0, None).is_err());
+        assert!(PdfToImage::args(5000, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pdf_to_img_dpi() {
+        let input_path_str = "../resources/pdf/Espresso Machine Cleaning Guide.pdf";
+        let mut low = vec![];
+        let mut high = vec![];
+
+        let input = tokio::fs::File::open(input_path_str).await.unwrap();
+        pdf_to_image().run_with_dpi(input, &mut low, 72).await.unwrap();
+        let input = tokio::fs::File::open(input_path_str).await.unwrap();
+        pdf_to_image().run_with_dpi(input, &mut high, 300).await.unwrap();
+
+        assert!(high.len() > low.len());
+    }
+
     #[tokio::test]
     async fn test_pdf_to_img() {
         let input_path_str = "../resources/pdf/Espresso Machine Cleaning Guide.pdf";
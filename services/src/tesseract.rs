@@ -0,0 +1,116 @@
+use std::process::ExitStatus;
+
+use lazy_static::lazy_static;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{config, stream_command, trim_to_string};
+
+pub(crate) const PROGRAM: &str = "tesseract";
+
+const DEFAULT_ARGS: [&str; 2] = [
+    "stdin",  // Read the image from stdin
+    "stdout", // Write the recognized text to stdout
+];
+
+/// The languages recognized by default, in tesseract's `-l` format.
+///
+pub const DEFAULT_OCR_LANGUAGES: &str = "eng";
+
+/// The type of the singleton instance of the `Tesseract` service.
+///
+pub type TesseractService = Box<Tesseract>;
+
+lazy_static! {
+    static ref TESSERACT: TesseractService = Box::<Tesseract>::default();
+}
+
+/// Returns the singleton instance of the `Tesseract` service.
+///
+pub fn tesseract() -> &'static TesseractService {
+    &TESSERACT
+}
+
+/// The output of the `Tesseract` service.
+///
+pub struct TesseractOutput {
+    /// The exit status of the call to the `Tesseract` CLI tool.
+    ///
+    pub exit_status: ExitStatus,
+
+    /// The stderr of the call to the `Tesseract` CLI tool.
+    ///
+    pub error: String,
+}
+
+/// The `Tesseract` service, recognizing the text of images.
+///
+#[derive(Default)]
+pub struct Tesseract {}
+
+impl Tesseract {
+    /// Run the `Tesseract` service on a single image.
+    ///
+    /// The languages to recognize can be configured with `OCR_LANGUAGES`, like `eng+deu`, and default to
+    /// [`DEFAULT_OCR_LANGUAGES`].
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input stream to read the image from.
+    /// * `output` - The output stream to write the recognized text to.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(TesseractOutput)` - If the `Tesseract` CLI tool was run successfully.
+    /// * `Err(_)` - If there was an error running the `Tesseract` CLI tool.
+    ///
+    pub async fn run<R, W>(&self, mut input: R, mut output: W) -> anyhow::Result<TesseractOutput>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let languages = config().get_or("OCR_LANGUAGES", DEFAULT_OCR_LANGUAGES);
+        let mut error = vec![];
+        let exit_status = stream_command(
+            PROGRAM,
+            Self::args(&languages),
+            Some(&mut input),
+            Some(&mut output),
+            Some(&mut error),
+        ).await?;
+
+        Ok(TesseractOutput {
+            exit_status,
+            error: trim_to_string(&error),
+        })
+    }
+
+    fn args(languages: &str) -> Vec<String> {
+        let mut args: Vec<String> = DEFAULT_ARGS.iter().map(|arg| arg.to_string()).collect();
+        args.extend(["-l".to_string(), languages.to_string()]);
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::{Any, TypeId};
+
+    use crate::test_utils::assert_command_successful;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn check_tesseract_installed() {
+        assert_command_successful("which tesseract").await.unwrap();
+    }
+
+    #[test]
+    fn check_singleton() {
+        assert_eq!(tesseract().type_id(), TypeId::of::<Box<Tesseract>>());
+    }
+
+    #[test]
+    fn test_args() {
+        assert_eq!(Tesseract::args("eng+deu"), vec!["stdin", "stdout", "-l", "eng+deu"]);
+    }
+}
@@ -1,57 +1,107 @@
+use std::io::Write;
 use std::path::Path;
 
 use anyhow::anyhow;
 use futures::StreamExt;
 use lazy_static::lazy_static;
 use log::{debug, info};
-use reqwest::{Body, Response};
-use tokio::io::{AsyncRead, AsyncWriteExt};
+use reqwest::{Body, RequestBuilder, Response};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio_util::codec::{BytesCodec, FramedRead};
 
-use crate::config;
+use crate::{config, http_client, HttpClientConfig, no_writer, stream_command_with_envs, trim_to_string};
+
+pub(crate) const JAVA_PROGRAM: &str = "java";
+
+const DEFAULT_APP_JAR: &str = "tika-app.jar";
 
 /// The type of the singleton instance of the `Tika` service.
 ///
 pub type TikaService = Box<Tika>;
 
 lazy_static! {
-    static ref TIKA: TikaService = Box::<Tika>::default();
+    static ref TIKA: anyhow::Result<TikaService> = Tika::new(TikaBackend::default()).map(Box::new);
 }
 
 /// Returns the singleton instance of the `Tika` service.
 ///
-pub fn tika() -> &'static TikaService {
-    &TIKA
+/// Fails if the service couldn't be created, like when the configured proxies are invalid; see [`Tika::new`].
+///
+pub fn tika() -> anyhow::Result<&'static TikaService> {
+    TIKA.as_ref().map_err(|err| anyhow!("failed to create the Tika service: {:#}", err))
 }
 
-/// The `Tika` service.
+/// The backend the `Tika` service uses to run Tika.
 ///
-pub struct Tika {
-    http_client: reqwest::Client,
-    tika_url: String,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TikaBackend {
+    /// Runs the Tika app jar (`TIKA_APP_JAR`) in a new JVM for each request.
+    ///
+    Cli,
+
+    /// Sends each request to a long-running Tika server.
+    ///
+    Server {
+        /// The base URL of the Tika server, i.e. `http://localhost:9998`.
+        ///
+        base_url: String,
+    },
 }
 
-impl Default for Tika {
+impl Default for TikaBackend {
+    /// Selects the backend using `TIKA_BACKEND`, which is either `server` (the default) or `cli`.
+    ///
+    /// The server's URL is built from `TIKA_HOST` and `TIKA_PORT`.
+    ///
     fn default() -> Self {
-        let host = config().get_or("TIKA_HOST", "localhost");
-        let port = config().get_or("TIKA_PORT", "9998");
-        let tika_url = format!("http://{}:{}", host, port);
-
-        Self {
-            http_client: reqwest::Client::new(),
-            tika_url,
+        match config().get_or("TIKA_BACKEND", "server").as_str() {
+            "cli" => TikaBackend::Cli,
+            _ => {
+                let host = config().get_or("TIKA_HOST", "localhost");
+                let port = config().get_or("TIKA_PORT", "9998");
+                TikaBackend::Server { base_url: format!("http://{}:{}", host, port) }
+            }
         }
     }
 }
 
+/// The `Tika` service.
+///
+pub struct Tika {
+    http_client: reqwest::Client,
+    backend: TikaBackend,
+    pdf_password: Option<String>,
+}
+
 impl Tika {
-    /// Checks if the Tika server is running.
+    /// Create a new `Tika` service using the given backend.
+    ///
+    /// Requests to a Tika server go through the proxies and use the timeout configured by [`HttpClientConfig`].
+    /// Encrypted PDFs are opened with the password configured by [`pdf_password`].
+    ///
+    /// Fails if the HTTP client can't be built from the configuration, like when a proxy URL is invalid.
+    ///
+    pub fn new(backend: TikaBackend) -> anyhow::Result<Self> {
+        let http_client = http_client(&HttpClientConfig::default())?;
+        Ok(Self {
+            http_client,
+            backend,
+            pdf_password: pdf_password(),
+        })
+    }
+
+    /// Checks if the Tika backend is available.
+    ///
+    /// For the server backend this checks that the server is running, and for the CLI backend that the app jar exists.
     ///
     pub async fn is_connected(&self) -> bool {
-        self.http_client
-            .get(self.url("/tika"))
-            .send().await
-            .is_ok()
+        match &self.backend {
+            TikaBackend::Server { base_url } => self.http_client
+                .get(url(base_url, "/tika"))
+                .send().await
+                .is_ok(),
+            TikaBackend::Cli => Path::new(&app_jar()).is_file(),
+        }
     }
 
     /// Extracts the text from the input file.
@@ -67,11 +117,20 @@ impl Tika {
     pub async fn text(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
         info!("Using Tika to extract text");
 
-        let response = self.request_text(path).await?;
-        debug!("Tika responded with {}", response.status());
-
-        let bytes = response.bytes().await?;
-        let text = String::from_utf8(bytes.to_vec())?;
+        let bytes = match &self.backend {
+            TikaBackend::Server { base_url } => {
+                let response = self.request_text(base_url, path).await?;
+                debug!("Tika responded with {}", response.status());
+                response.bytes().await?.to_vec()
+            },
+            TikaBackend::Cli => {
+                let mut output = vec![];
+                self.run_app(path, "--text", &mut output).await?;
+                output
+            }
+        };
+
+        let text = String::from_utf8(bytes)?;
         Ok(text)
     }
 
@@ -87,28 +146,93 @@ impl Tika {
     /// The text extracted from the input file.
     ///
     pub async fn text_into_file(&self, input_path: impl AsRef<Path>, output_path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let output_file = tokio::fs::File::create(output_path.as_ref()).await?;
+        self.text_into_async_writer(input_path, output_file).await?;
+        Ok(())
+    }
+
+    /// Extracts the text from the input file and writes it to the asynchronous writer as it's received.
+    ///
+    /// If writing fails, the rest of the text isn't received, stopping the request or the Tika app early.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_path` - The path to the input file.
+    /// * `writer` - The writer to write the text to.
+    ///
+    /// # Returns
+    ///
+    /// The writer, after all text has been written to it.
+    ///
+    pub async fn text_into_async_writer<W>(&self, input_path: impl AsRef<Path>, mut writer: W) -> anyhow::Result<W>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
         info!("Using Tika to extract text");
 
-        let response = self.request_text(input_path).await?;
-        debug!("Tika responded with {}", response.status());
+        match &self.backend {
+            TikaBackend::Server { base_url } => {
+                let response = self.request_text(base_url, input_path).await?;
+                debug!("Tika responded with {}", response.status());
+
+                let mut stream = response.bytes_stream();
+                while let Some(bytes) = stream.next().await {
+                    writer.write_all(&bytes?).await?;
+                }
+            },
+            TikaBackend::Cli => self.run_app(input_path, "--text", &mut writer).await?,
+        }
+        writer.flush().await?;
 
-        let mut stream = response.bytes_stream();
-        let mut output_file = tokio::fs::File::create(output_path.as_ref()).await?;
-        while let Some(bytes) = stream.next().await {
-            output_file.write_all(&bytes?).await?;
+        Ok(writer)
+    }
+
+    /// Extracts the text from the input file and writes it to the writer as it's received.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_path` - The path to the input file.
+    /// * `writer` - The writer to write the text to.
+    ///
+    /// # Returns
+    ///
+    /// The writer, after all text has been written to it.
+    ///
+    pub async fn text_into_writer<W>(&self, input_path: impl AsRef<Path>, mut writer: W) -> anyhow::Result<W>
+    where
+        W: Write + Send,
+    {
+        info!("Using Tika to extract text");
+
+        match &self.backend {
+            TikaBackend::Server { base_url } => {
+                let response = self.request_text(base_url, input_path).await?;
+                debug!("Tika responded with {}", response.status());
+
+                let mut stream = response.bytes_stream();
+                while let Some(bytes) = stream.next().await {
+                    writer.write_all(&bytes?)?;
+                }
+            },
+            TikaBackend::Cli => {
+                let mut output = vec![];
+                self.run_app(input_path, "--text", &mut output).await?;
+                writer.write_all(&output)?;
+            }
         }
+        writer.flush()?;
 
-        Ok(())
+        Ok(writer)
     }
 
-    async fn request_text(&self, input_path: impl AsRef<Path>) -> anyhow::Result<Response> {
+    async fn request_text(&self, base_url: &str, input_path: impl AsRef<Path>) -> anyhow::Result<Response> {
         let input = tokio::fs::File::open(input_path).await?;
-        Ok(self.http_client
-            .put(self.url("/tika"))
+        let request = self.http_client
+            .put(url(base_url, "/tika"))
             .header("Accept", "text/plain")
             .header("X-Tika-Skip-Embedded", "true")
-            .body(Self::body_from_input(input))
-            .send().await?)
+            .body(Self::body_from_input(input));
+        Ok(self.with_password(request).send().await?)
     }
 
     /// Extracts the metadata from the input file.
@@ -124,16 +248,25 @@ impl Tika {
     pub async fn metadata(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
         info!("Using Tika to extract metadata");
 
-        let input = tokio::fs::File::open(path).await?;
-        let response = self.http_client
-            .put(self.url("/meta"))
-            .header("Accept", "application/json")
-            .header("X-Tika-Skip-Embedded", "true")
-            .body(Self::body_from_input(input))
-            .send().await?;
-        debug!("Tika responded with {}", response.status());
-
-        Ok(response.text().await?)
+        match &self.backend {
+            TikaBackend::Server { base_url } => {
+                let input = tokio::fs::File::open(path).await?;
+                let request = self.http_client
+                    .put(url(base_url, "/meta"))
+                    .header("Accept", "application/json")
+                    .header("X-Tika-Skip-Embedded", "true")
+                    .body(Self::body_from_input(input));
+                let response = self.with_password(request).send().await?;
+                debug!("Tika responded with {}", response.status());
+
+                Ok(response.text().await?)
+            },
+            TikaBackend::Cli => {
+                let mut output = vec![];
+                self.run_app(path, "--json", &mut output).await?;
+                Ok(String::from_utf8(output)?)
+            }
+        }
     }
 
     /// Detects the mimetype of the input file.
@@ -149,24 +282,62 @@ impl Tika {
     pub async fn detect(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
         info!("Using Tika to detect mimetype");
 
-        let input = tokio::fs::File::open(path).await?;
-        let response = self.http_client
-            .put(self.url("/meta/Content-Type"))
-            .header("Accept", "application/json")
-            .header("X-Tika-Skip-Embedded", "true")
-            .body(Self::body_from_input(input))
-            .send().await?;
-        debug!("Tika responded with {}", response.status());
-
-        let mimetype = self.parse_detect_response(response).await;
-        debug!("Response body result: {:?}", mimetype);
+        let mimetype = match &self.backend {
+            TikaBackend::Server { base_url } => {
+                let input = tokio::fs::File::open(path).await?;
+                let response = self.http_client
+                    .put(url(base_url, "/meta/Content-Type"))
+                    .header("Accept", "application/json")
+                    .header("X-Tika-Skip-Embedded", "true")
+                    .body(Self::body_from_input(input))
+                    .send().await?;
+                debug!("Tika responded with {}", response.status());
+
+                self.parse_detect_response(response).await
+            },
+            TikaBackend::Cli => {
+                let mut output = vec![];
+                self.run_app(path, "--detect", &mut output).await?;
+                Ok(trim_to_string(&output))
+            }
+        };
+        debug!("Detect result: {:?}", mimetype);
 
         mimetype
     }
 
-    #[inline]
-    fn url(&self, endpoint: impl AsRef<str>) -> String {
-        format!("{}{}", self.tika_url, endpoint.as_ref())
+    /// Adds the PDF password to the request to a Tika server, if one is configured.
+    ///
+    fn with_password(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.pdf_password {
+            Some(password) => request.header("Password", password),
+            None => request,
+        }
+    }
+
+    /// Runs the Tika app jar with `flag`, streaming the input file into stdin and stdout into `output`.
+    ///
+    /// The PDF password is passed in the `TIKA_PASSWORD` environment variable rather than as an argument, where other
+    /// users could read it in the process list.
+    ///
+    async fn run_app<W>(&self, path: impl AsRef<Path>, flag: &str, output: W) -> anyhow::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let input = tokio::fs::File::open(path).await?;
+        let jar = app_jar();
+        let args = ["-jar".to_string(), jar, flag.to_string()];
+        let envs = self.pdf_password.iter().map(|password| ("TIKA_PASSWORD", password));
+        stream_command_with_envs(
+            JAVA_PROGRAM,
+            args,
+            envs,
+            Some(input),
+            Some(output),
+            no_writer(),
+        ).await
+            .map_err(|err| err.with_message("Tika app failed"))?;
+        Ok(())
     }
 
     #[inline]
@@ -188,15 +359,98 @@ impl Tika {
     }
 }
 
+#[inline]
+fn url(base_url: &str, endpoint: impl AsRef<str>) -> String {
+    format!("{}{}", base_url, endpoint.as_ref())
+}
+
+#[inline]
+fn app_jar() -> String {
+    config().get_or("TIKA_APP_JAR", DEFAULT_APP_JAR)
+}
+
+/// The password to open encrypted PDFs with, configured by `PDF_PASSWORD`, if any.
+///
+pub fn pdf_password() -> Option<String> {
+    config().get("PDF_PASSWORD")
+}
+
 #[cfg(test)]
 mod tests {
     use std::any::{Any, TypeId};
 
+    use httpmock::Method::PUT;
+    use httpmock::MockServer;
+    use tempfile::NamedTempFile;
+
     use super::*;
 
     #[test]
-    fn check_singleton() {
-        assert_eq!(tika().type_id(), TypeId::of::<Box<Tika>>());
+    fn check_singleton() -> anyhow::Result<()> {
+        assert_eq!(tika()?.type_id(), TypeId::of::<Box<Tika>>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_backend_endpoints() -> anyhow::Result<()> {
+        let server = MockServer::start_async().await;
+        let text_mock = server.mock_async(|when, then| {
+            when.method(PUT).path("/tika").header("Accept", "text/plain").body("hello world");
+            then.status(200).body("hello world");
+        }).await;
+        let meta_mock = server.mock_async(|when, then| {
+            when.method(PUT).path("/meta").header("Accept", "application/json").body("hello world");
+            then.status(200).body(r#"{"Content-Type":"text/plain"}"#);
+        }).await;
+
+        let mut input = NamedTempFile::new()?;
+        input.write_all(b"hello world")?;
+        let tika = Tika::new(TikaBackend::Server { base_url: server.base_url() })?;
+
+        assert_eq!(tika.text(input.path()).await?, "hello world");
+        assert_eq!(tika.metadata(input.path()).await?, r#"{"Content-Type":"text/plain"}"#);
+        text_mock.assert_async().await;
+        meta_mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_text_into_writer() -> anyhow::Result<()> {
+        let server = MockServer::start_async().await;
+        let text_mock = server.mock_async(|when, then| {
+            when.method(PUT).path("/tika");
+            then.status(200).body("hello world");
+        }).await;
+
+        let mut input = NamedTempFile::new()?;
+        input.write_all(b"hello world")?;
+        let tika = Tika::new(TikaBackend::Server { base_url: server.base_url() })?;
+
+        let output = tika.text_into_writer(input.path(), vec![]).await?;
+
+        assert_eq!(output, b"hello world");
+        text_mock.assert_async().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_backend_pdf_password() -> anyhow::Result<()> {
+        let server = MockServer::start_async().await;
+        let text_mock = server.mock_async(|when, then| {
+            when.method(PUT).path("/tika").header("Password", "secret");
+            then.status(200).body("hello world");
+        }).await;
+
+        let mut input = NamedTempFile::new()?;
+        input.write_all(b"hello world")?;
+        let tika = Tika {
+            pdf_password: Some("secret".to_string()),
+            ..Tika::new(TikaBackend::Server { base_url: server.base_url() })?
+        };
+
+        assert_eq!(tika.text(input.path()).await?, "hello world");
+        text_mock.assert_async().await;
+        Ok(())
     }
 
     #[test]
@@ -0,0 +1,97 @@
+use std::path::Path;
+use std::sync::RwLock;
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::{config, ExternalExtractor};
+
+lazy_static! {
+    static ref TRANSCRIBER: Transcriber = Transcriber::from_config();
+}
+
+/// Returns the singleton instance of the `Transcriber` service.
+///
+/// It's configured with the speech-to-text command template of `STT_COMMAND`, if it's set.
+///
+pub fn transcriber() -> &'static Transcriber {
+    &TRANSCRIBER
+}
+
+/// The `Transcriber` service, transcribing the speech of audio and video files with an external speech-to-text command.
+///
+/// Nothing is transcribed until a command is configured.
+///
+#[derive(Debug, Default)]
+pub struct Transcriber {
+    command: RwLock<Option<ExternalExtractor>>,
+}
+
+impl Transcriber {
+    fn from_config() -> Self {
+        let transcriber = Self::default();
+        if let Some(template) = config().get("STT_COMMAND") {
+            if let Err(err) = transcriber.configure(&template) {
+                warn!("Ignoring STT_COMMAND: {}", err);
+            }
+        }
+        transcriber
+    }
+
+    /// Configures the speech-to-text command, replacing any configured before.
+    ///
+    /// The command is a template like `whisper-transcribe --model base {input}`, writing the transcript to its stdout;
+    /// see [`ExternalExtractor::parse`] for its format.
+    ///
+    pub fn configure(&self, template: &str) -> anyhow::Result<()> {
+        let command = ExternalExtractor::parse(template)?;
+        *self.command.write().map_err(|_| anyhow!("transcriber poisoned"))? = Some(command);
+        Ok(())
+    }
+
+    /// Whether a speech-to-text command is configured.
+    ///
+    pub fn is_configured(&self) -> bool {
+        self.command.read().is_ok_and(|command| command.is_some())
+    }
+
+    /// Returns the program run by the configured command, if any.
+    ///
+    pub fn program(&self) -> Option<String> {
+        self.command.read().ok()?.as_ref().map(|command| command.program().to_string())
+    }
+
+    /// Transcribes the speech of the input file into the output file.
+    ///
+    pub async fn transcribe_into_file(&self, input_path: impl AsRef<Path>, output_path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let command = self.command.read()
+            .map_err(|_| anyhow!("transcriber poisoned"))?
+            .clone()
+            .ok_or(anyhow!("no speech-to-text command is configured"))?;
+        command.text_into_file(input_path, output_path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transcribe_into_file() -> anyhow::Result<()> {
+        let transcriber = Transcriber::default();
+        let input = NamedTempFile::new()?;
+        let output = NamedTempFile::new()?;
+        assert!(transcriber.transcribe_into_file(input.path(), output.path()).await.is_err());
+
+        transcriber.configure("echo Hello from the voicemail")?;
+        transcriber.transcribe_into_file(input.path(), output.path()).await?;
+
+        assert!(transcriber.is_configured());
+        assert_eq!(transcriber.program().as_deref(), Some("echo"));
+        assert_eq!(std::fs::read_to_string(output.path())?, "Hello from the voicemail\n");
+        Ok(())
+    }
+}
@@ -3,7 +3,9 @@ use std::path::Path;
 use anyhow::anyhow;
 use lazy_static::lazy_static;
 
-use crate::{CommandError, no_reader, stream_command, trim_to_string};
+use crate::{CommandError, no_reader, no_writer, stream_command, trim_to_string};
+
+pub(crate) const PROGRAM: &str = "xdg-mime";
 
 /// The type of the singleton instance of the `XdgMime` service.
 ///
@@ -38,23 +40,22 @@ impl XdgMime {
         let path_str = path.as_ref().to_str().ok_or(anyhow!("failed to convert path to string"))?;
 
         let mut output = vec![];
-        let mut error = vec![];
         let result = stream_command(
-            "xdg-mime",
+            PROGRAM,
             &["query", "filetype", path_str],
             no_reader(),
             Some(&mut output),
-            Some(&mut error),
+            no_writer(),
         ).await;
 
         match result {
             Ok(_) => Ok(trim_to_string(&output)),
             Err(CommandError::PreExit(err)) => Err(err),
-            Err(CommandError::PostExit(status, err)) => {
+            Err(CommandError::PostExit(status, err, stderr)) => {
                 let code = status.code()
                     .map(|c| c.to_string())
                     .unwrap_or("?".to_string());
-                Err(anyhow!("'xdg-mime' failed to detect mimetype: {} (code {}): {}", err, code, trim_to_string(&error)))
+                Err(anyhow!("'xdg-mime' failed to detect mimetype: {} (code {}): {}", err, code, stderr))
             }
         }
     }
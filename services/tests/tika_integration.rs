@@ -2,7 +2,7 @@ use services::tika;
 
 #[tokio::test]
 async fn test_tika_server_connection() {
-    assert!(tika().is_connected().await);
+    assert!(tika().unwrap().is_connected().await);
 }
 
 #[tokio::test]
@@ -36,7 +36,7 @@ Backflush w/ cleaner
 ";
     let path = "../resources/pdf/Espresso Machine Cleaning Guide.pdf";
 
-    let text = tika().text(path).await?;
+    let text = tika()?.text(path).await?;
 
     assert_eq!(text, expected_text);
     Ok(())
@@ -46,7 +46,7 @@ Backflush w/ cleaner
 async fn test_tika_text_with_ocr() -> anyhow::Result<()> {
     let path = "../resources/jpg/jQuery-text.jpg";
 
-    let text = tika().text(path).await?;
+    let text = tika()?.text(path).await?;
 
     assert_eq!(text, "jQuery $%&U6~\n\n\n");
     Ok(())
@@ -64,7 +64,7 @@ async fn test_tika_metadata() {
 }";
     let path = "../resources/mbox/ubuntu-no-small.mbox";
 
-    let metadata = tika().metadata(path).await.unwrap();
+    let metadata = tika().unwrap().metadata(path).await.unwrap();
 
     assert_eq!(metadata, expected_metadata);
 }
@@ -73,7 +73,7 @@ async fn test_tika_metadata() {
 async fn test_tika_detect() {
     let path = "../resources/zip/testzip.zip";
 
-    let mimetype = tika().detect(path).await.unwrap();
+    let mimetype = tika().unwrap().detect(path).await.unwrap();
 
     assert_eq!(mimetype, "application/zip");
 }
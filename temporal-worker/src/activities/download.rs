@@ -1,37 +1,292 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use anyhow::{anyhow, Error};
+use aws_sdk_s3::types::{ChecksumMode, ServerSideEncryption};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use bytesize::MB;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use temporal_sdk::ActContext;
-use crate::s3_client;
+use sha2::{Digest, Sha256};
+use temporal_sdk::{ActContext, NonRetryableActivityError};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use processing::processing::ProcessingError;
+use services::config;
 
+use crate::s3_client;
 use crate::util::parse_s3_uri;
 
 /// Input to the `download` activity.
-/// 
+///
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadInput {
     /// The S3 URI to download the file from.
-    /// 
+    ///
+    /// A specific version of the object is downloaded if the URI has a `versionId` query parameter.
+    ///
     pub s3_uri: String,
-    
+
     /// The local path to where the file should be downloaded to.
-    /// 
+    ///
     pub path: PathBuf,
+
+    /// The maximum number of bytes to download before aborting, if any.
+    ///
+    pub max_bytes: Option<u64>,
 }
 
 /// Activity for downloading a file from S3.
-/// 
+///
+/// If `S3_VERIFY_CHECKSUMS` is `true`, the downloaded bytes are verified against the object's stored SHA-256
+/// checksum, or its ETag if it's an MD5 digest, and the download fails if they don't match. ETags are only digests of
+/// objects that are unencrypted or encrypted with S3 managed keys.
+///
 pub async fn download(_ctx: ActContext, input: DownloadInput) -> anyhow::Result<()> {
-    let (bucket, key) = parse_s3_uri(input.s3_uri)?;
-    let object = s3_client().await
+    let verify_checksums = config().get("S3_VERIFY_CHECKSUMS")
+        .and_then(|verify| verify.parse().ok())
+        .unwrap_or(false);
+
+    let (bucket, key, version_id) = parse_s3_uri(&input.s3_uri)?;
+    let mut request = s3_client().await
         .get_object()
         .bucket(bucket)
         .key(key)
-        .send()
-        .await?;
+        .set_version_id(version_id);
+    if verify_checksums {
+        request = request.checksum_mode(ChecksumMode::Enabled);
+    }
+    let object = request.send().await?;
+
+    let expected = match verify_checksums {
+        true => {
+            let e_tag = object.e_tag()
+                .filter(|_| e_tag_is_digest(object.server_side_encryption(), object.sse_customer_algorithm()));
+            ObjectChecksum::from_object(e_tag, object.checksum_sha256())
+        },
+        false => None,
+    };
+    if verify_checksums && expected.is_none() {
+        warn!("Object {} has no checksum to verify the download against", input.s3_uri);
+    }
 
-    let mut file = tokio::fs::File::create(&input.path).await?;
     let mut body = object.body.into_async_read();
-    tokio::io::copy(&mut body, &mut file).await?;
+    copy_to_file(&mut body, &input.path, input.max_bytes, expected.as_ref()).await
+}
+
+/// Copies the reader into a new file at the path with [`copy_checked`], removing the file if the copy fails.
+///
+async fn copy_to_file<R>(
+    reader: &mut R,
+    path: &Path,
+    limit: Option<u64>,
+    expected: Option<&ObjectChecksum>,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut file = tokio::fs::File::create(path).await?;
+    if let Err(err) = copy_checked(reader, &mut file, limit, expected).await {
+        tokio::fs::remove_file(path).await?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// A checksum of an S3 object to verify its downloaded bytes against.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ObjectChecksum {
+    /// The hex-encoded MD5 digest of the object, from its ETag.
+    ///
+    Md5(String),
+
+    /// The base64-encoded SHA-256 digest stored with the object.
+    ///
+    Sha256(String),
+}
+
+impl ObjectChecksum {
+    /// Returns the checksum of an object from its ETag and stored SHA-256 checksum, preferring the latter.
+    ///
+    /// Objects uploaded in parts have composite checksums and ETags, suffixed by the number of parts, that aren't
+    /// digests of the whole object, so they can't be verified.
+    ///
+    fn from_object(e_tag: Option<&str>, checksum_sha256: Option<&str>) -> Option<Self> {
+        if let Some(checksum_sha256) = checksum_sha256.filter(|checksum| !checksum.contains('-')) {
+            return Some(Self::Sha256(checksum_sha256.to_string()));
+        }
+
+        let e_tag = e_tag?.trim_matches('"');
+        let is_md5 = e_tag.len() == 32 && e_tag.chars().all(|c| c.is_ascii_hexdigit());
+        is_md5.then(|| Self::Md5(e_tag.to_lowercase()))
+    }
+}
+
+/// Whether the ETag of an object encrypted as given is the MD5 digest of its content.
+///
+/// The ETags of objects encrypted with KMS or customer-provided keys aren't, even when they look like one.
+///
+fn e_tag_is_digest(server_side_encryption: Option<&ServerSideEncryption>, sse_customer_algorithm: Option<&str>) -> bool {
+    let s3_managed = matches!(server_side_encryption, None | Some(ServerSideEncryption::Aes256));
+    s3_managed && sse_customer_algorithm.is_none()
+}
+
+/// Calculates the checksum of bytes as they're copied, in the same form as the expected [`ObjectChecksum`].
+///
+enum ChecksumHasher {
+    Md5(md5::Context),
+    Sha256(Sha256),
+}
+
+impl ChecksumHasher {
+    fn new(expected: &ObjectChecksum) -> Self {
+        match expected {
+            ObjectChecksum::Md5(_) => Self::Md5(md5::Context::new()),
+            ObjectChecksum::Sha256(_) => Self::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Md5(context) => context.consume(bytes),
+            Self::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    fn finish(self) -> ObjectChecksum {
+        match self {
+            Self::Md5(context) => ObjectChecksum::Md5(format!("{:x}", context.compute())),
+            Self::Sha256(hasher) => ObjectChecksum::Sha256(BASE64.encode(hasher.finalize())),
+        }
+    }
+}
+
+/// Copies the reader into the writer, verifying the copied bytes against the expected checksum, if any.
+///
+/// If there's a `limit`, the copy is aborted as soon as more than `limit` bytes have arrived.
+///
+async fn copy_checked<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    limit: Option<u64>,
+    expected: Option<&ObjectChecksum>,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut hasher = expected.map(ChecksumHasher::new);
+    let mut buf = Box::new([0; MB as usize]);
+    let mut size = 0_u64;
+    loop {
+        let bytes_read = reader.read(buf.as_mut()).await?;
+        if bytes_read == 0 {
+            break;
+        }
+
+        size += bytes_read as u64;
+        if let Some(limit) = limit.filter(|limit| size > *limit) {
+            let err = ProcessingError::InputTooLarge { size, limit };
+            return Err(Error::from(NonRetryableActivityError(anyhow!(format!("{}", err)))));
+        }
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buf[..bytes_read]);
+        }
+        writer.write_all(&buf[..bytes_read]).await?;
+    }
+    writer.flush().await?;
+
+    if let (Some(hasher), Some(expected)) = (hasher, expected) {
+        let actual = hasher.finish();
+        if actual != *expected {
+            return Err(anyhow!("Checksum mismatch: expected {:?}, downloaded {:?}", expected, actual));
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &[u8] = b"rusty processing";
+
+    #[tokio::test]
+    async fn test_copy_checked_e_tag() -> anyhow::Result<()> {
+        let e_tag = format!("\"{:x}\"", md5::compute(CONTENT));
+        let expected = ObjectChecksum::from_object(Some(&e_tag), None);
+
+        let mut downloaded = vec![];
+        copy_checked(&mut &CONTENT[..], &mut downloaded, None, expected.as_ref()).await?;
+
+        assert_eq!(downloaded, CONTENT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_checked_e_tag_mismatch() {
+        let e_tag = format!("\"{:x}\"", md5::compute(b"something else"));
+        let expected = ObjectChecksum::from_object(Some(&e_tag), None);
+
+        let result = copy_checked(&mut &CONTENT[..], &mut vec![], None, expected.as_ref()).await;
+
+        assert!(result.is_err_and(|err| err.to_string().starts_with("Checksum mismatch")));
+    }
+
+    #[tokio::test]
+    async fn test_copy_checked_sha256_mismatch() {
+        let checksum = BASE64.encode(Sha256::digest(b"something else"));
+        let expected = ObjectChecksum::from_object(None, Some(&checksum));
+
+        let result = copy_checked(&mut &CONTENT[..], &mut vec![], None, expected.as_ref()).await;
+
+        assert!(result.is_err_and(|err| err.to_string().starts_with("Checksum mismatch")));
+    }
+
+    #[tokio::test]
+    async fn test_copy_to_file_over_limit() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("download");
+
+        let result = copy_to_file(&mut &CONTENT[..], &path, Some(CONTENT.len() as u64 - 1), None).await;
+
+        let err = result.expect_err("expected the download to exceed the limit");
+        let Some(NonRetryableActivityError(err)) = err.downcast_ref::<NonRetryableActivityError>() else {
+            panic!("expected a non-retryable error, got {:?}", err);
+        };
+        assert!(err.to_string().starts_with("Input too large"), "{}", err);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_to_file_at_limit() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("download");
+
+        copy_to_file(&mut &CONTENT[..], &path, Some(CONTENT.len() as u64), None).await?;
+
+        assert_eq!(std::fs::read(&path)?, CONTENT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_e_tag_is_digest() {
+        assert!(e_tag_is_digest(None, None));
+        assert!(e_tag_is_digest(Some(&ServerSideEncryption::Aes256), None));
+        assert!(!e_tag_is_digest(Some(&ServerSideEncryption::AwsKms), None));
+        assert!(!e_tag_is_digest(Some(&ServerSideEncryption::AwsKmsDsse), None));
+        assert!(!e_tag_is_digest(None, Some("AES256")));
+    }
+
+    #[test]
+    fn test_object_checksum_from_object() {
+        let md5 = "0cc175b9c0f1b6a831c399e269772661";
+
+        assert_eq!(ObjectChecksum::from_object(Some(&format!("\"{}\"", md5)), None), Some(ObjectChecksum::Md5(md5.to_string())));
+        assert_eq!(ObjectChecksum::from_object(Some("\"0cc175b9c0f1b6a831c399e269772661-3\""), None), None);
+        assert_eq!(ObjectChecksum::from_object(Some(md5), Some("c2hh")), Some(ObjectChecksum::Sha256("c2hh".to_string())));
+        assert_eq!(ObjectChecksum::from_object(None, Some("c2hh-3")), None);
+    }
+}
@@ -35,7 +35,11 @@ pub struct ProcessRustyFileInput {
 
     /// The name of the Redis stream to send output to.
     ///
-    output_stream_name: String
+    output_stream_name: String,
+
+    /// The maximum size of the file to process in bytes, if any.
+    ///
+    max_input_bytes: Option<u64>,
 }
 
 /// Output of the `process_rusty_file` activity.
@@ -56,7 +60,9 @@ pub async fn process_rusty_file(_ctx: ActContext, input: ProcessRustyFileInput)
         input.mimetype,
         input.types,
         output_sink,
-    ).build();
+    )
+        .max_input_bytes(input.max_input_bytes)
+        .build();
 
     let processing = tokio::spawn(processor().process(ctx, input.path));
     let output_handling = tokio::spawn(handle_outputs(
@@ -69,10 +75,15 @@ pub async fn process_rusty_file(_ctx: ActContext, input: ProcessRustyFileInput)
         .tap(log_err!("Failed to process file"))
         .map_err(|err| {
             match err {
-                ProcessingError::UnsupportedMimeType(_) => {
+                ProcessingError::UnsupportedMimeType(_) | ProcessingError::InputTooLarge { .. } => {
                     error!("Retryable error: {}", err);
                     Error::from(NonRetryableActivityError(anyhow!(format!("{}", err))))
                 },
+                // Retried, as another worker may have the dependency installed
+                ProcessingError::MissingDependency(_) => {
+                    error!("Missing dependency: {}", err);
+                    anyhow!(format!("{}", err))
+                },
                 ProcessingError::Unexpected(err) => {
                     error!("Unexpected error: {:?}", err);
                     err
@@ -107,6 +118,11 @@ async fn handle_outputs(
                     let output_path = output_dir.join(&data.checksum).join(&data.name);
                     copy_making_dirs(&data.path, &output_path)?;
 
+                    // Attachments are kept as they are, rather than processed any further
+                    if data.types.contains(&ProcessType::Attachments) {
+                        continue;
+                    }
+
                     info!("Adding embedded file to Redis stream: {:?}", &data.path);
                     batcher.push(BatchEntry {
                         path: output_path,
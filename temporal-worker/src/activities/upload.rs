@@ -1,14 +1,17 @@
 use std::path::Path;
 
+use anyhow::anyhow;
 use aws_sdk_s3::primitives::ByteStream;
+use bytesize::MB;
 use log::error;
 use serde::{Deserialize, Serialize};
 use tap::Tap;
 use temporal_sdk::ActContext;
 use tokio::io::AsyncReadExt;
 
+use crate::io::{content_md5, MultipartUploader, retry_transient, s3_error};
 use crate::s3_client;
-use crate::util::parse_s3_uri;
+use crate::util::{DEFAULT_OUTPUT_KEY_PATTERN, parse_s3_uri, templated_s3_uri};
 
 /// Input to the `upload` activity.
 /// 
@@ -19,39 +22,83 @@ pub struct UploadInput {
     pub path: String,
     
     /// The S3 URI to upload the file to.
-    /// 
-    pub s3_uri: String,
+    ///
+    /// If it's omitted, it's built from `source_s3_uri` and `s3_key_pattern` instead.
+    ///
+    pub s3_uri: Option<String>,
+
+    /// The S3 URI of the file the uploaded file was processed from, if any.
+    ///
+    pub source_s3_uri: Option<String>,
+
+    /// The pattern the S3 URI is built from when it's omitted, or [`DEFAULT_OUTPUT_KEY_PATTERN`] if it's omitted too.
+    ///
+    /// See [`templated_s3_uri`] for the format of the pattern.
+    ///
+    pub s3_key_pattern: Option<String>,
+}
+
+impl UploadInput {
+    /// Returns the S3 URI to upload the file to, building it from the source S3 URI if it's omitted.
+    ///
+    fn output_s3_uri(&self) -> anyhow::Result<String> {
+        match (&self.s3_uri, &self.source_s3_uri) {
+            (Some(s3_uri), _) => Ok(s3_uri.clone()),
+            (None, Some(source_s3_uri)) => {
+                let pattern = self.s3_key_pattern.as_deref().unwrap_or(DEFAULT_OUTPUT_KEY_PATTERN);
+                templated_s3_uri(source_s3_uri, pattern)
+            },
+            (None, None) => Err(anyhow!("Either the S3 URI or the source S3 URI to upload to is required")),
+        }
+    }
 }
 
+/// Files larger than this are uploaded in parts.
+///
+const MULTIPART_THRESHOLD: u64 = 10 * MB;
+
 /// Activity for uploading a file to S3.
 ///
+/// Large files are uploaded in parts, and the progress is kept next to the file so a retried activity resumes the
+/// upload rather than starting over. A multipart upload failing for good is aborted, so no orphaned parts are left.
+///
+/// Each request carries the MD5 digest of its body, so S3 rejects bodies corrupted on their way rather than storing
+/// them, and requests failing with transient errors are retried. The object is either uploaded whole or not at all.
+///
 pub async fn upload(_ctx: ActContext, input: UploadInput) -> anyhow::Result<()> {
-    let file = tokio::fs::File::open(&input.path).await?;
-
-    // if file.metadata().await?.size() > MB * 10 {
-    //     let uploader = MultipartUploader::new(&input.s3_uri)?;
-    //     uploader.upload(&mut file).await
-    // } else {
-        upload_file(file, &input.s3_uri).await
-    // }
+    let s3_uri = input.output_s3_uri()?;
+    let mut file = tokio::fs::File::open(&input.path).await?;
+
+    if file.metadata().await?.len() > MULTIPART_THRESHOLD {
+        let uploader = MultipartUploader::new(&s3_uri)?
+            .resumable(format!("{}.upload-state.json", input.path));
+        uploader.upload(&mut file).await
+    } else {
+        upload_file(file, &s3_uri).await
+    }
 }
 
 async fn upload_file(
     mut file: tokio::fs::File,
     output_s3_uri: impl AsRef<Path>,
 ) -> anyhow::Result<()> {
-    let (bucket, key) = parse_s3_uri(output_s3_uri)?;
+    let (bucket, key, _) = parse_s3_uri(output_s3_uri)?;
 
     let mut buf = vec![];
     file.read_to_end(&mut buf).await?;
+    let checksum = content_md5(&buf);
 
-    s3_client().await
-        .put_object()
-        .bucket(bucket)
-        .key(key)
-        .body(ByteStream::from(buf))
-        .send()
-        .await
+    retry_transient(|| async {
+        s3_client().await
+            .put_object()
+            .bucket(&bucket)
+            .key(&key)
+            .content_md5(&checksum)
+            .body(ByteStream::from(buf.clone()))
+            .send()
+            .await
+            .map_err(s3_error)
+    }).await
         .tap(|result| {
             if let Err(e) = result {
                 error!("Error uploading file to S3: {}", e);
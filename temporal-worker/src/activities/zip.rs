@@ -1,7 +1,8 @@
+use std::collections::HashSet;
 use std::fs::{self, DirEntry};
 use std::path::{Path, PathBuf};
 
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use tempfile::NamedTempFile;
 use temporal_sdk::ActContext;
@@ -15,6 +16,14 @@ pub struct ZipInput {
     /// The S3 URI to download the file from.
     /// 
     pub directory: PathBuf,
+
+    /// Whether to follow symbolic links in the directory.
+    ///
+    /// Symbolic links are skipped unless set. Directories that were already walked are skipped either way, so a link
+    /// back to a parent directory doesn't walk forever.
+    ///
+    #[serde(default)]
+    pub follow_symlinks: bool,
 }
 
 /// Output from the `zip` activity.
@@ -36,7 +45,7 @@ pub async fn zip(_ctx: ActContext, input: ZipInput) -> anyhow::Result<ZipOutput>
     let path = NamedTempFile::new()?.into_temp_path().to_path_buf();
     let file = fs::File::create(&path)?;
     let mut builder = ArchiveBuilder::new(file)?;
-    walk(&input.directory, &mut |entry| {
+    walk(&input.directory, input.follow_symlinks, &mut HashSet::new(), &mut |entry| {
         let path = entry.path();
         let zip_path = path.strip_prefix(&input.directory)?;
         info!("adding {:?} into {:?}", path, zip_path);
@@ -46,18 +55,71 @@ pub async fn zip(_ctx: ActContext, input: ZipInput) -> anyhow::Result<ZipOutput>
     Ok(ZipOutput { path })
 }
 
-fn walk(dir: impl AsRef<Path>, handle_file: &mut dyn FnMut(&DirEntry) -> anyhow::Result<()>) -> anyhow::Result<()> {
+/// Calls `handle_file` for every file under the directory.
+///
+/// `visited` holds the canonical paths of the directories already walked, which are skipped if they're reached again
+/// through a symbolic link.
+///
+fn walk(
+    dir: impl AsRef<Path>,
+    follow_symlinks: bool,
+    visited: &mut HashSet<PathBuf>,
+    handle_file: &mut dyn FnMut(&DirEntry) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
     let dir = dir.as_ref();
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                walk(path, handle_file)?;
-            } else {
-                handle_file(&entry)?;
-            }
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    if !visited.insert(dir.canonicalize()?) {
+        warn!("skipping {:?}, it was already walked", dir);
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_symlink() && !follow_symlinks {
+            info!("skipping symbolic link {:?}", path);
+        } else if path.is_dir() {
+            walk(path, follow_symlinks, visited, handle_file)?;
+        } else {
+            handle_file(&entry)?;
         }
     }
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    fn walked(dir: &Path, follow_symlinks: bool) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        walk(dir, follow_symlinks, &mut HashSet::new(), &mut |entry| {
+            paths.push(entry.path().strip_prefix(dir)?.to_path_buf());
+            Ok(())
+        })?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    #[test]
+    fn test_walk_symlink_loop() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("nested"))?;
+        fs::write(dir.path().join("nested/file.txt"), "rusty processing")?;
+        fs::write(dir.path().join("other.txt"), "rusty processing")?;
+        symlink(dir.path(), dir.path().join("nested/parent"))?;
+        symlink(dir.path().join("other.txt"), dir.path().join("nested/link.txt"))?;
+
+        assert_eq!(walked(dir.path(), false)?, vec![PathBuf::from("nested/file.txt"), PathBuf::from("other.txt")]);
+        assert_eq!(walked(dir.path(), true)?, vec![
+            PathBuf::from("nested/file.txt"),
+            PathBuf::from("nested/link.txt"),
+            PathBuf::from("other.txt"),
+        ]);
+        Ok(())
+    }
 }
\ No newline at end of file
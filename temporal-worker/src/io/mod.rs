@@ -1,5 +1,10 @@
 pub use multipart_uploader::*;
+pub use retry::*;
 
 /// Uploader for uploading large files to S3 using multipart uploads.
 ///
-mod multipart_uploader;
\ No newline at end of file
+mod multipart_uploader;
+
+/// Retrying of S3 requests failing with transient errors.
+///
+mod retry;
\ No newline at end of file
@@ -1,25 +1,75 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
+use async_trait::async_trait;
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
 use bytesize::MB;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncReadExt};
 
+use crate::io::{content_md5, retry_transient, s3_error};
 use crate::s3_client;
 use crate::util::parse_s3_uri;
 
-pub struct MultipartUploader {
+/// The size of each uploaded part, except the last; S3 requires parts to be at least 5 MB.
+///
+const DEFAULT_PART_SIZE: u64 = 8 * MB;
+
+/// A part of a multipart upload that's been uploaded.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadedPart {
+    pub part_number: i32,
+    pub e_tag: String,
+}
+
+/// The progress of a multipart upload, persisted so an interrupted upload can be resumed.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadState {
+    pub upload_id: String,
+    pub parts: Vec<UploadedPart>,
+}
+
+/// The operations of a multipart upload to an object store.
+///
+#[async_trait]
+pub trait MultipartStore: Send + Sync {
+    /// Starts a new multipart upload, returning its upload ID.
+    ///
+    async fn create_upload(&self) -> anyhow::Result<String>;
+
+    /// Lists the parts already uploaded for the upload.
+    ///
+    async fn list_parts(&self, upload_id: &str) -> anyhow::Result<Vec<UploadedPart>>;
+
+    /// Uploads a part, returning its ETag.
+    ///
+    /// Errors that may not happen again if the part is uploaded again are [`TransientError`](crate::io::TransientError)s.
+    ///
+    async fn upload_part(&self, upload_id: &str, part_number: i32, body: Vec<u8>) -> anyhow::Result<String>;
+
+    /// Completes the upload from the given parts.
+    ///
+    async fn complete_upload(&self, upload_id: &str, parts: Vec<UploadedPart>) -> anyhow::Result<()>;
+
+    /// Aborts the upload, removing the parts uploaded so far.
+    ///
+    async fn abort_upload(&self, upload_id: &str) -> anyhow::Result<()>;
+}
+
+/// Multipart uploads to an S3 object.
+///
+pub struct S3MultipartStore {
     bucket: String,
     key: String,
 }
 
-impl MultipartUploader {
-    pub fn new(s3_uri: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let (bucket, key) = parse_s3_uri(s3_uri.as_ref())?;
-        Ok(Self { bucket, key })
-    }
-
-    pub async fn upload(&self, reader: &mut (dyn AsyncRead + Send + Sync + Unpin)) -> anyhow::Result<()> {
+#[async_trait]
+impl MultipartStore for S3MultipartStore {
+    async fn create_upload(&self) -> anyhow::Result<String> {
         let multipart_upload = s3_client()
             .await
             .create_multipart_upload()
@@ -28,57 +78,56 @@ impl MultipartUploader {
             .send()
             .await?;
 
-        if let Some(upload_id) = &multipart_upload.upload_id {
-            let parts = self.upload_parts(upload_id, reader).await?;
-            self.complete_upload(upload_id, parts).await?;
-        }
-
-        Ok(())
+        multipart_upload.upload_id.ok_or(anyhow::anyhow!("S3 didn't return an upload ID"))
     }
 
-    async fn upload_parts(
-        &self,
-        upload_id: impl AsRef<str>,
-        reader: &mut (dyn AsyncRead + Send + Sync + Unpin),
-    ) -> anyhow::Result<Vec<CompletedPart>> {
+    async fn list_parts(&self, upload_id: &str) -> anyhow::Result<Vec<UploadedPart>> {
         let mut parts = vec![];
-        let mut buf = Box::new([0; MB as usize]);
-        let mut part_num = 1_i32;
-
-        while let Ok(bytes_read) = reader.read(buf.as_mut()).await {
-            if bytes_read == 0 {
-                break;
-            }
-
-            let upload_part = s3_client()
+        let mut marker = None;
+        loop {
+            let output = s3_client()
                 .await
-                .upload_part()
+                .list_parts()
                 .bucket(&self.bucket)
                 .key(&self.key)
-                .upload_id(upload_id.as_ref())
-                .body(ByteStream::from(buf.to_vec()))
-                .part_number(part_num)
+                .upload_id(upload_id)
+                .set_part_number_marker(marker)
                 .send()
                 .await?;
 
-            parts.push(
-                CompletedPart::builder()
-                    .e_tag(upload_part.e_tag.unwrap_or_default())
-                    .part_number(part_num)
-                    .build(),
-            );
+            parts.extend(output.parts().unwrap_or_default().iter().map(|part| UploadedPart {
+                part_number: part.part_number(),
+                e_tag: part.e_tag().unwrap_or_default().to_string(),
+            }));
 
-            part_num += 1;
+            if !output.is_truncated() {
+                return Ok(parts);
+            }
+            marker = output.next_part_number_marker().map(str::to_string);
         }
+    }
 
-        Ok(parts)
+    async fn upload_part(&self, upload_id: &str, part_number: i32, body: Vec<u8>) -> anyhow::Result<String> {
+        let upload_part = s3_client()
+            .await
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
+            .content_md5(content_md5(&body))
+            .body(ByteStream::from(body))
+            .part_number(part_number)
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        Ok(upload_part.e_tag.unwrap_or_default())
     }
 
-    async fn complete_upload(
-        &self,
-        upload_id: impl AsRef<str>,
-        parts: Vec<CompletedPart>,
-    ) -> anyhow::Result<()> {
+    async fn complete_upload(&self, upload_id: &str, parts: Vec<UploadedPart>) -> anyhow::Result<()> {
+        let parts = parts.into_iter()
+            .map(|part| CompletedPart::builder().e_tag(part.e_tag).part_number(part.part_number).build())
+            .collect();
         let completed_multipart_upload = CompletedMultipartUpload::builder()
             .set_parts(Some(parts))
             .build();
@@ -89,10 +138,303 @@ impl MultipartUploader {
             .bucket(&self.bucket)
             .key(&self.key)
             .multipart_upload(completed_multipart_upload)
-            .upload_id(upload_id.as_ref())
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(s3_error)?;
+
+        Ok(())
+    }
+
+    async fn abort_upload(&self, upload_id: &str) -> anyhow::Result<()> {
+        s3_client()
+            .await
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id)
             .send()
             .await?;
 
         Ok(())
     }
 }
+
+pub struct MultipartUploader<S = S3MultipartStore> {
+    store: S,
+    part_size: u64,
+    state_path: Option<PathBuf>,
+}
+
+impl MultipartUploader {
+    pub fn new(s3_uri: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let (bucket, key, _) = parse_s3_uri(s3_uri.as_ref())?;
+        Ok(Self::with_store(S3MultipartStore { bucket, key }))
+    }
+}
+
+impl<S: MultipartStore> MultipartUploader<S> {
+    pub fn with_store(store: S) -> Self {
+        Self {
+            store,
+            part_size: DEFAULT_PART_SIZE,
+            state_path: None,
+        }
+    }
+
+    /// Persists the progress of the upload to the file at `state_path`, so a failed upload can be resumed.
+    ///
+    /// Uploading again with the same state path resumes the upload, skipping the parts that were already uploaded.
+    /// The file is removed once the upload completes.
+    ///
+    pub fn resumable(mut self, state_path: impl Into<PathBuf>) -> Self {
+        self.state_path = Some(state_path.into());
+        self
+    }
+
+    /// Uploads the content of the reader.
+    ///
+    /// Parts and completing the upload are retried while they fail with transient errors. If the upload still fails,
+    /// it's aborted so its parts aren't left behind, and the persisted progress is removed. An upload that's
+    /// interrupted instead, such as by the worker stopping, can still be resumed.
+    ///
+    pub async fn upload(&self, reader: &mut (dyn AsyncRead + Send + Sync + Unpin)) -> anyhow::Result<()> {
+        let mut state = self.resume_or_create().await?;
+        if let Err(err) = self.upload_and_complete(&mut state, reader).await {
+            self.abort(&state.upload_id).await;
+            return Err(err);
+        }
+
+        if let Some(state_path) = &self.state_path {
+            tokio::fs::remove_file(state_path).await?;
+        }
+        Ok(())
+    }
+
+    async fn upload_and_complete(
+        &self,
+        state: &mut UploadState,
+        reader: &mut (dyn AsyncRead + Send + Sync + Unpin),
+    ) -> anyhow::Result<()> {
+        self.upload_parts(state, reader).await?;
+
+        let mut parts = state.parts.clone();
+        parts.sort_by_key(|part| part.part_number);
+        retry_transient(|| self.store.complete_upload(&state.upload_id, parts.clone())).await
+    }
+
+    /// Aborts the upload and removes its persisted progress, logging rather than returning failures to do so.
+    ///
+    async fn abort(&self, upload_id: &str) {
+        warn!("Aborting upload {}", upload_id);
+        if let Err(err) = self.store.abort_upload(upload_id).await {
+            warn!("Failed to abort upload {}, its parts are left behind: {}", upload_id, err);
+        }
+        if let Some(state_path) = self.state_path.as_ref().filter(|state_path| state_path.exists()) {
+            if let Err(err) = tokio::fs::remove_file(state_path).await {
+                warn!("Failed to remove the progress of upload {}: {}", upload_id, err);
+            }
+        }
+    }
+
+    /// Resumes the upload persisted at the state path, or creates a new upload if there isn't one.
+    ///
+    /// The uploaded parts are listed from the store, since parts persisted locally may have been lost.
+    ///
+    async fn resume_or_create(&self) -> anyhow::Result<UploadState> {
+        if let Some(persisted) = self.load_state().await? {
+            match self.store.list_parts(&persisted.upload_id).await {
+                Ok(parts) => {
+                    info!("Resuming upload {} with {} uploaded parts", persisted.upload_id, parts.len());
+                    return Ok(UploadState { upload_id: persisted.upload_id, parts });
+                },
+                Err(err) => warn!("Failed to resume upload {}, starting over: {}", persisted.upload_id, err),
+            }
+        }
+
+        let state = UploadState {
+            upload_id: self.store.create_upload().await?,
+            parts: vec![],
+        };
+        self.save_state(&state).await?;
+        Ok(state)
+    }
+
+    async fn upload_parts(
+        &self,
+        state: &mut UploadState,
+        reader: &mut (dyn AsyncRead + Send + Sync + Unpin),
+    ) -> anyhow::Result<()> {
+        let uploaded: BTreeMap<i32, UploadedPart> = state.parts.iter()
+            .map(|part| (part.part_number, part.clone()))
+            .collect();
+        let mut part_number = 1_i32;
+
+        loop {
+            // Parts are read in full so their boundaries are the same between attempts
+            let mut body = vec![];
+            (&mut *reader).take(self.part_size).read_to_end(&mut body).await?;
+            if body.is_empty() && part_number > 1 {
+                break;
+            }
+
+            if !uploaded.contains_key(&part_number) {
+                let upload_id = &state.upload_id;
+                let e_tag = retry_transient(|| self.store.upload_part(upload_id, part_number, body.clone())).await?;
+                state.parts.push(UploadedPart { part_number, e_tag });
+                self.save_state(state).await?;
+            }
+
+            part_number += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn load_state(&self) -> anyhow::Result<Option<UploadState>> {
+        match &self.state_path {
+            Some(state_path) if state_path.exists() => {
+                let content = tokio::fs::read(state_path).await?;
+                Ok(Some(serde_json::from_slice(&content)?))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    async fn save_state(&self, state: &UploadState) -> anyhow::Result<()> {
+        if let Some(state_path) = &self.state_path {
+            tokio::fs::write(state_path, serde_json::to_vec(state)?).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+    use tokio::io::AsyncWriteExt;
+
+    use crate::io::TransientError;
+
+    use super::*;
+
+    /// In-memory store that can fail uploading a part, to simulate a network failure or a rejected part mid-upload.
+    ///
+    #[derive(Default)]
+    struct MockStore {
+        parts: Mutex<HashMap<i32, Vec<u8>>>,
+        uploaded_part_numbers: Mutex<Vec<i32>>,
+        /// The part failing once with a transient error.
+        ///
+        fail_part_number: Mutex<Option<i32>>,
+        /// The part failing every time with a permanent error.
+        ///
+        reject_part_number: Option<i32>,
+        completed: Mutex<Option<Vec<u8>>>,
+        aborted: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl MultipartStore for MockStore {
+        async fn create_upload(&self) -> anyhow::Result<String> {
+            Ok("upload-1".to_string())
+        }
+
+        async fn list_parts(&self, _: &str) -> anyhow::Result<Vec<UploadedPart>> {
+            Ok(self.parts.lock().unwrap().keys()
+                .map(|part_number| UploadedPart { part_number: *part_number, e_tag: format!("etag-{}", part_number) })
+                .collect())
+        }
+
+        async fn upload_part(&self, _: &str, part_number: i32, body: Vec<u8>) -> anyhow::Result<String> {
+            if self.reject_part_number == Some(part_number) {
+                return Err(anyhow!("access denied"));
+            }
+            if self.fail_part_number.lock().unwrap().take_if(|fail| *fail == part_number).is_some() {
+                return Err(TransientError("connection reset".into()).into());
+            }
+            self.uploaded_part_numbers.lock().unwrap().push(part_number);
+            self.parts.lock().unwrap().insert(part_number, body);
+            Ok(format!("etag-{}", part_number))
+        }
+
+        async fn complete_upload(&self, _: &str, parts: Vec<UploadedPart>) -> anyhow::Result<()> {
+            let stored = self.parts.lock().unwrap();
+            let content = parts.iter().flat_map(|part| stored[&part.part_number].clone()).collect();
+            *self.completed.lock().unwrap() = Some(content);
+            Ok(())
+        }
+
+        async fn abort_upload(&self, upload_id: &str) -> anyhow::Result<()> {
+            self.aborted.lock().unwrap().push(upload_id.to_string());
+            self.parts.lock().unwrap().clear();
+            Ok(())
+        }
+    }
+
+    fn content() -> Vec<u8> {
+        (0..10).flat_map(|i| vec![i as u8; 3]).collect()
+    }
+
+    #[tokio::test]
+    async fn test_upload_resumes_missing_parts() -> anyhow::Result<()> {
+        let workspace = tempfile::tempdir()?;
+        let state_path = workspace.path().join("upload-state.json");
+        let content = content();
+
+        let mut uploader = MultipartUploader::with_store(MockStore::default()).resumable(&state_path);
+        uploader.part_size = 7;
+
+        // The upload is interrupted while reading the third part, as if the worker stopped
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        writer.write_all(&content[..14]).await?;
+        let interrupted = tokio::time::timeout(Duration::from_millis(100), uploader.upload(&mut reader)).await;
+        assert!(interrupted.is_err());
+        assert!(state_path.exists());
+        assert_eq!(*uploader.store.uploaded_part_numbers.lock().unwrap(), vec![1, 2]);
+
+        uploader.store.uploaded_part_numbers.lock().unwrap().clear();
+        uploader.upload(&mut content.as_slice()).await?;
+
+        assert_eq!(*uploader.store.uploaded_part_numbers.lock().unwrap(), vec![3, 4, 5]);
+        assert_eq!(uploader.store.completed.lock().unwrap().as_ref(), Some(&content));
+        assert!(!state_path.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_retries_transient_failures() -> anyhow::Result<()> {
+        let content = content();
+        let store = MockStore { fail_part_number: Mutex::new(Some(3)), ..Default::default() };
+        let mut uploader = MultipartUploader::with_store(store);
+        uploader.part_size = 7;
+
+        uploader.upload(&mut content.as_slice()).await?;
+
+        assert_eq!(*uploader.store.uploaded_part_numbers.lock().unwrap(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(uploader.store.completed.lock().unwrap().as_ref(), Some(&content));
+        assert!(uploader.store.aborted.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_aborts_on_failure() -> anyhow::Result<()> {
+        let workspace = tempfile::tempdir()?;
+        let state_path = workspace.path().join("upload-state.json");
+        let store = MockStore { reject_part_number: Some(3), ..Default::default() };
+        let mut uploader = MultipartUploader::with_store(store).resumable(&state_path);
+        uploader.part_size = 7;
+
+        assert!(uploader.upload(&mut content().as_slice()).await.is_err());
+
+        assert_eq!(*uploader.store.aborted.lock().unwrap(), vec!["upload-1"]);
+        assert!(uploader.store.parts.lock().unwrap().is_empty());
+        assert!(uploader.store.completed.lock().unwrap().is_none());
+        assert!(!state_path.exists());
+        Ok(())
+    }
+}
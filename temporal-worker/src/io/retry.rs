@@ -0,0 +1,144 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::time::Duration;
+
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::warn;
+
+/// The maximum number of attempts of a request failing with transient errors.
+///
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// The delay before the first retry, doubled before each retry after it.
+///
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Codes of S3 errors that may not happen again if the request is retried.
+///
+/// `BadDigest` means the body was corrupted on its way, so sending it again can succeed.
+///
+const TRANSIENT_S3_ERROR_CODES: [&str; 5] = ["BadDigest", "InternalError", "RequestTimeout", "ServiceUnavailable", "SlowDown"];
+
+/// An error of a request that may succeed if it's retried, like a timeout or a throttled request.
+///
+pub struct TransientError(pub Box<dyn std::error::Error + Send + Sync>);
+
+impl Debug for TransientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TransientError({:?})", self.0)
+    }
+}
+
+impl Display for TransientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TransientError {}
+
+/// Converts the error of an S3 request, marking it as a [`TransientError`] if retrying the request may succeed.
+///
+pub fn s3_error<E, R>(err: SdkError<E, R>) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    R: Debug + Send + Sync + 'static,
+{
+    let transient = match &err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => true,
+        SdkError::ServiceError(context) => context.err().code()
+            .is_some_and(|code| TRANSIENT_S3_ERROR_CODES.contains(&code)),
+        _ => false,
+    };
+
+    match transient {
+        true => TransientError(Box::new(err)).into(),
+        false => err.into(),
+    }
+}
+
+/// Runs the operation, retrying it up to [`MAX_ATTEMPTS`] attempts in all while it fails with a [`TransientError`].
+///
+/// Any other error is returned right away.
+///
+pub async fn retry_transient<T, F, Fut>(mut operation: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(err) if attempt < MAX_ATTEMPTS && err.is::<TransientError>() => {
+                warn!("Retrying after a transient error (attempt {} of {}): {}", attempt, MAX_ATTEMPTS, err);
+                tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempt - 1)).await;
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// The base64 encoded MD5 digest of a request body, for S3 to check the body against with `Content-MD5`.
+///
+pub fn content_md5(body: &[u8]) -> String {
+    STANDARD.encode(md5::compute(body).0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_retry_transient() -> anyhow::Result<()> {
+        let attempts = AtomicU32::new(0);
+
+        let result = retry_transient(|| async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(TransientError("connection reset".into()).into()),
+                _ => Ok("uploaded"),
+            }
+        }).await?;
+
+        assert_eq!(result, "uploaded");
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_gives_up() {
+        let attempts = AtomicU32::new(0);
+
+        let result: anyhow::Result<()> = retry_transient(|| async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(TransientError("connection reset".into()).into())
+        }).await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), MAX_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_permanent_error() {
+        let attempts = AtomicU32::new(0);
+
+        let result: anyhow::Result<()> = retry_transient(|| async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow!("access denied"))
+        }).await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_content_md5() {
+        assert_eq!(content_md5(b""), "1B2M2Y8AsgTpgAmY7PhCfg==");
+    }
+}
@@ -3,6 +3,7 @@
 //!
 #![warn(missing_docs)]
 
+use std::future::Future;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -15,6 +16,8 @@ use temporal_sdk::{sdk_client_options, Worker};
 use temporal_sdk_core::{Client, CoreRuntime, init_worker, RetryClient};
 use temporal_sdk_core_api::telemetry::TelemetryOptionsBuilder;
 use temporal_sdk_core_api::worker::WorkerConfigBuilder;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
 use url::Url;
 
 use services::config;
@@ -37,8 +40,8 @@ const NAMESPACE: &str = "default";
 
 lazy_static! {
     static ref S3_CLIENT: AsyncOnce<s3::Client> = AsyncOnce::new(async {
-        let config = aws_config::load_from_env().await;
-        s3::Client::new(&config)
+        let sdk_config = aws_config::load_from_env().await;
+        s3::Client::from_conf(s3_config(&sdk_config, config().get("S3_ENDPOINT_URL")))
     });
 
     static ref REDIS: redis::Client = {
@@ -64,6 +67,20 @@ pub(crate) async fn s3_client() -> &'static s3::Client {
     S3_CLIENT.get().await
 }
 
+/// Builds the S3 client configuration, using an S3-compatible store (i.e. MinIO) at `endpoint_url` if provided.
+///
+/// S3-compatible stores generally don't support virtual-hosted-style addressing, so path-style addressing is forced for them.
+///
+fn s3_config(sdk_config: &aws_config::SdkConfig, endpoint_url: Option<String>) -> s3::Config {
+    let mut builder = s3::config::Builder::from(sdk_config);
+    if let Some(endpoint_url) = endpoint_url {
+        builder = builder
+            .endpoint_url(endpoint_url)
+            .force_path_style(true);
+    }
+    builder.build()
+}
+
 pub(crate) fn redis() -> &'static redis::Client {
     &REDIS
 }
@@ -76,6 +93,29 @@ fn temporal_address() -> &'static str {
     TEMPORAL_ADDRESS.as_str()
 }
 
+/// Receiver of the signal to gracefully shut down the workers, which is sent once with `true`.
+///
+pub type Shutdown = watch::Receiver<bool>;
+
+/// Waits for SIGTERM or SIGINT, then signals the workers to shut down gracefully.
+///
+/// Returns the receiver the workers are run with.
+///
+pub fn shutdown_on_signal() -> anyhow::Result<Shutdown> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let (shutdown_sink, shutdown) = watch::channel(false);
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM"),
+            _ = sigint.recv() => info!("Received SIGINT"),
+        }
+        let _ = shutdown_sink.send(true);
+    });
+    Ok(shutdown)
+}
+
 /// Run the "dynamic" Temporal worker.
 ///
 /// This worker is responsible for polling activity tasks on a generalized task queue.
@@ -83,7 +123,9 @@ fn temporal_address() -> &'static str {
 /// It allows the activities to provide machine-specific task queues for future activities
 /// in a workflow to use allowing for inter-activity filesystem access.
 ///
-pub async fn run_dynamic_worker() -> anyhow::Result<()> {
+/// The worker shuts down gracefully once `shutdown` is signalled; see [`run_until_shutdown`].
+///
+pub async fn run_dynamic_worker(shutdown: Shutdown) -> anyhow::Result<()> {
     let client = connect_to_server().await?;
     let telemetry_options = TelemetryOptionsBuilder::default().build()?;
     let runtime = CoreRuntime::new_assume_tokio(telemetry_options)?;
@@ -98,7 +140,8 @@ pub async fn run_dynamic_worker() -> anyhow::Result<()> {
     let core_worker = init_worker(&runtime, worker_config, client)?;
     let mut worker = Worker::new_from_core(Arc::new(core_worker), TASK_QUEUE);
     worker.register_activity("CreateWorkspace", activities::create_workspace);
-    worker.run().await
+    let shutdown_worker = worker.shutdown_handle();
+    run_until_shutdown(worker.run(), shutdown_worker, shutdown).await
 }
 
 /// Run the "sticky" Temporal worker.
@@ -108,7 +151,9 @@ pub async fn run_dynamic_worker() -> anyhow::Result<()> {
 /// Activity tasks run on this worker will rely on the local filesystem having been operated
 /// on by previous activity tasks.
 ///
-pub async fn run_sticky_worker() -> anyhow::Result<()> {
+/// The worker shuts down gracefully once `shutdown` is signalled; see [`run_until_shutdown`].
+///
+pub async fn run_sticky_worker(shutdown: Shutdown) -> anyhow::Result<()> {
     let client = connect_to_server().await?;
     let telemetry_options = TelemetryOptionsBuilder::default().build()?;
     let runtime = CoreRuntime::new_assume_tokio(telemetry_options)?;
@@ -128,7 +173,30 @@ pub async fn run_sticky_worker() -> anyhow::Result<()> {
     worker.register_activity("Download", activities::download);
     worker.register_activity("Upload", activities::upload);
     worker.register_activity("Zip", activities::zip);
-    worker.run().await
+    let shutdown_worker = worker.shutdown_handle();
+    run_until_shutdown(worker.run(), shutdown_worker, shutdown).await
+}
+
+/// Runs a worker until it finishes, initiating its graceful shutdown once `shutdown` is signalled.
+///
+/// Once shutdown is initiated, the worker stops polling for new activity tasks but keeps running until its in-flight
+/// activities finish, so they clean up their temporary files and complete or resume their uploads rather than being
+/// killed partway through.
+///
+async fn run_until_shutdown(
+    run: impl Future<Output = anyhow::Result<()>>,
+    initiate_shutdown: impl FnOnce(),
+    mut shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    tokio::pin!(run);
+    tokio::select! {
+        result = &mut run => return result,
+        Ok(_) = shutdown.wait_for(|shutdown| *shutdown) => {},
+    }
+
+    info!("Shutting down worker, waiting for in-flight activities to finish");
+    initiate_shutdown();
+    run.await
 }
 
 async fn connect_to_server() -> anyhow::Result<RetryClient<Client>> {
@@ -142,3 +210,65 @@ async fn connect_to_server() -> anyhow::Result<RetryClient<Client>> {
     Ok(client)
 
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_until_shutdown_drains_in_flight_activity() -> anyhow::Result<()> {
+        let (shutdown_sink, shutdown) = watch::channel(false);
+        let (initiated_sink, initiated) = oneshot::channel();
+        let activity_finished = Arc::new(AtomicBool::new(false));
+
+        // A worker with an in-flight activity, which only returns once shutdown is initiated and the activity finishes
+        let worker_activity_finished = activity_finished.clone();
+        let run = async move {
+            let activity = tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                worker_activity_finished.store(true, Ordering::SeqCst);
+            });
+            initiated.await?;
+            activity.await?;
+            anyhow::Ok(())
+        };
+
+        shutdown_sink.send(true)?;
+        run_until_shutdown(run, move || initiated_sink.send(()).unwrap(), shutdown).await?;
+
+        assert!(activity_finished.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_until_shutdown_without_signal() -> anyhow::Result<()> {
+        let (_shutdown_sink, shutdown) = watch::channel(false);
+
+        run_until_shutdown(async { Ok(()) }, || panic!("shutdown initiated"), shutdown).await
+    }
+
+    #[test]
+    fn test_s3_config_with_endpoint_url() {
+        let sdk_config = aws_config::SdkConfig::builder().build();
+
+        let s3_config = format!("{:?}", s3_config(&sdk_config, Some("http://localhost:9000".to_string())));
+
+        assert!(s3_config.contains(r#"EndpointUrl("http://localhost:9000")"#));
+        assert!(s3_config.contains("ForcePathStyle(true)"));
+    }
+
+    #[test]
+    fn test_s3_config_without_endpoint_url() {
+        let sdk_config = aws_config::SdkConfig::builder().build();
+
+        let s3_config = format!("{:?}", s3_config(&sdk_config, None));
+
+        assert!(!s3_config.contains(r#"EndpointUrl(""#));
+        assert!(!s3_config.contains("ForcePathStyle"));
+    }
+}
@@ -1,12 +1,31 @@
+use clap::Parser;
+use log::warn;
 use tokio::try_join;
-use temporal_worker::{run_dynamic_worker, run_sticky_worker};
+
+use services::{check_dependencies, log_level};
+use temporal_worker::{run_dynamic_worker, run_sticky_worker, shutdown_on_signal};
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[arg(short = 'q', long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    #[arg(short = 'v', long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    simple_logger::init_with_level(log::Level::Info)?;
+    let args = Args::parse();
+    simple_logger::init_with_level(log_level(args.quiet, args.verbose))?;
+    for missing in check_dependencies() {
+        warn!("Files that need it will fail to be processed: {}", missing);
+    }
+
+    let shutdown = shutdown_on_signal()?;
     try_join!(
-        run_dynamic_worker(),
-        run_sticky_worker()
+        run_dynamic_worker(shutdown.clone()),
+        run_sticky_worker(shutdown)
     )?;
     Ok(())
-}
\ No newline at end of file
+}
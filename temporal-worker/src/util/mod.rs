@@ -8,7 +8,11 @@ use url::{ParseError, Url};
 
 use crate::redis;
 
-pub fn parse_s3_uri(s3_uri_str: impl AsRef<Path>) -> anyhow::Result<(String, String)> {
+/// Parses an S3 URI, like `s3://bucket/key?versionId=version`, into its bucket, key, and version ID, if any.
+///
+/// The version ID pins a specific version of the object; other query parameters are ignored.
+///
+pub fn parse_s3_uri(s3_uri_str: impl AsRef<Path>) -> anyhow::Result<(String, String, Option<String>)> {
     let s3_uri_str = s3_uri_str.as_ref().to_string_lossy().to_string();
     let source_url = Url::from_str(s3_uri_str.as_str())
         .map_err(|_| anyhow!("Failed to parse S3 URL"))?;
@@ -19,13 +23,59 @@ pub fn parse_s3_uri(s3_uri_str: impl AsRef<Path>) -> anyhow::Result<(String, Str
         } else {
             key
         };
+        let version_id = source_url.query_pairs()
+            .find(|(name, _)| name == "versionId")
+            .map(|(_, version_id)| version_id.to_string());
 
-        Ok((bucket.to_string(), key.to_string()))
+        Ok((bucket.to_string(), key.to_string(), version_id))
     } else {
         Err(ParseError::EmptyHost)?
     }
 }
 
+/// The pattern of output keys used when none is given, nesting the archive under `processed/` by its source key.
+///
+pub const DEFAULT_OUTPUT_KEY_PATTERN: &str = "processed/{source_key}.zip";
+
+/// Builds an output S3 URI from the source S3 URI and a pattern.
+///
+/// The pattern is either a full `s3://` URI, or a key in the source's bucket. These placeholders are replaced by parts
+/// of the source URI:
+///
+/// * `{bucket}` - The bucket, i.e. `inbox`.
+/// * `{source_key}` - The whole key, i.e. `2023/10/report.eml`.
+/// * `{dir}` - The key's directory, i.e. `2023/10`, or nothing for top-level keys.
+/// * `{name}` - The key's file name, i.e. `report.eml`.
+/// * `{stem}` - The key's file name without its extension, i.e. `report`.
+///
+/// Empty segments left by placeholders, like `{dir}` of a top-level key, are removed from the output key.
+///
+pub fn templated_s3_uri(source_s3_uri: impl AsRef<Path>, pattern: &str) -> anyhow::Result<String> {
+    let (bucket, source_key, _) = parse_s3_uri(source_s3_uri)?;
+    let (dir, name) = source_key.rsplit_once('/').unwrap_or(("", &source_key));
+    let stem = Path::new(name).file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or(name.to_string());
+
+    let templated = pattern
+        .replace("{bucket}", &bucket)
+        .replace("{source_key}", &source_key)
+        .replace("{dir}", dir)
+        .replace("{name}", name)
+        .replace("{stem}", &stem);
+
+    let (output_bucket, output_key) = match templated.strip_prefix("s3://") {
+        Some(uri) => uri.split_once('/').unwrap_or((uri, "")),
+        None => (bucket.as_str(), templated.as_str()),
+    };
+    let output_key = output_key.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>().join("/");
+    if output_bucket.is_empty() || output_key.is_empty() {
+        return Err(anyhow!("Pattern {} builds no output S3 URI from {}", pattern, source_key));
+    }
+
+    Ok(format!("s3://{}/{}", output_bucket, output_key))
+}
+
 pub struct BatchEntry {
     pub path: PathBuf,
     pub mimetype: String,
@@ -79,3 +129,51 @@ impl<'a> ProcessOutputBatcher<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_s3_uri() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_s3_uri("s3://inbox/2023/10/report.eml")?,
+            ("inbox".to_string(), "2023/10/report.eml".to_string(), None),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_s3_uri_version_id() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_s3_uri("s3://inbox/2023/10/report.eml?versionId=3HL4kqtJlcpXroDTDmjVBH40Nrjfkd")?,
+            ("inbox".to_string(), "2023/10/report.eml".to_string(), Some("3HL4kqtJlcpXroDTDmjVBH40Nrjfkd".to_string())),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_templated_s3_uri() -> anyhow::Result<()> {
+        let source = "s3://inbox/2023/10/report.eml";
+
+        assert_eq!(templated_s3_uri(source, DEFAULT_OUTPUT_KEY_PATTERN)?, "s3://inbox/processed/2023/10/report.eml.zip");
+        assert_eq!(templated_s3_uri(source, "{dir}/processed/{stem}.zip")?, "s3://inbox/2023/10/processed/report.zip");
+        assert_eq!(templated_s3_uri(source, "s3://outbox/{bucket}/{name}.zip")?, "s3://outbox/inbox/report.eml.zip");
+        Ok(())
+    }
+
+    #[test]
+    fn test_templated_s3_uri_top_level_key() -> anyhow::Result<()> {
+        let source = "s3://inbox/report.eml";
+
+        assert_eq!(templated_s3_uri(source, DEFAULT_OUTPUT_KEY_PATTERN)?, "s3://inbox/processed/report.eml.zip");
+        assert_eq!(templated_s3_uri(source, "{dir}/{stem}.zip")?, "s3://inbox/report.zip");
+        Ok(())
+    }
+
+    #[test]
+    fn test_templated_s3_uri_invalid() {
+        assert!(templated_s3_uri("s3://inbox/report.eml", "s3://outbox/{dir}").is_err());
+        assert!(templated_s3_uri("not a uri", DEFAULT_OUTPUT_KEY_PATTERN).is_err());
+    }
+}
@@ -6,7 +6,10 @@
 use std::io::Read;
 use std::path;
 
+use async_stream::stream;
+use rand::RngCore;
 use tempfile::{NamedTempFile, TempPath};
+use tokio_stream::Stream;
 
 /// Reads the contents of a file into a `Vec<u8>`.
 ///
@@ -33,6 +36,26 @@ pub fn temp_path() -> std::io::Result<TempPath> {
     Ok(NamedTempFile::new()?.into_temp_path())
 }
 
+/// Creates random bytes, along with a stream of them split into chunks.
+///
+/// # Arguments
+///
+/// * `len` - The number of bytes to create.
+/// * `chunk_size` - The size of each chunk of the stream; the last chunk may be smaller.
+///
+pub fn random_byte_stream(len: usize, chunk_size: usize) -> (Vec<u8>, impl Stream<Item = Vec<u8>> + Send) {
+    let mut bytes = vec![0; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    let chunks: Vec<Vec<u8>> = bytes.chunks(chunk_size).map(<[u8]>::to_vec).collect();
+    let stream = stream! {
+        for chunk in chunks {
+            yield chunk;
+        }
+    };
+    (bytes, stream)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;